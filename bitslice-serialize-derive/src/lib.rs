@@ -0,0 +1,519 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Expr, Fields, Generics, Ident, LitInt,
+    Path, Type,
+};
+
+// The generated code refers to firmware-common items through `crate::`, so
+// the derives only work inside firmware-common, like `fixed_point_factory!`.
+
+/// How a field is written by the `BitArraySerializable` derive
+enum FieldEncoding {
+    /// `BitSlicePrimitive` of the field type
+    Primitive,
+    /// integer packed into `bits` bits
+    Bits(usize),
+    /// `IntEnum` packed into `bits` bits
+    IntEnum(usize),
+    /// `PrimitiveEnum` packed into `bits` bits
+    PrimitiveEnum(usize),
+    /// f32 / f64 (or an array of them) packed through a fixed point factory
+    FixedPoint(Path),
+}
+
+struct FieldInfo {
+    ident: Ident,
+    ty: Type,
+    encoding: FieldEncoding,
+    delta_factory: Option<Path>,
+}
+
+/// The float type of a field, and the array length if the field is an array
+struct FloatField {
+    float: Ident,
+    array_len: Option<Expr>,
+}
+
+fn float_field(ty: &Type) -> Option<FloatField> {
+    match ty {
+        Type::Path(path) => {
+            let ident = path.path.get_ident()?;
+            if ident == "f32" || ident == "f64" {
+                Some(FloatField {
+                    float: ident.clone(),
+                    array_len: None,
+                })
+            } else {
+                None
+            }
+        }
+        Type::Array(array) => {
+            let inner = float_field(&array.elem)?;
+            if inner.array_len.is_some() {
+                return None;
+            }
+            Some(FloatField {
+                float: inner.float,
+                array_len: Some(array.len.clone()),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn factory_trait(float: &Ident) -> TokenStream2 {
+    if float == "f64" {
+        quote!(crate::common::fixed_point::F64FixedPointFactory)
+    } else {
+        quote!(crate::common::fixed_point::F32FixedPointFactory)
+    }
+}
+
+fn packed_type(factory: &Path, float_field: &FloatField) -> TokenStream2 {
+    let factory_trait = factory_trait(&float_field.float);
+    let packed = quote! {
+        <<#factory as #factory_trait>::VI as crate::common::variable_int::VariableIntTrait>::Packed
+    };
+    if let Some(len) = &float_field.array_len {
+        quote!([#packed; #len])
+    } else {
+        packed
+    }
+}
+
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<FieldInfo>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "only structs with named fields are supported",
+                ))
+            }
+        },
+        _ => return Err(syn::Error::new(input.span(), "only structs are supported")),
+    };
+
+    let mut infos = Vec::new();
+    for field in fields {
+        let mut encoding = FieldEncoding::Primitive;
+        let mut delta_factory = None;
+        for attr in &field.attrs {
+            if attr.path().is_ident("bits") {
+                // #[bits(5)] or #[bits(4, int_enum)]
+                let mut bits = None;
+                let mut kind: Option<Ident> = None;
+                attr.parse_args_with(|input: syn::parse::ParseStream| {
+                    let lit: LitInt = input.parse()?;
+                    bits = Some(lit.base10_parse::<usize>()?);
+                    if input.parse::<syn::Token![,]>().is_ok() {
+                        kind = Some(input.parse()?);
+                    }
+                    Ok(())
+                })?;
+                let bits = bits.ok_or(syn::Error::new(attr.span(), "missing bit width"))?;
+                encoding = match kind {
+                    None => FieldEncoding::Bits(bits),
+                    Some(kind) if kind == "int_enum" => FieldEncoding::IntEnum(bits),
+                    Some(kind) if kind == "primitive_enum" => FieldEncoding::PrimitiveEnum(bits),
+                    Some(kind) => {
+                        return Err(syn::Error::new(
+                            kind.span(),
+                            "expected `int_enum` or `primitive_enum`",
+                        ))
+                    }
+                };
+            } else if attr.path().is_ident("fixed_point") {
+                let factory: Path = attr.parse_args()?;
+                if float_field(&field.ty).is_none() {
+                    return Err(syn::Error::new(
+                        field.ty.span(),
+                        "fixed_point only supports f32, f64 and arrays of them",
+                    ));
+                }
+                encoding = FieldEncoding::FixedPoint(factory);
+            } else if attr.path().is_ident("delta") {
+                let factory: Path = attr.parse_args()?;
+                if float_field(&field.ty).is_none() {
+                    return Err(syn::Error::new(
+                        field.ty.span(),
+                        "delta only supports f32, f64 and arrays of them",
+                    ));
+                }
+                delta_factory = Some(factory);
+            }
+        }
+        infos.push(FieldInfo {
+            ident: field.ident.clone().unwrap(),
+            ty: field.ty.clone(),
+            encoding,
+            delta_factory,
+        });
+    }
+    Ok(infos)
+}
+
+fn packed_integer(repr: &TokenStream2, bits: usize) -> TokenStream2 {
+    quote!(packed_struct::prelude::Integer<#repr, packed_struct::prelude::packed_bits::Bits<#bits>>)
+}
+
+/// Returns (serialize statement, deserialize expression, len_bits expression)
+fn field_codec(field: &FieldInfo) -> (TokenStream2, TokenStream2, TokenStream2) {
+    let ident = &field.ident;
+    let ty = &field.ty;
+    match &field.encoding {
+        FieldEncoding::Primitive => (
+            quote!(writer.write(self.#ident);),
+            quote!(reader.read::<#ty>().unwrap()),
+            quote!(<#ty as crate::common::delta_logger::prelude::BitSlicePrimitive>::len_bits()),
+        ),
+        FieldEncoding::Bits(bits) => {
+            let packed = packed_integer(&quote!(#ty), *bits);
+            (
+                quote!(writer.write::<#packed>(self.#ident.into());),
+                quote!(reader.read::<#packed>().unwrap().into()),
+                quote!(#bits),
+            )
+        }
+        FieldEncoding::IntEnum(bits) => {
+            let packed = packed_integer(&quote!(u8), *bits);
+            (
+                quote!(writer.write::<#packed>(u8::from(self.#ident).into());),
+                quote!(<#ty as core::convert::TryFrom<u8>>::try_from(u8::from(
+                    reader.read::<#packed>().unwrap()
+                )).unwrap()),
+                quote!(#bits),
+            )
+        }
+        FieldEncoding::PrimitiveEnum(bits) => {
+            let packed = packed_integer(&quote!(u8), *bits);
+            (
+                quote!(writer.write::<#packed>(
+                    packed_struct::prelude::PrimitiveEnum::to_primitive(&self.#ident).into()
+                );),
+                quote!(<#ty as packed_struct::prelude::PrimitiveEnum>::from_primitive(
+                    reader.read::<#packed>().unwrap().into()
+                ).unwrap()),
+                quote!(#bits),
+            )
+        }
+        FieldEncoding::FixedPoint(factory) => {
+            let float_field = float_field(ty).unwrap();
+            let factory_trait = factory_trait(&float_field.float);
+            let packed = packed_type(factory, &float_field);
+            let (write, read) = if float_field.array_len.is_some() {
+                (
+                    quote!(self.#ident.map(|v| <#factory as #factory_trait>::to_fixed_point_capped(v))),
+                    quote!(reader.read::<#packed>().unwrap().map(|v| <#factory as #factory_trait>::to_float(v))),
+                )
+            } else {
+                (
+                    quote!(<#factory as #factory_trait>::to_fixed_point_capped(self.#ident)),
+                    quote!(<#factory as #factory_trait>::to_float(reader.read::<#packed>().unwrap())),
+                )
+            };
+            (
+                quote!(writer.write::<#packed>(#write);),
+                read,
+                quote!(<#packed as crate::common::delta_logger::prelude::BitSlicePrimitive>::len_bits()),
+            )
+        }
+    }
+}
+
+fn bit_array_serializable_impl(
+    name: &Ident,
+    generics: &Generics,
+    fields: &[FieldInfo],
+) -> TokenStream2 {
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let mut serialize = Vec::new();
+    let mut deserialize = Vec::new();
+    let mut len_bits = Vec::new();
+    for field in fields {
+        let ident = &field.ident;
+        let (write, read, len) = field_codec(field);
+        serialize.push(write);
+        deserialize.push(quote!(#ident: #read,));
+        len_bits.push(len);
+    }
+    if len_bits.is_empty() {
+        len_bits.push(quote!(0));
+    }
+
+    quote! {
+        impl #impl_generics crate::common::delta_logger::prelude::BitArraySerializable
+            for #name #ty_generics #where_clause
+        {
+            fn serialize<const N: usize>(
+                &self,
+                writer: &mut crate::common::delta_logger::prelude::BitSliceWriter<N>,
+            ) {
+                #(#serialize)*
+            }
+
+            fn deserialize<const N: usize>(
+                reader: &mut crate::common::delta_logger::prelude::BitSliceReader<N>,
+            ) -> Self {
+                Self {
+                    #(#deserialize)*
+                }
+            }
+
+            fn len_bits() -> usize {
+                #(#len_bits)+*
+            }
+        }
+    }
+}
+
+/// Derives `BitArraySerializable`, fields are written in declaration order.
+///
+/// Field attributes:
+/// - `#[bits(N)]`: packs an unsigned integer into N bits
+/// - `#[bits(N, int_enum)]` / `#[bits(N, primitive_enum)]`: packs a u8 backed
+///   `IntEnum` / `PrimitiveEnum` into N bits
+/// - `#[fixed_point(Factory)]`: packs a f32 / f64 (or an array of them)
+///   through a `fixed_point_factory!`, values outside the range are capped
+///
+/// Other fields must implement `BitSlicePrimitive`.
+#[proc_macro_derive(BitArraySerializable, attributes(bits, fixed_point))]
+pub fn derive_bit_array_serializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match parse_fields(&input) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    bit_array_serializable_impl(&input.ident, &input.generics, &fields).into()
+}
+
+/// Derives `Deltable` and generates the `{Name}Delta` struct.
+///
+/// Every field needs a `#[delta(Factory)]` attribute naming the fixed point
+/// factory of its delta, only f32 / f64 fields and arrays of them are
+/// supported. `subtract` returns `None` when any delta is out of range.
+#[proc_macro_derive(Deltable, attributes(delta))]
+pub fn derive_deltable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let fields = match parse_fields(&input) {
+        Ok(fields) => fields,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    if !input.generics.params.is_empty() {
+        return syn::Error::new(input.generics.span(), "generic structs are not supported")
+            .to_compile_error()
+            .into();
+    }
+    let delta_name = format_ident!("{}Delta", name);
+
+    let mut delta_fields = Vec::new();
+    let mut delta_field_infos = Vec::new();
+    let mut add_delta = Vec::new();
+    let mut subtract = Vec::new();
+    for field in &fields {
+        let ident = &field.ident;
+        let Some(factory) = &field.delta_factory else {
+            return syn::Error::new(ident.span(), "missing #[delta(Factory)] attribute")
+                .to_compile_error()
+                .into();
+        };
+        let float_field = float_field(&field.ty).unwrap();
+        let factory_trait = factory_trait(&float_field.float);
+        let packed = packed_type(factory, &float_field);
+
+        delta_fields.push(quote! {
+            #[defmt(Debug2Format)]
+            pub #ident: #packed,
+        });
+        delta_field_infos.push(FieldInfo {
+            ident: ident.clone(),
+            ty: syn::parse2(packed).unwrap(),
+            encoding: FieldEncoding::Primitive,
+            delta_factory: None,
+        });
+
+        if let Some(len) = &float_field.array_len {
+            add_delta.push(quote! {
+                #ident: core::array::from_fn(|i| {
+                    self.#ident[i] + <#factory as #factory_trait>::to_float(delta.#ident[i])
+                }),
+            });
+            subtract.push(quote! {
+                #ident: {
+                    let delta: [Option<_>; #len] = core::array::from_fn(|i| {
+                        <#factory as #factory_trait>::to_fixed_point(self.#ident[i] - other.#ident[i])
+                    });
+                    if delta.iter().any(Option::is_none) {
+                        return None;
+                    }
+                    delta.map(Option::unwrap)
+                },
+            });
+        } else {
+            add_delta.push(quote! {
+                #ident: self.#ident + <#factory as #factory_trait>::to_float(delta.#ident),
+            });
+            subtract.push(quote! {
+                #ident: <#factory as #factory_trait>::to_fixed_point(self.#ident - other.#ident)?,
+            });
+        }
+    }
+
+    let delta_serializable =
+        bit_array_serializable_impl(&delta_name, &Generics::default(), &delta_field_infos);
+
+    quote! {
+        #[derive(defmt::Format, Debug, Clone)]
+        #vis struct #delta_name {
+            #(#delta_fields)*
+        }
+
+        #delta_serializable
+
+        impl crate::common::delta_logger::prelude::Deltable for #name {
+            type DeltaType = #delta_name;
+
+            fn add_delta(&self, delta: &Self::DeltaType) -> Option<Self> {
+                Some(Self {
+                    #(#add_delta)*
+                })
+            }
+
+            fn subtract(&self, other: &Self) -> Option<Self::DeltaType> {
+                Some(#delta_name {
+                    #(#subtract)*
+                })
+            }
+        }
+    }
+    .into()
+}
+
+/// Derives `SensorData`, the schema id is given with
+/// `#[sensor_data(schema_id = ID)]`, optionally with `schema_version = N`
+#[proc_macro_derive(SensorData, attributes(sensor_data))]
+pub fn derive_sensor_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut schema_id: Option<Expr> = None;
+    let mut schema_version: Option<Expr> = None;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("sensor_data") {
+            continue;
+        }
+        let result = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("schema_id") {
+                schema_id = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("schema_version") {
+                schema_version = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `schema_id` or `schema_version`"))
+            }
+        });
+        if let Err(e) = result {
+            return e.to_compile_error().into();
+        }
+    }
+
+    let Some(schema_id) = schema_id else {
+        return syn::Error::new(
+            name.span(),
+            "missing #[sensor_data(schema_id = ...)] attribute",
+        )
+        .to_compile_error()
+        .into();
+    };
+    let schema_version = schema_version.map(|version| quote!(const SCHEMA_VERSION: u8 = #version;));
+
+    quote! {
+        impl #impl_generics crate::common::sensor_reading::SensorData
+            for #name #ty_generics #where_clause
+        {
+            const SCHEMA_ID: u8 = #schema_id;
+            #schema_version
+        }
+    }
+    .into()
+}
+
+/// Chained `SchemaHasher` calls hashing the names and `SchemaHash`es of the
+/// fields
+fn schema_hash_fields(fields: &Fields) -> TokenStream2 {
+    let hashes = fields.iter().enumerate().map(|(i, field)| {
+        let name = match &field.ident {
+            Some(ident) => ident.to_string(),
+            None => i.to_string(),
+        };
+        let ty = &field.ty;
+        quote! {
+            .write_str(#name)
+            .write_u32(<#ty as crate::common::schema_hash::SchemaHash>::SCHEMA_HASH)
+        }
+    });
+    quote!(#(#hashes)*)
+}
+
+/// Derives `SchemaHash` from the field names and the `SchemaHash` of every
+/// field type, enum variants are hashed with their names and discriminants.
+/// The name of the type itself is not part of the hash.
+#[proc_macro_derive(SchemaHash)]
+pub fn derive_schema_hash(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    for param in input.generics.type_params_mut() {
+        param
+            .bounds
+            .push(syn::parse_quote!(crate::common::schema_hash::SchemaHash));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let hash = match &input.data {
+        Data::Struct(data) => {
+            let fields = schema_hash_fields(&data.fields);
+            quote!(.write_str("struct") #fields)
+        }
+        Data::Enum(data) => {
+            let variants = data.variants.iter().map(|variant| {
+                let variant_name = variant.ident.to_string();
+                let discriminant = variant
+                    .discriminant
+                    .as_ref()
+                    .map(|(_, expr)| quote!(#expr).to_string())
+                    .unwrap_or_default();
+                let fields = schema_hash_fields(&variant.fields);
+                quote! {
+                    .write_str(#variant_name)
+                    .write_str(#discriminant)
+                    #fields
+                }
+            });
+            quote!(.write_str("enum") #(#variants)*)
+        }
+        Data::Union(_) => {
+            return syn::Error::new(name.span(), "unions are not supported")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    quote! {
+        impl #impl_generics crate::common::schema_hash::SchemaHash
+            for #name #ty_generics #where_clause
+        {
+            const SCHEMA_HASH: u32 = crate::common::schema_hash::SchemaHasher::new()
+                #hash
+                .finish();
+        }
+    }
+    .into()
+}
@@ -0,0 +1,16 @@
+use std::process::Command;
+
+fn main() {
+    // reported by the rpc handshake so the host can tell which commit a device runs
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=8", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or("unknown".into());
+    println!("cargo:rustc-env=VL_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+}
@@ -60,6 +60,14 @@ impl<R: RawMutex> ArmingStateManager<R> {
         self.state.lock(|s| s.borrow().is_armed())
     }
 
+    /// Neither software nor hardware armed
+    pub fn is_disarmed(&self) -> bool {
+        self.state.lock(|s| {
+            let s = s.borrow();
+            !s.software_armed && !s.hardware_armed
+        })
+    }
+
     pub fn subscriber(&self) -> Subscriber<R, ArmingState, 1, 5, 1> {
         self.pub_sub.subscriber().unwrap()
     }
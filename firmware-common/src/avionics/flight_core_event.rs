@@ -5,6 +5,7 @@ use rkyv::{Archive, Deserialize, Serialize};
 
 #[repr(u8)]
 #[derive(defmt::Format, Debug, Clone, Copy, IntEnum, Archive, Deserialize, Serialize, PartialEq)]
+#[archive(check_bytes)]
 pub enum FlightCoreState {
     DisArmed = 0,
     Armed = 1,
@@ -17,6 +18,7 @@ pub enum FlightCoreState {
 }
 
 #[derive(Debug, Clone, Copy, Archive, Deserialize, Serialize, defmt::Format, PartialEq)]
+#[archive(check_bytes)]
 pub enum FlightCoreEvent {
     CriticalError,
     DidNotReachMinApogee,
@@ -1,15 +1,31 @@
 use int_enum::IntEnum;
 use rkyv::{Archive, Deserialize, Serialize};
 
+use crate::common::schema_hash::SchemaHash;
+use crate::common::versioned::Versioned;
+
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, defmt::Format, PartialEq, Archive, Serialize, Deserialize, IntEnum)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    defmt::Format,
+    PartialEq,
+    Archive,
+    Serialize,
+    Deserialize,
+    IntEnum,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub enum PyroSelection {
     Pyro1 = 1,
     Pyro2 = 2,
     Pyro3 = 3,
 }
 
-#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize, SchemaHash)]
+#[archive(check_bytes)]
 pub struct FlightProfile {
     pub drogue_pyro: PyroSelection,
     pub drogue_chute_minimum_time_ms: f64,
@@ -21,3 +37,5 @@ pub struct FlightProfile {
     pub drouge_to_main_ms: f64, // 107s
     pub main_to_landed_ms: f64, // 76s
 }
+
+impl Versioned for FlightProfile {}
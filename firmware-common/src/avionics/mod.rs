@@ -18,10 +18,12 @@ use imu_calibration_info::IMUCalibrationInfo;
 use libm::fabsf;
 use nalgebra::Vector3;
 use vlfs::FileEntry;
-use vlfs::{Crc, Flash};
+use vlfs::{Crc, FileType, Flash};
 
 use crate::common::delta_logger::buffered_logger::BufferedLoggerState;
-use crate::common::delta_logger::ring_delta_logger::RingDeltaLoggerConfig;
+use crate::common::delta_logger::ring_delta_logger::{
+    RingDeltaLoggerConfig, RingPinConfig, MIN_PINNED_SEGMENTS,
+};
 use crate::common::delta_logger::ring_delta_logger::RingDeltaLoggerState;
 use crate::{
     avionics::{
@@ -30,8 +32,22 @@ use crate::{
     },
     claim_devices,
     common::{
-        can_bus::messages::ResetMessage,
-        delta_logger::{delta_logger::UnixTimestampLog, merged_logger::MergedLogger},
+        can_bus::{
+            clock_sync::{ClockSyncMaster, CLOCK_SYNC_INTERVAL_MS},
+            command::{
+                CanCommandClient, CanCommandClientEvent, CanCommandResult,
+                CAN_COMMAND_BROADCAST_NODE_ID,
+            },
+            messages::{HealthState, NodeCommand, ResetMessage},
+            node_registry::{create_heartbeat_message, CanNodeRegistry, CAN_HEARTBEAT_INTERVAL_MS},
+            statistics::CanBusStatistics,
+            tx_queue::CanBusTXQueue,
+        },
+        delta_logger::{
+            delta_logger::UnixTimestampLog,
+            merged_logger::MergedLogger,
+            timestamp_factories::{GPSFF1, GPSFF2, SensorsFF1, SensorsFF2},
+        },
         sensor_reading::SensorReading,
         sensor_snapshot::PartialSensorSnapshot,
         ticker::Ticker,
@@ -47,9 +63,12 @@ use crate::{
         indicator::Indicator,
         mag::MagData,
     },
-    fixed_point_factory, pyro, vl_device_manager_type,
+    delta_logger_file_types, pyro, try_or_warn, vl_device_manager_type,
+};
+use crate::{
+    common::can_bus::node_types::{STRAIN_GAUGES_NODE_TYPE, VOID_LAKE_NODE_TYPE},
+    driver::can_bus::{CanBusRX, CanBusRawMessage as _, CanBusTX},
 };
-use crate::{common::can_bus::node_types::VOID_LAKE_NODE_TYPE, driver::can_bus::CanBusTX};
 use crate::{
     common::{
         can_bus::messages as can_messages,
@@ -57,7 +76,15 @@ use crate::{
         device_config::DeviceConfig,
         file_types::*,
         vlp::{
-            packet::{LowPowerModePacket, SoftArmPacket, VLPUplinkPacket},
+            config_update::{
+                config_hash, deserialize_config, ConfigUpdateStager, ConfigUpdateState,
+                DeviceConfigUpdate,
+            },
+            packet::{
+                CanCommandPacket, CanCommandResultPacket, ConfigUpdateResultPacket,
+                ConfigUpdateStatus, ConfigUpdateType, LowPowerModePacket, SoftArmPacket,
+                VLPUplinkPacket,
+            },
             telemetry_packet::TelemetryPacketBuilder,
             uplink_client::VLPUplinkClient,
         },
@@ -65,7 +92,7 @@ use crate::{
     driver::timestamp::BootTimestamp,
 };
 use paste::paste;
-use self_test::{self_test, SelfTestResult};
+use self_test::{can_nodes_self_test, self_test, SelfTestResult};
 
 pub mod arming_state;
 mod backup_backup_flight_core;
@@ -79,13 +106,66 @@ mod imu_calibration_info;
 mod self_test;
 pub mod vertical_speed_filter;
 
+/// Ring delta loggers of `avionics_main`, see `delta_logger_file_types`
+#[macro_export]
+macro_rules! avionics_delta_loggers {
+    ($callback:ident!($($args:expr),* $(,)?)) => {
+        $callback!($($args,)* [
+            ($crate::common::file_types::AVIONICS_BARO_LOGGER_TIER_1, $crate::driver::barometer::BaroData, $crate::common::delta_logger::timestamp_factories::SensorsFF1),
+            ($crate::common::file_types::AVIONICS_BARO_LOGGER_TIER_2, $crate::driver::barometer::BaroData, $crate::common::delta_logger::timestamp_factories::SensorsFF2),
+            ($crate::common::file_types::AVIONICS_LOW_G_IMU_LOGGER_TIER_1, $crate::driver::imu::IMUData, $crate::common::delta_logger::timestamp_factories::SensorsFF1),
+            ($crate::common::file_types::AVIONICS_LOW_G_IMU_LOGGER_TIER_2, $crate::driver::imu::IMUData, $crate::common::delta_logger::timestamp_factories::SensorsFF2),
+            ($crate::common::file_types::AVIONICS_GPS_LOGGER_TIER_1, $crate::driver::gps::GPSData, $crate::common::delta_logger::timestamp_factories::GPSFF1),
+            ($crate::common::file_types::AVIONICS_GPS_LOGGER_TIER_2, $crate::driver::gps::GPSData, $crate::common::delta_logger::timestamp_factories::GPSFF2),
+        ])
+    };
+}
+
+/// Segments of `avionics_delta_loggers` pinned around a flight
+#[macro_export]
+macro_rules! avionics_pinned_delta_loggers {
+    ($callback:ident!($($args:expr),* $(,)?)) => {
+        $callback!($($args,)* [
+            ($crate::common::file_types::AVIONICS_BARO_LOGGER_TIER_1_PINNED, $crate::driver::barometer::BaroData, $crate::common::delta_logger::timestamp_factories::SensorsFF1),
+            ($crate::common::file_types::AVIONICS_BARO_LOGGER_TIER_2_PINNED, $crate::driver::barometer::BaroData, $crate::common::delta_logger::timestamp_factories::SensorsFF2),
+            ($crate::common::file_types::AVIONICS_LOW_G_IMU_LOGGER_TIER_1_PINNED, $crate::driver::imu::IMUData, $crate::common::delta_logger::timestamp_factories::SensorsFF1),
+            ($crate::common::file_types::AVIONICS_LOW_G_IMU_LOGGER_TIER_2_PINNED, $crate::driver::imu::IMUData, $crate::common::delta_logger::timestamp_factories::SensorsFF2),
+            ($crate::common::file_types::AVIONICS_GPS_LOGGER_TIER_1_PINNED, $crate::driver::gps::GPSData, $crate::common::delta_logger::timestamp_factories::GPSFF1),
+            ($crate::common::file_types::AVIONICS_GPS_LOGGER_TIER_2_PINNED, $crate::driver::gps::GPSData, $crate::common::delta_logger::timestamp_factories::GPSFF2),
+        ])
+    };
+}
+
+const DELTA_LOGGER_FILE_TYPES: &[FileType] = avionics_delta_loggers!(delta_logger_file_types!());
+const PINNED_DELTA_LOGGER_FILE_TYPES: &[FileType] =
+    avionics_pinned_delta_loggers!(delta_logger_file_types!());
+
+/// Ring segments covering this long before launch are kept
+const PIN_SECONDS_BEFORE_LAUNCH: u32 = 10 * 60;
+/// Ring segments covering this long after landing are kept
+const PIN_SECONDS_AFTER_LANDING: u32 = 10 * 60;
+const TIER_1_MAX_PINNED_SEGMENTS: u32 = 24; // 2 hours
+const TIER_2_MAX_PINNED_SEGMENTS: u32 = 8; // 4 hours
+const _: () = assert!(
+    TIER_1_MAX_PINNED_SEGMENTS >= MIN_PINNED_SEGMENTS
+        && TIER_2_MAX_PINNED_SEGMENTS >= MIN_PINNED_SEGMENTS
+);
+
 macro_rules! create_buffered_tiered_logger {
     (
-        $logger_name: ident, $logger_fut_name: ident, $sensor_data_type: ty, $buffer_length: literal, $services: ident,
-        $tier_1_ff: ty: $tier_1_file_type: ident, $tier_1_first_segment_seconds: expr,
-        $tier_2_ff: ty: $tier_2_file_type: ident, $tier_2_first_segment_seconds: expr,
+        $logger_name: ident, $logger_fut_name: ident, $logger_pin_name: ident, $sensor_data_type: ty, $buffer_length: literal, $services: ident, $device_serial_number: ident,
+        $tier_1_ff: ty: $tier_1_file_type: ident / $tier_1_pinned_file_type: ident, $tier_1_first_segment_seconds: expr,
+        $tier_2_ff: ty: $tier_2_file_type: ident / $tier_2_pinned_file_type: ident, $tier_2_first_segment_seconds: expr,
     ) => {
         paste! {
+            const _: () = assert!(
+                contains_file_type(DELTA_LOGGER_FILE_TYPES, $tier_1_file_type)
+                    && contains_file_type(DELTA_LOGGER_FILE_TYPES, $tier_2_file_type)
+                    && contains_file_type(PINNED_DELTA_LOGGER_FILE_TYPES, $tier_1_pinned_file_type)
+                    && contains_file_type(PINNED_DELTA_LOGGER_FILE_TYPES, $tier_2_pinned_file_type),
+                "Logger missing from avionics_delta_loggers"
+            );
+
             let [< $logger_name _tier_1_state >] = RingDeltaLoggerState::<$sensor_data_type, _, _, $tier_1_ff, _, _>::new(
                 $services.fs,
                 $services.delay.clone(),
@@ -95,7 +175,14 @@ macro_rules! create_buffered_tiered_logger {
                     seconds_per_segment: 5 * 60,
                     first_segment_seconds: $tier_1_first_segment_seconds,
                     segments_per_ring: 6, // 30 min
+                    pin: Some(RingPinConfig {
+                        file_type: $tier_1_pinned_file_type,
+                        seconds_before_pin: PIN_SECONDS_BEFORE_LAUNCH,
+                        seconds_after_unpin: PIN_SECONDS_AFTER_LANDING,
+                        max_pinned_segments: TIER_1_MAX_PINNED_SEGMENTS,
+                    }),
                 },
+                *$device_serial_number,
             )
             .await
             .unwrap();
@@ -111,7 +198,14 @@ macro_rules! create_buffered_tiered_logger {
                     seconds_per_segment: 30 * 60,
                     first_segment_seconds: $tier_2_first_segment_seconds,
                     segments_per_ring: 10, // 5 hours
+                    pin: Some(RingPinConfig {
+                        file_type: $tier_2_pinned_file_type,
+                        seconds_before_pin: PIN_SECONDS_BEFORE_LAUNCH,
+                        seconds_after_unpin: PIN_SECONDS_AFTER_LANDING,
+                        max_pinned_segments: TIER_2_MAX_PINNED_SEGMENTS,
+                    }),
                 },
+                *$device_serial_number,
             )
             .await
             .unwrap();
@@ -122,20 +216,29 @@ macro_rules! create_buffered_tiered_logger {
             let [< $logger_name _buffered_state >] = BufferedLoggerState::<_, _, _, $buffer_length>::new([< $logger_name _merged >]);
             let ($logger_name, mut [< $logger_name _buffered_runner >]) = [< $logger_name _buffered_state >].get_logger_runner();
 
+            let $logger_pin_name = |pinned: bool| {
+                if pinned {
+                    [< $logger_name _tier_1_state >].pin();
+                    [< $logger_name _tier_2_state >].pin();
+                } else {
+                    [< $logger_name _tier_1_state >].unpin();
+                    [< $logger_name _tier_2_state >].unpin();
+                }
+            };
+
             let $logger_fut_name = join3([< $logger_name _tier_1_runner >].run(), [< $logger_name _tier_2_runner >].run(), [< $logger_name _buffered_runner >].run());
         }
     };
 }
 
-fixed_point_factory!(SensorsFF1, f64, 4.9, 7.0, 0.05);
-fixed_point_factory!(SensorsFF2, f64, 199.0, 210.0, 0.5);
-
 #[inline(never)]
 pub async fn avionics_main(
     device_manager: vl_device_manager_type!(),
     services: system_services_type!(),
     config: &DeviceConfig,
     device_serial_number: &[u8; 12],
+    can_node_registry: &BlockingMutex<NoopRawMutex, RefCell<CanNodeRegistry>>,
+    can_bus_statistics: &BlockingMutex<NoopRawMutex, RefCell<CanBusStatistics>>,
 ) -> ! {
     claim_devices!(device_manager, indicators);
 
@@ -154,7 +257,13 @@ pub async fn avionics_main(
         };
 
     log_info!("Running self test");
-    match self_test(device_manager).await {
+    let self_test_result = self_test(device_manager).await;
+    let self_health = match self_test_result {
+        SelfTestResult::Ok => HealthState::Healthy,
+        SelfTestResult::PartialFailed => HealthState::Degraded,
+        SelfTestResult::Failed => HealthState::UnHealthy,
+    };
+    match self_test_result {
         SelfTestResult::Ok => {
             log_info!("Self test passed");
             services.buzzer_queue.publish(2000, 50, 150);
@@ -180,46 +289,44 @@ pub async fn avionics_main(
     }
 
     log_info!("Creating GPS logger");
-    fixed_point_factory!(GPSFF1, f64, 99.0, 110.0, 0.5);
-    fixed_point_factory!(GPSFF2, f64, 4999.0, 5010.0, 0.5);
     create_buffered_tiered_logger!(
-        gps_logger, gps_logger_fut, GPSData, 10, services,
-        GPSFF1: AVIONICS_GPS_LOGGER_TIER_1, 25 * 1,
-        GPSFF2: AVIONICS_GPS_LOGGER_TIER_2, 25 * 2,
+        gps_logger, gps_logger_fut, gps_logger_pin, GPSData, 10, services, device_serial_number,
+        GPSFF1: AVIONICS_GPS_LOGGER_TIER_1 / AVIONICS_GPS_LOGGER_TIER_1_PINNED, 25 * 1,
+        GPSFF2: AVIONICS_GPS_LOGGER_TIER_2 / AVIONICS_GPS_LOGGER_TIER_2_PINNED, 25 * 2,
     );
 
     log_info!("Creating low G IMU logger");
     create_buffered_tiered_logger!(
-        low_g_imu_logger, low_g_imu_logger_fut, IMUData, 40, services,
-        SensorsFF1: AVIONICS_LOW_G_IMU_LOGGER_TIER_1, 25 * 3,
-        SensorsFF2: AVIONICS_LOW_G_IMU_LOGGER_TIER_2, 25 * 4,
+        low_g_imu_logger, low_g_imu_logger_fut, low_g_imu_logger_pin, IMUData, 40, services, device_serial_number,
+        SensorsFF1: AVIONICS_LOW_G_IMU_LOGGER_TIER_1 / AVIONICS_LOW_G_IMU_LOGGER_TIER_1_PINNED, 25 * 3,
+        SensorsFF2: AVIONICS_LOW_G_IMU_LOGGER_TIER_2 / AVIONICS_LOW_G_IMU_LOGGER_TIER_2_PINNED, 25 * 4,
     );
 
     // log_info!("Creating High G IMU logger");
     // create_buffered_tiered_logger!(
-    //     high_g_imu_logger, high_g_imu_logger_fut, IMUData, 40, services,
+    //     high_g_imu_logger, high_g_imu_logger_fut, IMUData, 40, services, device_serial_number,
     //     SensorsFF1: AVIONICS_HIGH_G_IMU_LOGGER_TIER_1, 25 * 5,
     //     SensorsFF2: AVIONICS_HIGH_G_IMU_LOGGER_TIER_2, 25 * 6,
     // );
 
     log_info!("Creating baro logger");
     create_buffered_tiered_logger!(
-        baro_logger, baro_logger_fut, BaroData, 40, services,
-        SensorsFF1: AVIONICS_BARO_LOGGER_TIER_1, 25 * 7,
-        SensorsFF2: AVIONICS_BARO_LOGGER_TIER_2, 25 * 8,
+        baro_logger, baro_logger_fut, baro_logger_pin, BaroData, 40, services, device_serial_number,
+        SensorsFF1: AVIONICS_BARO_LOGGER_TIER_1 / AVIONICS_BARO_LOGGER_TIER_1_PINNED, 25 * 7,
+        SensorsFF2: AVIONICS_BARO_LOGGER_TIER_2 / AVIONICS_BARO_LOGGER_TIER_2_PINNED, 25 * 8,
     );
 
     // log_info!("Creating mag logger");
     // fixed_point_factory!(MagFF1, f64, 49.9, 55.0, 0.05);
     // create_buffered_tiered_logger!(
-    //     mag_logger, mag_logger_fut, MagData, 40, services,
+    //     mag_logger, mag_logger_fut, MagData, 40, services, device_serial_number,
     //     MagFF1: AVIONICS_MAG_LOGGER_TIER_1, 25 * 9,
     //     SensorsFF2: AVIONICS_MAG_LOGGER_TIER_2, 25 * 10,
     // );
 
     // log_info!("Creating battery logger");
     // create_buffered_tiered_logger!(
-    //     battery_logger, battery_logger_fut, ADCData<Volt>, 40, services,
+    //     battery_logger, battery_logger_fut, ADCData<Volt>, 40, services, device_serial_number,
     //     SensorsFF1: AVIONICS_BATTERY_LOGGER_TIER_1, 25 * 11,
     //     SensorsFF2: AVIONICS_BATTERY_LOGGER_TIER_2, 25 * 12,
     // );
@@ -277,14 +384,74 @@ pub async fn avionics_main(
     );
     log_info!("Devices claimed");
 
+    let vlp_device_id = config.vlp_device_id(device_serial_number);
+    log_info!("VLP device id: {}", vlp_device_id);
+    let vlp = VLPUplinkClient::new(vlp_device_id);
+
     let mut can_bus = can_bus.take().unwrap();
-    let (mut can_tx, _) = can_bus.split();
+    let (mut can_tx, mut can_rx) = can_bus.split();
     can_tx.configure_self_node(
         VOID_LAKE_NODE_TYPE,
         can_node_id_from_serial_number(device_serial_number),
     );
 
     let can_tx = Mutex::<NoopRawMutex, _>::new(can_tx);
+    // clock sync messages are sent directly through can_tx, they need the
+    // time right after the frame is sent
+    let can_tx_queue = CanBusTXQueue::<16>::new(can_bus_statistics);
+    let can_tx_queue_fut = can_tx_queue.run(&can_tx, services.delay());
+    let clock_sync_master = BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(ClockSyncMaster::new()));
+    let can_command_client =
+        BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(CanCommandClient::new()));
+
+    let can_send_command = async |command: NodeCommand,
+                                  argument: u32,
+                                  target_node_type: u8,
+                                  target_node_id: u16,
+                                  relay: bool| {
+        let message = can_command_client.lock(|c| {
+            c.borrow_mut().create_command(
+                command,
+                argument,
+                target_node_type,
+                target_node_id,
+                relay,
+                services.clock.now_ms(),
+            )
+        });
+        can_tx_queue.enqueue(&message, 4);
+        log_info!("Sent CAN command {:?}", command);
+    };
+    let relay_can_command_result = |result: CanCommandResult| {
+        if result.relay {
+            vlp.send_priority(
+                CanCommandResultPacket {
+                    node_type: result.node_type,
+                    node_id: result.node_id,
+                    command: result.command,
+                    status: result.status,
+                }
+                .into(),
+            );
+        }
+    };
+
+    let can_command_fut = async {
+        let mut ticker = Ticker::every(services.clock(), services.delay(), 50.0);
+        loop {
+            let event = can_command_client.lock(|c| c.borrow_mut().poll(services.clock.now_ms()));
+            match event {
+                Some(CanCommandClientEvent::Resend(message)) => {
+                    can_tx_queue.enqueue(&message, 4);
+                }
+                Some(CanCommandClientEvent::TimedOut(result)) => {
+                    relay_can_command_result(result);
+                }
+                None => {}
+            }
+            ticker.next().await;
+        }
+    };
 
     let can_tx_avionics_status_fut = async {
         let mut ticker = Ticker::every(services.clock(), services.delay(), 2000.0);
@@ -293,26 +460,90 @@ pub async fn avionics_main(
                 low_power: low_power_mode.lock(|r| *r.borrow()),
                 armed: arming_state.is_armed(),
             };
-            let mut can_tx = can_tx.lock().await;
-            can_tx.send(&message, 3).await.ok();
+            can_tx_queue.enqueue(&message, 3);
             log_info!("Sent CAN avionics status message");
-            drop(can_tx);
 
             ticker.next().await;
         }
     };
 
+    let can_tx_heartbeat_fut = async {
+        let mut ticker = Ticker::every(services.clock(), services.delay(), CAN_HEARTBEAT_INTERVAL_MS);
+        loop {
+            let message = create_heartbeat_message(
+                self_health,
+                services.clock.now_ms(),
+                device_serial_number,
+            );
+            can_tx_queue.enqueue(&message, 3);
+
+            ticker.next().await;
+        }
+    };
+
+    let can_rx_fut = async {
+        loop {
+            let result = can_rx.receive().await;
+            can_bus_statistics.lock(|s| s.borrow_mut().record_rx(result.is_ok()));
+            match result {
+                Ok(message) => {
+                    if can_node_registry.lock(|r| r.borrow_mut().process_message(&message)) {
+                        continue;
+                    }
+                    if let Some(result) =
+                        can_command_client.lock(|c| c.borrow_mut().process_message(&message))
+                    {
+                        log_info!("CAN command result: {:?}", result);
+                        relay_can_command_result(result);
+                        continue;
+                    }
+                    let receive_time = services.unix_clock.convert_to_unix(message.timestamp());
+                    if let Some(response) = clock_sync_master
+                        .lock(|m| m.borrow().process_message(&message, receive_time))
+                    {
+                        can_tx_queue.enqueue(&response, 6);
+                    }
+                }
+                Err(e) => {
+                    log_error!("Error receiving CAN message: {:?}", e);
+                    services.delay().delay_ms(150.0).await;
+                }
+            }
+        }
+    };
+
     let can_tx_unix_time_fut = async {
         let mut unix_clock_sub = services.unix_clock.subscribe_unix_clock_update();
         loop {
             let unix_timestamp = unix_clock_sub.next_message_pure().await;
-            let mut can_tx = can_tx.lock().await;
             let message = can_messages::UnixTimeMessage {
                 timestamp: (unix_timestamp as u64).into(),
             };
-            can_tx.send(&message, 2).await.ok();
+            can_tx_queue.enqueue(&message, 2);
             log_info!("Sent CAN unix time message");
+        }
+    };
+
+    let can_tx_clock_sync_fut = async {
+        services.unix_clock.wait_until_ready().await;
+        let mut ticker = Ticker::every(services.clock(), services.delay(), CLOCK_SYNC_INTERVAL_MS);
+        loop {
+            let mut can_tx = can_tx.lock().await;
+            let message = clock_sync_master.lock(|m| m.borrow_mut().create_sync_message());
+            let send_start = services.unix_clock.now_ms();
+            let result = can_tx.send(&message, 6).await;
+            let send_end = services.unix_clock.now_ms();
+            can_bus_statistics.lock(|s| s.borrow_mut().record_tx(result.is_ok()));
+            if result.is_ok()
+                && let Some(follow_up) =
+                    clock_sync_master.lock(|m| m.borrow_mut().sync_sent(send_start, send_end))
+            {
+                let result = can_tx.send(&follow_up, 6).await;
+                can_bus_statistics.lock(|s| s.borrow_mut().record_tx(result.is_ok()));
+            }
             drop(can_tx);
+
+            ticker.next().await;
         }
     };
 
@@ -324,7 +555,36 @@ pub async fn avionics_main(
     };
 
     let telemetry_packet_builder = TelemetryPacketBuilder::new(services.unix_clock());
-    let vlp = VLPUplinkClient::new();
+    let can_nodes_monitor_fut = async {
+        let mut ticker = Ticker::every(services.clock(), services.delay(), 1000.0);
+        let mut last_result = SelfTestResult::Ok;
+        loop {
+            ticker.next().await;
+            let summary = can_node_registry.lock(|r| r.borrow().summary(services.clock.now_ms()));
+            let can_bus_statistics = can_bus_statistics.lock(|s| s.borrow().clone());
+            telemetry_packet_builder.update(|b| {
+                b.can_nodes_online = summary.online;
+                b.can_nodes_faulty = summary.faulty();
+                b.can_bus_state = can_bus_statistics.state;
+                b.can_bus_tx_lost = can_bus_statistics.tx_lost();
+            });
+
+            let result = can_nodes_self_test(&summary);
+            if result != last_result {
+                if result != SelfTestResult::Ok {
+                    log_warn!("CAN nodes self test: {:?}, {:?}", result, summary);
+                    services.buzzer_queue.publish(2000, 50, 150);
+                    services.buzzer_queue.publish(2000, 50, 150);
+                    services.buzzer_queue.publish(3000, 50, 150);
+                    services.buzzer_queue.publish(2000, 50, 150);
+                } else {
+                    log_info!("CAN nodes self test passed");
+                }
+                last_result = result;
+            }
+        }
+    };
+
     let vlp_tx_fut = async {
         let mut update_ticker = Ticker::every(services.clock(), services.delay(), 1000.0);
         loop {
@@ -340,6 +600,10 @@ pub async fn avionics_main(
         }
     };
     let vlp_rx_fut = async {
+        let config_update_state_file =
+            ConfigFile::<ConfigUpdateState, _, _>::new(services.fs, CONFIG_UPDATE_STATE_FILE_TYPE);
+        let config_update_state = config_update_state_file.read().await.unwrap_or_default();
+        let mut config_update_stager = ConfigUpdateStager::new(&config_update_state);
         loop {
             let (packet, _) = vlp.wait_receive().await;
             low_power_mode.lock(|r| r.replace(false));
@@ -388,10 +652,8 @@ pub async fn avionics_main(
                     }
                 }
                 VLPUplinkPacket::ResetPacket(_) => {
-                    let mut can_tx = can_tx.lock().await;
-                    can_tx.send(&ResetMessage {}, 7).await.ok();
+                    can_tx_queue.enqueue(&ResetMessage {}, 7);
                     log_info!("Sent CAN reset message");
-                    drop(can_tx);
                     services.delay().delay_ms(100.0).await;
                     services.reset();
                 }
@@ -399,23 +661,7 @@ pub async fn avionics_main(
                     services
                         .fs
                         .remove_files(|file_entry: &FileEntry| {
-                            let typ = file_entry.typ;
-                            return typ == BENCHMARK_FILE_TYPE
-                                || typ == AVIONICS_SENSORS_FILE_TYPE
-                                || typ == AVIONICS_LOG_FILE_TYPE
-                                || typ == GROUND_TEST_LOG_FILE_TYPE
-                                || typ == AVIONICS_GPS_LOGGER_TIER_1
-                                || typ == AVIONICS_GPS_LOGGER_TIER_2
-                                || typ == AVIONICS_LOW_G_IMU_LOGGER_TIER_1
-                                || typ == AVIONICS_LOW_G_IMU_LOGGER_TIER_2
-                                || typ == AVIONICS_HIGH_G_IMU_LOGGER_TIER_1
-                                || typ == AVIONICS_HIGH_G_IMU_LOGGER_TIER_2
-                                || typ == AVIONICS_BARO_LOGGER_TIER_1
-                                || typ == AVIONICS_BARO_LOGGER_TIER_2
-                                || typ == AVIONICS_MAG_LOGGER_TIER_1
-                                || typ == AVIONICS_MAG_LOGGER_TIER_2
-                                || typ == AVIONICS_BATTERY_LOGGER_TIER_1
-                                || typ == AVIONICS_BATTERY_LOGGER_TIER_2;
+                            removed_by_delete_logs(file_entry.typ)
                         })
                         .await
                         .ok();
@@ -431,6 +677,84 @@ pub async fn avionics_main(
                         }
                     });
                 }
+                VLPUplinkPacket::CanCommandPacket(CanCommandPacket {
+                    command,
+                    target_node_type,
+                    target_node_id,
+                    argument,
+                    ..
+                }) => {
+                    can_send_command(command, argument, target_node_type, target_node_id, true)
+                        .await;
+                }
+                VLPUplinkPacket::ConfigChunkPacket(chunk) => {
+                    config_update_stager.add_chunk(&chunk);
+                }
+                VLPUplinkPacket::ConfigCommitPacket(commit) => {
+                    let unix_now = services
+                        .unix_clock
+                        .ready()
+                        .then(|| services.unix_clock.now_ms());
+                    let result = if let Some(hash) = config_update_stager.applied_hash(&commit) {
+                        // the result of this commit got lost, the config is already written
+                        Ok(hash)
+                    } else {
+                        let result: Result<u32, ConfigUpdateStatus> = try {
+                            let data =
+                                config_update_stager.validate(&commit, &config.lora_key, unix_now)?;
+                            if !arming_state.is_disarmed() {
+                                Err(ConfigUpdateStatus::RejectedArmed)?;
+                            }
+
+                            let write_result = match commit.config_type {
+                                ConfigUpdateType::FlightProfile => {
+                                    let flight_profile = deserialize_config::<FlightProfile>(data)
+                                        .ok_or(ConfigUpdateStatus::Invalid)?;
+                                    log_info!("New flight profile: {:?}", flight_profile);
+                                    flight_profile_file.write(&flight_profile).await
+                                }
+                                ConfigUpdateType::DeviceConfig => {
+                                    let update = deserialize_config::<DeviceConfigUpdate>(data)
+                                        .ok_or(ConfigUpdateStatus::Invalid)?;
+                                    log_info!("New device config: {:?}", update);
+                                    let mut new_config = config.clone();
+                                    update.apply(&mut new_config);
+                                    ConfigFile::<DeviceConfig, _, _>::new(
+                                        services.fs,
+                                        DEVICE_CONFIG_FILE_TYPE,
+                                    )
+                                    .write(&new_config)
+                                    .await
+                                }
+                            };
+                            if let Err(e) = write_result {
+                                log_error!("Failed to write config: {:?}", e);
+                                Err(ConfigUpdateStatus::WriteFailed)?;
+                            }
+                            config_hash(data)
+                        };
+                        if let Ok(hash) = result {
+                            let state = config_update_stager.mark_applied(&commit, hash);
+                            try_or_warn!(config_update_state_file.write(&state).await);
+                        }
+                        result
+                    };
+
+                    let (status, hash) = match result {
+                        Ok(hash) => (ConfigUpdateStatus::Applied, hash),
+                        Err(status) => (status, 0),
+                    };
+                    log_info!("Config update {:?}: {:?}", commit.config_type, status);
+                    vlp.send_priority(
+                        ConfigUpdateResultPacket {
+                            config_type: commit.config_type,
+                            transfer_id: commit.transfer_id,
+                            status,
+                            hash,
+                        }
+                        .into(),
+                    );
+                }
             }
         }
     };
@@ -729,10 +1053,8 @@ pub async fn avionics_main(
                 timestamp: (services.unix_clock.now_ms() as u64).into(),
                 event,
             };
-            let mut can_tx = can_tx.lock().await;
-            can_tx.send(&message, 7).await.ok();
+            can_tx_queue.enqueue(&message, 7);
             log_info!("Sent CAN flight event message");
-            drop(can_tx);
         };
 
         loop {
@@ -740,7 +1062,18 @@ pub async fn avionics_main(
             if let (_, FlightCoreEvent::ChangeState(state)) = sub.next_message_pure().await {
                 match state {
                     FlightCoreState::PowerAscend => {
+                        gps_logger_pin(true);
+                        low_g_imu_logger_pin(true);
+                        baro_logger_pin(true);
                         can_send_flight_event(can_messages::FlightEvent::Ignition).await;
+                        can_send_command(
+                            NodeCommand::SetHighRateLogging,
+                            1,
+                            STRAIN_GAUGES_NODE_TYPE,
+                            CAN_COMMAND_BROADCAST_NODE_ID,
+                            false,
+                        )
+                        .await;
                     }
                     FlightCoreState::Coast => {
                         can_send_flight_event(can_messages::FlightEvent::Coast).await;
@@ -749,7 +1082,18 @@ pub async fn avionics_main(
                         can_send_flight_event(can_messages::FlightEvent::Apogee).await;
                     }
                     FlightCoreState::Landed => {
+                        gps_logger_pin(false);
+                        low_g_imu_logger_pin(false);
+                        baro_logger_pin(false);
                         can_send_flight_event(can_messages::FlightEvent::Landed).await;
+                        can_send_command(
+                            NodeCommand::SetLowPower,
+                            1,
+                            STRAIN_GAUGES_NODE_TYPE,
+                            CAN_COMMAND_BROADCAST_NODE_ID,
+                            false,
+                        )
+                        .await;
                     }
                     _ => {}
                 }
@@ -882,6 +1226,12 @@ pub async fn avionics_main(
             camera_ctrl_fut,
             can_tx_avionics_status_fut,
             can_tx_unix_time_fut,
+            can_tx_clock_sync_fut,
+            can_command_fut,
+            can_tx_heartbeat_fut,
+            can_tx_queue_fut,
+            can_rx_fut,
+            can_nodes_monitor_fut,
             indicators_fut,
             storage_full_detection_fut,
             arming_state_debounce_fut,
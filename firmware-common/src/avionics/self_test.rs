@@ -1,5 +1,9 @@
-use crate::{claim_devices, common::vl_device_manager::prelude::*};
+use crate::{
+    claim_devices,
+    common::{can_bus::node_registry::CanNodeSummary, vl_device_manager::prelude::*},
+};
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
 pub enum SelfTestResult {
     Ok,
     PartialFailed,
@@ -39,3 +43,14 @@ pub async fn self_test(device_manager: vl_device_manager_type!()) -> SelfTestRes
         return SelfTestResult::PartialFailed;
     }
 }
+
+/// CAN nodes can join after boot, so this is checked continuously
+pub fn can_nodes_self_test(summary: &CanNodeSummary) -> SelfTestResult {
+    if summary.id_collisions > 0 {
+        SelfTestResult::Failed
+    } else if summary.faulty() > 0 {
+        SelfTestResult::PartialFailed
+    } else {
+        SelfTestResult::Ok
+    }
+}
@@ -0,0 +1,592 @@
+//! Two-way clock synchronization between the avionics (master, unix time) and
+//! the other nodes on the bus (slaves), similar to PTP:
+//!
+//! 1. master sends `TimeSyncMessage`, remembers when it was sent (t1)
+//! 2. master sends `TimeSyncFollowUpMessage` containing t1
+//! 3. slave records when `TimeSyncMessage` was received (t2), sends
+//!    `TimeSyncDelayRequestMessage` after the follow up and records when it was sent (t3)
+//! 4. master replies `TimeSyncDelayResponseMessage` with when the delay request
+//!    was received (t4)
+//!
+//! offset = ((t2 - t1) - (t4 - t3)) / 2, the offsets of multiple rounds are
+//! fitted to a line to estimate the drift between the crystals.
+//!
+//! t1 and t3 are taken right before the frame is queued, rounds where queueing
+//! took longer than `MAX_CLOCK_SYNC_SEND_MS` (e.g. waiting for a free TX
+//! mailbox) are dropped.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex as BlockingMutex};
+use heapless::Deque;
+use libm::fabs;
+
+use crate::driver::{can_bus::CanBusRawMessage, clock::Clock};
+
+use super::{
+    id::CanBusExtendedId,
+    messages::{
+        DecodedCanMessage, TimeSyncDelayRequestMessage, TimeSyncDelayResponseMessage,
+        TimeSyncFollowUpMessage, TimeSyncMessage,
+    },
+    node_types::VOID_LAKE_NODE_TYPE,
+};
+
+pub const CLOCK_SYNC_INTERVAL_MS: f64 = 1000.0;
+/// Rounds with a longer one way delay are ignored, a frame takes ~0.13ms at 1Mbps
+pub const MAX_CLOCK_SYNC_DELAY_MS: f64 = 2.0;
+/// Rounds where queueing a frame took longer are ignored
+pub const MAX_CLOCK_SYNC_SEND_MS: f64 = 0.5;
+/// The estimate is reset when a round disagrees with it by more than this,
+/// e.g. after the master rebooted or got a new GPS fix
+pub const CLOCK_SYNC_RESET_THRESHOLD_MS: f64 = 50.0;
+/// Clocks are considered out of sync after this long without a round
+pub const CLOCK_SYNC_TIMEOUT_MS: f64 = 10000.0;
+/// How fast `SyncedClock` corrects towards the estimate, in ms per ms of
+/// local time. Below 1 so the clock never runs backwards
+pub const MAX_CLOCK_SLEW_RATE: f64 = 0.1;
+const MAX_DRIFT: f64 = 500e-6;
+const MAX_SAMPLES: usize = 8;
+
+/// Sends the sync rounds, runs on the avionics
+pub struct ClockSyncMaster {
+    sequence: u8,
+    /// (sequence, t1 in master time)
+    last_sync: Option<(u8, f64)>,
+}
+
+impl ClockSyncMaster {
+    pub fn new() -> Self {
+        Self {
+            sequence: 0,
+            last_sync: None,
+        }
+    }
+
+    pub fn create_sync_message(&mut self) -> TimeSyncMessage {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.last_sync = None;
+        TimeSyncMessage {
+            sequence: self.sequence,
+        }
+    }
+
+    /// `send_start` and `send_end` are the master time right before and after
+    /// the `TimeSyncMessage` was sent. Returns the follow up to send, or `None`
+    /// if the round is dropped.
+    pub fn sync_sent(&mut self, send_start: f64, send_end: f64) -> Option<TimeSyncFollowUpMessage> {
+        if send_end - send_start > MAX_CLOCK_SYNC_SEND_MS {
+            log_warn!(
+                "Sending clock sync took {}ms, skipping round",
+                send_end - send_start
+            );
+            return None;
+        }
+        self.last_sync = Some((self.sequence, send_start));
+        Some(TimeSyncFollowUpMessage {
+            sequence: self.sequence,
+            timestamp_us: ((send_start * 1000.0) as u64).into(),
+        })
+    }
+
+    /// `receive_time` is the master time when the message was received.
+    /// Returns the response to send if the message is a delay request of the
+    /// current round.
+    pub fn process_message(
+        &self,
+        message: &impl CanBusRawMessage,
+        receive_time: f64,
+    ) -> Option<TimeSyncDelayResponseMessage> {
+        if message.rtr() {
+            return None;
+        }
+        let Some(DecodedCanMessage::TimeSyncDelayRequestMessage(request)) =
+            DecodedCanMessage::decode(message.id(), message.data())
+        else {
+            return None;
+        };
+        let (sequence, sent_time) = self.last_sync?;
+        if request.sequence != sequence || receive_time < sent_time {
+            return None;
+        }
+
+        let id = CanBusExtendedId::from_raw(message.id());
+        Some(TimeSyncDelayResponseMessage {
+            sequence,
+            node_type: id.node_type,
+            node_id: id.node_id,
+            receive_delay_us: ((receive_time - sent_time) * 1000.0) as u32,
+        })
+    }
+}
+
+/// Fits a line through the measured offsets to estimate offset and drift
+pub struct ClockSyncEstimator {
+    /// (local time, master time - local time)
+    samples: Deque<(f64, f64), MAX_SAMPLES>,
+    /// (mean local time, mean offset, drift)
+    fit: Option<(f64, f64, f64)>,
+}
+
+impl ClockSyncEstimator {
+    pub fn new() -> Self {
+        Self {
+            samples: Deque::new(),
+            fit: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.fit = None;
+    }
+
+    pub fn add_sample(&mut self, local_time: f64, offset: f64) {
+        if let Some(predicted) = self.master_time(local_time)
+            && fabs(predicted - (local_time + offset)) > CLOCK_SYNC_RESET_THRESHOLD_MS
+        {
+            log_warn!("Clock sync estimate is off, resetting");
+            self.reset();
+        }
+
+        if self.samples.is_full() {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((local_time, offset)).unwrap();
+        self.update_fit();
+    }
+
+    fn update_fit(&mut self) {
+        let n = self.samples.len() as f64;
+        let mut mean_x = 0.0;
+        let mut mean_y = 0.0;
+        for (x, y) in self.samples.iter() {
+            mean_x += x;
+            mean_y += y;
+        }
+        mean_x /= n;
+        mean_y /= n;
+
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for (x, y) in self.samples.iter() {
+            covariance += (x - mean_x) * (y - mean_y);
+            variance += (x - mean_x) * (x - mean_x);
+        }
+        let drift = if variance > 0.0 {
+            (covariance / variance).clamp(-MAX_DRIFT, MAX_DRIFT)
+        } else {
+            0.0
+        };
+        self.fit = Some((mean_x, mean_y, drift));
+    }
+
+    pub fn master_time(&self, local_time: f64) -> Option<f64> {
+        let (mean_x, mean_y, drift) = self.fit?;
+        Some(local_time + mean_y + drift * (local_time - mean_x))
+    }
+
+    /// master time - local time, at `local_time`
+    pub fn offset_ms(&self, local_time: f64) -> Option<f64> {
+        self.master_time(local_time).map(|t| t - local_time)
+    }
+
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.fit.map(|(_, _, drift)| drift * 1e6)
+    }
+
+    /// Local time of the latest sample
+    pub fn last_sample_time(&self) -> Option<f64> {
+        self.samples.back().map(|(x, _)| *x)
+    }
+}
+
+struct PendingSync {
+    sequence: u8,
+    /// local time when `TimeSyncMessage` was received
+    t2: f64,
+    /// master time when `TimeSyncMessage` was sent
+    t1: Option<f64>,
+    /// local time when the delay request was sent
+    t3: Option<f64>,
+}
+
+pub enum ClockSyncEvent {
+    /// Send this, then call `delay_request_sent`
+    SendDelayRequest(TimeSyncDelayRequestMessage),
+    /// A round finished and the estimate was updated
+    Synced,
+}
+
+/// Follows the master clock, runs on every node except the avionics
+pub struct ClockSyncSlave {
+    node_type: u8,
+    node_id: u16,
+    pending: Option<PendingSync>,
+    estimator: ClockSyncEstimator,
+    /// (local time, offset applied by `SyncedClock` at that time)
+    slew: Option<(f64, f64)>,
+}
+
+impl ClockSyncSlave {
+    pub fn new(node_type: u8, node_id: u16) -> Self {
+        Self {
+            node_type,
+            node_id,
+            pending: None,
+            estimator: ClockSyncEstimator::new(),
+            slew: None,
+        }
+    }
+
+    /// Only the avionics is accepted as the master
+    pub fn process_message(&mut self, message: &impl CanBusRawMessage) -> Option<ClockSyncEvent> {
+        if message.rtr() {
+            return None;
+        }
+        let node_type: u8 = CanBusExtendedId::from_raw(message.id()).node_type.into();
+        if node_type != VOID_LAKE_NODE_TYPE {
+            return None;
+        }
+        match DecodedCanMessage::decode(message.id(), message.data())? {
+            DecodedCanMessage::TimeSyncMessage(sync) => {
+                self.pending = Some(PendingSync {
+                    sequence: sync.sequence,
+                    t2: message.timestamp(),
+                    t1: None,
+                    t3: None,
+                });
+                None
+            }
+            DecodedCanMessage::TimeSyncFollowUpMessage(follow_up) => {
+                let pending = self.pending.as_mut()?;
+                if pending.sequence != follow_up.sequence || pending.t1.is_some() {
+                    return None;
+                }
+                let timestamp_us: u64 = follow_up.timestamp_us.into();
+                pending.t1 = Some(timestamp_us as f64 / 1000.0);
+                Some(ClockSyncEvent::SendDelayRequest(
+                    TimeSyncDelayRequestMessage {
+                        sequence: follow_up.sequence,
+                    },
+                ))
+            }
+            DecodedCanMessage::TimeSyncDelayResponseMessage(response) => {
+                let node_type: u8 = response.node_type.into();
+                let node_id: u16 = response.node_id.into();
+                if node_type != self.node_type || node_id != self.node_id {
+                    return None;
+                }
+                let pending = self.pending.take_if(|p| p.sequence == response.sequence)?;
+                let (Some(t1), Some(t3)) = (pending.t1, pending.t3) else {
+                    return None;
+                };
+                let t4 = t1 + response.receive_delay_us as f64 / 1000.0;
+                let t2 = pending.t2;
+
+                let delay = ((t2 - t1) + (t4 - t3)) / 2.0;
+                let offset = ((t2 - t1) - (t4 - t3)) / 2.0;
+                let round_trip = (t4 - t1) - (t3 - t2);
+                if round_trip < 0.0 || delay > MAX_CLOCK_SYNC_DELAY_MS {
+                    log_warn!("Ignoring clock sync round with round trip {}ms", round_trip);
+                    return None;
+                }
+                // offset above is local - master
+                self.estimator.add_sample(t2, -offset);
+                Some(ClockSyncEvent::Synced)
+            }
+            _ => None,
+        }
+    }
+
+    /// `send_start` and `send_end` are the local time right before and after
+    /// the delay request was sent
+    pub fn delay_request_sent(&mut self, sequence: u8, send_start: f64, send_end: f64) {
+        if send_end - send_start > MAX_CLOCK_SYNC_SEND_MS {
+            log_warn!(
+                "Sending clock sync delay request took {}ms, skipping round",
+                send_end - send_start
+            );
+            self.pending = None;
+            return;
+        }
+        if let Some(pending) = self.pending.as_mut()
+            && pending.sequence == sequence
+        {
+            pending.t3 = Some(send_start);
+        }
+    }
+
+    pub fn estimator(&self) -> &ClockSyncEstimator {
+        &self.estimator
+    }
+
+    pub fn synced(&self, now: f64) -> bool {
+        self.estimator
+            .last_sample_time()
+            .map_or(false, |t| now - t < CLOCK_SYNC_TIMEOUT_MS)
+    }
+
+    /// Master time that never goes backwards, see `SyncedClock`
+    pub fn slewed_master_time(&mut self, local_time: f64) -> f64 {
+        let target = self.estimator.offset_ms(local_time);
+        let offset = match (self.slew, target) {
+            (None, None) => 0.0,
+            // first round, local boot time is far behind the master
+            (None, Some(target)) => target.max(0.0),
+            (Some((_, applied)), None) => applied,
+            (Some((last_local_time, applied)), Some(target)) => {
+                let max_step = (local_time - last_local_time).max(0.0) * MAX_CLOCK_SLEW_RATE;
+                applied + (target - applied).clamp(-max_step, max_step)
+            }
+        };
+        if self.slew.is_some() || target.is_some() {
+            self.slew = Some((local_time, offset));
+        }
+        local_time + offset
+    }
+}
+
+/// Master (unix) time on a slave node, the local time before the first sync
+/// round. The first round steps the time forward to the master clock, after
+/// that corrections are slewed at `MAX_CLOCK_SLEW_RATE` so the time stays
+/// monotonic. Use `convert_to_master` for the raw estimate.
+#[derive(Clone)]
+pub struct SyncedClock<'a, K: Clock> {
+    slave: &'a BlockingMutex<NoopRawMutex, RefCell<ClockSyncSlave>>,
+    clock: K,
+}
+
+impl<'a, K: Clock> SyncedClock<'a, K> {
+    pub fn new(slave: &'a BlockingMutex<NoopRawMutex, RefCell<ClockSyncSlave>>, clock: K) -> Self {
+        Self { slave, clock }
+    }
+
+    pub fn synced(&self) -> bool {
+        let now = self.clock.now_ms();
+        self.slave.lock(|slave| slave.borrow().synced(now))
+    }
+
+    pub fn convert_to_master(&self, local_time: f64) -> Option<f64> {
+        self.slave
+            .lock(|slave| slave.borrow().estimator().master_time(local_time))
+    }
+
+    /// Current master time, `None` before the first sync round
+    pub fn synced_now_ms(&self) -> Option<f64> {
+        self.convert_to_master(self.clock.now_ms())
+    }
+}
+
+impl<'a, K: Clock> Clock for SyncedClock<'a, K> {
+    fn now_ms(&self) -> f64 {
+        self.slave
+            .lock(|slave| slave.borrow_mut().slewed_master_time(self.clock.now_ms()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::can_bus::{message::CanBusMessage, node_types::STRAIN_GAUGES_NODE_TYPE};
+
+    struct TestMessage {
+        timestamp: f64,
+        id: u32,
+        data: heapless::Vec<u8, 8>,
+    }
+
+    impl CanBusRawMessage for TestMessage {
+        fn timestamp(&self) -> f64 {
+            self.timestamp
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn rtr(&self) -> bool {
+            false
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    fn frame<T: CanBusMessage>(message: &T, node_type: u8, timestamp: f64) -> TestMessage {
+        TestMessage {
+            timestamp,
+            id: T::create_id(2, node_type, 7).into(),
+            data: message.to_data(),
+        }
+    }
+
+    /// slave local time = master time * (1 + drift) - offset
+    fn run_round(
+        master: &mut ClockSyncMaster,
+        slave: &mut ClockSyncSlave,
+        master_time: f64,
+        offset: f64,
+        drift: f64,
+    ) {
+        let to_local = |t: f64| t * (1.0 + drift) - offset;
+        let wire_delay = 0.15;
+
+        let sync = master.create_sync_message();
+        let follow_up = master.sync_sent(master_time, master_time + 0.1).unwrap();
+        assert!(slave
+            .process_message(&frame(&sync, 40, to_local(master_time + wire_delay)))
+            .is_none());
+        let Some(ClockSyncEvent::SendDelayRequest(request)) =
+            slave.process_message(&frame(&follow_up, 40, to_local(master_time + 0.5)))
+        else {
+            panic!("expected delay request");
+        };
+        slave.delay_request_sent(
+            request.sequence,
+            to_local(master_time + 1.0),
+            to_local(master_time + 1.1),
+        );
+
+        let request_frame = frame(&request, STRAIN_GAUGES_NODE_TYPE, 0.0);
+        let response = master
+            .process_message(&request_frame, master_time + 1.0 + wire_delay)
+            .unwrap();
+        assert!(matches!(
+            slave.process_message(&frame(&response, 40, to_local(master_time + 2.0))),
+            Some(ClockSyncEvent::Synced)
+        ));
+    }
+
+    #[test]
+    fn test_offset() {
+        let mut master = ClockSyncMaster::new();
+        let mut slave = ClockSyncSlave::new(STRAIN_GAUGES_NODE_TYPE, 7);
+        let master_time = 1_700_000_000_000.0;
+        let offset = master_time - 5000.0;
+
+        run_round(&mut master, &mut slave, master_time, offset, 0.0);
+        let local = master_time + 500.0 - offset;
+        let estimated = slave.estimator().master_time(local).unwrap();
+        assert!(fabs(estimated - (master_time + 500.0)) < 0.01);
+        assert!(slave.synced(local));
+    }
+
+    #[test]
+    fn test_drift() {
+        let mut master = ClockSyncMaster::new();
+        let mut slave = ClockSyncSlave::new(STRAIN_GAUGES_NODE_TYPE, 7);
+        let start = 1_700_000_000_000.0;
+        let offset = start - 5000.0;
+        let drift = 100e-6;
+
+        for i in 0..8 {
+            run_round(
+                &mut master,
+                &mut slave,
+                start + i as f64 * 1000.0,
+                offset,
+                drift,
+            );
+        }
+        assert!(fabs(slave.estimator().drift_ppm().unwrap() + 100.0) < 5.0);
+
+        // extrapolate 5 seconds past the last round
+        let master_time = start + 12000.0;
+        let local = master_time * (1.0 + drift) - offset;
+        let estimated = slave.estimator().master_time(local).unwrap();
+        assert!(fabs(estimated - master_time) < 0.5);
+    }
+
+    #[test]
+    fn test_slewed_master_time() {
+        let mut master = ClockSyncMaster::new();
+        let mut slave = ClockSyncSlave::new(STRAIN_GAUGES_NODE_TYPE, 7);
+        let master_time = 1_700_000_000_000.0;
+        let offset = master_time - 5000.0;
+
+        // local time before the first round
+        assert_eq!(slave.slewed_master_time(4000.0), 4000.0);
+
+        run_round(&mut master, &mut slave, master_time, offset, 0.0);
+        let local = master_time + 10.0 - offset;
+        let stepped = slave.slewed_master_time(local);
+        assert!(fabs(stepped - (master_time + 10.0)) < 0.01);
+
+        // master jumps back 20ms, the clock slows down instead of going back
+        slave.estimator.reset();
+        run_round(
+            &mut master,
+            &mut slave,
+            master_time + 1000.0,
+            offset - 20.0,
+            0.0,
+        );
+        let mut last = stepped;
+        for i in 1..=300 {
+            let now = slave.slewed_master_time(local + i as f64);
+            assert!(now >= last);
+            last = now;
+        }
+        let target = slave.estimator().master_time(local + 300.0).unwrap();
+        assert!(fabs(last - target) < 0.01);
+    }
+
+    #[test]
+    fn test_ignores_other_nodes() {
+        let mut master = ClockSyncMaster::new();
+        let mut slave = ClockSyncSlave::new(STRAIN_GAUGES_NODE_TYPE, 8);
+        let sync = master.create_sync_message();
+        let follow_up = master.sync_sent(1000.0, 1000.1).unwrap();
+        slave.process_message(&frame(&sync, 40, 10.0));
+        slave.process_message(&frame(&follow_up, 40, 11.0));
+        slave.delay_request_sent(sync.sequence, 12.0, 12.1);
+
+        let request = TimeSyncDelayRequestMessage {
+            sequence: sync.sequence,
+        };
+        let response = master
+            .process_message(&frame(&request, STRAIN_GAUGES_NODE_TYPE, 0.0), 1002.0)
+            .unwrap();
+        // response is for node id 7
+        assert!(slave.process_message(&frame(&response, 40, 13.0)).is_none());
+    }
+
+    #[test]
+    fn test_ignores_other_masters() {
+        let mut master = ClockSyncMaster::new();
+        let mut slave = ClockSyncSlave::new(STRAIN_GAUGES_NODE_TYPE, 7);
+        let sync = master.create_sync_message();
+        let follow_up = master.sync_sent(1000.0, 1000.1).unwrap();
+        slave.process_message(&frame(&sync, 40, 10.0));
+        // same round, but not sent by the avionics
+        assert!(slave
+            .process_message(&frame(&follow_up, STRAIN_GAUGES_NODE_TYPE, 11.0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_slow_send() {
+        let mut master = ClockSyncMaster::new();
+        master.create_sync_message();
+        assert!(master
+            .sync_sent(1000.0, 1000.0 + MAX_CLOCK_SYNC_SEND_MS * 2.0)
+            .is_none());
+
+        let mut slave = ClockSyncSlave::new(STRAIN_GAUGES_NODE_TYPE, 7);
+        let sync = master.create_sync_message();
+        let follow_up = master.sync_sent(2000.0, 2000.1).unwrap();
+        slave.process_message(&frame(&sync, 40, 10.0));
+        slave.process_message(&frame(&follow_up, 40, 11.0));
+        slave.delay_request_sent(sync.sequence, 12.0, 12.0 + MAX_CLOCK_SYNC_SEND_MS * 2.0);
+
+        let request = TimeSyncDelayRequestMessage {
+            sequence: sync.sequence,
+        };
+        let response = master
+            .process_message(&frame(&request, STRAIN_GAUGES_NODE_TYPE, 0.0), 2002.0)
+            .unwrap();
+        assert!(slave.process_message(&frame(&response, 40, 13.0)).is_none());
+    }
+}
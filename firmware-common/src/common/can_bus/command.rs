@@ -0,0 +1,404 @@
+//! Commands from the avionics to the other nodes on the bus.
+//!
+//! Every `NodeCommandMessage` carries a request id, the target nodes reply with a
+//! `NodeCommandResponseMessage` with the same request id. Commands without any
+//! response are resent, the nodes remember their last response so a resent
+//! command is not executed twice. The last response expires once the client
+//! stopped resending, so a rebooted avionics reusing the request id is not
+//! answered with a stale response.
+
+use heapless::Vec;
+
+use crate::driver::can_bus::CanBusRawMessage;
+
+use super::{
+    id::CanBusExtendedId,
+    messages::{
+        DecodedCanMessage, NodeCommand, NodeCommandMessage, NodeCommandResponseMessage,
+        NodeCommandStatus,
+    },
+};
+
+/// Addresses every node of the target node type
+pub const CAN_COMMAND_BROADCAST_NODE_ID: u16 = 0xFFF;
+pub const CAN_COMMAND_TIMEOUT_MS: f64 = 200.0;
+pub const CAN_COMMAND_MAX_RETRIES: u8 = 3;
+/// How long the server remembers its last response, the client gives up after
+/// this
+pub const CAN_COMMAND_RESPONSE_EXPIRY_MS: f64 =
+    CAN_COMMAND_TIMEOUT_MS * (CAN_COMMAND_MAX_RETRIES as f64 + 1.0);
+const MAX_PENDING_COMMANDS: usize = 4;
+
+#[derive(Debug, Clone, defmt::Format)]
+pub struct CanCommandResult {
+    pub request_id: u8,
+    pub node_type: u8,
+    pub node_id: u16,
+    pub command: NodeCommand,
+    pub status: NodeCommandStatus,
+    /// The command was relayed from the ground through VLP
+    pub relay: bool,
+}
+
+pub enum CanCommandClientEvent {
+    Resend(NodeCommandMessage),
+    /// No node responded to the command
+    TimedOut(CanCommandResult),
+}
+
+struct PendingCommand {
+    message: NodeCommandMessage,
+    sent_time: f64,
+    retries: u8,
+    acked: bool,
+    relay: bool,
+}
+
+/// Sends commands and tracks their responses, runs on the avionics
+pub struct CanCommandClient {
+    next_request_id: u8,
+    pending: Vec<PendingCommand, MAX_PENDING_COMMANDS>,
+}
+
+impl CanCommandClient {
+    pub fn new() -> Self {
+        Self {
+            next_request_id: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// `relay` is passed through to the results of this command
+    pub fn create_command(
+        &mut self,
+        command: NodeCommand,
+        argument: u32,
+        target_node_type: u8,
+        target_node_id: u16,
+        relay: bool,
+        now: f64,
+    ) -> NodeCommandMessage {
+        let message = NodeCommandMessage {
+            request_id: self.next_request_id,
+            command,
+            target_node_type: target_node_type.into(),
+            target_node_id: target_node_id.into(),
+            argument,
+        };
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+
+        if self.pending.is_full() {
+            let dropped = self.pending.remove(0);
+            log_warn!(
+                "Too many pending CAN commands, dropping request {}",
+                dropped.message.request_id
+            );
+        }
+        self.pending
+            .push(PendingCommand {
+                message,
+                sent_time: now,
+                retries: 0,
+                acked: false,
+                relay,
+            })
+            .ok();
+        message
+    }
+
+    /// Returns the result if the message is a response to a pending command
+    pub fn process_message(&mut self, message: &impl CanBusRawMessage) -> Option<CanCommandResult> {
+        if message.rtr() {
+            return None;
+        }
+        let Some(DecodedCanMessage::NodeCommandResponseMessage(response)) =
+            DecodedCanMessage::decode(message.id(), message.data())
+        else {
+            return None;
+        };
+        let id = CanBusExtendedId::from_raw(message.id());
+        let node_type: u8 = id.node_type.into();
+        let node_id: u16 = id.node_id.into();
+
+        let i = self.pending.iter().position(|pending| {
+            let target_node_type: u8 = pending.message.target_node_type.into();
+            let target_node_id: u16 = pending.message.target_node_id.into();
+            pending.message.request_id == response.request_id
+                && pending.message.command == response.command
+                && target_node_type == node_type
+                && (target_node_id == CAN_COMMAND_BROADCAST_NODE_ID || target_node_id == node_id)
+        })?;
+        let pending = &mut self.pending[i];
+        pending.acked = true;
+        let relay = pending.relay;
+        if u16::from(pending.message.target_node_id) != CAN_COMMAND_BROADCAST_NODE_ID {
+            self.pending.remove(i);
+        }
+
+        Some(CanCommandResult {
+            request_id: response.request_id,
+            node_type,
+            node_id,
+            command: response.command,
+            status: response.status,
+            relay,
+        })
+    }
+
+    /// Call periodically, faster than `CAN_COMMAND_TIMEOUT_MS`
+    pub fn poll(&mut self, now: f64) -> Option<CanCommandClientEvent> {
+        let i = self
+            .pending
+            .iter()
+            .position(|pending| now - pending.sent_time > CAN_COMMAND_TIMEOUT_MS)?;
+        let pending = &mut self.pending[i];
+
+        if pending.acked {
+            // broadcast commands wait one timeout for the responses of every node
+            self.pending.remove(i);
+            None
+        } else if pending.retries < CAN_COMMAND_MAX_RETRIES {
+            pending.retries += 1;
+            pending.sent_time = now;
+            Some(CanCommandClientEvent::Resend(pending.message))
+        } else {
+            let pending = self.pending.remove(i);
+            log_warn!(
+                "CAN command {:?} timed out, request {}",
+                pending.message.command,
+                pending.message.request_id
+            );
+            Some(CanCommandClientEvent::TimedOut(CanCommandResult {
+                request_id: pending.message.request_id,
+                node_type: pending.message.target_node_type.into(),
+                node_id: pending.message.target_node_id.into(),
+                command: pending.message.command,
+                status: NodeCommandStatus::Timeout,
+                relay: pending.relay,
+            }))
+        }
+    }
+}
+
+pub enum CanCommandServerEvent {
+    /// Execute the command, then send the response from `CanCommandServer::respond`
+    Execute(NodeCommandMessage),
+    /// The command was already executed, send this response again
+    Resend(NodeCommandResponseMessage),
+}
+
+/// Receives the commands addressed to this node
+pub struct CanCommandServer {
+    node_type: u8,
+    node_id: u16,
+    /// Timestamp of the last executed command
+    command_timestamp: f64,
+    /// The response and the timestamp of the command it answered
+    last_response: Option<(NodeCommandResponseMessage, f64)>,
+}
+
+impl CanCommandServer {
+    pub fn new(node_type: u8, node_id: u16) -> Self {
+        Self {
+            node_type,
+            node_id,
+            command_timestamp: 0.0,
+            last_response: None,
+        }
+    }
+
+    pub fn process_message(
+        &mut self,
+        message: &impl CanBusRawMessage,
+    ) -> Option<CanCommandServerEvent> {
+        if message.rtr() {
+            return None;
+        }
+        let Some(DecodedCanMessage::NodeCommandMessage(command)) =
+            DecodedCanMessage::decode(message.id(), message.data())
+        else {
+            return None;
+        };
+        let target_node_type: u8 = command.target_node_type.into();
+        let target_node_id: u16 = command.target_node_id.into();
+        if target_node_type != self.node_type
+            || (target_node_id != CAN_COMMAND_BROADCAST_NODE_ID && target_node_id != self.node_id)
+        {
+            return None;
+        }
+
+        if let Some((last_response, command_timestamp)) = &self.last_response
+            && last_response.request_id == command.request_id
+            && last_response.command == command.command
+            && message.timestamp() - command_timestamp < CAN_COMMAND_RESPONSE_EXPIRY_MS
+        {
+            return Some(CanCommandServerEvent::Resend(*last_response));
+        }
+        self.command_timestamp = message.timestamp();
+        Some(CanCommandServerEvent::Execute(command))
+    }
+
+    pub fn respond(
+        &mut self,
+        command: &NodeCommandMessage,
+        status: NodeCommandStatus,
+    ) -> NodeCommandResponseMessage {
+        let response = NodeCommandResponseMessage {
+            request_id: command.request_id,
+            command: command.command,
+            status,
+        };
+        self.last_response = Some((response, self.command_timestamp));
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::can_bus::{
+        message::CanBusMessage,
+        node_types::{STRAIN_GAUGES_NODE_TYPE, VOID_LAKE_NODE_TYPE},
+    };
+
+    struct TestMessage {
+        timestamp: f64,
+        id: u32,
+        data: heapless::Vec<u8, 8>,
+    }
+
+    impl CanBusRawMessage for TestMessage {
+        fn timestamp(&self) -> f64 {
+            self.timestamp
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn rtr(&self) -> bool {
+            false
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    fn frame<T: CanBusMessage>(message: &T, node_type: u8, node_id: u16) -> TestMessage {
+        TestMessage {
+            timestamp: 0.0,
+            id: T::create_id(4, node_type, node_id).into(),
+            data: message.to_data(),
+        }
+    }
+
+    #[test]
+    fn test_command_response() {
+        let mut client = CanCommandClient::new();
+        let mut server = CanCommandServer::new(STRAIN_GAUGES_NODE_TYPE, 5);
+        let command = client.create_command(
+            NodeCommand::SetHighRateLogging,
+            1,
+            STRAIN_GAUGES_NODE_TYPE,
+            CAN_COMMAND_BROADCAST_NODE_ID,
+            false,
+            0.0,
+        );
+
+        let command_frame = frame(&command, VOID_LAKE_NODE_TYPE, 1);
+        let Some(CanCommandServerEvent::Execute(received)) = server.process_message(&command_frame)
+        else {
+            panic!("expected command");
+        };
+        assert_eq!(received, command);
+        let response = server.respond(&received, NodeCommandStatus::Ok);
+
+        // resent command is not executed again
+        assert!(matches!(
+            server.process_message(&command_frame),
+            Some(CanCommandServerEvent::Resend(r)) if r == response
+        ));
+
+        let result = client
+            .process_message(&frame(&response, STRAIN_GAUGES_NODE_TYPE, 5))
+            .unwrap();
+        assert_eq!(result.node_id, 5);
+        assert_eq!(result.status, NodeCommandStatus::Ok);
+
+        // acked broadcast commands are not resent
+        assert!(client.poll(CAN_COMMAND_TIMEOUT_MS + 1.0).is_none());
+        assert!(client.poll(CAN_COMMAND_TIMEOUT_MS * 3.0).is_none());
+    }
+
+    #[test]
+    fn test_last_response_expires() {
+        let mut server = CanCommandServer::new(STRAIN_GAUGES_NODE_TYPE, 5);
+        let command = NodeCommandMessage {
+            request_id: 0,
+            command: NodeCommand::SetHighRateLogging,
+            target_node_type: STRAIN_GAUGES_NODE_TYPE.into(),
+            target_node_id: 5.into(),
+            argument: 1,
+        };
+        let mut command_frame = frame(&command, VOID_LAKE_NODE_TYPE, 1);
+        assert!(matches!(
+            server.process_message(&command_frame),
+            Some(CanCommandServerEvent::Execute(_))
+        ));
+        server.respond(&command, NodeCommandStatus::Ok);
+
+        // the avionics rebooted and reused the request id
+        command_frame.timestamp = CAN_COMMAND_RESPONSE_EXPIRY_MS + 1.0;
+        assert!(matches!(
+            server.process_message(&command_frame),
+            Some(CanCommandServerEvent::Execute(_))
+        ));
+    }
+
+    #[test]
+    fn test_other_node() {
+        let mut client = CanCommandClient::new();
+        let mut server = CanCommandServer::new(STRAIN_GAUGES_NODE_TYPE, 5);
+        let command = client.create_command(
+            NodeCommand::ClearData,
+            0,
+            STRAIN_GAUGES_NODE_TYPE,
+            6,
+            false,
+            0.0,
+        );
+        assert!(server
+            .process_message(&frame(&command, VOID_LAKE_NODE_TYPE, 1))
+            .is_none());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut client = CanCommandClient::new();
+        client.create_command(
+            NodeCommand::SetLowPower,
+            1,
+            STRAIN_GAUGES_NODE_TYPE,
+            6,
+            true,
+            0.0,
+        );
+
+        let mut now = 0.0;
+        for _ in 0..CAN_COMMAND_MAX_RETRIES {
+            now += CAN_COMMAND_TIMEOUT_MS + 1.0;
+            assert!(matches!(
+                client.poll(now),
+                Some(CanCommandClientEvent::Resend(_))
+            ));
+        }
+        now += CAN_COMMAND_TIMEOUT_MS + 1.0;
+        let Some(CanCommandClientEvent::TimedOut(result)) = client.poll(now) else {
+            panic!("expected timeout");
+        };
+        assert_eq!(result.status, NodeCommandStatus::Timeout);
+        assert!(result.relay);
+        assert!(client.poll(now * 2.0).is_none());
+    }
+}
@@ -1,6 +1,14 @@
 use core::fmt::Debug;
 use packed_struct::prelude::*;
 
+use super::multi_frame::{multi_frame_message_type, MULTI_FRAME_MESSAGE_TYPE_FLAG, MAX_TRANSFER_ID};
+
+/// (shift, bits) of each field in the raw id, must match `CanBusExtendedId`
+pub const CAN_BUS_ID_PRIORITY_FIELD: (u32, u32) = (26, 3);
+pub const CAN_BUS_ID_MESSAGE_TYPE_FIELD: (u32, u32) = (18, 8);
+pub const CAN_BUS_ID_NODE_TYPE_FIELD: (u32, u32) = (12, 6);
+pub const CAN_BUS_ID_NODE_ID_FIELD: (u32, u32) = (0, 12);
+
 #[derive(PackedStruct, Default, Clone, Copy, Debug, PartialEq, Eq)]
 #[packed_struct(endian = "msb", size_bytes = "4")]
 pub struct CanBusExtendedId {
@@ -22,6 +30,24 @@ impl CanBusExtendedId {
         }
     }
 
+    pub fn new_multi_frame(priority: u8, transfer_id: u8, node_type: u8, node_id: u16) -> Self {
+        Self::new(
+            priority,
+            multi_frame_message_type(transfer_id),
+            node_type,
+            node_id,
+        )
+    }
+
+    /// Returns the transfer id if this is a frame of a multi-frame transfer
+    pub fn transfer_id(&self) -> Option<u8> {
+        if self.message_type & MULTI_FRAME_MESSAGE_TYPE_FLAG != 0 {
+            Some(self.message_type & MAX_TRANSFER_ID)
+        } else {
+            None
+        }
+    }
+
     pub fn from_raw(raw: u32) -> Self {
         let unpacked = raw.to_be_bytes();
         let mut packed = [0; 4];
@@ -36,3 +62,24 @@ impl Into<u32> for CanBusExtendedId {
         u32::from_be_bytes(packed)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field_mask((shift, bits): (u32, u32)) -> u32 {
+        ((1 << bits) - 1) << shift
+    }
+
+    #[test]
+    fn test_id_fields() {
+        let id: u32 = CanBusExtendedId::new(0b111, 0, 0, 0).into();
+        assert_eq!(id, field_mask(CAN_BUS_ID_PRIORITY_FIELD));
+        let id: u32 = CanBusExtendedId::new(0, 0xFF, 0, 0).into();
+        assert_eq!(id, field_mask(CAN_BUS_ID_MESSAGE_TYPE_FIELD));
+        let id: u32 = CanBusExtendedId::new(0, 0, 63, 0).into();
+        assert_eq!(id, field_mask(CAN_BUS_ID_NODE_TYPE_FIELD));
+        let id: u32 = CanBusExtendedId::new(0, 0, 0, 0xFFF).into();
+        assert_eq!(id, field_mask(CAN_BUS_ID_NODE_ID_FIELD));
+    }
+}
@@ -3,7 +3,7 @@ use heapless::Vec;
 use packed_bits::ByteArray;
 use packed_struct::prelude::*;
 
-use super::id::CanBusExtendedId;
+use super::{id::CanBusExtendedId, multi_frame::MULTI_FRAME_MESSAGE_TYPE_FLAG};
 
 pub trait CanBusMessage: PackedStruct + Clone + Debug {
     fn message_type() -> u8;
@@ -25,3 +25,117 @@ pub trait CanBusMessage: PackedStruct + Clone + Debug {
         Self::ByteArray::len()
     }
 }
+
+/// Machine readable description of a message, for nodes not written in Rust
+#[derive(Debug, Clone, Copy)]
+pub struct CanBusMessageDescription {
+    pub name: &'static str,
+    pub message_type: u8,
+    /// Length of the packed message in bytes, fields are packed msb first
+    pub length: usize,
+}
+
+pub const fn assert_unique_message_types(message_types: &[u8]) {
+    let mut i = 0;
+    while i < message_types.len() {
+        if message_types[i] >= MULTI_FRAME_MESSAGE_TYPE_FLAG {
+            panic!("CAN bus message types must be below 0x80");
+        }
+        let mut j = i + 1;
+        while j < message_types.len() {
+            if message_types[i] == message_types[j] {
+                panic!("Duplicated CAN bus message type");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Declares every CAN bus message once: implements `CanBusMessage` for each of
+/// them, checks the message types are unique at compile time, and generates an
+/// enum of all the messages with a `decode` function plus a list of message
+/// descriptions.
+#[macro_export]
+macro_rules! create_can_bus_messages {
+    ($enum_name:ident, $descriptions_name:ident, $(($message_type:literal, $message:ident)),* $(,)?) => {
+        $(
+            impl $crate::common::can_bus::message::CanBusMessage for $message {
+                fn message_type() -> u8 {
+                    $message_type
+                }
+            }
+        )*
+
+        const _: () = $crate::common::can_bus::message::assert_unique_message_types(&[$($message_type),*]);
+
+        #[derive(Debug, Clone)]
+        pub enum $enum_name {
+            $(
+                $message($message),
+            )*
+        }
+
+        $(
+            impl From<$message> for $enum_name {
+                fn from(message: $message) -> Self {
+                    $enum_name::$message(message)
+                }
+            }
+        )*
+
+        impl $enum_name {
+            /// Returns None for unknown message types, multi-frame transfer frames
+            /// and data with the wrong length
+            pub fn decode(id: u32, data: &[u8]) -> Option<Self> {
+                use packed_struct::prelude::*;
+
+                let id = $crate::common::can_bus::id::CanBusExtendedId::from_raw(id);
+                match id.message_type {
+                    $(
+                        $message_type => {
+                            let data: &<$message as PackedStruct>::ByteArray = data.try_into().ok()?;
+                            $message::unpack(data).ok().map($enum_name::$message)
+                        }
+                    )*
+                    _ => None,
+                }
+            }
+
+            pub fn message_type(&self) -> u8 {
+                match self {
+                    $(
+                        $enum_name::$message(_) => $message_type,
+                    )*
+                }
+            }
+
+            /// priority can be 0 - 7, 7 being the highest priority
+            pub async fn send<T: $crate::driver::can_bus::CanBusTX>(&self, tx: &mut T, priority: u8) -> Result<(), T::Error> {
+                match self {
+                    $(
+                        $enum_name::$message(message) => tx.send(message, priority).await,
+                    )*
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(
+                        $enum_name::$message(_) => stringify!($message),
+                    )*
+                }
+            }
+        }
+
+        pub const $descriptions_name: &[$crate::common::can_bus::message::CanBusMessageDescription] = &[
+            $(
+                $crate::common::can_bus::message::CanBusMessageDescription {
+                    name: stringify!($message),
+                    message_type: $message_type,
+                    length: core::mem::size_of::<<$message as packed_struct::PackedStruct>::ByteArray>(),
+                },
+            )*
+        ];
+    };
+}
@@ -1,6 +1,8 @@
 use packed_struct::prelude::*;
+use rkyv::{Archive, Deserialize, Serialize};
 
-use super::message::CanBusMessage;
+use crate::common::schema_hash::SchemaHash;
+use crate::{create_can_bus_messages, create_can_bus_multi_frame_messages};
 
 #[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
 #[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "6")]
@@ -9,12 +11,6 @@ pub struct UnixTimeMessage {
     pub timestamp: Integer<u64, packed_bits::Bits<48>>,
 }
 
-impl CanBusMessage for UnixTimeMessage {
-    fn message_type() -> u8 {
-        0
-    }
-}
-
 #[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
 #[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "1")]
 pub struct AvionicsStatusMessage {
@@ -24,12 +20,6 @@ pub struct AvionicsStatusMessage {
     pub armed: bool,
 }
 
-impl CanBusMessage for AvionicsStatusMessage {
-    fn message_type() -> u8 {
-        1
-    }
-}
-
 #[derive(PrimitiveEnum_u8, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FlightEvent {
     Ignition = 0,
@@ -49,13 +39,11 @@ pub struct FlightEventMessage {
     pub event: FlightEvent,
 }
 
-impl CanBusMessage for FlightEventMessage {
-    fn message_type() -> u8 {
-        2
-    }
-}
-
-#[derive(PrimitiveEnum_u8, Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(
+    PrimitiveEnum_u8, Clone, Copy, Debug, PartialEq, Eq, defmt::Format, Archive, Serialize, Deserialize,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub enum HealthState {
     Healthy = 0,
     Degraded = 1,
@@ -69,19 +57,175 @@ pub struct HealthMessage {
     pub state: HealthState,
 }
 
-impl CanBusMessage for HealthMessage {
-    fn message_type() -> u8 {
-        3
-    }
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "1")]
+pub struct ResetMessage {
+}
+
+/// Sent periodically by every node, see `node_registry`
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "8")]
+pub struct NodeHeartbeatMessage {
+    #[packed_field(bits = "0..=1", ty = "enum")]
+    pub health: HealthState,
+    /// Seconds since boot, wraps after 48 days
+    #[packed_field(bits = "2..=23")]
+    pub uptime_s: Integer<u32, packed_bits::Bits<22>>,
+    #[packed_field(bits = "24..=47")]
+    pub firmware_version: [u8; 3],
+    /// Second hash of the serial number, independent from the node id,
+    /// used to detect node id collisions
+    #[packed_field(bits = "48..=63")]
+    pub serial_hash: u16,
 }
 
+/// Start of a clock sync round, sent by the avionics, see `clock_sync`
 #[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
 #[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "1")]
-pub struct ResetMessage {
+pub struct TimeSyncMessage {
+    #[packed_field(bits = "0..=7")]
+    pub sequence: u8,
 }
 
-impl CanBusMessage for ResetMessage {
-    fn message_type() -> u8 {
-        4
+/// Sent by the avionics right after `TimeSyncMessage`
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "8")]
+pub struct TimeSyncFollowUpMessage {
+    #[packed_field(bits = "0..=7")]
+    pub sequence: u8,
+    /// Microseconds since Unix epoch when `TimeSyncMessage` was sent
+    #[packed_field(bits = "8..=63")]
+    pub timestamp_us: Integer<u64, packed_bits::Bits<56>>,
+}
+
+/// Sent by the slave nodes after receiving `TimeSyncFollowUpMessage`
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "1")]
+pub struct TimeSyncDelayRequestMessage {
+    #[packed_field(bits = "0..=7")]
+    pub sequence: u8,
+}
+
+/// Sent by the avionics for every `TimeSyncDelayRequestMessage`
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "8")]
+pub struct TimeSyncDelayResponseMessage {
+    #[packed_field(bits = "0..=7")]
+    pub sequence: u8,
+    /// Node that sent the delay request
+    #[packed_field(bits = "8..=13")]
+    pub node_type: Integer<u8, packed_bits::Bits<6>>,
+    #[packed_field(bits = "14..=25")]
+    pub node_id: Integer<u16, packed_bits::Bits<12>>,
+    /// Microseconds between sending `TimeSyncMessage` and receiving the delay request
+    #[packed_field(bits = "32..=63")]
+    pub receive_delay_us: u32,
+}
+
+#[derive(
+    PrimitiveEnum_u8, Clone, Copy, Debug, PartialEq, Eq, defmt::Format, Archive, Serialize, Deserialize,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub enum NodeCommand {
+    /// argument: 1 to start, 0 to stop
+    SetHighRateLogging = 0,
+    /// argument: samples per second
+    SetSampleRate = 1,
+    ClearData = 2,
+    /// argument: 1 to enter, 0 to leave
+    SetLowPower = 3,
+}
+
+#[derive(
+    PrimitiveEnum_u8, Clone, Copy, Debug, PartialEq, Eq, defmt::Format, Archive, Serialize, Deserialize,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub enum NodeCommandStatus {
+    Ok = 0,
+    /// The node does not implement this command
+    Unsupported = 1,
+    InvalidArgument = 2,
+    /// The command can't be executed in the current state, e.g. clearing data while recording
+    Busy = 3,
+    Failed = 4,
+    /// No response from the node, only reported by the avionics
+    Timeout = 5,
+}
+
+/// Sent by the avionics, see `command`
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "8")]
+pub struct NodeCommandMessage {
+    #[packed_field(bits = "0..=7")]
+    pub request_id: u8,
+    #[packed_field(bits = "8..=11", ty = "enum")]
+    pub command: NodeCommand,
+    #[packed_field(bits = "12..=17")]
+    pub target_node_type: Integer<u8, packed_bits::Bits<6>>,
+    /// `CAN_COMMAND_BROADCAST_NODE_ID` to address every node of `target_node_type`
+    #[packed_field(bits = "18..=29")]
+    pub target_node_id: Integer<u16, packed_bits::Bits<12>>,
+    #[packed_field(bits = "32..=63")]
+    pub argument: u32,
+}
+
+/// Sent by the target nodes for every `NodeCommandMessage`
+#[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "2")]
+pub struct NodeCommandResponseMessage {
+    #[packed_field(bits = "0..=7")]
+    pub request_id: u8,
+    #[packed_field(bits = "8..=11", ty = "enum")]
+    pub command: NodeCommand,
+    #[packed_field(bits = "12..=14", ty = "enum")]
+    pub status: NodeCommandStatus,
+}
+
+create_can_bus_messages!(
+    DecodedCanMessage,
+    CAN_BUS_MESSAGE_DESCRIPTIONS,
+    (0, UnixTimeMessage),
+    (1, AvionicsStatusMessage),
+    (2, FlightEventMessage),
+    (3, HealthMessage),
+    (4, ResetMessage),
+    (5, NodeHeartbeatMessage),
+    (6, TimeSyncMessage),
+    (7, TimeSyncFollowUpMessage),
+    (8, TimeSyncDelayRequestMessage),
+    (9, TimeSyncDelayResponseMessage),
+    (10, NodeCommandMessage),
+    (11, NodeCommandResponseMessage),
+);
+
+// no message needs more than one frame yet
+create_can_bus_multi_frame_messages!(DecodedCanMultiFrameMessage);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::can_bus::{message::CanBusMessage, node_types::STRAIN_GAUGES_NODE_TYPE};
+
+    #[test]
+    fn test_decode() {
+        let message = FlightEventMessage {
+            timestamp: 1234.into(),
+            event: FlightEvent::Apogee,
+        };
+        let id: u32 = FlightEventMessage::create_id(2, STRAIN_GAUGES_NODE_TYPE, 1).into();
+
+        let decoded = DecodedCanMessage::decode(id, &message.to_data()).unwrap();
+        assert_eq!(decoded.message_type(), 2);
+        assert_eq!(decoded.name(), "FlightEventMessage");
+        assert!(matches!(
+            decoded,
+            DecodedCanMessage::FlightEventMessage(m) if m == message
+        ));
+
+        // wrong length
+        assert!(DecodedCanMessage::decode(id, &[0; 3]).is_none());
+        assert_eq!(CAN_BUS_MESSAGE_DESCRIPTIONS[2].length, 7);
     }
-}
\ No newline at end of file
+}
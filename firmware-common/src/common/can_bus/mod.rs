@@ -1,4 +1,10 @@
 pub mod message;
 pub mod messages;
+pub mod multi_frame;
+pub mod node_registry;
+pub mod clock_sync;
+pub mod command;
 pub mod id;
-pub mod node_types;
\ No newline at end of file
+pub mod node_types;
+pub mod statistics;
+pub mod tx_queue;
\ No newline at end of file
@@ -0,0 +1,556 @@
+use crc::{Crc, CRC_16_IBM_3740};
+use heapless::Vec;
+
+use crate::driver::can_bus::{CanBusRawMessage, CanBusRawTX};
+
+use super::id::CanBusExtendedId;
+
+/// Message types with this bit set in `CanBusExtendedId` are frames of a
+/// multi-frame transfer, the lower 7 bits are the transfer id.
+/// Single frame messages must use message types below 0x80.
+pub const MULTI_FRAME_MESSAGE_TYPE_FLAG: u8 = 0x80;
+pub const MAX_TRANSFER_ID: u8 = 0x7F;
+pub const MAX_MULTI_FRAME_LENGTH: usize = 512;
+
+// first frame: frame index, message type, length (u16), crc (u16), payload
+const FIRST_FRAME_HEADER_SIZE: usize = 6;
+// other frames: frame index, payload
+const FRAME_HEADER_SIZE: usize = 1;
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_3740);
+
+pub fn multi_frame_message_type(transfer_id: u8) -> u8 {
+    MULTI_FRAME_MESSAGE_TYPE_FLAG | (transfer_id & MAX_TRANSFER_ID)
+}
+
+/// A message that can be larger than a single CAN frame.
+///
+/// The message type is a separate namespace from `CanBusMessage::message_type`.
+/// Use `impl_packed_multi_frame_message!` or `impl_rkyv_multi_frame_message!`
+/// to implement this for `PackedStruct` or `rkyv` types, the `rkyv` types
+/// need `#[archive(check_bytes)]`.
+pub trait CanBusMultiFrameMessage: Sized {
+    fn message_type() -> u8;
+
+    /// Returns the serialized length, at most `MAX_MULTI_FRAME_LENGTH`
+    fn serialize(&self, buffer: &mut [u8]) -> usize;
+
+    fn deserialize(data: &[u8]) -> Option<Self>;
+}
+
+#[macro_export]
+macro_rules! impl_packed_multi_frame_message {
+    ($message:ty, $message_type:expr) => {
+        impl $crate::common::can_bus::multi_frame::CanBusMultiFrameMessage for $message {
+            fn message_type() -> u8 {
+                $message_type
+            }
+
+            fn serialize(&self, buffer: &mut [u8]) -> usize {
+                use packed_struct::prelude::*;
+                use packed_bits::ByteArray;
+
+                let packed = self.pack().unwrap();
+                let bytes = packed.as_bytes_slice();
+                buffer[..bytes.len()].copy_from_slice(bytes);
+                bytes.len()
+            }
+
+            fn deserialize(data: &[u8]) -> Option<Self> {
+                use packed_struct::prelude::*;
+                use packed_bits::ByteArray;
+
+                let mut packed = <Self as PackedStruct>::ByteArray::new(0);
+                if data.len() != <Self as PackedStruct>::ByteArray::len() {
+                    return None;
+                }
+                packed.as_mut_bytes_slice().copy_from_slice(data);
+                Self::unpack(&packed).ok()
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! impl_rkyv_multi_frame_message {
+    ($message:ty, $message_type:expr) => {
+        impl $crate::common::can_bus::multi_frame::CanBusMultiFrameMessage for $message {
+            fn message_type() -> u8 {
+                $message_type
+            }
+
+            fn serialize(&self, buffer: &mut [u8]) -> usize {
+                use core::mem::size_of;
+                use rkyv::ser::{serializers::BufferSerializer, Serializer};
+
+                let mut serializer = BufferSerializer::new(buffer);
+                serializer.serialize_value(self).unwrap();
+                size_of::<<$message as rkyv::Archive>::Archived>()
+            }
+
+            fn deserialize(data: &[u8]) -> Option<Self> {
+                // the transfer crc only protects against corrupted frames,
+                // the bytes come from other nodes and are validated as well
+                $crate::common::versioned::decode_archived::<$message>(data)
+            }
+        }
+    };
+}
+
+/// Creates an enum of the multi-frame messages a node can receive, with a
+/// `decode` that picks the message by the message type of the transfer
+#[macro_export]
+macro_rules! create_can_bus_multi_frame_messages {
+    ($enum_name:ident $(, $message:ident)* $(,)?) => {
+        #[derive(Debug, Clone)]
+        pub enum $enum_name {
+            $(
+                $message($message),
+            )*
+        }
+
+        impl $enum_name {
+            /// Returns None for unknown message types and invalid data
+            #[allow(unused_variables)]
+            pub fn decode(
+                transfer: &$crate::common::can_bus::multi_frame::CanBusMultiFrameTransfer,
+            ) -> Option<Self> {
+                $(
+                    if let Some(message) = transfer.decode::<$message>() {
+                        return Some($enum_name::$message(message));
+                    }
+                )*
+                None
+            }
+        }
+    };
+}
+
+/// Splits a serialized message into frames
+pub struct CanBusMultiFrameEncoder<'a> {
+    message_type: u8,
+    data: &'a [u8],
+    frame_index: u8,
+    offset: usize,
+}
+
+impl<'a> CanBusMultiFrameEncoder<'a> {
+    pub fn new(message_type: u8, data: &'a [u8]) -> Self {
+        assert!(data.len() <= MAX_MULTI_FRAME_LENGTH);
+        Self {
+            message_type,
+            data,
+            frame_index: 0,
+            offset: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for CanBusMultiFrameEncoder<'a> {
+    type Item = Vec<u8, 8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = Vec::<u8, 8>::new();
+        if self.frame_index == 0 && self.offset == 0 {
+            let length = self.data.len() as u16;
+            let crc = CRC.checksum(self.data);
+            frame.push(0).unwrap();
+            frame.push(self.message_type).unwrap();
+            frame.extend_from_slice(&length.to_be_bytes()).unwrap();
+            frame.extend_from_slice(&crc.to_be_bytes()).unwrap();
+        } else if self.offset >= self.data.len() {
+            return None;
+        } else {
+            frame.push(self.frame_index).unwrap();
+        }
+
+        let end = (self.offset + 8 - frame.len()).min(self.data.len());
+        frame
+            .extend_from_slice(&self.data[self.offset..end])
+            .unwrap();
+        self.offset = end;
+        self.frame_index = self.frame_index.wrapping_add(1);
+        if self.frame_index == 0 {
+            // more frames than the frame index can count, can't happen within
+            // MAX_MULTI_FRAME_LENGTH
+            self.offset = self.data.len();
+        }
+        Some(frame)
+    }
+}
+
+/// Sends `CanBusMultiFrameMessage`s, assigning a new transfer id to every message
+pub struct CanBusMultiFrameSender {
+    next_transfer_id: u8,
+    buffer: [u8; MAX_MULTI_FRAME_LENGTH],
+}
+
+impl CanBusMultiFrameSender {
+    pub fn new() -> Self {
+        Self {
+            next_transfer_id: 0,
+            buffer: [0; MAX_MULTI_FRAME_LENGTH],
+        }
+    }
+
+    /// priority can be 0 - 7, 7 being the highest priority
+    pub async fn send<T: CanBusMultiFrameMessage, TX: CanBusRawTX>(
+        &mut self,
+        tx: &mut TX,
+        message: &T,
+        priority: u8,
+    ) -> Result<(), TX::Error> {
+        let length = message.serialize(&mut self.buffer);
+        let transfer_id = self.next_transfer_id;
+        self.next_transfer_id = (self.next_transfer_id + 1) & MAX_TRANSFER_ID;
+
+        let message_type = multi_frame_message_type(transfer_id);
+        for frame in CanBusMultiFrameEncoder::new(T::message_type(), &self.buffer[..length]) {
+            tx.send_raw(message_type, &frame, priority).await?;
+        }
+        Ok(())
+    }
+}
+
+/// A reassembled multi-frame transfer
+#[derive(Debug, Clone)]
+pub struct CanBusMultiFrameTransfer {
+    /// Timestamp of the first frame
+    pub timestamp: f64,
+    pub node_type: u8,
+    pub node_id: u16,
+    pub message_type: u8,
+    pub data: Vec<u8, MAX_MULTI_FRAME_LENGTH>,
+}
+
+impl CanBusMultiFrameTransfer {
+    /// Returns None if the transfer is not a `T`
+    pub fn decode<T: CanBusMultiFrameMessage>(&self) -> Option<T> {
+        if self.message_type != T::message_type() {
+            return None;
+        }
+        T::deserialize(&self.data)
+    }
+}
+
+struct ReassemblyBuffer {
+    transfer: CanBusMultiFrameTransfer,
+    transfer_id: u8,
+    length: usize,
+    crc: u16,
+    next_frame_index: u8,
+    last_frame_timestamp: f64,
+}
+
+/// Reassembles multi-frame transfers from up to `N` sources at the same time.
+///
+/// A source can only have one transfer in progress, a new first frame from the
+/// same source discards the unfinished transfer.
+pub struct CanBusMultiFrameDecoder<const N: usize> {
+    buffers: Vec<ReassemblyBuffer, N>,
+    timeout_ms: f64,
+}
+
+impl<const N: usize> CanBusMultiFrameDecoder<N> {
+    /// Unfinished transfers are discarded when no frame is received
+    /// from the source for `timeout_ms`
+    pub fn new(timeout_ms: f64) -> Self {
+        Self {
+            buffers: Vec::new(),
+            timeout_ms,
+        }
+    }
+
+    /// Returns the transfer when `frame` completes it,
+    /// frames that are not part of a multi-frame transfer are ignored
+    pub fn process_frame(
+        &mut self,
+        frame: &impl CanBusRawMessage,
+    ) -> Option<CanBusMultiFrameTransfer> {
+        if frame.rtr() {
+            return None;
+        }
+        let id = CanBusExtendedId::from_raw(frame.id());
+        let transfer_id = id.transfer_id()?;
+        let node_type: u8 = id.node_type.into();
+        let node_id: u16 = id.node_id.into();
+        let timestamp = frame.timestamp();
+        let data = frame.data();
+        if data.is_empty() {
+            return None;
+        }
+
+        let timeout_ms = self.timeout_ms;
+        self.buffers.retain(|buffer| {
+            let timed_out = timestamp - buffer.last_frame_timestamp > timeout_ms;
+            if timed_out {
+                log_warn!(
+                    "Multi-frame transfer from node {} timed out",
+                    buffer.transfer.node_id
+                );
+            }
+            !timed_out
+        });
+
+        let buffer_i = self.buffers.iter().position(|buffer| {
+            buffer.transfer.node_type == node_type && buffer.transfer.node_id == node_id
+        });
+
+        let buffer_i = if data[0] == 0 {
+            if let Some(i) = buffer_i {
+                self.buffers.swap_remove(i);
+            }
+            if data.len() < FIRST_FRAME_HEADER_SIZE {
+                return None;
+            }
+            let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+            if length > MAX_MULTI_FRAME_LENGTH {
+                log_warn!("Multi-frame transfer too long: {}", length);
+                return None;
+            }
+
+            if self.buffers.is_full() {
+                // evict the least recently active transfer
+                let oldest_i = self
+                    .buffers
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| {
+                        a.last_frame_timestamp
+                            .partial_cmp(&b.last_frame_timestamp)
+                            .unwrap_or(core::cmp::Ordering::Equal)
+                    })
+                    .map(|(i, _)| i)
+                    .unwrap();
+                self.buffers.swap_remove(oldest_i);
+            }
+
+            let buffer = ReassemblyBuffer {
+                transfer: CanBusMultiFrameTransfer {
+                    timestamp,
+                    node_type,
+                    node_id,
+                    message_type: data[1],
+                    data: Vec::new(),
+                },
+                transfer_id,
+                length,
+                crc: u16::from_be_bytes([data[4], data[5]]),
+                next_frame_index: 0,
+                last_frame_timestamp: timestamp,
+            };
+            self.buffers.push(buffer).ok().unwrap();
+            self.buffers.len() - 1
+        } else {
+            let i = buffer_i?;
+            let buffer = &self.buffers[i];
+            if buffer.transfer_id != transfer_id || buffer.next_frame_index != data[0] {
+                log_warn!(
+                    "Multi-frame transfer from node {} lost frame",
+                    buffer.transfer.node_id
+                );
+                self.buffers.swap_remove(i);
+                return None;
+            }
+            i
+        };
+
+        let buffer = &mut self.buffers[buffer_i];
+        let header_size = if data[0] == 0 {
+            FIRST_FRAME_HEADER_SIZE
+        } else {
+            FRAME_HEADER_SIZE
+        };
+        let payload = &data[header_size..];
+        let remaining = buffer.length - buffer.transfer.data.len();
+        buffer
+            .transfer
+            .data
+            .extend_from_slice(&payload[..payload.len().min(remaining)])
+            .unwrap();
+        buffer.next_frame_index = buffer.next_frame_index.wrapping_add(1);
+        buffer.last_frame_timestamp = timestamp;
+
+        if buffer.transfer.data.len() < buffer.length {
+            return None;
+        }
+
+        let buffer = self.buffers.swap_remove(buffer_i);
+        if CRC.checksum(&buffer.transfer.data) != buffer.crc {
+            log_warn!(
+                "Multi-frame transfer from node {} crc mismatch",
+                buffer.transfer.node_id
+            );
+            return None;
+        }
+        Some(buffer.transfer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use packed_struct::prelude::*;
+    use rkyv::{Archive, Deserialize, Serialize};
+
+    use super::*;
+
+    struct TestFrame {
+        timestamp: f64,
+        id: u32,
+        data: Vec<u8, 8>,
+    }
+
+    impl CanBusRawMessage for TestFrame {
+        fn timestamp(&self) -> f64 {
+            self.timestamp
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn rtr(&self) -> bool {
+            false
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    #[derive(PackedStruct, Clone, Copy, Debug, PartialEq, Eq)]
+    #[packed_struct(bit_numbering = "msb0", endian = "msb", size_bytes = "12")]
+    struct TestPackedMessage {
+        a: u32,
+        b: u64,
+    }
+
+    impl_packed_multi_frame_message!(TestPackedMessage, 0);
+
+    #[derive(Archive, Serialize, Deserialize, Clone, Debug, PartialEq)]
+    #[archive(check_bytes)]
+    struct TestRkyvMessage {
+        values: [f32; 10],
+        flag: bool,
+    }
+
+    impl_rkyv_multi_frame_message!(TestRkyvMessage, 1);
+
+    fn create_frames<T: CanBusMultiFrameMessage>(
+        message: &T,
+        transfer_id: u8,
+        node_id: u16,
+        start_timestamp: f64,
+    ) -> std::vec::Vec<TestFrame> {
+        let mut buffer = [0u8; MAX_MULTI_FRAME_LENGTH];
+        let length = message.serialize(&mut buffer);
+        let id: u32 =
+            CanBusExtendedId::new(3, multi_frame_message_type(transfer_id), 10, node_id).into();
+        CanBusMultiFrameEncoder::new(T::message_type(), &buffer[..length])
+            .enumerate()
+            .map(|(i, data)| TestFrame {
+                timestamp: start_timestamp + i as f64,
+                id,
+                data,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_packed_round_trip() {
+        let message = TestPackedMessage {
+            a: 0xDEADBEEF,
+            b: 0x0123456789ABCDEF,
+        };
+        let frames = create_frames(&message, 5, 1, 0.0);
+        assert_eq!(frames.len(), 3);
+
+        let mut decoder = CanBusMultiFrameDecoder::<4>::new(100.0);
+        let mut result = None;
+        for frame in &frames {
+            assert!(result.is_none());
+            result = decoder.process_frame(frame);
+        }
+        let transfer = result.unwrap();
+        assert_eq!(transfer.node_id, 1);
+        assert_eq!(transfer.node_type, 10);
+        assert_eq!(transfer.decode::<TestPackedMessage>(), Some(message));
+        assert!(transfer.decode::<TestRkyvMessage>().is_none());
+    }
+
+    create_can_bus_multi_frame_messages!(TestMultiFrameMessage, TestPackedMessage, TestRkyvMessage);
+
+    #[test]
+    fn test_decode_multi_frame_message() {
+        let message = TestRkyvMessage {
+            values: [4.0; 10],
+            flag: false,
+        };
+        let mut decoder = CanBusMultiFrameDecoder::<4>::new(100.0);
+        let transfer = create_frames(&message, 0, 1, 0.0)
+            .iter()
+            .find_map(|frame| decoder.process_frame(frame))
+            .unwrap();
+        assert!(matches!(
+            TestMultiFrameMessage::decode(&transfer),
+            Some(TestMultiFrameMessage::TestRkyvMessage(m)) if m == message
+        ));
+    }
+
+    #[test]
+    fn test_interleaved_sources() {
+        let message_1 = TestRkyvMessage {
+            values: [1.0; 10],
+            flag: true,
+        };
+        let message_2 = TestRkyvMessage {
+            values: [2.0; 10],
+            flag: false,
+        };
+        let frames_1 = create_frames(&message_1, 0, 1, 0.0);
+        let frames_2 = create_frames(&message_2, 0, 2, 0.0);
+
+        let mut decoder = CanBusMultiFrameDecoder::<4>::new(100.0);
+        let mut results = std::vec::Vec::new();
+        for (frame_1, frame_2) in frames_1.iter().zip(frames_2.iter()) {
+            results.extend(decoder.process_frame(frame_1));
+            results.extend(decoder.process_frame(frame_2));
+        }
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].decode::<TestRkyvMessage>(), Some(message_1));
+        assert_eq!(results[1].decode::<TestRkyvMessage>(), Some(message_2));
+    }
+
+    #[test]
+    fn test_lost_frame_and_corruption() {
+        let message = TestRkyvMessage {
+            values: [3.0; 10],
+            flag: true,
+        };
+        let mut decoder = CanBusMultiFrameDecoder::<4>::new(100.0);
+
+        let mut frames = create_frames(&message, 0, 1, 0.0);
+        frames.remove(2);
+        assert!(frames.iter().all(|frame| decoder.process_frame(frame).is_none()));
+
+        let mut frames = create_frames(&message, 1, 1, 100.0);
+        frames[3].data[4] ^= 0xFF;
+        assert!(frames.iter().all(|frame| decoder.process_frame(frame).is_none()));
+
+        let frames = create_frames(&message, 2, 1, 200.0);
+        let results: std::vec::Vec<_> = frames
+            .iter()
+            .filter_map(|frame| decoder.process_frame(frame))
+            .collect();
+        assert_eq!(results.len(), 1);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let message = TestPackedMessage { a: 1, b: 2 };
+        let mut decoder = CanBusMultiFrameDecoder::<4>::new(100.0);
+
+        let mut frames = create_frames(&message, 0, 1, 0.0);
+        frames.last_mut().unwrap().timestamp = 500.0;
+        assert!(frames.iter().all(|frame| decoder.process_frame(frame).is_none()));
+    }
+}
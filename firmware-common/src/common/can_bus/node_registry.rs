@@ -0,0 +1,314 @@
+use heapless::Vec;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::common::schema_hash::SchemaHash;
+use crate::driver::can_bus::{can_node_serial_hash, CanBusRawMessage};
+
+use super::{
+    id::CanBusExtendedId,
+    messages::{DecodedCanMessage, HealthState, NodeHeartbeatMessage},
+};
+
+pub const MAX_CAN_NODES: usize = 8;
+pub const CAN_HEARTBEAT_INTERVAL_MS: f64 = 1000.0;
+/// A node is missing after this long without a heartbeat
+pub const CAN_NODE_TIMEOUT_MS: f64 = 3500.0;
+
+const fn parse_version_part(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    let mut value: u8 = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0');
+        i += 1;
+    }
+    value
+}
+
+/// major, minor, patch
+pub const FIRMWARE_VERSION: [u8; 3] = [
+    parse_version_part(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_version_part(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_version_part(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+pub fn create_heartbeat_message(
+    health: HealthState,
+    uptime_ms: f64,
+    serial_number: &[u8],
+) -> NodeHeartbeatMessage {
+    NodeHeartbeatMessage {
+        health,
+        uptime_s: (((uptime_ms / 1000.0) as u32) & 0x3FFFFF).into(),
+        firmware_version: FIRMWARE_VERSION,
+        serial_hash: can_node_serial_hash(serial_number),
+    }
+}
+
+#[derive(
+    defmt::Format, Debug, Clone, Copy, PartialEq, Eq, Archive, Deserialize, Serialize, SchemaHash,
+)]
+#[archive(check_bytes)]
+pub enum CanNodeStatus {
+    Online,
+    Unhealthy,
+    Missing,
+    IdCollision,
+}
+
+#[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize, SchemaHash)]
+#[archive(check_bytes)]
+pub struct CanNodeInfo {
+    pub node_type: u8,
+    pub node_id: u16,
+    /// None if the node only sent `HealthMessage`s
+    pub serial_hash: Option<u16>,
+    pub firmware_version: Option<[u8; 3]>,
+    pub uptime_s: u32,
+    pub health: HealthState,
+    /// Boot timestamp of the last message from this node
+    pub last_seen: f64,
+    /// Another node with the same node type and id was seen
+    pub id_collision: bool,
+}
+
+impl CanNodeInfo {
+    pub fn status(&self, now: f64) -> CanNodeStatus {
+        if self.id_collision {
+            CanNodeStatus::IdCollision
+        } else if now - self.last_seen > CAN_NODE_TIMEOUT_MS {
+            CanNodeStatus::Missing
+        } else if self.health != HealthState::Healthy {
+            CanNodeStatus::Unhealthy
+        } else {
+            CanNodeStatus::Online
+        }
+    }
+}
+
+#[derive(defmt::Format, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanNodeSummary {
+    pub online: u8,
+    pub unhealthy: u8,
+    pub missing: u8,
+    pub id_collisions: u8,
+}
+
+impl CanNodeSummary {
+    /// Number of nodes that are not online
+    pub fn faulty(&self) -> u8 {
+        self.unhealthy + self.missing + self.id_collisions
+    }
+}
+
+/// Tracks the other nodes on the bus from their heartbeats
+pub struct CanNodeRegistry {
+    self_node_type: u8,
+    self_node_id: u16,
+    self_id_collision: bool,
+    nodes: Vec<CanNodeInfo, MAX_CAN_NODES>,
+}
+
+impl CanNodeRegistry {
+    pub fn new(self_node_type: u8, self_node_id: u16) -> Self {
+        Self {
+            self_node_type,
+            self_node_id,
+            self_id_collision: false,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Returns false if the message is not a heartbeat or health message
+    pub fn process_message(&mut self, message: &impl CanBusRawMessage) -> bool {
+        if message.rtr() {
+            return false;
+        }
+        let id = CanBusExtendedId::from_raw(message.id());
+        let node_type: u8 = id.node_type.into();
+        let node_id: u16 = id.node_id.into();
+        let timestamp = message.timestamp();
+
+        let (heartbeat, health) = match DecodedCanMessage::decode(message.id(), message.data()) {
+            Some(DecodedCanMessage::NodeHeartbeatMessage(heartbeat)) => {
+                let health = heartbeat.health;
+                (Some(heartbeat), health)
+            }
+            Some(DecodedCanMessage::HealthMessage(health)) => (None, health.state),
+            _ => return false,
+        };
+
+        if node_type == self.self_node_type && node_id == self.self_node_id {
+            if !self.self_id_collision {
+                log_warn!(
+                    "Another node is using the node id of this node: {}",
+                    node_id
+                );
+            }
+            self.self_id_collision = true;
+            return true;
+        }
+
+        let node = self
+            .nodes
+            .iter_mut()
+            .find(|node| node.node_type == node_type && node.node_id == node_id);
+        let node = if let Some(node) = node {
+            node
+        } else {
+            let node = CanNodeInfo {
+                node_type,
+                node_id,
+                serial_hash: None,
+                firmware_version: None,
+                uptime_s: 0,
+                health,
+                last_seen: timestamp,
+                id_collision: false,
+            };
+            if self.nodes.push(node).is_err() {
+                log_warn!("Too many CAN nodes, not tracking node {}", node_id);
+                return true;
+            }
+            log_info!("New CAN node: type {}, id {}", node_type, node_id);
+            self.nodes.last_mut().unwrap()
+        };
+
+        if let Some(heartbeat) = heartbeat {
+            if let Some(serial_hash) = node.serial_hash
+                && serial_hash != heartbeat.serial_hash
+                && timestamp - node.last_seen <= CAN_NODE_TIMEOUT_MS
+                && !node.id_collision
+            {
+                // a node that was swapped out stops sending heartbeats first,
+                // so two serial hashes alternating means two nodes share an id
+                log_warn!(
+                    "CAN node id collision: type {}, id {}",
+                    node_type,
+                    node_id
+                );
+                node.id_collision = true;
+            }
+            node.serial_hash = Some(heartbeat.serial_hash);
+            node.firmware_version = Some(heartbeat.firmware_version);
+            node.uptime_s = heartbeat.uptime_s.into();
+        }
+        node.health = health;
+        node.last_seen = timestamp;
+        true
+    }
+
+    pub fn nodes(&self) -> &[CanNodeInfo] {
+        &self.nodes
+    }
+
+    pub fn self_id_collision(&self) -> bool {
+        self.self_id_collision
+    }
+
+    pub fn summary(&self, now: f64) -> CanNodeSummary {
+        let mut summary = CanNodeSummary::default();
+        if self.self_id_collision {
+            summary.id_collisions += 1;
+        }
+        for node in &self.nodes {
+            match node.status(now) {
+                CanNodeStatus::Online => summary.online += 1,
+                CanNodeStatus::Unhealthy => summary.unhealthy += 1,
+                CanNodeStatus::Missing => summary.missing += 1,
+                CanNodeStatus::IdCollision => summary.id_collisions += 1,
+            }
+        }
+        summary
+    }
+
+    /// Fixed size node table for the console RPC
+    pub fn table(&self, now: f64) -> [Option<(CanNodeInfo, CanNodeStatus)>; MAX_CAN_NODES] {
+        let mut table: [Option<(CanNodeInfo, CanNodeStatus)>; MAX_CAN_NODES] = Default::default();
+        for (entry, node) in table.iter_mut().zip(self.nodes.iter()) {
+            *entry = Some((node.clone(), node.status(now)));
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::can_bus::{message::CanBusMessage, node_types::STRAIN_GAUGES_NODE_TYPE};
+
+    struct TestMessage {
+        timestamp: f64,
+        id: u32,
+        data: heapless::Vec<u8, 8>,
+    }
+
+    impl CanBusRawMessage for TestMessage {
+        fn timestamp(&self) -> f64 {
+            self.timestamp
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn rtr(&self) -> bool {
+            false
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    fn heartbeat(timestamp: f64, node_id: u16, serial_number: &[u8], health: HealthState) -> TestMessage {
+        TestMessage {
+            timestamp,
+            id: NodeHeartbeatMessage::create_id(3, STRAIN_GAUGES_NODE_TYPE, node_id).into(),
+            data: create_heartbeat_message(health, timestamp, serial_number).to_data(),
+        }
+    }
+
+    #[test]
+    fn test_liveness() {
+        let mut registry = CanNodeRegistry::new(40, 1);
+        assert!(registry.process_message(&heartbeat(0.0, 2, &[1; 12], HealthState::Healthy)));
+        assert!(registry.process_message(&heartbeat(0.0, 3, &[2; 12], HealthState::Degraded)));
+
+        assert_eq!(
+            registry.summary(1000.0),
+            CanNodeSummary {
+                online: 1,
+                unhealthy: 1,
+                missing: 0,
+                id_collisions: 0,
+            }
+        );
+        assert_eq!(registry.nodes()[0].firmware_version, Some(FIRMWARE_VERSION));
+
+        registry.process_message(&heartbeat(4000.0, 2, &[1; 12], HealthState::Healthy));
+        assert_eq!(registry.nodes()[0].uptime_s, 4);
+        assert_eq!(registry.nodes()[1].status(4000.0), CanNodeStatus::Missing);
+    }
+
+    #[test]
+    fn test_id_collision() {
+        let mut registry = CanNodeRegistry::new(40, 1);
+        registry.process_message(&heartbeat(0.0, 2, &[1; 12], HealthState::Healthy));
+        registry.process_message(&heartbeat(500.0, 2, &[2; 12], HealthState::Healthy));
+        assert_eq!(registry.nodes()[0].status(500.0), CanNodeStatus::IdCollision);
+
+        // a node replaced after it went missing is not a collision
+        registry.process_message(&heartbeat(0.0, 3, &[1; 12], HealthState::Healthy));
+        registry.process_message(&heartbeat(10000.0, 3, &[3; 12], HealthState::Healthy));
+        assert_eq!(registry.nodes()[1].status(10000.0), CanNodeStatus::Online);
+    }
+
+    #[test]
+    fn test_self_id_collision() {
+        let mut registry = CanNodeRegistry::new(STRAIN_GAUGES_NODE_TYPE, 2);
+        registry.process_message(&heartbeat(0.0, 2, &[1; 12], HealthState::Healthy));
+        assert!(registry.self_id_collision());
+        assert_eq!(registry.summary(0.0).id_collisions, 1);
+    }
+}
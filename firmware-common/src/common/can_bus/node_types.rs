@@ -1,5 +1,48 @@
 // the higher the number, the higher the priority
 // the maximum node type is 63
 
-pub const VOID_LAKE_NODE_TYPE: u8 = 40;
-pub const STRAIN_GAUGES_NODE_TYPE: u8 = 20;
\ No newline at end of file
+pub const fn assert_valid_node_types(node_types: &[u8]) {
+    let mut i = 0;
+    while i < node_types.len() {
+        if node_types[i] > 63 {
+            panic!("CAN bus node types must be below 64");
+        }
+        let mut j = i + 1;
+        while j < node_types.len() {
+            if node_types[i] == node_types[j] {
+                panic!("Duplicated CAN bus node type");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Declares every node type once: the constants, a compile time check that
+/// they are unique, and the (node type, name) list.
+macro_rules! create_can_bus_node_types {
+    ($list_name:ident, $(($node_type:literal, $const_name:ident, $name:literal)),* $(,)?) => {
+        $(
+            pub const $const_name: u8 = $node_type;
+        )*
+
+        const _: () = assert_valid_node_types(&[$($node_type),*]);
+
+        pub const $list_name: &[(u8, &str)] = &[
+            $(($const_name, $name),)*
+        ];
+    };
+}
+
+create_can_bus_node_types! {
+    CAN_BUS_NODE_TYPES,
+    (40, VOID_LAKE_NODE_TYPE, "VoidLake"),
+    (20, STRAIN_GAUGES_NODE_TYPE, "StrainGauges"),
+}
+
+pub fn node_type_name(node_type: u8) -> Option<&'static str> {
+    CAN_BUS_NODE_TYPES
+        .iter()
+        .find(|(t, _)| *t == node_type)
+        .map(|(_, name)| *name)
+}
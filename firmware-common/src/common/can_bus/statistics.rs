@@ -0,0 +1,151 @@
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::common::schema_hash::SchemaHash;
+use crate::driver::can_bus::{CanBusState, CanBusStatus};
+
+pub const CAN_BUS_OFF_MIN_BACKOFF_MS: f64 = 10.0;
+pub const CAN_BUS_OFF_MAX_BACKOFF_MS: f64 = 1000.0;
+
+/// Health of the CAN bus as seen by this node, all counts are totals since boot
+#[derive(
+    defmt::Format, Debug, Clone, Default, PartialEq, Eq, Archive, Deserialize, Serialize, SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct CanBusStatistics {
+    pub state: CanBusState,
+    pub tx_error_count: u8,
+    pub rx_error_count: u8,
+
+    pub tx_frames: u32,
+    /// frames the driver failed to send
+    pub tx_failed: u32,
+    /// frames dropped because the TX queue is full
+    pub tx_dropped: u32,
+    pub lost_arbitration: u32,
+
+    pub rx_frames: u32,
+    pub rx_errors: u32,
+    /// frames dropped by the controller because the RX buffer is full
+    pub rx_overruns: u32,
+
+    pub bus_off_count: u32,
+}
+
+impl CanBusStatistics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update_status(&mut self, status: &CanBusStatus) {
+        if status.state == CanBusState::BusOff && self.state != CanBusState::BusOff {
+            self.bus_off_count = self.bus_off_count.wrapping_add(1);
+        }
+        self.state = status.state;
+        self.tx_error_count = status.tx_error_count;
+        self.rx_error_count = status.rx_error_count;
+        self.lost_arbitration = status.lost_arbitration_count;
+        self.rx_overruns = status.rx_overrun_count;
+    }
+
+    /// Frames dropped by the TX queue are counted with `record_tx_dropped`
+    pub fn record_tx(&mut self, ok: bool) {
+        if ok {
+            self.tx_frames = self.tx_frames.wrapping_add(1);
+        } else {
+            self.tx_failed = self.tx_failed.wrapping_add(1);
+        }
+    }
+
+    pub fn record_tx_dropped(&mut self) {
+        self.tx_dropped = self.tx_dropped.wrapping_add(1);
+    }
+
+    pub fn record_rx(&mut self, ok: bool) {
+        if ok {
+            self.rx_frames = self.rx_frames.wrapping_add(1);
+        } else {
+            self.rx_errors = self.rx_errors.wrapping_add(1);
+        }
+    }
+
+    /// Frames that never made it onto the bus
+    pub fn tx_lost(&self) -> u32 {
+        self.tx_failed.saturating_add(self.tx_dropped)
+    }
+}
+
+/// Exponential backoff between bus-off recovery attempts, so a node with a
+/// broken transceiver doesn't keep destroying traffic on the bus
+pub struct BusOffBackoff {
+    next_delay_ms: f64,
+}
+
+impl BusOffBackoff {
+    pub fn new() -> Self {
+        Self {
+            next_delay_ms: CAN_BUS_OFF_MIN_BACKOFF_MS,
+        }
+    }
+
+    pub fn next_delay_ms(&mut self) -> f64 {
+        let delay = self.next_delay_ms;
+        self.next_delay_ms = (self.next_delay_ms * 2.0).min(CAN_BUS_OFF_MAX_BACKOFF_MS);
+        delay
+    }
+
+    /// Call after a frame is sent successfully
+    pub fn reset(&mut self) {
+        self.next_delay_ms = CAN_BUS_OFF_MIN_BACKOFF_MS;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bus_off_count() {
+        let mut statistics = CanBusStatistics::new();
+        let mut status = CanBusStatus {
+            state: CanBusState::BusOff,
+            tx_error_count: 255,
+            ..Default::default()
+        };
+        statistics.update_status(&status);
+        statistics.update_status(&status);
+        assert_eq!(statistics.bus_off_count, 1);
+
+        status.state = CanBusState::ErrorActive;
+        statistics.update_status(&status);
+        status.state = CanBusState::BusOff;
+        statistics.update_status(&status);
+        assert_eq!(statistics.bus_off_count, 2);
+    }
+
+    #[test]
+    fn test_counters_wrap() {
+        let mut statistics = CanBusStatistics {
+            tx_frames: u32::MAX,
+            tx_dropped: u32::MAX,
+            ..Default::default()
+        };
+        statistics.record_tx(true);
+        statistics.record_tx_dropped();
+        assert_eq!(statistics.tx_frames, 0);
+        assert_eq!(statistics.tx_dropped, 0);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = BusOffBackoff::new();
+        assert_eq!(backoff.next_delay_ms(), CAN_BUS_OFF_MIN_BACKOFF_MS);
+        assert_eq!(backoff.next_delay_ms(), CAN_BUS_OFF_MIN_BACKOFF_MS * 2.0);
+        for _ in 0..20 {
+            backoff.next_delay_ms();
+        }
+        assert_eq!(backoff.next_delay_ms(), CAN_BUS_OFF_MAX_BACKOFF_MS);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay_ms(), CAN_BUS_OFF_MIN_BACKOFF_MS);
+    }
+}
@@ -0,0 +1,211 @@
+//! Bounded priority queue in front of `CanBusTX`.
+//!
+//! Frames are sent highest priority first, and in order within the same
+//! priority. When the queue is full the lowest priority frame is dropped, so
+//! flight events are never stuck behind status messages. The runner also
+//! recovers the controller from bus-off.
+
+use core::cell::RefCell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::NoopRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+    signal::Signal,
+};
+use heapless::Vec;
+
+use crate::driver::{
+    can_bus::{CanBusState, CanBusTX},
+    delay::Delay,
+};
+
+use super::{
+    message::CanBusMessage,
+    messages::DecodedCanMessage,
+    statistics::{BusOffBackoff, CanBusStatistics},
+};
+
+struct QueuedFrame<T> {
+    frame: T,
+    /// 0 - 7, 7 being the highest priority
+    priority: u8,
+    sequence: u32,
+}
+
+pub struct CanBusTXQueueState<T, const N: usize> {
+    frames: Vec<QueuedFrame<T>, N>,
+    next_sequence: u32,
+}
+
+impl<T, const N: usize> CanBusTXQueueState<T, N> {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            next_sequence: 0,
+        }
+    }
+
+    /// Returns the dropped frame if the queue is full, which may be `frame` itself
+    pub fn push(&mut self, frame: T, priority: u8) -> Option<T> {
+        let queued = QueuedFrame {
+            frame,
+            priority,
+            sequence: self.next_sequence,
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        if !self.frames.is_full() {
+            self.frames.push(queued).ok();
+            return None;
+        }
+
+        // newest frame with the lowest priority
+        let (i, lowest) = self
+            .frames
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, f)| (f.priority, u32::MAX - f.sequence))?;
+        if lowest.priority >= queued.priority {
+            return Some(queued.frame);
+        }
+        let dropped = self.frames.swap_remove(i);
+        self.frames.push(queued).ok();
+        Some(dropped.frame)
+    }
+
+    /// Returns the frame and its priority
+    pub fn pop(&mut self) -> Option<(T, u8)> {
+        let (i, _) = self
+            .frames
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, f)| (f.priority, u32::MAX - f.sequence))?;
+        let queued = self.frames.swap_remove(i);
+        Some((queued.frame, queued.priority))
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+pub struct CanBusTXQueue<'a, const N: usize> {
+    state: BlockingMutex<NoopRawMutex, RefCell<CanBusTXQueueState<DecodedCanMessage, N>>>,
+    signal: Signal<NoopRawMutex, ()>,
+    statistics: &'a BlockingMutex<NoopRawMutex, RefCell<CanBusStatistics>>,
+}
+
+impl<'a, const N: usize> CanBusTXQueue<'a, N> {
+    pub fn new(statistics: &'a BlockingMutex<NoopRawMutex, RefCell<CanBusStatistics>>) -> Self {
+        Self {
+            state: BlockingMutex::new(RefCell::new(CanBusTXQueueState::new())),
+            signal: Signal::new(),
+            statistics,
+        }
+    }
+
+    /// priority can be 0 - 7, 7 being the highest priority
+    pub fn enqueue<T: CanBusMessage + Into<DecodedCanMessage>>(&self, message: &T, priority: u8) {
+        let dropped = self
+            .state
+            .lock(|s| s.borrow_mut().push(message.clone().into(), priority));
+        if let Some(dropped) = dropped {
+            log_warn!("CAN TX queue full, dropped {}", dropped.name());
+            self.statistics.lock(|s| s.borrow_mut().record_tx_dropped());
+        }
+        self.signal.signal(());
+    }
+
+    async fn dequeue(&self) -> (DecodedCanMessage, u8) {
+        loop {
+            if let Some(frame) = self.state.lock(|s| s.borrow_mut().pop()) {
+                return frame;
+            }
+            self.signal.wait().await;
+        }
+    }
+
+    /// `tx` is shared with the time critical senders (e.g. clock sync), which
+    /// bypass the queue and record their own statistics. The lock is released
+    /// while waiting for the bus-off backoff.
+    pub async fn run<T: CanBusTX>(&self, tx: &Mutex<NoopRawMutex, T>, delay: impl Delay) -> ! {
+        let mut backoff = BusOffBackoff::new();
+        loop {
+            let (message, priority) = self.dequeue().await;
+            loop {
+                let bus_off = self.update_status(&*tx.lock().await) == CanBusState::BusOff;
+                if bus_off {
+                    let delay_ms = backoff.next_delay_ms();
+                    log_warn!("CAN bus off, recovering in {}ms", delay_ms);
+                    delay.delay_ms(delay_ms).await;
+                    if let Err(e) = tx.lock().await.recover_from_bus_off().await {
+                        log_error!("Failed to recover from CAN bus off: {:?}", e);
+                    }
+                    continue;
+                }
+
+                let mut tx = tx.lock().await;
+                let result = message.send(&mut *tx, priority).await;
+                let state = self.update_status(&*tx);
+                drop(tx);
+                match result {
+                    Ok(_) => {
+                        backoff.reset();
+                        self.statistics.lock(|s| s.borrow_mut().record_tx(true));
+                        break;
+                    }
+                    // retry the frame after recovering
+                    Err(_) if state == CanBusState::BusOff => {}
+                    Err(e) => {
+                        log_warn!("Failed to send CAN message: {:?}", e);
+                        self.statistics.lock(|s| s.borrow_mut().record_tx(false));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_status<T: CanBusTX>(&self, tx: &T) -> CanBusState {
+        let status = tx.status();
+        self.statistics
+            .lock(|s| s.borrow_mut().update_status(&status));
+        status.state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_priority_order() {
+        let mut queue = CanBusTXQueueState::<u8, 4>::new();
+        queue.push(1, 3);
+        queue.push(2, 7);
+        queue.push(3, 3);
+        queue.push(4, 5);
+
+        let order: std::vec::Vec<u8> = core::iter::from_fn(|| queue.pop())
+            .map(|(frame, _)| frame)
+            .collect();
+        assert_eq!(order, [2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn test_full_queue() {
+        let mut queue = CanBusTXQueueState::<u8, 2>::new();
+        assert!(queue.push(1, 3).is_none());
+        assert!(queue.push(2, 3).is_none());
+
+        // same priority, the new frame is dropped
+        assert_eq!(queue.push(3, 3), Some(3));
+
+        // higher priority, the newest low priority frame is dropped
+        assert_eq!(queue.push(4, 7), Some(2));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some((4, 7)));
+        assert_eq!(queue.pop(), Some((1, 3)));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -3,16 +3,22 @@ use core::mem::size_of;
 
 use embedded_io_async::Read;
 use rkyv::{
-    archived_root,
     ser::{serializers::BufferSerializer, Serializer},
-    AlignedBytes, Archive, Deserialize, Serialize,
+    validation::validators::ArchiveValidator,
+    Archive, CheckBytes, Deserialize, Serialize,
 };
-use vlfs::{AsyncWriter, Crc, FileType, Flash, VLFSError, VLFS};
+use vlfs::{AsyncReader, AsyncWriter, Crc, FileID, FileType, Flash, VLFSError, VLFS};
+
+use super::versioned::{decode_versioned, EnvelopeHeader, Versioned, ENVELOPE_HEADER_SIZE};
+
+/// Upper bound of the archived size of any version of a config, older
+/// versions can be larger than the current one
+pub const MAX_CONFIG_PAYLOAD_SIZE: usize = 512;
 
 pub struct ConfigFile<'a, T, F, C>
 where
-    T: Archive + Serialize<BufferSerializer<[u8; size_of::<T::Archived>()]>>,
-    T::Archived: Deserialize<T, rkyv::Infallible>,
+    T: Versioned + Serialize<BufferSerializer<[u8; size_of::<T::Archived>()]>>,
+    T::Archived: Deserialize<T, rkyv::Infallible> + for<'b> CheckBytes<ArchiveValidator<'b>>,
     F: Flash,
     C: Crc,
     [(); size_of::<T::Archived>()]:,
@@ -24,13 +30,16 @@ where
 
 impl<'a, T, F, C> ConfigFile<'a, T, F, C>
 where
-    T: Archive + Serialize<BufferSerializer<[u8; size_of::<T::Archived>()]>>,
-    T::Archived: Deserialize<T, rkyv::Infallible>,
+    T: Versioned + Serialize<BufferSerializer<[u8; size_of::<T::Archived>()]>>,
+    T::Archived: Deserialize<T, rkyv::Infallible> + for<'b> CheckBytes<ArchiveValidator<'b>>,
     F: Flash,
     C: Crc,
     [(); size_of::<T::Archived>()]:,
 {
     pub fn new(fs: &'a VLFS<F, C>, file_type: FileType) -> Self {
+        const {
+            assert!(size_of::<T::Archived>() <= MAX_CONFIG_PAYLOAD_SIZE);
+        }
         ConfigFile {
             _phantom: PhantomData,
             fs,
@@ -40,34 +49,68 @@ where
 
     pub async fn read(&self) -> Option<T> {
         if let Ok(Some(file)) = self.fs.find_first_file(self.file_type).await {
-            match self.fs.open_file_for_read(file.id).await {
-                Ok(mut reader) => {
-                    let mut buffer: AlignedBytes<{ size_of::<T::Archived>() }> = Default::default();
-                    let result = reader.read_exact(buffer.as_mut()).await;
-                    reader.close().await;
-                    if let Err(e) = result {
-                        log_warn!("Failed to read config file {:?}: {:?}", self.file_type, e);
-                        return None;
-                    }
-
-                    let archived = unsafe { archived_root::<T>(buffer.as_ref()) };
-                    let deserialized =
-                        <T::Archived as rkyv::Deserialize<T, rkyv::Infallible>>::deserialize(
-                            archived,
-                            &mut rkyv::Infallible,
-                        )
-                        .unwrap();
-                    return Some(deserialized);
-                }
-                Err(e) => {
-                    log_warn!("Failed to open config file {:?}: {:?}", self.file_type, e);
-                    return None;
-                }
+            if let Some(config) = self.read_enveloped(file.id).await {
+                return Some(config);
             }
+
+            // config files written before the envelope was introduced only
+            // contain the archived bytes of version 0
+            let config = self.read_legacy(file.id).await;
+            if config.is_none() {
+                log_warn!("Config file {:?} is invalid", self.file_type);
+            }
+            config
         } else {
             log_info!("Config file {:?} not found", self.file_type);
+            None
+        }
+    }
+
+    async fn read_enveloped(&self, file_id: FileID) -> Option<T> {
+        let mut reader = match self.fs.open_file_for_read(file_id).await {
+            Ok(reader) => reader,
+            Err(e) => {
+                log_warn!("Failed to open config file {:?}: {:?}", self.file_type, e);
+                return None;
+            }
+        };
+
+        let mut header = [0u8; ENVELOPE_HEADER_SIZE];
+        let mut payload = [0u8; MAX_CONFIG_PAYLOAD_SIZE];
+        let result: Option<EnvelopeHeader> = try {
+            reader.read_exact(&mut header).await.ok()?;
+            let header = EnvelopeHeader::from_bytes(&header);
+            if header.type_tag != self.file_type.0 || header.length as usize > payload.len() {
+                None?;
+            }
+            reader
+                .read_exact(&mut payload[..header.length as usize])
+                .await
+                .ok()?;
+            header
+        };
+        reader.close().await;
+
+        let header = result?;
+        let payload = &payload[..header.length as usize];
+        if !header.matches(payload) {
             return None;
         }
+        decode_versioned::<T>(header.version, payload)
+    }
+
+    /// Legacy files are decoded as version 0, types whose layout changed since
+    /// then go through their version 0 migration
+    async fn read_legacy(&self, file_id: FileID) -> Option<T> {
+        let mut reader = self.fs.open_file_for_read(file_id).await.ok()?;
+        let mut payload = [0u8; MAX_CONFIG_PAYLOAD_SIZE];
+        let result = reader
+            .read_slice(&mut payload, MAX_CONFIG_PAYLOAD_SIZE)
+            .await
+            .map(|(payload, _)| payload.len());
+        reader.close().await;
+        let length = result.ok()?;
+        decode_versioned::<T>(0, &payload[..length])
     }
 
     pub async fn write(&self, config: &T) -> Result<(), VLFSError<F::Error>> {
@@ -80,9 +123,69 @@ where
         let mut serializer = BufferSerializer::new(buffer);
         serializer.serialize_value(config).unwrap();
         let buffer = serializer.into_inner();
+        let header = EnvelopeHeader::new(self.file_type.0, T::VERSION, &buffer);
 
+        writer.extend_from_slice(&header.to_bytes()).await?;
         writer.extend_from_slice(&buffer).await?;
         writer.close().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use rkyv::ser::{serializers::BufferSerializer, Serializer};
+    use vlfs::{DummyCrc, MemoryFlash};
+
+    use super::*;
+    use crate::common::{
+        device_config::{DeviceConfig, DeviceConfigV0, DeviceModeConfig, LoraConfig},
+        file_types::DEVICE_CONFIG_FILE_TYPE,
+        rkyv_structs::RkyvString,
+    };
+
+    fn legacy_device_config() -> DeviceConfigV0 {
+        DeviceConfigV0 {
+            name: RkyvString::from_str("legacy"),
+            mode: DeviceModeConfig::GCM,
+            lora: LoraConfig {
+                frequency: 915_000_000,
+                sf: 12,
+                bw: 250000,
+                cr: 8,
+                power: 22,
+            },
+            lora_key: [0x69; 32],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_legacy_device_config() {
+        let mut fs = VLFS::new(MemoryFlash::new(None), DummyCrc {});
+        fs.init().await.unwrap();
+
+        // written the way the firmware did before the envelope was introduced
+        let mut serializer =
+            BufferSerializer::new([0u8; size_of::<<DeviceConfigV0 as Archive>::Archived>()]);
+        serializer.serialize_value(&legacy_device_config()).unwrap();
+        let buffer = serializer.into_inner();
+        let file = fs.create_file(DEVICE_CONFIG_FILE_TYPE).await.unwrap();
+        let mut writer = fs.open_file_for_write(file.id).await.unwrap();
+        writer.extend_from_slice(&buffer).await.unwrap();
+        writer.close().await.unwrap();
+
+        let config_file = ConfigFile::<DeviceConfig, _, _>::new(&fs, DEVICE_CONFIG_FILE_TYPE);
+        let config = config_file.read().await.unwrap();
+        assert_eq!(config.name.as_str(), "legacy");
+        assert!(matches!(config.mode, DeviceModeConfig::GCM));
+        assert_eq!(config.lora.sf, 12);
+        assert_eq!(config.lora_key, [0x69; 32]);
+        assert_eq!(config.vlp_device_id, None);
+
+        // rewritten in the current version
+        config_file.write(&config).await.unwrap();
+        let config = config_file.read().await.unwrap();
+        assert_eq!(config.name.as_str(), "legacy");
+        assert_eq!(config.vlp_device_id, None);
+    }
+}
@@ -1,20 +1,38 @@
+use core::future::Future;
+
 use vlfs::{FileID, FileType};
 
-use crate::driver::serial::SplitableSerial;
+use crate::{common::device_log::DeviceLogRecord, driver::serial::SplitableSerial};
 
-use super::{create_rpc::RpcClientError, DeviceType, OpenFileStatus, ReadFileResult};
+use super::{
+    create_rpc::RpcClientError, file_transfer::FileTransferSummary, DeviceType, OpenFileStatus,
+    ReadFileResult,
+};
 
 pub trait CommonRPCTrait<S: SplitableSerial> {
     async fn get_device_type(&mut self) -> Result<DeviceType, RpcClientError<S>>;
     async fn open_file(&mut self, file_id: FileID) -> Result<OpenFileStatus, RpcClientError<S>>;
     async fn read_file(&mut self) -> Result<ReadFileResult, RpcClientError<S>>;
     async fn close_file(&mut self) -> Result<(), RpcClientError<S>>;
+    /// Streams the rest of the opened file into `sink`, much faster than `read_file`
+    async fn read_file_stream(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]),
+    ) -> Result<FileTransferSummary, RpcClientError<S>>;
+    /// Streams the buffered device logs into `sink`, then keeps streaming the
+    /// new logs until `stop` completes if `follow` is true
+    async fn read_device_logs(
+        &mut self,
+        follow: bool,
+        stop: impl Future<Output = ()>,
+        sink: &mut impl FnMut(DeviceLogRecord),
+    ) -> Result<(), RpcClientError<S>>;
 
     async fn start_list_files(
         &mut self,
         file_type: Option<FileType>,
     ) -> Result<(), RpcClientError<S>>;
-    async fn get_listed_file(&mut self) -> Result<Option<FileID>, RpcClientError<S>>;
+    async fn get_listed_file(&mut self) -> Result<Option<(FileID, FileType)>, RpcClientError<S>>;
 }
 
 #[macro_export]
@@ -40,6 +58,24 @@ macro_rules! impl_common_rpc_trait {
             async fn close_file(&mut self) -> Result<(), crate::common::console::create_rpc::RpcClientError<S>> {
                 self.close_file().await.map(|_| ())
             }
+
+            async fn read_file_stream(
+                &mut self,
+                sink: &mut impl FnMut(&[u8]),
+            ) -> Result<crate::common::console::file_transfer::FileTransferSummary, crate::common::console::create_rpc::RpcClientError<S>> {
+                let (tx, rx, delay) = self.read_file_stream().await?;
+                crate::common::console::file_transfer::receive_file_stream::<S>(tx, rx, delay, sink).await
+            }
+
+            async fn read_device_logs(
+                &mut self,
+                follow: bool,
+                stop: impl core::future::Future<Output = ()>,
+                sink: &mut impl FnMut(crate::common::device_log::DeviceLogRecord),
+            ) -> Result<(), crate::common::console::create_rpc::RpcClientError<S>> {
+                let (tx, rx, delay) = self.device_log_stream().await?;
+                crate::common::console::log_stream::receive_device_log_stream::<S>(tx, rx, delay, follow, stop, sink).await
+            }
         
             async fn start_list_files(
                 &mut self,
@@ -50,10 +86,10 @@ macro_rules! impl_common_rpc_trait {
                     .map(|_| ())
             }
         
-            async fn get_listed_file(&mut self) -> Result<Option<FileID>, crate::common::console::create_rpc::RpcClientError<S>> {
+            async fn get_listed_file(&mut self) -> Result<Option<(FileID, FileType)>, crate::common::console::create_rpc::RpcClientError<S>> {
                 self.get_listed_file()
                     .await
-                    .map(|response| response.file_id.map(FileID))
+                    .map(|response| response.file.map(|(file_id, file_type)| (FileID(file_id), FileType(file_type))))
             }
         }
         
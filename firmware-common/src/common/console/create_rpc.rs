@@ -1,14 +1,94 @@
 #![allow(warnings, unused)]
 
 use embedded_io::ReadExactError;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::{
+    common::{
+        can_bus::node_registry::FIRMWARE_VERSION,
+        rkyv_structs::{RkyvString, RkyvVec},
+    },
+    driver::serial::SplitableSerial,
+};
+
+/// Bump when the framing of `create_rpc!` changes
+pub const RPC_PROTOCOL_VERSION: u16 = 1;
+pub const RPC_HANDSHAKE_ID: u8 = 254;
+pub const MAX_RPCS: usize = 32;
+
+#[derive(Archive, Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, defmt::Format)]
+#[archive(check_bytes)]
+pub struct RpcSchema {
+    pub rpc_id: u8,
+    pub schema_hash: u32,
+}
+
+/// Response of the handshake rpc every `create_rpc!` server answers, its
+/// layout must not change without bumping `RPC_PROTOCOL_VERSION`
+#[derive(Archive, Deserialize, Serialize, Debug, Clone, defmt::Format)]
+#[archive(check_bytes)]
+pub struct RpcHandshake {
+    pub protocol_version: u16,
+    /// major, minor, patch
+    pub firmware_version: [u8; 3],
+    pub git_hash: RkyvString<16>,
+    pub rpcs: RkyvVec<MAX_RPCS, RpcSchema>,
+}
+
+impl RpcHandshake {
+    /// `create_rpc!` checks at compile time that `rpcs` fits in `MAX_RPCS`
+    pub fn new(rpcs: &[RpcSchema]) -> Self {
+        Self {
+            protocol_version: RPC_PROTOCOL_VERSION,
+            firmware_version: FIRMWARE_VERSION,
+            git_hash: RkyvString::from_str(env!("VL_GIT_HASH")),
+            rpcs: RkyvVec::from_slice(rpcs),
+        }
+    }
+
+    pub fn supports(&self, rpc_id: u8) -> bool {
+        self.rpcs.as_slice().iter().any(|schema| schema.rpc_id == rpc_id)
+    }
+
+    /// Rpcs missing on the device are allowed, calling them returns
+    /// `RpcClientError::Unsupported`
+    pub fn check(&self, local_rpcs: &[RpcSchema]) -> Result<(), IncompatibleReason> {
+        if self.protocol_version != RPC_PROTOCOL_VERSION {
+            return Err(IncompatibleReason::ProtocolVersion(self.protocol_version));
+        }
+        for local in local_rpcs {
+            let remote = self.rpcs.as_slice().iter().find(|remote| remote.rpc_id == local.rpc_id);
+            if let Some(remote) = remote
+                && remote.schema_hash != local.schema_hash
+            {
+                return Err(IncompatibleReason::SchemaMismatch(local.rpc_id));
+            }
+        }
+        Ok(())
+    }
+}
 
-use crate::driver::serial::SplitableSerial;
+#[derive(defmt::Format, core::fmt::Debug, Clone, PartialEq)]
+pub enum IncompatibleReason {
+    /// The firmware predates the handshake
+    NoHandshake,
+    InvalidHandshake,
+    ProtocolVersion(u16),
+    /// The request or response layout of this rpc id is different
+    SchemaMismatch(u8),
+}
 
 #[derive(defmt::Format, core::fmt::Debug)]
 pub enum RpcClientError<S: SplitableSerial> {
     Timeout,
     ECCMismatch,
     UnexpectedEof,
+    /// The device did not respond to a streaming rpc, or does not have the rpc
+    Unsupported,
+    FileChecksumMismatch,
+    /// The response passed the CRC check but is not a valid archive
+    InvalidResponse,
+    IncompatibleFirmware(IncompatibleReason),
     Serial(S::Error),
 }
 
@@ -35,10 +115,12 @@ macro_rules! create_rpc {
             $( $state_body:tt )*
         }
         $(rpc $rpc_i:literal $name:ident |$($req_var_name:ident: $req_var_type:ty),*| -> ($($res_var_name:ident: $res_var_type:ty),*) $handler:expr)*
+        $(stream $stream_i:expr, $stream_name:ident |$stream_tx:ident, $stream_rx:ident| $stream_handler:expr)*
     } => {
         $(
             $(
-                #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, defmt::Format)]
+                #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, PartialEq, defmt::Format, crate::common::schema_hash::SchemaHash)]
+                #[archive(check_bytes)]
                 pub enum $enum_name {
                     $( $enum_body )*
                 }
@@ -47,7 +129,8 @@ macro_rules! create_rpc {
 
         $(
             paste::paste! {
-                #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, defmt::Format)]
+                #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, defmt::Format, crate::common::schema_hash::SchemaHash)]
+                #[archive(check_bytes)]
                 pub struct [< $name Request >] {
                     $(
                         pub $req_var_name: $req_var_type,
@@ -55,7 +138,8 @@ macro_rules! create_rpc {
                 }
             }
             paste::paste! {
-                #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, defmt::Format)]
+                #[derive(rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, Debug, Clone, defmt::Format, crate::common::schema_hash::SchemaHash)]
+                #[archive(check_bytes)]
                 pub struct [< $name Response >] {
                     $(
                         pub $res_var_name: $res_var_type,
@@ -84,6 +168,36 @@ macro_rules! create_rpc {
             }
         }
 
+        paste::paste! {
+            /// Rpc ids and schema hashes reported by the handshake
+            pub const RPC_SCHEMAS: &[crate::common::console::create_rpc::RpcSchema] = &[
+                $(
+                    crate::common::console::create_rpc::RpcSchema {
+                        rpc_id: $rpc_i,
+                        schema_hash: crate::common::schema_hash::SchemaHasher::new()
+                            .write_str(stringify!($name))
+                            .write_u32(<[< $name Request >] as crate::common::schema_hash::SchemaHash>::SCHEMA_HASH)
+                            .write_u32(<[< $name Response >] as crate::common::schema_hash::SchemaHash>::SCHEMA_HASH)
+                            .finish(),
+                    },
+                )*
+                $(
+                    crate::common::console::create_rpc::RpcSchema {
+                        rpc_id: $stream_i,
+                        schema_hash: crate::common::schema_hash::SchemaHasher::new()
+                            .write_str("stream")
+                            .write_str(stringify!($stream_name))
+                            .finish(),
+                    },
+                )*
+            ];
+
+            const _: () = assert!(
+                RPC_SCHEMAS.len() <= crate::common::console::create_rpc::MAX_RPCS,
+                "too many rpcs for the handshake, increase MAX_RPCS"
+            );
+        }
+
         paste::paste! {
             pub async fn run_rpc_server<
                 S: crate::driver::serial::SplitableSerial,
@@ -100,9 +214,9 @@ macro_rules! create_rpc {
                     use core::mem::size_of;
                     use rkyv::ser::Serializer;
                     use rkyv::{ser::serializers::BufferSerializer};
-                    use rkyv::archived_root;
                     use rkyv::util::AlignedBytes;
                     use crc::{Crc, CRC_8_SMBUS};
+                    use crate::common::versioned::decode_archived;
                     use embedded_io_async::Read;
                     use embedded_io_async::ReadExactError;
                     use embedded_io_async::Write;
@@ -113,16 +227,22 @@ macro_rules! create_rpc {
                     let mut request_buffer: AlignedBytes<{size_of::<<RequestEnum as rkyv::Archive>::Archived>() + 17}> = Default::default();
                     let mut response_buffer = [0u8; size_of::<<ResponseEnum as rkyv::Archive>::Archived>()];
                     let (mut tx, mut rx) = serial.split();
+                    // first byte of the next request, already read by a stream
+                    let mut pending_rpc_id: Option<u8> = None;
 
                     loop {
-                        match rx.read_exact(&mut request_buffer[15..16]).await {
-                            Ok(_) => {},
-                            Err(ReadExactError::UnexpectedEof)=>{
-                                log_info!("Unexpected EOF, skipping.");
-                                continue;
-                            },
-                            Err(ReadExactError::Other(e))=>{
-                                Err(e)?;
+                        if let Some(rpc_id) = pending_rpc_id.take() {
+                            request_buffer[15] = rpc_id;
+                        } else {
+                            match rx.read_exact(&mut request_buffer[15..16]).await {
+                                Ok(_) => {},
+                                Err(ReadExactError::UnexpectedEof)=>{
+                                    log_info!("Unexpected EOF, skipping.");
+                                    continue;
+                                },
+                                Err(ReadExactError::Other(e))=>{
+                                    Err(e)?;
+                                }
                             }
                         }
                         // log_info!("Received command: {:x}", request_buffer[15]);
@@ -148,9 +268,11 @@ macro_rules! create_rpc {
                                     }
                                     //log_info!("Command CRC matched.");
 
-                                    let archived = unsafe { archived_root::<[< $name Request >]>(&request_buffer[16..(request_size+16)]) };
                                     #[allow(unused)]
-                                    let request = <[<Archived $name Request>] as rkyv::Deserialize<[< $name Request >], rkyv::Infallible>>::deserialize(archived, &mut rkyv::Infallible).unwrap();
+                                    let Some(request) = decode_archived::<[< $name Request >]>(&request_buffer[16..(request_size+16)]) else {
+                                        log_info!("Invalid request, skipping.");
+                                        continue;
+                                    };
 
                                     $(
                                         let $req_var_name = request.$req_var_name;
@@ -168,6 +290,57 @@ macro_rules! create_rpc {
                                     log_info!("Response sent, crc: {}", response_buffer[response_size]);
                                 }
                             )*
+                            $(
+                                rpc_id if rpc_id == $stream_i => {
+                                    // streams take no arguments, the request is only protected by a crc
+                                    match rx.read_exact(&mut request_buffer[16..17]).await {
+                                        Ok(_) => {},
+                                        Err(ReadExactError::UnexpectedEof)=>{
+                                            continue;
+                                        },
+                                        Err(ReadExactError::Other(e))=>{
+                                            Err(e)?;
+                                        }
+                                    }
+                                    if crc.checksum(&request_buffer[15..16]) != request_buffer[16] {
+                                        log_info!("Command CRC mismatch, skipping.");
+                                        continue;
+                                    }
+
+                                    let $stream_tx = &mut tx;
+                                    let $stream_rx = &mut rx;
+                                    // the handler returns the first byte of the next request
+                                    // if it read one
+                                    pending_rpc_id = $stream_handler;
+                                    log_info!("Stream {} finished", stringify!($stream_name));
+                                }
+                            )*
+                            crate::common::console::create_rpc::RPC_HANDSHAKE_ID => {
+                                use crate::common::console::create_rpc::{RpcHandshake, ArchivedRpcHandshake};
+
+                                match rx.read_exact(&mut request_buffer[16..17]).await {
+                                    Ok(_) => {},
+                                    Err(ReadExactError::UnexpectedEof)=>{
+                                        continue;
+                                    },
+                                    Err(ReadExactError::Other(e))=>{
+                                        Err(e)?;
+                                    }
+                                }
+                                if crc.checksum(&request_buffer[15..16]) != request_buffer[16] {
+                                    log_info!("Command CRC mismatch, skipping.");
+                                    continue;
+                                }
+
+                                let handshake_size = size_of::<ArchivedRpcHandshake>();
+                                let mut handshake_buffer = [0u8; size_of::<ArchivedRpcHandshake>() + 1];
+                                let mut handshake_serializer = BufferSerializer::new(&mut handshake_buffer[..handshake_size]);
+                                handshake_serializer.serialize_value(&RpcHandshake::new(RPC_SCHEMAS)).unwrap();
+                                drop(handshake_serializer);
+
+                                handshake_buffer[handshake_size] = crc.checksum(&handshake_buffer[..handshake_size]);
+                                tx.write_all(&handshake_buffer).await?;
+                            }
                             255 => {
                                 tx.write_all(&[255, 0x69]).await?;
                             }
@@ -187,11 +360,24 @@ macro_rules! create_rpc {
             pub struct RpcClient<'a, S: crate::driver::serial::SplitableSerial, D: embedded_hal_async::delay::DelayNs> {
                 serial: &'a mut S,
                 delay: D,
+                remote_handshake: Option<crate::common::console::create_rpc::RpcHandshake>,
             }
 
             impl<'a, S: crate::driver::serial::SplitableSerial, D: embedded_hal_async::delay::DelayNs> RpcClient<'a, S, D> {
                 pub fn new(serial: &'a mut S, delay: D) -> Self {
-                    Self { serial , delay}
+                    Self { serial , delay, remote_handshake: None }
+                }
+
+                /// Handshake of the device, available after `reset`, even if the firmware is incompatible
+                pub fn remote_handshake(&self) -> Option<&crate::common::console::create_rpc::RpcHandshake> {
+                    self.remote_handshake.as_ref()
+                }
+
+                fn check_supported(&self, rpc_id: u8) -> Result<(), crate::common::console::create_rpc::RpcClientError<S>> {
+                    match &self.remote_handshake {
+                        Some(handshake) if !handshake.supports(rpc_id) => Err(crate::common::console::create_rpc::RpcClientError::Unsupported),
+                        _ => Ok(()),
+                    }
                 }
 
                 async fn clear_read_buffer<T: embedded_io_async::Read>(delay: &mut D, rx: &mut T) {
@@ -208,7 +394,30 @@ macro_rules! create_rpc {
                     run_with_timeout(delay, 100.0, read_fut).await.ok();
                 }
 
-                pub async fn reset(&mut self) ->  Result<bool, crate::common::console::create_rpc::RpcClientError<S>> {
+                /// Resets the rpc server and checks the firmware is compatible with this client
+                pub async fn reset(&mut self) -> Result<bool, crate::common::console::create_rpc::RpcClientError<S>> {
+                    use crate::common::console::create_rpc::{IncompatibleReason, RpcClientError};
+
+                    self.remote_handshake = None;
+                    if !self.reset_unchecked().await? {
+                        return Ok(false);
+                    }
+
+                    let handshake = match self.handshake().await {
+                        Ok(handshake) => handshake,
+                        Err(RpcClientError::Timeout) => {
+                            return Err(RpcClientError::IncompatibleFirmware(IncompatibleReason::NoHandshake));
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let result = handshake.check(RPC_SCHEMAS);
+                    self.remote_handshake = Some(handshake);
+                    result.map_err(RpcClientError::IncompatibleFirmware)?;
+                    Ok(true)
+                }
+
+                /// Resets the rpc server without the handshake
+                pub async fn reset_unchecked(&mut self) ->  Result<bool, crate::common::console::create_rpc::RpcClientError<S>> {
                     use embedded_io_async::Write;
                     use embedded_io_async::Read;
                     use crate::common::console::create_rpc::RpcClientError;
@@ -232,20 +441,52 @@ macro_rules! create_rpc {
                     }
                 }
 
+                pub async fn handshake(&mut self) -> Result<crate::common::console::create_rpc::RpcHandshake, crate::common::console::create_rpc::RpcClientError<S>> {
+                    use core::mem::size_of;
+                    use embedded_io_async::Write;
+                    use embedded_io_async::Read;
+                    use crate::common::console::create_rpc::{
+                        ArchivedRpcHandshake, IncompatibleReason, RpcClientError, RpcHandshake, RPC_HANDSHAKE_ID,
+                    };
+                    use crate::common::versioned::decode_archived;
+                    use crc::{Crc, CRC_8_SMBUS};
+                    use crate::utils::run_with_timeout;
+
+                    let crc = Crc::<u8>::new(&CRC_8_SMBUS);
+                    let (mut tx, mut rx) = self.serial.split();
+                    tx.write_all(&[RPC_HANDSHAKE_ID, crc.checksum(&[RPC_HANDSHAKE_ID])]).await.map_err(RpcClientError::Serial)?;
+
+                    let handshake_size = size_of::<ArchivedRpcHandshake>();
+                    let mut handshake_buffer = [0u8; size_of::<ArchivedRpcHandshake>() + 1];
+                    match run_with_timeout(&mut self.delay, 1000.0, rx.read_exact(&mut handshake_buffer)).await {
+                        Ok(Ok(_)) => {}
+                        Ok(Err(e)) => return Err(RpcClientError::from(e)),
+                        Err(_) => return Err(RpcClientError::Timeout),
+                    }
+                    if crc.checksum(&handshake_buffer[..handshake_size]) != handshake_buffer[handshake_size] {
+                        return Err(RpcClientError::ECCMismatch);
+                    }
+
+                    // validated, an older protocol version may have a different layout
+                    decode_archived::<RpcHandshake>(&handshake_buffer[..handshake_size])
+                        .ok_or(RpcClientError::IncompatibleFirmware(IncompatibleReason::InvalidHandshake))
+                }
+
                 $(
                     pub async fn [< $name:snake >](&mut self, $($req_var_name: $req_var_type, )*) -> Result<[< $name Response >], crate::common::console::create_rpc::RpcClientError<S>> {
                         use core::mem::size_of;
                         use rkyv::ser::Serializer;
                         use rkyv::{ser::serializers::BufferSerializer};
-                        use rkyv::archived_root;
                         use rkyv::util::AlignedBytes;
                         use embedded_io_async::Write;
                         use embedded_io_async::Read;
                         use crate::common::console::create_rpc::RpcClientError;
+                        use crate::common::versioned::decode_archived;
                         use crc::{Crc, CRC_8_SMBUS};
                         use crate::utils::run_with_timeout;
                         use futures::join;
 
+                        self.check_supported($rpc_i)?;
                         let crc = Crc::<u8>::new(&CRC_8_SMBUS);
                         let (mut tx, mut rx) = self.serial.split();
 
@@ -292,9 +533,8 @@ macro_rules! create_rpc {
                                 }
                                 // log_debug!("Response CRC matched.");
 
-                                let archived = unsafe { archived_root::<[< $name Response >]>(&response_buffer[..response_size]) };
-                                let deserialized = <[<Archived $name Response>] as rkyv::Deserialize<[< $name Response >], rkyv::Infallible>>::deserialize(archived, &mut rkyv::Infallible).unwrap();
-                                deserialized
+                                decode_archived::<[< $name Response >]>(&response_buffer[..response_size])
+                                    .ok_or(RpcClientError::InvalidResponse)?
                             };
                             result
                         };
@@ -308,7 +548,57 @@ macro_rules! create_rpc {
                     }
                 )*
 
+                $(
+                    /// Starts the stream, the device pushes the data on the returned serial
+                    pub async fn [< $stream_name:snake >](&mut self) -> Result<(S::TX<'_>, S::RX<'_>, &mut D), crate::common::console::create_rpc::RpcClientError<S>> {
+                        use embedded_io_async::Write;
+                        use crate::common::console::create_rpc::RpcClientError;
+                        use crc::{Crc, CRC_8_SMBUS};
+
+                        self.check_supported($stream_i)?;
+                        let crc = Crc::<u8>::new(&CRC_8_SMBUS);
+                        let (mut tx, rx) = self.serial.split();
+                        tx.write_all(&[$stream_i, crc.checksum(&[$stream_i])]).await.map_err(RpcClientError::Serial)?;
+                        Ok((tx, rx, &mut self.delay))
+                    }
+                )*
+
             }
         }
     };
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_handshake_check() {
+        let remote = [
+            RpcSchema { rpc_id: 0, schema_hash: 10 },
+            RpcSchema { rpc_id: 1, schema_hash: 11 },
+        ];
+        let handshake = RpcHandshake::new(&remote);
+        assert!(handshake.supports(1));
+        assert!(!handshake.supports(2));
+
+        let local = [
+            RpcSchema { rpc_id: 0, schema_hash: 10 },
+            RpcSchema { rpc_id: 2, schema_hash: 12 },
+        ];
+        assert_eq!(handshake.check(&local), Ok(()));
+
+        let local = [RpcSchema { rpc_id: 1, schema_hash: 12 }];
+        assert_eq!(
+            handshake.check(&local),
+            Err(IncompatibleReason::SchemaMismatch(1))
+        );
+
+        let mut handshake = handshake;
+        handshake.protocol_version += 1;
+        assert_eq!(
+            handshake.check(&[]),
+            Err(IncompatibleReason::ProtocolVersion(RPC_PROTOCOL_VERSION + 1))
+        );
+    }
+}
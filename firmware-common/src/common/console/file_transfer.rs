@@ -0,0 +1,636 @@
+//! Streaming file transfer used by the `ReadFileStream` rpc.
+//!
+//! After the rpc is requested, the device pushes the opened file as chunk
+//! frames without waiting for a request per chunk:
+//!
+//! `[magic][seq: u32][length: u16][data][crc32 of everything before]`
+//!
+//! At most `FILE_TRANSFER_WINDOW` chunks are in flight. The host acknowledges
+//! the received chunks cumulatively every `FILE_TRANSFER_ACK_INTERVAL` chunks
+//! and asks for a retransmission starting at the first missing chunk when it
+//! receives a bad or out of order frame, or when nothing arrives in time.
+//! The last frame is an end frame carrying the file length and the CRC32 of
+//! the whole file, the transfer is over once the host acknowledges it. The
+//! device resends the unacknowledged frames when the host stays silent, and
+//! gives up after `MAX_RETRIES` timeouts.
+
+use core::fmt::Debug;
+
+use crc::{Crc, CRC_32_ISO_HDLC, CRC_8_SMBUS};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, ReadExactError, Write};
+use vlfs::{AsyncReader, VLFSReadStatus};
+
+use crate::{driver::serial::SplitableSerial, utils::run_with_timeout};
+
+use super::create_rpc::RpcClientError;
+
+/// Same on every device, so the host can stream files without knowing the
+/// device type
+pub const READ_FILE_STREAM_RPC_ID: u8 = 14;
+pub const FILE_TRANSFER_CHUNK_SIZE: usize = 512;
+pub const FILE_TRANSFER_WINDOW: usize = 8;
+pub const FILE_TRANSFER_ACK_INTERVAL: u32 = FILE_TRANSFER_WINDOW as u32 / 2;
+
+const FRAME_HEADER_SIZE: usize = 7;
+const END_PAYLOAD_SIZE: usize = 9;
+const HOST_MESSAGE_SIZE: usize = 6;
+
+const CHUNK_FRAME_MAGIC: u8 = 0xC5;
+const END_FRAME_MAGIC: u8 = 0xE5;
+const ACK_MESSAGE: u8 = 0xA0;
+const NACK_MESSAGE: u8 = 0xA1;
+
+const FRAME_TIMEOUT_MS: f64 = 1000.0;
+const MAX_RETRIES: usize = 5;
+
+/// Returned to the host once the whole file checksum is verified
+#[derive(Debug, Clone, PartialEq, defmt::Format)]
+pub struct FileTransferSummary {
+    pub length: u32,
+    pub crc: u32,
+    /// The device hit a corrupted page while reading the file
+    pub corrupted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HostMessage {
+    /// All chunks before `seq` are received
+    Ack(u32),
+    /// Retransmit starting from `seq`, implies all chunks before it are received
+    Nack(u32),
+}
+
+impl HostMessage {
+    fn to_bytes(&self) -> [u8; HOST_MESSAGE_SIZE] {
+        let (kind, seq) = match self {
+            HostMessage::Ack(seq) => (ACK_MESSAGE, seq),
+            HostMessage::Nack(seq) => (NACK_MESSAGE, seq),
+        };
+        let mut bytes = [0u8; HOST_MESSAGE_SIZE];
+        bytes[0] = kind;
+        bytes[1..5].copy_from_slice(&seq.to_le_bytes());
+        bytes[5] = Crc::<u8>::new(&CRC_8_SMBUS).checksum(&bytes[..5]);
+        bytes
+    }
+
+    /// `Err(())` means the bytes are not part of the transfer at all (e.g. the
+    /// host moved on to another rpc), `Ok(None)` means the message is corrupted
+    fn from_bytes(bytes: &[u8; HOST_MESSAGE_SIZE]) -> Result<Option<Self>, ()> {
+        if bytes[0] != ACK_MESSAGE && bytes[0] != NACK_MESSAGE {
+            return Err(());
+        }
+        if Crc::<u8>::new(&CRC_8_SMBUS).checksum(&bytes[..5]) != bytes[5] {
+            return Ok(None);
+        }
+        let seq = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        Ok(Some(if bytes[0] == ACK_MESSAGE {
+            HostMessage::Ack(seq)
+        } else {
+            HostMessage::Nack(seq)
+        }))
+    }
+}
+
+async fn write_frame<T: Write>(
+    tx: &mut T,
+    magic: u8,
+    seq: u32,
+    data: &[u8],
+) -> Result<(), T::Error> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    header[0] = magic;
+    header[1..5].copy_from_slice(&seq.to_le_bytes());
+    header[5..7].copy_from_slice(&(data.len() as u16).to_le_bytes());
+
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut digest = crc.digest();
+    digest.update(&header);
+    digest.update(data);
+
+    tx.write_all(&header).await?;
+    tx.write_all(data).await?;
+    tx.write_all(&digest.finalize().to_le_bytes()).await
+}
+
+struct Frame {
+    magic: u8,
+    seq: u32,
+    length: usize,
+}
+
+/// Skips everything before the next frame magic, returns `None` if the frame
+/// is corrupted
+async fn read_frame<R: Read>(
+    rx: &mut R,
+    buffer: &mut [u8; FILE_TRANSFER_CHUNK_SIZE + 4],
+) -> Result<Option<Frame>, ReadExactError<R::Error>> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    loop {
+        rx.read_exact(&mut header[..1]).await?;
+        if header[0] == CHUNK_FRAME_MAGIC || header[0] == END_FRAME_MAGIC {
+            break;
+        }
+    }
+    rx.read_exact(&mut header[1..]).await?;
+
+    let frame = Frame {
+        magic: header[0],
+        seq: u32::from_le_bytes([header[1], header[2], header[3], header[4]]),
+        length: u16::from_le_bytes([header[5], header[6]]) as usize,
+    };
+    let max_length = if frame.magic == END_FRAME_MAGIC {
+        END_PAYLOAD_SIZE
+    } else {
+        FILE_TRANSFER_CHUNK_SIZE
+    };
+    if frame.length > max_length {
+        return Ok(None);
+    }
+
+    rx.read_exact(&mut buffer[..(frame.length + 4)]).await?;
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut digest = crc.digest();
+    digest.update(&header);
+    digest.update(&buffer[..frame.length]);
+    let received_crc =
+        u32::from_le_bytes(buffer[frame.length..(frame.length + 4)].try_into().unwrap());
+    if digest.finalize() != received_crc {
+        return Ok(None);
+    }
+    Ok(Some(frame))
+}
+
+/// Device side of the transfer, streams the rest of `reader`. Sends an empty
+/// file if no file is opened.
+///
+/// Returns the first byte of the next rpc request if the host moved on
+/// without acknowledging the end frame, the rpc server should handle it
+/// instead of reading a new one.
+pub async fn serve_file_stream<T, R, FR>(
+    tx: &mut T,
+    rx: &mut R,
+    delay: &mut impl DelayNs,
+    mut reader: Option<&mut FR>,
+) -> Result<Option<u8>, T::Error>
+where
+    T: Write,
+    R: Read<Error = T::Error>,
+    FR: AsyncReader<ReadStatus = VLFSReadStatus>,
+    FR::Error: Debug + defmt::Format,
+{
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut file_digest = crc.digest();
+    let mut file_length = 0u32;
+    let mut corrupted = false;
+
+    let mut chunks = [[0u8; FILE_TRANSFER_CHUNK_SIZE]; FILE_TRANSFER_WINDOW];
+    let mut chunk_lengths = [0usize; FILE_TRANSFER_WINDOW];
+    // oldest chunk not acknowledged by the host
+    let mut base_seq = 0u32;
+    let mut next_seq = 0u32;
+    let mut eof = reader.is_none();
+    let mut end_payload: Option<[u8; END_PAYLOAD_SIZE]> = None;
+    let mut retries = 0;
+
+    loop {
+        while !eof && next_seq - base_seq < FILE_TRANSFER_WINDOW as u32 {
+            let slot = next_seq as usize % FILE_TRANSFER_WINDOW;
+            let reader = reader.as_mut().unwrap();
+            let length = match reader.read_all(&mut chunks[slot]).await {
+                Ok((data, read_status)) => {
+                    corrupted |= matches!(read_status, VLFSReadStatus::CorruptedPage { .. });
+                    data.len()
+                }
+                Err(e) => {
+                    log_warn!("Error reading file: {:?}", e);
+                    corrupted = true;
+                    0
+                }
+            };
+            if length == 0 {
+                eof = true;
+                break;
+            }
+
+            file_digest.update(&chunks[slot][..length]);
+            file_length += length as u32;
+            chunk_lengths[slot] = length;
+            write_frame(tx, CHUNK_FRAME_MAGIC, next_seq, &chunks[slot][..length]).await?;
+            next_seq += 1;
+        }
+
+        if eof && end_payload.is_none() {
+            let mut payload = [0u8; END_PAYLOAD_SIZE];
+            payload[0..4].copy_from_slice(&file_length.to_le_bytes());
+            payload[4..8].copy_from_slice(&file_digest.clone().finalize().to_le_bytes());
+            payload[8] = corrupted as u8;
+            write_frame(tx, END_FRAME_MAGIC, next_seq, &payload).await?;
+            end_payload = Some(payload);
+        }
+
+        let mut message = [0u8; HOST_MESSAGE_SIZE];
+        let read_result = run_with_timeout(delay, FRAME_TIMEOUT_MS, async {
+            rx.read_exact(&mut message[..1]).await?;
+            if message[0] != ACK_MESSAGE && message[0] != NACK_MESSAGE {
+                // not part of the transfer, leave the rest to the rpc server
+                return Ok(());
+            }
+            rx.read_exact(&mut message[1..]).await
+        })
+        .await;
+        let resend_from = match read_result {
+            Ok(Ok(_)) => match HostMessage::from_bytes(&message) {
+                Ok(Some(HostMessage::Ack(seq))) => {
+                    retries = 0;
+                    if seq > next_seq {
+                        // end frame acknowledged
+                        return Ok(None);
+                    }
+                    base_seq = base_seq.max(seq);
+                    None
+                }
+                Ok(Some(HostMessage::Nack(seq))) => {
+                    retries = 0;
+                    if seq < base_seq || seq > next_seq {
+                        continue;
+                    }
+                    base_seq = seq;
+                    Some(base_seq)
+                }
+                Ok(None) => {
+                    log_warn!("File transfer message CRC mismatch, skipping.");
+                    None
+                }
+                Err(_) => {
+                    log_warn!("File transfer aborted by host");
+                    return Ok(Some(message[0]));
+                }
+            },
+            Ok(Err(ReadExactError::UnexpectedEof)) => continue,
+            Ok(Err(ReadExactError::Other(e))) => return Err(e),
+            Err(_) => {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    log_warn!("File transfer timed out");
+                    return Ok(None);
+                }
+                // the host may have missed the last frames, including the end frame
+                Some(base_seq)
+            }
+        };
+
+        if let Some(resend_from) = resend_from {
+            for seq in resend_from..next_seq {
+                let slot = seq as usize % FILE_TRANSFER_WINDOW;
+                write_frame(
+                    tx,
+                    CHUNK_FRAME_MAGIC,
+                    seq,
+                    &chunks[slot][..chunk_lengths[slot]],
+                )
+                .await?;
+            }
+            if let Some(end_payload) = &end_payload {
+                write_frame(tx, END_FRAME_MAGIC, next_seq, end_payload).await?;
+            }
+        }
+    }
+}
+
+/// Host side of the transfer, `sink` is called with the file content in order.
+/// Returns `RpcClientError::Unsupported` if the device never answers, so the
+/// caller can fall back to `ReadFile`.
+pub async fn receive_file_stream<S: SplitableSerial>(
+    mut tx: S::TX<'_>,
+    mut rx: S::RX<'_>,
+    delay: &mut impl DelayNs,
+    sink: &mut impl FnMut(&[u8]),
+) -> Result<FileTransferSummary, RpcClientError<S>> {
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut file_digest = crc.digest();
+    let mut file_length = 0u32;
+
+    let mut buffer = [0u8; FILE_TRANSFER_CHUNK_SIZE + 4];
+    let mut expected_seq = 0u32;
+    let mut received_any = false;
+    let mut nack_sent = false;
+    let mut retries = 0;
+
+    loop {
+        let frame =
+            match run_with_timeout(delay, FRAME_TIMEOUT_MS, read_frame(&mut rx, &mut buffer)).await
+            {
+                Ok(Ok(frame)) => frame,
+                Ok(Err(e)) => return Err(RpcClientError::from(e)),
+                Err(_) => {
+                    if !received_any {
+                        return Err(RpcClientError::Unsupported);
+                    }
+                    retries += 1;
+                    if retries > MAX_RETRIES {
+                        return Err(RpcClientError::Timeout);
+                    }
+                    let message = HostMessage::Nack(expected_seq).to_bytes();
+                    tx.write_all(&message)
+                        .await
+                        .map_err(RpcClientError::Serial)?;
+                    nack_sent = true;
+                    continue;
+                }
+            };
+        received_any = true;
+
+        let frame = match frame {
+            Some(frame) if frame.seq == expected_seq => frame,
+            Some(frame) if frame.seq < expected_seq => {
+                // retransmission of a chunk that is already received
+                continue;
+            }
+            _ => {
+                if !nack_sent {
+                    let message = HostMessage::Nack(expected_seq).to_bytes();
+                    tx.write_all(&message)
+                        .await
+                        .map_err(RpcClientError::Serial)?;
+                    nack_sent = true;
+                }
+                continue;
+            }
+        };
+        retries = 0;
+        nack_sent = false;
+
+        if frame.magic == END_FRAME_MAGIC {
+            if frame.length != END_PAYLOAD_SIZE {
+                return Err(RpcClientError::ECCMismatch);
+            }
+            let message = HostMessage::Ack(expected_seq + 1).to_bytes();
+            tx.write_all(&message)
+                .await
+                .map_err(RpcClientError::Serial)?;
+
+            let summary = FileTransferSummary {
+                length: u32::from_le_bytes(buffer[0..4].try_into().unwrap()),
+                crc: u32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+                corrupted: buffer[8] != 0,
+            };
+            if summary.length != file_length || summary.crc != file_digest.finalize() {
+                return Err(RpcClientError::FileChecksumMismatch);
+            }
+            return Ok(summary);
+        }
+
+        let data = &buffer[..frame.length];
+        sink(data);
+        file_digest.update(data);
+        file_length += data.len() as u32;
+        expected_seq += 1;
+        if expected_seq % FILE_TRANSFER_ACK_INTERVAL == 0 {
+            let message = HostMessage::Ack(expected_seq).to_bytes();
+            tx.write_all(&message)
+                .await
+                .map_err(RpcClientError::Serial)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::test_utils::BufferWriter;
+    use crate::driver::serial::SplitableSerialWrapper;
+
+    #[futures_test::test]
+    async fn test_frame_round_trip() {
+        let mut buffer = [0u8; 4096];
+        let mut writer = BufferWriter::new(&mut buffer);
+        writer.write_all(&[0x12, 0x34]).await.unwrap();
+        write_frame(&mut writer, CHUNK_FRAME_MAGIC, 7, &[1, 2, 3, 4, 5])
+            .await
+            .unwrap();
+        write_frame(&mut writer, CHUNK_FRAME_MAGIC, 8, &[6, 7, 8])
+            .await
+            .unwrap();
+        let second_frame_data = writer.offset - 4 - 3;
+        writer.buffer[second_frame_data] ^= 0xFF;
+        write_frame(&mut writer, END_FRAME_MAGIC, 9, &[0; END_PAYLOAD_SIZE])
+            .await
+            .unwrap();
+        let mut reader = writer.into_reader();
+
+        let mut frame_buffer = [0u8; FILE_TRANSFER_CHUNK_SIZE + 4];
+        let frame = read_frame(&mut reader, &mut frame_buffer)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.magic, CHUNK_FRAME_MAGIC);
+        assert_eq!(frame.seq, 7);
+        assert_eq!(&frame_buffer[..frame.length], &[1, 2, 3, 4, 5]);
+
+        assert!(read_frame(&mut reader, &mut frame_buffer)
+            .await
+            .unwrap()
+            .is_none());
+
+        let frame = read_frame(&mut reader, &mut frame_buffer)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.magic, END_FRAME_MAGIC);
+        assert_eq!(frame.seq, 9);
+        assert_eq!(frame.length, END_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn test_host_message() {
+        let bytes = HostMessage::Nack(1234).to_bytes();
+        assert_eq!(
+            HostMessage::from_bytes(&bytes),
+            Ok(Some(HostMessage::Nack(1234)))
+        );
+
+        let mut corrupted = HostMessage::Ack(1234).to_bytes();
+        corrupted[2] ^= 0x01;
+        assert_eq!(HostMessage::from_bytes(&corrupted), Ok(None));
+
+        let mut not_a_message = bytes;
+        not_a_message[0] = 4;
+        assert_eq!(HostMessage::from_bytes(&not_a_message), Err(()));
+    }
+
+    enum Fault {
+        Drop,
+        Corrupt,
+    }
+
+    /// One direction of an in-memory serial port. Faults are applied to whole
+    /// frames, keyed by (magic, seq) and only on their first transmission.
+    struct PipeTX {
+        sender: tokio::sync::mpsc::UnboundedSender<u8>,
+        faults: std::vec::Vec<(u8, u32, Fault)>,
+        writes: usize,
+        current_fault: Option<Fault>,
+    }
+
+    impl embedded_io_async::ErrorType for PipeTX {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Write for PipeTX {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            // every frame is written as header, data and crc
+            if self.writes % 3 == 0 && buf.len() == FRAME_HEADER_SIZE {
+                let seq = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]);
+                self.current_fault = self
+                    .faults
+                    .iter()
+                    .position(|(magic, fault_seq, _)| *magic == buf[0] && *fault_seq == seq)
+                    .map(|i| self.faults.remove(i).2);
+            }
+            let part = self.writes % 3;
+            self.writes += 1;
+
+            match self.current_fault {
+                Some(Fault::Drop) => {}
+                Some(Fault::Corrupt) if part == 1 => {
+                    self.sender.send(buf[0] ^ 0xFF).unwrap();
+                    for byte in &buf[1..] {
+                        self.sender.send(*byte).unwrap();
+                    }
+                }
+                _ => {
+                    for byte in buf {
+                        self.sender.send(*byte).unwrap();
+                    }
+                }
+            }
+            Ok(buf.len())
+        }
+    }
+
+    struct PipeRX {
+        receiver: tokio::sync::mpsc::UnboundedReceiver<u8>,
+    }
+
+    impl embedded_io_async::ErrorType for PipeRX {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for PipeRX {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let Some(byte) = self.receiver.recv().await else {
+                return Ok(0);
+            };
+            buf[0] = byte;
+            let mut length = 1;
+            while length < buf.len() {
+                match self.receiver.try_recv() {
+                    Ok(byte) => {
+                        buf[length] = byte;
+                        length += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+            Ok(length)
+        }
+    }
+
+    fn pipe(faults: std::vec::Vec<(u8, u32, Fault)>) -> (PipeTX, PipeRX) {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        (
+            PipeTX {
+                sender,
+                faults,
+                writes: 0,
+                current_fault: None,
+            },
+            PipeRX { receiver },
+        )
+    }
+
+    struct SliceReader<'a> {
+        data: &'a [u8],
+        offset: usize,
+    }
+
+    impl<'a> AsyncReader for SliceReader<'a> {
+        type Error = core::convert::Infallible;
+        type ReadStatus = VLFSReadStatus;
+
+        async fn read_slice<'b>(
+            &mut self,
+            buffer: &'b mut [u8],
+            length: usize,
+        ) -> Result<(&'b [u8], Self::ReadStatus), Self::Error> {
+            let length = length.min(self.data.len() - self.offset);
+            buffer[..length].copy_from_slice(&self.data[self.offset..self.offset + length]);
+            self.offset += length;
+            Ok((&buffer[..length], VLFSReadStatus::Ok))
+        }
+    }
+
+    struct TokioDelay;
+
+    impl DelayNs for TokioDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            tokio::time::sleep(std::time::Duration::from_nanos(ns as u64)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lossy_transfer() {
+        let file: std::vec::Vec<u8> = (0..(FILE_TRANSFER_CHUNK_SIZE * 10 + 100))
+            .map(|i| (i * 31 % 251) as u8)
+            .collect();
+        let chunk_count = file.len().div_ceil(FILE_TRANSFER_CHUNK_SIZE) as u32;
+
+        let (mut device_tx, host_rx) = pipe(std::vec![
+            (CHUNK_FRAME_MAGIC, 2, Fault::Drop),
+            (CHUNK_FRAME_MAGIC, 5, Fault::Corrupt),
+            (CHUNK_FRAME_MAGIC, 9, Fault::Corrupt),
+            // the host has to nack after timing out
+            (END_FRAME_MAGIC, chunk_count, Fault::Drop),
+        ]);
+        let (host_tx, mut device_rx) = pipe(std::vec![]);
+        let mut host_serial = SplitableSerialWrapper::new(host_tx, host_rx);
+
+        let device = async {
+            let mut reader = SliceReader {
+                data: &file,
+                offset: 0,
+            };
+            serve_file_stream(
+                &mut device_tx,
+                &mut device_rx,
+                &mut TokioDelay,
+                Some(&mut reader),
+            )
+            .await
+        };
+        let mut received = std::vec::Vec::new();
+        let host = async {
+            let (tx, rx) = host_serial.split();
+            receive_file_stream::<SplitableSerialWrapper<_, _, _>>(
+                tx,
+                rx,
+                &mut TokioDelay,
+                &mut |data| received.extend_from_slice(data),
+            )
+            .await
+        };
+        let (device_result, host_result) = tokio::join!(device, host);
+
+        // end frame acknowledged, no request for the rpc server
+        assert_eq!(device_result, Ok(None));
+        let summary = host_result.unwrap();
+        assert_eq!(summary.length, file.len() as u32);
+        assert_eq!(
+            summary.crc,
+            Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(&file)
+        );
+        assert!(!summary.corrupted);
+        assert_eq!(received, file);
+    }
+}
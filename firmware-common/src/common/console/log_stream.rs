@@ -0,0 +1,305 @@
+//! Device log streaming used by the `DeviceLogStream` rpc.
+//!
+//! After the rpc is requested, the device sends every record in the log ring
+//! buffer followed by a caught up frame, then keeps pushing new records as
+//! they are logged, with a caught up frame after each batch:
+//!
+//! `[magic][seq: u32][length: u16][payload][crc32 of everything before]`
+//!
+//! A record payload is `[timestamp: f64][level: u8][message]`. The host ends
+//! the stream with a stop message, the device answers with an end frame and
+//! goes back to serving rpcs. Any other byte from the host also ends the
+//! stream and is handed back to the rpc server as the start of the next
+//! request, so a host that went away without stopping can still use the
+//! rpc server.
+
+use core::future::Future;
+
+use crc::{Crc, CRC_32_ISO_HDLC, CRC_8_SMBUS};
+use embassy_futures::select::{select, Either};
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Read, ReadExactError, Write};
+use heapless::String;
+
+use crate::{
+    common::device_log::{DeviceLog, DeviceLogLevel, DeviceLogRecord, DEVICE_LOG_MESSAGE_SIZE},
+    driver::serial::SplitableSerial,
+    utils::run_with_timeout,
+};
+
+use super::create_rpc::RpcClientError;
+
+/// Same on every device, like `READ_FILE_STREAM_RPC_ID`
+pub const DEVICE_LOG_STREAM_RPC_ID: u8 = 17;
+
+const FRAME_HEADER_SIZE: usize = 7;
+const RECORD_HEADER_SIZE: usize = 9;
+const MAX_PAYLOAD_SIZE: usize = RECORD_HEADER_SIZE + DEVICE_LOG_MESSAGE_SIZE;
+
+const RECORD_FRAME_MAGIC: u8 = 0xD0;
+const CAUGHT_UP_FRAME_MAGIC: u8 = 0xD1;
+const END_FRAME_MAGIC: u8 = 0xD2;
+const STOP_MESSAGE: u8 = 0xB0;
+
+const FRAME_TIMEOUT_MS: f64 = 1000.0;
+
+#[derive(Debug, Clone, PartialEq)]
+enum LogFrame {
+    Record(DeviceLogRecord),
+    /// All the records before `seq` are sent
+    CaughtUp(u32),
+    End,
+}
+
+async fn write_frame<T: Write>(
+    tx: &mut T,
+    magic: u8,
+    seq: u32,
+    payload: &[u8],
+) -> Result<(), T::Error> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    header[0] = magic;
+    header[1..5].copy_from_slice(&seq.to_le_bytes());
+    header[5..7].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut digest = crc.digest();
+    digest.update(&header);
+    digest.update(payload);
+
+    tx.write_all(&header).await?;
+    tx.write_all(payload).await?;
+    tx.write_all(&digest.finalize().to_le_bytes()).await
+}
+
+async fn write_record_frame<T: Write>(
+    tx: &mut T,
+    record: &DeviceLogRecord,
+) -> Result<(), T::Error> {
+    let message = record.message.as_bytes();
+    let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+    payload[0..8].copy_from_slice(&record.timestamp.to_le_bytes());
+    payload[8] = record.level as u8;
+    payload[RECORD_HEADER_SIZE..(RECORD_HEADER_SIZE + message.len())].copy_from_slice(message);
+    write_frame(
+        tx,
+        RECORD_FRAME_MAGIC,
+        record.seq,
+        &payload[..(RECORD_HEADER_SIZE + message.len())],
+    )
+    .await
+}
+
+/// Skips everything before the next frame magic, returns `None` if the frame
+/// is corrupted
+async fn read_frame<R: Read>(rx: &mut R) -> Result<Option<LogFrame>, ReadExactError<R::Error>> {
+    let mut header = [0u8; FRAME_HEADER_SIZE];
+    loop {
+        rx.read_exact(&mut header[..1]).await?;
+        if matches!(
+            header[0],
+            RECORD_FRAME_MAGIC | CAUGHT_UP_FRAME_MAGIC | END_FRAME_MAGIC
+        ) {
+            break;
+        }
+    }
+    rx.read_exact(&mut header[1..]).await?;
+
+    let seq = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    let length = u16::from_le_bytes([header[5], header[6]]) as usize;
+    if length > MAX_PAYLOAD_SIZE {
+        return Ok(None);
+    }
+
+    let mut buffer = [0u8; MAX_PAYLOAD_SIZE + 4];
+    rx.read_exact(&mut buffer[..(length + 4)]).await?;
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut digest = crc.digest();
+    digest.update(&header);
+    digest.update(&buffer[..length]);
+    let received_crc = u32::from_le_bytes(buffer[length..(length + 4)].try_into().unwrap());
+    if digest.finalize() != received_crc {
+        return Ok(None);
+    }
+
+    let payload = &buffer[..length];
+    let frame = match header[0] {
+        RECORD_FRAME_MAGIC => {
+            if length < RECORD_HEADER_SIZE {
+                return Ok(None);
+            }
+            let Some(level) = DeviceLogLevel::from_u8(payload[8]) else {
+                return Ok(None);
+            };
+            let Ok(message) = core::str::from_utf8(&payload[RECORD_HEADER_SIZE..]) else {
+                return Ok(None);
+            };
+            let mut record_message = String::new();
+            record_message.push_str(message).ok();
+            LogFrame::Record(DeviceLogRecord {
+                seq,
+                timestamp: f64::from_le_bytes(payload[0..8].try_into().unwrap()),
+                level,
+                message: record_message,
+            })
+        }
+        CAUGHT_UP_FRAME_MAGIC => LogFrame::CaughtUp(seq),
+        _ => LogFrame::End,
+    };
+    Ok(Some(frame))
+}
+
+fn stop_message() -> [u8; 2] {
+    [
+        STOP_MESSAGE,
+        Crc::<u8>::new(&CRC_8_SMBUS).checksum(&[STOP_MESSAGE]),
+    ]
+}
+
+/// Device side of the stream, runs until the host stops it. Returns the first
+/// byte of the next rpc request if the host sent one instead of stopping.
+pub async fn serve_device_log_stream<T, R, const N: usize>(
+    tx: &mut T,
+    rx: &mut R,
+    log: &DeviceLog<N>,
+) -> Result<Option<u8>, T::Error>
+where
+    T: Write,
+    R: Read<Error = T::Error>,
+{
+    let mut next_seq = 0u32;
+
+    loop {
+        while let Some(record) = log.read(next_seq) {
+            write_record_frame(tx, &record).await?;
+            next_seq = record.seq.wrapping_add(1);
+        }
+        write_frame(tx, CAUGHT_UP_FRAME_MAGIC, next_seq, &[]).await?;
+
+        let mut message = [0u8; 2];
+        match select(rx.read(&mut message[..1]), log.wait_new_record()).await {
+            Either::First(Ok(0)) => return Ok(None),
+            Either::First(Ok(_)) => {}
+            Either::First(Err(e)) => return Err(e),
+            Either::Second(_) => continue,
+        }
+        if message[0] != STOP_MESSAGE {
+            log_warn!("Device log stream aborted by host");
+            return Ok(Some(message[0]));
+        }
+        match rx.read_exact(&mut message[1..]).await {
+            Ok(_) => {}
+            Err(ReadExactError::UnexpectedEof) => return Ok(None),
+            Err(ReadExactError::Other(e)) => return Err(e),
+        }
+        if message != stop_message() {
+            log_warn!("Device log stream message CRC mismatch, skipping.");
+            continue;
+        }
+
+        write_frame(tx, END_FRAME_MAGIC, next_seq, &[]).await?;
+        return Ok(None);
+    }
+}
+
+/// Host side of the stream, `sink` is called with the records in order.
+///
+/// Returns once the device sent all the buffered records if `follow` is
+/// false, otherwise keeps receiving new records until `stop` completes.
+pub async fn receive_device_log_stream<S: SplitableSerial>(
+    mut tx: S::TX<'_>,
+    mut rx: S::RX<'_>,
+    delay: &mut impl DelayNs,
+    follow: bool,
+    stop: impl Future<Output = ()>,
+    sink: &mut impl FnMut(DeviceLogRecord),
+) -> Result<(), RpcClientError<S>> {
+    let receive_fut = async {
+        // the device answers right away with at least a caught up frame
+        let mut first_frame = true;
+        loop {
+            let frame = if first_frame {
+                match run_with_timeout(delay, FRAME_TIMEOUT_MS, read_frame(&mut rx)).await {
+                    Ok(frame) => frame,
+                    Err(_) => return Err(RpcClientError::Timeout),
+                }
+            } else {
+                read_frame(&mut rx).await
+            };
+            first_frame = false;
+
+            match frame {
+                Ok(Some(LogFrame::Record(record))) => sink(record),
+                Ok(Some(LogFrame::CaughtUp(_))) => {
+                    if !follow {
+                        return Ok(false);
+                    }
+                }
+                // the device ended the stream by itself
+                Ok(Some(LogFrame::End)) => return Ok(true),
+                Ok(None) => {
+                    log_warn!("Device log frame corrupted, skipping.");
+                }
+                Err(e) => return Err(RpcClientError::from(e)),
+            }
+        }
+    };
+    let ended = match select(receive_fut, stop).await {
+        Either::First(result) => result?,
+        Either::Second(_) => false,
+    };
+    if ended {
+        return Ok(());
+    }
+
+    tx.write_all(&stop_message())
+        .await
+        .map_err(RpcClientError::Serial)?;
+    // skip the records logged in the meantime
+    loop {
+        match run_with_timeout(delay, FRAME_TIMEOUT_MS, read_frame(&mut rx)).await {
+            Ok(Ok(Some(LogFrame::End))) => return Ok(()),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(RpcClientError::from(e)),
+            Err(_) => return Err(RpcClientError::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::test_utils::BufferWriter;
+
+    #[futures_test::test]
+    async fn test_frame_round_trip() {
+        let record = DeviceLogRecord {
+            seq: 42,
+            timestamp: 1234.5,
+            level: DeviceLogLevel::Warn,
+            message: String::try_from("Battery low: 3.3V").unwrap(),
+        };
+
+        let mut buffer = [0u8; 1024];
+        let mut writer = BufferWriter::new(&mut buffer);
+        writer.write_all(&[0x12, 0x34]).await.unwrap();
+        write_record_frame(&mut writer, &record).await.unwrap();
+        write_record_frame(&mut writer, &record).await.unwrap();
+        let second_frame_message = writer.offset - 4 - 1;
+        writer.buffer[second_frame_message] ^= 0xFF;
+        write_frame(&mut writer, CAUGHT_UP_FRAME_MAGIC, 43, &[])
+            .await
+            .unwrap();
+        let mut reader = writer.into_reader();
+
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap(),
+            Some(LogFrame::Record(record))
+        );
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+        assert_eq!(
+            read_frame(&mut reader).await.unwrap(),
+            Some(LogFrame::CaughtUp(43))
+        );
+    }
+}
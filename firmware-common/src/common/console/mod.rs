@@ -1,24 +1,31 @@
 use rkyv::{Archive, Deserialize, Serialize};
 
+use crate::common::schema_hash::SchemaHash;
+
 pub mod create_rpc;
+pub mod file_transfer;
+pub mod log_stream;
 pub mod common_rpc_trait;
 pub mod vl_rpc;
 pub mod sg_rpc;
 
-#[derive(Archive, Deserialize, Serialize, Debug, Clone, PartialEq, defmt::Format)]
+#[derive(Archive, Deserialize, Serialize, Debug, Clone, PartialEq, defmt::Format, SchemaHash)]
+#[archive(check_bytes)]
 pub enum DeviceType {
     VoidLake,
     OZYS,
 }
 
-#[derive(Archive, Deserialize, Serialize, Debug, Clone, PartialEq, defmt::Format)]
+#[derive(Archive, Deserialize, Serialize, Debug, Clone, PartialEq, defmt::Format, SchemaHash)]
+#[archive(check_bytes)]
 pub enum OpenFileStatus {
     Sucess,
     DoesNotExist,
     Error,
 }
 
-#[derive(Archive, Deserialize, Serialize, Debug, Clone, PartialEq, defmt::Format)]
+#[derive(Archive, Deserialize, Serialize, Debug, Clone, PartialEq, defmt::Format, SchemaHash)]
+#[archive(check_bytes)]
 pub struct ReadFileResult {
     pub data: [u8; 128],
     pub length: u8,
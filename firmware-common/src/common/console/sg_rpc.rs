@@ -1,4 +1,7 @@
 use crate::common::console::DeviceType;
+use crate::common::console::file_transfer::{serve_file_stream, READ_FILE_STREAM_RPC_ID};
+use crate::common::console::log_stream::{serve_device_log_stream, DEVICE_LOG_STREAM_RPC_ID};
+use crate::common::device_log::DEVICE_LOG;
 use crate::common::console::OpenFileStatus;
 use crate::common::console::ReadFileResult;
 use crate::common::vl_device_manager::prelude::*;
@@ -17,6 +20,7 @@ create_rpc! {
     state<F: Flash, C: Crc, D: SysReset>(
         fs: &VLFS<F, C>,
         sys_reset: &D,
+        delay: impl Delay,
         device_serial_number: &[u8; 12],
         sg_adc_controller: &Mutex::<NoopRawMutex, impl SGAdcController>,
         states: &SGGlobalStates<impl RawMutex, impl RawSGReadingsTrait>
@@ -94,23 +98,23 @@ create_rpc! {
         file_iter = Some(fs.concurrent_files_iter(file_type.map(FileType)).await);
         StartListFilesResponse {}
     }
-    rpc 6 GetListedFile | | -> (file_id: Option<u64>) {
+    rpc 6 GetListedFile | | -> (file: Option<(u64, u16)>) {
         if let Some(file_iter) = &mut file_iter {
             match file_iter.next().await {
                 Ok(Some(file)) => {
                     GetListedFileResponse {
-                        file_id: Some(file.id.0),
+                        file: Some((file.id.0, file.typ.0)),
                     }
                 }
                 Ok(None) => {
-                    GetListedFileResponse { file_id: None }
+                    GetListedFileResponse { file: None }
                 }
                 Err(_) => {
-                    GetListedFileResponse { file_id: None }
+                    GetListedFileResponse { file: None }
                 }
             }
         }else{
-            GetListedFileResponse { file_id: None }
+            GetListedFileResponse { file: None }
         }
     }
     rpc 7 ResetDevice | | -> () {
@@ -134,6 +138,13 @@ create_rpc! {
             sample: realtime_sample_sub.try_next_message_pure()
         }
     }
+    stream READ_FILE_STREAM_RPC_ID, ReadFileStream |tx, rx| {
+        let mut delay = delay.clone();
+        serve_file_stream(tx, rx, &mut delay, reader.as_mut()).await?
+    }
+    stream DEVICE_LOG_STREAM_RPC_ID, DeviceLogStream |tx, rx| {
+        serve_device_log_stream(tx, rx, &DEVICE_LOG).await?
+    }
 }
 
 impl_common_rpc_trait!(RpcClient);
\ No newline at end of file
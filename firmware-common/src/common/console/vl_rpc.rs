@@ -1,24 +1,38 @@
 use crate::avionics::flight_profile::FlightProfile;
+use crate::common::can_bus::node_registry::{
+    CanNodeInfo, CanNodeRegistry, CanNodeStatus, MAX_CAN_NODES,
+};
+use crate::common::can_bus::statistics::CanBusStatistics;
 use crate::common::config_file::ConfigFile;
 use crate::common::console::DeviceType;
+use crate::common::console::file_transfer::{serve_file_stream, READ_FILE_STREAM_RPC_ID};
+use crate::common::console::log_stream::{serve_device_log_stream, DEVICE_LOG_STREAM_RPC_ID};
 use crate::common::console::OpenFileStatus;
 use crate::common::console::ReadFileResult;
 use crate::common::device_config::DeviceConfig;
+use crate::common::device_log::DEVICE_LOG;
 use crate::common::file_types::{DEVICE_CONFIG_FILE_TYPE, FLIGHT_PROFILE_FILE_TYPE};
 use crate::common::rkyv_structs::RkyvString;
 use crate::common::rpc_channel::RpcChannelClient;
+use crate::common::schema_hash::SchemaHash;
 use crate::common::vl_device_manager::prelude::*;
 use crate::common::vlp::packet::VLPDownlinkPacket;
 use crate::common::vlp::packet::VLPUplinkPacket;
 use crate::create_rpc;
+use crate::gcm::{GCMDeviceState, GCMDeviceTable, GCMRecordingStatus, MAX_GCM_DEVICES};
 use crate::impl_common_rpc_trait;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Receiver};
+use core::cell::RefCell;
+use embassy_sync::{
+    blocking_mutex::{raw::NoopRawMutex, Mutex as BlockingMutex},
+    channel::Receiver,
+};
 use lora_phy::mod_params::PacketStatus;
 use rkyv::{Archive, Deserialize, Serialize};
 use vlfs::ConcurrentFilesIterator;
 use vlfs::{AsyncReader, Crc, FileID, FileReader, FileType, Flash, VLFSError, VLFSReadStatus};
 
-#[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize)]
+#[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize, SchemaHash)]
+#[archive(check_bytes)]
 pub struct RpcPacketStatus {
     pub rssi: i16,
     pub snr: i16,
@@ -38,13 +52,17 @@ create_rpc! {
         services: &VLSystemServices<'_, '_, '_, '_, impl Delay, impl Clock, F, C, D>,
         config: &Option<DeviceConfig>,
         device_serial_number: &[u8; 12],
-        downlink_package_receiver: Receiver<'_, NoopRawMutex, (VLPDownlinkPacket, PacketStatus), 1>,
+        downlink_package_receiver: Receiver<'_, NoopRawMutex, (u8, VLPDownlinkPacket, PacketStatus), 1>,
         send_uplink_packet_rpc_client: RpcChannelClient<
             '_,
             NoopRawMutex,
-            VLPUplinkPacket,
+            (u8, VLPUplinkPacket),
             Option<PacketStatus>,
-        >
+        >,
+        can_node_registry: &BlockingMutex<NoopRawMutex, RefCell<CanNodeRegistry>>,
+        can_bus_statistics: &BlockingMutex<NoopRawMutex, RefCell<CanBusStatistics>>,
+        gcm_recording_status: &BlockingMutex<NoopRawMutex, RefCell<GCMRecordingStatus>>,
+        gcm_devices: &BlockingMutex<NoopRawMutex, RefCell<GCMDeviceTable>>
     ) {
         let mut send_uplink_packet_rpc_client = send_uplink_packet_rpc_client;
         let fs = &services.fs;
@@ -122,34 +140,34 @@ create_rpc! {
         file_iter = Some(fs.concurrent_files_iter(file_type.map(FileType)).await);
         StartListFilesResponse {}
     }
-    rpc 6 GetListedFile | | -> (file_id: Option<u64>) {
+    rpc 6 GetListedFile | | -> (file: Option<(u64, u16)>) {
         if let Some(file_iter) = &mut file_iter {
             match file_iter.next().await {
                 Ok(Some(file)) => {
                     GetListedFileResponse {
-                        file_id: Some(file.id.0),
+                        file: Some((file.id.0, file.typ.0)),
                     }
                 }
                 Ok(None) => {
-                    GetListedFileResponse { file_id: None }
+                    GetListedFileResponse { file: None }
                 }
                 Err(_) => {
-                    GetListedFileResponse { file_id: None }
+                    GetListedFileResponse { file: None }
                 }
             }
         } else {
-            GetListedFileResponse { file_id: None }
+            GetListedFileResponse { file: None }
         }
     }
-    rpc 7 GCMSendUplinkPacket |packet: VLPUplinkPacket| -> (status: Option<RpcPacketStatus>) {
-        let status = send_uplink_packet_rpc_client.call(packet).await;
+    rpc 7 GCMSendUplinkPacket |device_id: u8, packet: VLPUplinkPacket| -> (status: Option<RpcPacketStatus>) {
+        let status = send_uplink_packet_rpc_client.call((device_id, packet)).await;
         GCMSendUplinkPacketResponse {
             status: status.map(|status|status.into())
         }
     }
-    rpc 8 GCMPollDownlinkPacket | | -> (packet: Option<(VLPDownlinkPacket, RpcPacketStatus)>) {
+    rpc 8 GCMPollDownlinkPacket | | -> (packet: Option<(u8, VLPDownlinkPacket, RpcPacketStatus)>) {
         GCMPollDownlinkPacketResponse {
-            packet: downlink_package_receiver.try_receive().ok().map(|(packet, status)|(packet, status.into()))
+            packet: downlink_package_receiver.try_receive().ok().map(|(device_id, packet, status)|(device_id, packet, status.into()))
         }
     }
     rpc 9 SetFlightProfile |
@@ -172,6 +190,57 @@ create_rpc! {
         services.reset();
         ResetDeviceResponse {}
     }
+    rpc 12 GetCanNodes | | -> (
+        nodes: [Option<(CanNodeInfo, CanNodeStatus)>; MAX_CAN_NODES],
+        self_id_collision: bool
+    ) {
+        let now = services.clock.now_ms();
+        can_node_registry.lock(|r| {
+            let r = r.borrow();
+            GetCanNodesResponse {
+                nodes: r.table(now),
+                self_id_collision: r.self_id_collision(),
+            }
+        })
+    }
+    rpc 13 GetCanBusStatistics | | -> (statistics: CanBusStatistics) {
+        GetCanBusStatisticsResponse {
+            statistics: can_bus_statistics.lock(|s| s.borrow().clone()),
+        }
+    }
+    rpc 15 GetFlightProfile | | -> (flight_profile: Option<FlightProfile>) {
+        let flight_profile_file = ConfigFile::<FlightProfile, _, _>::new(services.fs, FLIGHT_PROFILE_FILE_TYPE);
+        GetFlightProfileResponse {
+            flight_profile: flight_profile_file.read().await,
+        }
+    }
+    rpc 16 GetDeviceConfig | | -> (device_config: Option<DeviceConfig>) {
+        let device_config_file = ConfigFile::<DeviceConfig, _, _>::new(services.fs, DEVICE_CONFIG_FILE_TYPE);
+        GetDeviceConfigResponse {
+            device_config: device_config_file.read().await,
+        }
+    }
+    rpc 18 GCMGetRecordingStatus | | -> (file_id: Option<u64>, recorded_packets: u32) {
+        gcm_recording_status.lock(|status| {
+            let status = status.borrow();
+            GCMGetRecordingStatusResponse {
+                file_id: status.file_id,
+                recorded_packets: status.recorded_packets,
+            }
+        })
+    }
+    rpc 19 GCMGetDevices | | -> (devices: [Option<(u8, GCMDeviceState)>; MAX_GCM_DEVICES]) {
+        GCMGetDevicesResponse {
+            devices: gcm_devices.lock(|devices| devices.borrow().table()),
+        }
+    }
+    stream READ_FILE_STREAM_RPC_ID, ReadFileStream |tx, rx| {
+        let mut delay = services.delay();
+        serve_file_stream(tx, rx, &mut delay, reader.as_mut()).await?
+    }
+    stream DEVICE_LOG_STREAM_RPC_ID, DeviceLogStream |tx, rx| {
+        serve_device_log_stream(tx, rx, &DEVICE_LOG).await?
+    }
 }
 
 impl_common_rpc_trait!(RpcClient);
\ No newline at end of file
@@ -13,6 +13,13 @@ impl<const N: usize> BitSliceWriter<N> {
         self.len_bits += T::len_bits();
     }
 
+    /// Writes the lowest `bits` bits of `value`, for fields whose width is
+    /// only known at runtime
+    pub fn write_bits(&mut self, value: u32, bits: usize) {
+        self.data[self.len_bits..(self.len_bits + bits)].store_le(value);
+        self.len_bits += bits;
+    }
+
     /// returns a slice of the full bytes
     /// e.g. if the length is 20 bits, this will return the first 2 bytes
     pub fn view_full_byte_slice(&self) -> &[u8] {
@@ -134,6 +141,15 @@ impl<const N: usize> BitSliceReader<N> {
         Some(data)
     }
 
+    pub fn read_bits(&mut self, bits: usize) -> Option<u32> {
+        if self.len_bits() < bits {
+            return None;
+        }
+        let data = self.buffer[self.start..(self.start + bits)].load_le::<u32>();
+        self.start += bits;
+        Some(data)
+    }
+
     pub fn skip_byte(&mut self) {
         self.start = (self.start + 7) / 8 * 8;
     }
@@ -152,6 +168,11 @@ mod test {
     use core::convert::Infallible;
 
     use super::*;
+    use crate::common::delta_logger::prelude::{BitArraySerializable, Deltable};
+    use crate::common::fixed_point::F32FixedPointFactory;
+    use crate::common::variable_int::VariableIntTrait;
+    use crate::common::vlp::packet::ConfigUpdateType;
+    use crate::fixed_point_factory;
 
     #[test]
     fn test_bit_slice_reader() {
@@ -258,4 +279,74 @@ mod test {
         assert_eq!(reader.len_bits(), 8);
         assert_eq!(reader.read::<u8>(), Some(0b11111110));
     }
+
+    fixed_point_factory!(TestFac, f32, -10.0, 10.0, 0.01);
+
+    #[derive(defmt::Format, Debug, Clone, BitArraySerializable)]
+    struct DerivedPacket {
+        flag: bool,
+        #[bits(5)]
+        small: u8,
+        #[bits(1, int_enum)]
+        config_type: ConfigUpdateType,
+        #[fixed_point(TestFac)]
+        packed: [f32; 2],
+    }
+
+    #[derive(defmt::Format, Debug, Clone, BitArraySerializable, Deltable)]
+    struct DerivedSensorData {
+        #[delta(TestFac)]
+        value: f32,
+        #[delta(TestFac)]
+        values: [f32; 2],
+    }
+
+    #[test]
+    fn test_derive_bit_array_serializable() {
+        let packet = DerivedPacket {
+            flag: true,
+            small: 17,
+            config_type: ConfigUpdateType::DeviceConfig,
+            packed: [1.5, -2.25],
+        };
+        let packed_bits =
+            <<TestFac as F32FixedPointFactory>::VI as VariableIntTrait>::Packed::len_bits();
+        assert_eq!(DerivedPacket::len_bits(), 1 + 5 + 1 + packed_bits * 2);
+
+        let mut writer = BitSliceWriter::<16>::default();
+        packet.serialize(&mut writer);
+        assert_eq!(writer.len_bits, DerivedPacket::len_bits());
+
+        let mut reader = BitSliceReader::<16>::default();
+        reader.replenish_bytes(writer.view_all_data_slice());
+        let deserialized = DerivedPacket::deserialize(&mut reader);
+        assert_eq!(deserialized.flag, true);
+        assert_eq!(deserialized.small, 17);
+        assert_eq!(deserialized.config_type, ConfigUpdateType::DeviceConfig);
+        assert!((deserialized.packed[0] - 1.5).abs() <= 0.01);
+        assert!((deserialized.packed[1] + 2.25).abs() <= 0.01);
+    }
+
+    #[test]
+    fn test_derive_deltable() {
+        let a = DerivedSensorData {
+            value: 100.0,
+            values: [1.0, 2.0],
+        };
+        let b = DerivedSensorData {
+            value: 100.5,
+            values: [1.0, -3.0],
+        };
+        let delta = b.subtract(&a).unwrap();
+        let restored = a.add_delta(&delta).unwrap();
+        assert!((restored.value - b.value).abs() <= 0.01);
+        assert!((restored.values[0] - b.values[0]).abs() <= 0.01);
+        assert!((restored.values[1] - b.values[1]).abs() <= 0.01);
+
+        let c = DerivedSensorData {
+            value: 100.0,
+            values: [1.0, 20.0],
+        };
+        assert!(c.subtract(&a).is_none());
+    }
 }
@@ -4,13 +4,14 @@ use either::Either;
 
 use crate::{
     common::{
-        embedded_error_wrapper::EmbeddedErrorWrapper, fixed_point::F64FixedPointFactory, sensor_reading::{SensorData, SensorReading}, variable_int::VariableIntTrait
+        embedded_error_wrapper::EmbeddedErrorWrapper, fixed_point::F64FixedPointFactory, sensor_reading::{SensorData, SensorReading}, variable_int::VariableIntTrait, versioned::Versioned
     },
     driver::timestamp::BootTimestamp,
 };
 use core::mem::size_of;
 use super::{bitslice_serialize::{BitArraySerializable, BitSliceReader, BitSliceWriter}, prelude::DeltaLoggerTrait};
 use super::delta_factory::{DeltaFactory, Deltable, UnDeltaFactory};
+use super::header::DeltaLogHeader;
 use crate::common::delta_logger::bitslice_primitive::BitSlicePrimitive;
 
 #[derive(Debug, Clone)]
@@ -65,11 +66,14 @@ impl Header {
 }
 
 #[derive(defmt::Format, Debug, Clone, rkyv::Archive, rkyv::Deserialize, rkyv::Serialize, PartialEq)]
+#[archive(check_bytes)]
 pub struct UnixTimestampLog {
     pub boot_timestamp: f64,
     pub unix_timestamp: f64,
 }
 
+impl Versioned for UnixTimestampLog {}
+
 impl BitArraySerializable for UnixTimestampLog {
     fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
         writer.write(self.boot_timestamp);
@@ -90,6 +94,8 @@ impl BitArraySerializable for UnixTimestampLog {
 
 /// If the readings are closer than the minimum value supported by the
 /// fixed point factory, they will be ignored
+///
+/// A `DeltaLogHeader` is written before the first entry
 pub struct DeltaLogger<D, W, FF>
 where
     D: SensorData,
@@ -103,6 +109,8 @@ where
     bit_writer: BitSliceWriter<{ size_of::<D>() + 10 }>,
     last_entry_is_unix_time: bool,
     unix_time_log_buffer: Option<UnixTimestampLog>,
+    serial_number: [u8; 12],
+    header_written: bool,
 }
 
 impl<D, W, FF> DeltaLogger<D, W, FF>
@@ -112,7 +120,7 @@ where
     FF: F64FixedPointFactory,
     [(); size_of::<D>() + 10]:,
 {
-    pub fn new(writer: W) -> Self {
+    pub fn new(writer: W, serial_number: [u8; 12]) -> Self {
         Self {
             factory: DeltaFactory::new(),
             timestamp_factory: DeltaFactory::new(),
@@ -120,7 +128,18 @@ where
             bit_writer: Default::default(),
             last_entry_is_unix_time: false,
             unix_time_log_buffer: None,
+            serial_number,
+            header_written: false,
+        }
+    }
+
+    async fn ensure_header_written(&mut self) -> Result<(), EmbeddedErrorWrapper<W::Error>> {
+        if !self.header_written {
+            let header = DeltaLogHeader::new::<D, FF>(self.serial_number);
+            self.writer.write_all(&header.serialize()).await?;
+            self.header_written = true;
         }
+        Ok(())
     }
 }
 
@@ -141,6 +160,7 @@ where
             }
         }
 
+        self.ensure_header_written().await?;
         if let Some(unix_time_log) = self.unix_time_log_buffer.take() {
             self.log_unix_time(unix_time_log).await?;
         }
@@ -181,6 +201,7 @@ where
             return Ok(());
         }
 
+        self.ensure_header_written().await?;
         Header::UnixTimeStampLog.serialize(&mut self.bit_writer);
         log.serialize(&mut self.bit_writer);
         self.writer
@@ -193,6 +214,7 @@ where
     }
 
     async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.ensure_header_written().await?;
         Header::EndOfByte.serialize(&mut self.bit_writer);
         self.writer
             .write_all(self.bit_writer.view_all_data_slice())
@@ -218,6 +240,23 @@ where
     timestamp_factory: UnDeltaFactory<Timestamp<FF>>,
     reader: R,
     bit_reader: BitSliceReader<{ size_of::<D>() + 10 }>,
+    header_read: bool,
+    /// None for legacy files written before the header existed
+    header: Option<DeltaLogHeader>,
+    /// Bytes read while looking for the header that turned out to be entries
+    /// of a legacy file
+    prefix: [u8; DeltaLogHeader::SIZE],
+    prefix_start: usize,
+    prefix_end: usize,
+}
+
+#[derive(defmt::Format, Debug)]
+pub enum DeltaLoggerReaderError<E> {
+    Read(E),
+    /// The header has an unsupported format version
+    InvalidHeader,
+    /// The file is written with another `SensorData` type or timestamp fixed point factory
+    SchemaMismatch(DeltaLogHeader),
 }
 
 enum DeltaLoggerReaderResult<D>
@@ -242,14 +281,64 @@ where
             timestamp_factory: UnDeltaFactory::new(),
             reader,
             bit_reader: Default::default(),
+            header_read: false,
+            header: None,
+            prefix: [0u8; DeltaLogHeader::SIZE],
+            prefix_start: 0,
+            prefix_end: 0,
         }
     }
 
+    /// Reads the header if it is not read yet, fails if the file can't be
+    /// decoded with `D` and `F`.
+    ///
+    /// Returns None for legacy files without a header, they are assumed to be
+    /// written with `D` and `F`.
+    pub async fn read_header(
+        &mut self,
+    ) -> Result<Option<&DeltaLogHeader>, DeltaLoggerReaderError<R::Error>> {
+        if !self.header_read {
+            // the header is byte aligned and larger than the bit reader buffer,
+            // read it directly
+            while self.prefix_end < DeltaLogHeader::SIZE {
+                let read_bytes = self
+                    .reader
+                    .read(&mut self.prefix[self.prefix_end..])
+                    .await
+                    .map_err(DeltaLoggerReaderError::Read)?;
+                if read_bytes == 0 {
+                    break;
+                }
+                self.prefix_end += read_bytes;
+            }
+            self.header_read = true;
+
+            if self.prefix_end == DeltaLogHeader::SIZE && DeltaLogHeader::has_magic(&self.prefix) {
+                let header = DeltaLogHeader::deserialize(&self.prefix)
+                    .ok_or(DeltaLoggerReaderError::InvalidHeader)?;
+                if !header.matches::<D, F>() {
+                    return Err(DeltaLoggerReaderError::SchemaMismatch(header));
+                }
+                self.header = Some(header);
+                self.prefix_end = 0;
+            }
+        }
+        Ok(self.header.as_ref())
+    }
+
     pub async fn read(
         &mut self,
-    ) -> Result<Option<Either<SensorReading<BootTimestamp, D>, UnixTimestampLog>>, R::Error> {
+    ) -> Result<
+        Option<Either<SensorReading<BootTimestamp, D>, UnixTimestampLog>>,
+        DeltaLoggerReaderError<R::Error>,
+    > {
+        self.read_header().await?;
         loop {
-            match self.inner_read().await? {
+            match self
+                .inner_read()
+                .await
+                .map_err(DeltaLoggerReaderError::Read)?
+            {
                 DeltaLoggerReaderResult::EOF => {
                     return Ok(None);
                 }
@@ -361,7 +450,17 @@ where
         while self.bit_reader.len_bits() < min_bits {
             let read_bytes = self
                 .bit_reader
-                .replenish_bytes_async_mut(async |buffer| self.reader.read(buffer).await)
+                .replenish_bytes_async_mut(async |buffer| {
+                    if self.prefix_start < self.prefix_end {
+                        let length = buffer.len().min(self.prefix_end - self.prefix_start);
+                        buffer[..length]
+                            .copy_from_slice(&self.prefix[self.prefix_start..][..length]);
+                        self.prefix_start += length;
+                        Ok(length)
+                    } else {
+                        self.reader.read(buffer).await
+                    }
+                })
                 .await?;
             if read_bytes == 0 {
                 return Ok(false);
@@ -412,9 +511,9 @@ mod test {
             ))
         }
 
-        let mut buffer = [0u8; 512];
+        let mut buffer = [0u8; 1024];
         let writer = BufferWriter::new(&mut buffer);
-        let mut logger = DeltaLogger::<_, _, TimestampFac>::new(writer);
+        let mut logger = DeltaLogger::<_, _, TimestampFac>::new(writer, [0x42; 12]);
         for reading in readings.iter() {
             logger.log(reading.clone()).await.unwrap();
         }
@@ -444,4 +543,63 @@ mod test {
         }
         assert_eq!(i, readings.len());
     }
+
+    #[tokio::test]
+    async fn test_delta_logger_schema_mismatch() {
+        fixed_point_factory!(TimestampFac, f64, 0.0, 510.0, 0.5);
+        fixed_point_factory!(OtherTimestampFac, f64, 0.0, 510.0, 0.1);
+
+        let mut buffer = [0u8; 128];
+        let writer = BufferWriter::new(&mut buffer);
+        let mut logger = DeltaLogger::<_, _, TimestampFac>::new(writer, [0x42; 12]);
+        logger
+            .log(SensorReading::<BootTimestamp, ADCData<Volt>>::new(0.0, ADCData::new(1.0)))
+            .await
+            .unwrap();
+        logger.flush().await.unwrap();
+        let reader = logger.into_inner().await.unwrap().into_reader();
+
+        let mut log_reader = DeltaLoggerReader::<ADCData<Volt>, _, OtherTimestampFac>::new(reader);
+        match log_reader.read().await {
+            Err(DeltaLoggerReaderError::SchemaMismatch(header)) => {
+                assert_eq!(header.serial_number, [0x42; 12]);
+                assert_eq!(header.schema_id, ADCData::<Volt>::SCHEMA_ID);
+            }
+            _ => panic!("expected schema mismatch"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delta_logger_read_legacy() {
+        fixed_point_factory!(TimestampFac, f64, 0.0, 510.0, 0.5);
+
+        let mut buffer = [0u8; 1024];
+        let writer = BufferWriter::new(&mut buffer);
+        let mut logger = DeltaLogger::<_, _, TimestampFac>::new(writer, [0x42; 12]);
+        for i in 0..20 {
+            logger
+                .log(SensorReading::<BootTimestamp, ADCData<Volt>>::new(
+                    i as f64 * 100.0,
+                    ADCData::new(i as f32 * 0.1),
+                ))
+                .await
+                .unwrap();
+        }
+        logger.flush().await.unwrap();
+
+        // files written before the header existed start with the first entry
+        let mut reader = logger.into_inner().await.unwrap().into_reader();
+        reader.offset = DeltaLogHeader::SIZE;
+
+        let mut log_reader = DeltaLoggerReader::<ADCData<Volt>, _, TimestampFac>::new(reader);
+        assert!(log_reader.read_header().await.unwrap().is_none());
+        let mut i = 0usize;
+        while let Some(reading) = log_reader.read().await.unwrap() {
+            let reading = reading.unwrap_left();
+            assert_relative_eq!(reading.timestamp, i as f64 * 100.0, epsilon = 0.5);
+            assert_relative_eq!(reading.data.value, i as f32 * 0.1, epsilon = 0.1);
+            i += 1;
+        }
+        assert_eq!(i, 20);
+    }
 }
@@ -0,0 +1,211 @@
+//! Header at the start of every delta log file.
+//!
+//! The header records which `SensorData` type and which timestamp fixed point
+//! factory the file is written with, so the reader can refuse files it would
+//! decode into garbage.
+
+use crate::common::{
+    can_bus::node_registry::FIRMWARE_VERSION, fixed_point::F64FixedPointFactory,
+    sensor_reading::SensorData,
+};
+
+pub const DELTA_LOG_MAGIC: [u8; 4] = *b"VLDL";
+/// Version of the header and the entry encoding, not of the sensor data
+pub const DELTA_LOG_FORMAT_VERSION: u8 = 1;
+
+/// Declares the schema id constants and `DELTA_LOG_SCHEMAS` from one list,
+/// with a compile time check that the ids are unique
+macro_rules! delta_log_schemas {
+    ($(($id:literal, $const_name:ident, $name:literal)),* $(,)?) => {
+        $(
+            pub const $const_name: u8 = $id;
+        )*
+
+        /// (schema id, name) of every `SensorData` type
+        pub const DELTA_LOG_SCHEMAS: &[(u8, &str)] = &[$(($id, $name)),*];
+
+        const _: () = {
+            let ids = [$($id),*];
+            let mut i = 0;
+            while i < ids.len() {
+                let mut j = i + 1;
+                while j < ids.len() {
+                    if ids[i] == ids[j] {
+                        panic!("Duplicated delta log schema id");
+                    }
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+    };
+}
+
+// ids are stored in the files, never change or reuse them. New `SensorData`
+// types must be added here and use their constant as `SCHEMA_ID`
+delta_log_schemas! {
+    (1, BARO_DATA_SCHEMA_ID, "BaroData"),
+    (2, IMU_DATA_SCHEMA_ID, "IMUData"),
+    (3, GPS_DATA_SCHEMA_ID, "GPSData"),
+    (4, MAG_DATA_SCHEMA_ID, "MagData"),
+    (5, ADC_VOLT_DATA_SCHEMA_ID, "ADCData<Volt>"),
+    (6, ADC_AMPERE_DATA_SCHEMA_ID, "ADCData<Ampere>"),
+    // `Quantized` sensor data, the quantizer defines the error bounds so every
+    // quantizer needs its own id
+    (7, QUANTIZED_IMU_TIER_1_SCHEMA_ID, "Quantized<IMUQuantizerTier1>"),
+    (8, QUANTIZED_IMU_TIER_2_SCHEMA_ID, "Quantized<IMUQuantizerTier2>"),
+    (9, QUANTIZED_BARO_TIER_1_SCHEMA_ID, "Quantized<BaroQuantizerTier1>"),
+    (10, QUANTIZED_BARO_TIER_2_SCHEMA_ID, "Quantized<BaroQuantizerTier2>"),
+}
+
+pub fn delta_log_schema_name(schema_id: u8) -> Option<&'static str> {
+    DELTA_LOG_SCHEMAS
+        .iter()
+        .find(|(id, _)| *id == schema_id)
+        .map(|(_, name)| *name)
+}
+
+#[derive(defmt::Format, Debug, Clone, PartialEq)]
+pub struct DeltaLogHeader {
+    pub schema_id: u8,
+    pub schema_version: u8,
+    /// parameters of the timestamp fixed point factory
+    pub timestamp_min: f64,
+    pub timestamp_max: f64,
+    pub timestamp_max_error: f64,
+    /// major, minor, patch
+    pub firmware_version: [u8; 3],
+    pub serial_number: [u8; 12],
+}
+
+impl DeltaLogHeader {
+    pub const SIZE: usize = 4 + 1 + 1 + 1 + 8 * 3 + 3 + 12;
+
+    pub fn new<D: SensorData, FF: F64FixedPointFactory>(serial_number: [u8; 12]) -> Self {
+        Self {
+            schema_id: D::SCHEMA_ID,
+            schema_version: D::SCHEMA_VERSION,
+            timestamp_min: FF::min(),
+            timestamp_max: FF::max(),
+            timestamp_max_error: FF::max_error(),
+            firmware_version: FIRMWARE_VERSION,
+            serial_number,
+        }
+    }
+
+    pub fn serialize(&self) -> [u8; Self::SIZE] {
+        let mut buffer = [0u8; Self::SIZE];
+        buffer[0..4].copy_from_slice(&DELTA_LOG_MAGIC);
+        buffer[4] = DELTA_LOG_FORMAT_VERSION;
+        buffer[5] = self.schema_id;
+        buffer[6] = self.schema_version;
+        buffer[7..15].copy_from_slice(&self.timestamp_min.to_le_bytes());
+        buffer[15..23].copy_from_slice(&self.timestamp_max.to_le_bytes());
+        buffer[23..31].copy_from_slice(&self.timestamp_max_error.to_le_bytes());
+        buffer[31..34].copy_from_slice(&self.firmware_version);
+        buffer[34..46].copy_from_slice(&self.serial_number);
+        buffer
+    }
+
+    /// Whether the buffer starts like a delta log header of any format version,
+    /// files written before the header existed don't
+    pub fn has_magic(buffer: &[u8; Self::SIZE]) -> bool {
+        buffer[0..4] == DELTA_LOG_MAGIC
+    }
+
+    /// Returns None if the buffer is not a delta log header of a supported format version
+    pub fn deserialize(buffer: &[u8; Self::SIZE]) -> Option<Self> {
+        if buffer[0..4] != DELTA_LOG_MAGIC || buffer[4] != DELTA_LOG_FORMAT_VERSION {
+            return None;
+        }
+        Some(Self {
+            schema_id: buffer[5],
+            schema_version: buffer[6],
+            timestamp_min: f64::from_le_bytes(buffer[7..15].try_into().unwrap()),
+            timestamp_max: f64::from_le_bytes(buffer[15..23].try_into().unwrap()),
+            timestamp_max_error: f64::from_le_bytes(buffer[23..31].try_into().unwrap()),
+            firmware_version: buffer[31..34].try_into().unwrap(),
+            serial_number: buffer[34..46].try_into().unwrap(),
+        })
+    }
+
+    /// Whether the file can be decoded with `D` and `FF`
+    pub fn matches<D: SensorData, FF: F64FixedPointFactory>(&self) -> bool {
+        self.schema_id == D::SCHEMA_ID
+            && self.schema_version == D::SCHEMA_VERSION
+            && self.timestamp_min == FF::min()
+            && self.timestamp_max == FF::max()
+            && self.timestamp_max_error == FF::max_error()
+    }
+
+    pub fn schema_name(&self) -> Option<&'static str> {
+        delta_log_schema_name(self.schema_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::delta_logger::quantized::Quantized,
+        driver::{
+            adc::{ADCData, Ampere, Volt},
+            barometer::{BaroData, BaroQuantizerTier1, BaroQuantizerTier2},
+            gps::GPSData,
+            imu::{IMUData, IMUQuantizerTier1, IMUQuantizerTier2},
+            mag::MagData,
+        },
+        fixed_point_factory,
+    };
+
+    #[test]
+    fn test_serialize_deserialize() {
+        fixed_point_factory!(TimestampFac, f64, 4.9, 7.0, 0.05);
+        fixed_point_factory!(OtherTimestampFac, f64, 199.0, 210.0, 0.5);
+
+        let header = DeltaLogHeader::new::<BaroData, TimestampFac>([7; 12]);
+        let deserialized = DeltaLogHeader::deserialize(&header.serialize()).unwrap();
+        assert_eq!(deserialized, header);
+        assert_eq!(deserialized.schema_name(), Some("BaroData"));
+        assert!(deserialized.matches::<BaroData, TimestampFac>());
+        assert!(!deserialized.matches::<BaroData, OtherTimestampFac>());
+
+        let mut buffer = header.serialize();
+        buffer[0] = 0;
+        assert!(DeltaLogHeader::deserialize(&buffer).is_none());
+        assert!(!DeltaLogHeader::has_magic(&buffer));
+    }
+
+    #[test]
+    fn test_sensor_data_schema_ids() {
+        // every `SensorData` implementation, by the name it is registered with
+        let impls = [
+            (BaroData::SCHEMA_ID, "BaroData"),
+            (IMUData::SCHEMA_ID, "IMUData"),
+            (GPSData::SCHEMA_ID, "GPSData"),
+            (MagData::SCHEMA_ID, "MagData"),
+            (ADCData::<Volt>::SCHEMA_ID, "ADCData<Volt>"),
+            (ADCData::<Ampere>::SCHEMA_ID, "ADCData<Ampere>"),
+            (
+                Quantized::<IMUQuantizerTier1>::SCHEMA_ID,
+                "Quantized<IMUQuantizerTier1>",
+            ),
+            (
+                Quantized::<IMUQuantizerTier2>::SCHEMA_ID,
+                "Quantized<IMUQuantizerTier2>",
+            ),
+            (
+                Quantized::<BaroQuantizerTier1>::SCHEMA_ID,
+                "Quantized<BaroQuantizerTier1>",
+            ),
+            (
+                Quantized::<BaroQuantizerTier2>::SCHEMA_ID,
+                "Quantized<BaroQuantizerTier2>",
+            ),
+        ];
+        assert_eq!(impls.len(), DELTA_LOG_SCHEMAS.len());
+        for (schema_id, name) in impls {
+            assert_eq!(delta_log_schema_name(schema_id), Some(name));
+        }
+    }
+}
@@ -6,12 +6,15 @@ pub mod bitslice_primitive;
 pub mod bitslice_serialize;
 pub mod delta_factory;
 pub mod delta_logger;
+pub mod header;
+pub mod quantized;
 pub mod ring_delta_logger;
 pub mod tiered_ring_delta_logger;
 pub mod ring_file_writer;
 pub mod buffered_logger;
 pub mod delta_logger_trait;
 pub mod merged_logger;
+pub mod timestamp_factories;
 
 pub mod prelude {
     pub use super::bitslice_serialize::{BitArraySerializable, BitSliceReader, BitSliceWriter};
@@ -19,4 +22,5 @@ pub mod prelude {
     pub use super::ring_file_writer::RingFileWriter;
     pub use super::delta_logger_trait::DeltaLoggerTrait;
     pub use super::bitslice_primitive::BitSlicePrimitive;
+    pub use bitslice_serialize_derive::{BitArraySerializable, Deltable};
 }
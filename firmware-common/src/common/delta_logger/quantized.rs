@@ -0,0 +1,415 @@
+//! Lossy delta log mode with a bounded error per field.
+//!
+//! Every field is rounded to the grid of its own `fixed_point_factory!`
+//! before delta coding, so deltas are whole grid steps and reconstruct the
+//! quantized value exactly. Sensor noise below the error bound no longer
+//! makes the deltas as large as full values.
+//!
+//! Log `Quantized<Q>` instead of the sensor data to use this mode, the
+//! quantizer has its own schema id so the reader knows the error bounds.
+
+use core::marker::PhantomData;
+
+use crate::common::sensor_reading::SensorData;
+
+use super::{
+    bitslice_serialize::{BitArraySerializable, BitSliceReader, BitSliceWriter},
+    delta_factory::Deltable,
+};
+
+pub const MAX_QUANTIZED_FIELDS: usize = 8;
+
+/// Sensor data made of f32 fields
+pub trait QuantizableSensorData: SensorData {
+    /// Order of the fields in `to_fields` and `from_fields`
+    const FIELD_NAMES: &'static [&'static str];
+
+    /// Unused fields are 0
+    fn to_fields(&self) -> [f32; MAX_QUANTIZED_FIELDS];
+
+    fn from_fields(fields: &[f32; MAX_QUANTIZED_FIELDS]) -> Self;
+}
+
+/// Per-field quantization of a `QuantizableSensorData`, use `sensor_quantizer!`
+/// to implement it
+pub trait SensorQuantizer: Clone + core::fmt::Debug + defmt::Format {
+    type Data: QuantizableSensorData;
+    const NAME: &'static str;
+    /// Identifies the quantizer in delta log headers, see
+    /// `delta_logger::header::DELTA_LOG_SCHEMAS`
+    const SCHEMA_ID: u8;
+
+    /// Grid index of `value`, capped to the range of the field
+    fn quantize(field_i: usize, value: f32) -> u32;
+    fn dequantize(field_i: usize, code: u32) -> f32;
+    fn max_error(field_i: usize) -> f32;
+    /// Bits of a full value
+    fn code_bits(field_i: usize) -> usize;
+    /// Bits of a delta, deltas larger than that are logged as full values
+    fn delta_bits(field_i: usize) -> usize;
+
+    fn fields() -> usize {
+        Self::Data::FIELD_NAMES.len()
+    }
+}
+
+/// Declares the fixed point factory of every field and a `SensorQuantizer`
+/// using them.
+///
+/// ```ignore
+/// sensor_quantizer!(
+///     BaroQuantizerTier1,
+///     BaroData,
+///     QUANTIZED_BARO_TIER_1_SCHEMA_ID,
+///     // (field index, factory, min, max, max error, delta bits)
+///     (0, BaroT1TemperatureFac, -40.0, 85.0, 0.01, 4),
+///     (1, BaroT1PressureFac, 0.0, 120000.0, 1.0, 6),
+/// );
+/// ```
+#[macro_export]
+macro_rules! sensor_quantizer {
+    ($name:ident, $data:ty, $schema_id:expr, $(($i:literal, $fac:ident, $min:literal, $max:literal, $max_error:literal, $delta_bits:literal)),* $(,)?) => {
+        $(
+            crate::fixed_point_factory!($fac, f32, $min, $max, $max_error);
+        )*
+
+        const _: () = {
+            use crate::common::delta_logger::quantized::QuantizableSensorData;
+            let indexes = [$($i),*];
+            if indexes.len() != <$data as QuantizableSensorData>::FIELD_NAMES.len() {
+                panic!("Every field needs a quantization");
+            }
+            let mut i = 0;
+            while i < indexes.len() {
+                if indexes[i] != i {
+                    panic!("Field indexes must be in order");
+                }
+                i += 1;
+            }
+            $(
+                if $delta_bits < 1 || $delta_bits > 32 {
+                    panic!("Delta bits must be within 1..=32");
+                }
+            )*
+        };
+
+        #[derive(Debug, Clone, defmt::Format)]
+        pub struct $name;
+
+        paste::paste! {
+            impl crate::common::delta_logger::quantized::SensorQuantizer for $name {
+                type Data = $data;
+                const NAME: &'static str = stringify!($name);
+                const SCHEMA_ID: u8 = $schema_id;
+
+                fn quantize(field_i: usize, value: f32) -> u32 {
+                    use crate::common::fixed_point::F32FixedPointFactory;
+                    // NaN would fail the range check of the factory
+                    let value = if value.is_nan() { 0.0 } else { value };
+                    match field_i {
+                        $(
+                            $i => {
+                                let base: [<$fac Base>] = $fac::to_fixed_point_capped(value).into();
+                                base as u32
+                            }
+                        )*
+                        _ => 0,
+                    }
+                }
+
+                fn dequantize(field_i: usize, code: u32) -> f32 {
+                    use crate::common::fixed_point::F32FixedPointFactory;
+                    match field_i {
+                        $(
+                            $i => $fac::to_float((code as [<$fac Base>]).into()),
+                        )*
+                        _ => 0.0,
+                    }
+                }
+
+                fn max_error(field_i: usize) -> f32 {
+                    use crate::common::fixed_point::F32FixedPointFactory;
+                    match field_i {
+                        $(
+                            $i => $fac::max_error(),
+                        )*
+                        _ => 0.0,
+                    }
+                }
+
+                fn code_bits(field_i: usize) -> usize {
+                    use crate::common::delta_logger::bitslice_primitive::BitSlicePrimitive;
+                    match field_i {
+                        $(
+                            $i => [<$fac Packed>]::len_bits(),
+                        )*
+                        _ => 0,
+                    }
+                }
+
+                fn delta_bits(field_i: usize) -> usize {
+                    match field_i {
+                        $(
+                            $i => $delta_bits,
+                        )*
+                        _ => 0,
+                    }
+                }
+            }
+        }
+    };
+}
+
+/// Sensor data rounded to the grid of `Q`
+#[derive(Debug, Clone, defmt::Format)]
+pub struct Quantized<Q: SensorQuantizer> {
+    codes: [u32; MAX_QUANTIZED_FIELDS],
+    _phantom: PhantomData<Q>,
+}
+
+impl<Q: SensorQuantizer> PartialEq for Quantized<Q> {
+    fn eq(&self, other: &Self) -> bool {
+        self.codes == other.codes
+    }
+}
+
+impl<Q: SensorQuantizer> Quantized<Q> {
+    pub fn new(data: &Q::Data) -> Self {
+        let fields = data.to_fields();
+        let mut codes = [0u32; MAX_QUANTIZED_FIELDS];
+        for i in 0..Q::fields() {
+            codes[i] = Q::quantize(i, fields[i]);
+        }
+        Self {
+            codes,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn data(&self) -> Q::Data {
+        let mut fields = [0f32; MAX_QUANTIZED_FIELDS];
+        for i in 0..Q::fields() {
+            fields[i] = Q::dequantize(i, self.codes[i]);
+        }
+        Q::Data::from_fields(&fields)
+    }
+
+    pub fn codes(&self) -> &[u32] {
+        &self.codes[..Q::fields()]
+    }
+}
+
+impl<Q: SensorQuantizer> BitArraySerializable for Quantized<Q> {
+    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
+        for i in 0..Q::fields() {
+            writer.write_bits(self.codes[i], Q::code_bits(i));
+        }
+    }
+
+    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
+        let mut codes = [0u32; MAX_QUANTIZED_FIELDS];
+        for i in 0..Q::fields() {
+            codes[i] = reader.read_bits(Q::code_bits(i)).unwrap();
+        }
+        Self {
+            codes,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn len_bits() -> usize {
+        (0..Q::fields()).map(Q::code_bits).sum()
+    }
+}
+
+/// Grid steps between two readings
+#[derive(Debug, Clone, defmt::Format)]
+pub struct QuantizedDelta<Q: SensorQuantizer> {
+    steps: [i32; MAX_QUANTIZED_FIELDS],
+    _phantom: PhantomData<Q>,
+}
+
+impl<Q: SensorQuantizer> BitArraySerializable for QuantizedDelta<Q> {
+    // stored with an offset of half the range so it is unsigned
+    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
+        for i in 0..Q::fields() {
+            let bits = Q::delta_bits(i);
+            let offset = 1i64 << (bits - 1);
+            writer.write_bits((self.steps[i] as i64 + offset) as u32, bits);
+        }
+    }
+
+    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
+        let mut steps = [0i32; MAX_QUANTIZED_FIELDS];
+        for i in 0..Q::fields() {
+            let bits = Q::delta_bits(i);
+            let offset = 1i64 << (bits - 1);
+            steps[i] = (reader.read_bits(bits).unwrap() as i64 - offset) as i32;
+        }
+        Self {
+            steps,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn len_bits() -> usize {
+        (0..Q::fields()).map(Q::delta_bits).sum()
+    }
+}
+
+impl<Q: SensorQuantizer> Deltable for Quantized<Q> {
+    type DeltaType = QuantizedDelta<Q>;
+
+    fn add_delta(&self, delta: &Self::DeltaType) -> Option<Self> {
+        let mut codes = [0u32; MAX_QUANTIZED_FIELDS];
+        for i in 0..Q::fields() {
+            codes[i] = u32::try_from(self.codes[i] as i64 + delta.steps[i] as i64).ok()?;
+        }
+        Some(Self {
+            codes,
+            _phantom: PhantomData,
+        })
+    }
+
+    fn subtract(&self, other: &Self) -> Option<Self::DeltaType> {
+        let mut steps = [0i32; MAX_QUANTIZED_FIELDS];
+        for i in 0..Q::fields() {
+            let half_range = 1i64 << (Q::delta_bits(i) - 1);
+            let step = self.codes[i] as i64 - other.codes[i] as i64;
+            if step < -half_range || step >= half_range {
+                return None;
+            }
+            steps[i] = step as i32;
+        }
+        Some(QuantizedDelta {
+            steps,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<Q: SensorQuantizer> SensorData for Quantized<Q> {
+    const SCHEMA_ID: u8 = Q::SCHEMA_ID;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::{
+            delta_logger::{
+                delta_factory::{DeltaFactory, UnDeltaFactory},
+                header::QUANTIZED_BARO_TIER_1_SCHEMA_ID,
+            },
+            test_utils::BufferWriter,
+        },
+        driver::barometer::BaroData,
+    };
+
+    sensor_quantizer!(
+        TestBaroQuantizer,
+        BaroData,
+        QUANTIZED_BARO_TIER_1_SCHEMA_ID,
+        (0, TestTemperatureFac, -40.0, 85.0, 0.01, 4),
+        (1, TestPressureFac, 0.0, 120000.0, 1.0, 6),
+    );
+
+    #[test]
+    fn test_quantize_error_bound() {
+        let data = BaroData {
+            temperature: 21.337,
+            pressure: 101325.4,
+        };
+        let quantized = Quantized::<TestBaroQuantizer>::new(&data);
+        let reconstructed = quantized.data();
+        assert!((reconstructed.temperature - data.temperature).abs() <= 0.01);
+        assert!((reconstructed.pressure - data.pressure).abs() <= 1.0);
+
+        let capped = Quantized::<TestBaroQuantizer>::new(&BaroData {
+            temperature: 200.0,
+            pressure: f32::NAN,
+        });
+        assert_eq!(capped.data().temperature, 85.0);
+    }
+
+    #[test]
+    fn test_quantized_delta() {
+        let a = Quantized::<TestBaroQuantizer>::new(&BaroData {
+            temperature: 20.0,
+            pressure: 100000.0,
+        });
+        let b = Quantized::<TestBaroQuantizer>::new(&BaroData {
+            temperature: 20.03,
+            pressure: 99990.0,
+        });
+        // pressure moved more than 32 steps
+        let c = Quantized::<TestBaroQuantizer>::new(&BaroData {
+            temperature: 20.03,
+            pressure: 99900.0,
+        });
+
+        let mut factory = DeltaFactory::<Quantized<TestBaroQuantizer>>::new();
+        let mut undelta_factory = UnDeltaFactory::<Quantized<TestBaroQuantizer>>::new();
+        undelta_factory.push(factory.push(a.clone()).unwrap_left());
+
+        let delta = factory.push(b.clone()).unwrap_right();
+        let mut writer = BitSliceWriter::<16>::default();
+        delta.serialize(&mut writer);
+        assert_eq!(writer.len_bits, 10);
+        let mut reader = BitSliceReader::<16>::default();
+        reader.replenish_bytes(writer.view_all_data_slice());
+        let delta = QuantizedDelta::<TestBaroQuantizer>::deserialize(&mut reader);
+        // deltas are exact, no error on top of the quantization
+        assert_eq!(undelta_factory.push_delta(delta).unwrap(), b);
+
+        assert!(factory.push(c).is_left());
+    }
+
+    #[tokio::test]
+    async fn test_quantized_delta_logger() {
+        use crate::{
+            common::{
+                delta_logger::{delta_logger::*, prelude::DeltaLoggerTrait},
+                sensor_reading::SensorReading,
+            },
+            driver::timestamp::BootTimestamp,
+            fixed_point_factory,
+        };
+        fixed_point_factory!(TimestampFac, f64, 0.0, 510.0, 0.5);
+
+        let readings: Vec<BaroData> = (0..100)
+            .map(|i| BaroData {
+                temperature: 20.0 + (i as f32 * 0.7).sin() * 0.05,
+                pressure: 100000.0 - i as f32 * 3.3,
+            })
+            .collect();
+
+        let mut buffer = [0u8; 1024];
+        let writer = BufferWriter::new(&mut buffer);
+        let mut logger =
+            DeltaLogger::<Quantized<TestBaroQuantizer>, _, TimestampFac>::new(writer, [0; 12]);
+        for (i, data) in readings.iter().enumerate() {
+            logger
+                .log(SensorReading::new(i as f64 * 10.0, Quantized::new(data)))
+                .await
+                .unwrap();
+        }
+        logger.flush().await.unwrap();
+        let reader = logger.into_inner().await.unwrap().into_reader();
+        let mut reader =
+            DeltaLoggerReader::<Quantized<TestBaroQuantizer>, _, TimestampFac>::new(reader);
+        for data in readings.iter() {
+            let read = reader
+                .read()
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap_left()
+                .data
+                .data();
+            assert!((read.temperature - data.temperature).abs() <= 0.01);
+            assert!((read.pressure - data.pressure).abs() <= 1.0);
+        }
+        assert!(reader.read().await.unwrap().is_none());
+    }
+}
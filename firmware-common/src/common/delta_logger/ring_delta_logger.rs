@@ -2,7 +2,9 @@ use core::cell::RefCell;
 use core::mem::replace;
 use core::mem::size_of;
 
-use super::delta_logger::{DeltaLogger, DeltaLoggerReader, UnixTimestampLog};
+use super::delta_logger::{
+    DeltaLogger, DeltaLoggerReader, DeltaLoggerReaderError, UnixTimestampLog,
+};
 use super::prelude::DeltaLoggerTrait;
 use crate::common::delta_logger::bitslice_primitive::BitSlicePrimitive;
 use crate::common::{
@@ -14,16 +16,56 @@ use crate::common::{
     ticker::Ticker,
 };
 use crate::driver::{clock::Clock, delay::Delay, timestamp::BootTimestamp};
-use embassy_futures::select::select;
-use embassy_futures::select::Either;
+use embassy_futures::select::select3;
+use embassy_futures::select::Either3;
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex, signal::Signal};
-use vlfs::{ConcurrentFilesIterator, Crc, FileType, Flash, VLFSError, VLFS};
+use vlfs::{ConcurrentFilesIterator, Crc, FileID, FileType, Flash, VLFSError, VLFS};
 
 pub struct RingDeltaLoggerConfig {
     pub file_type: FileType,
     pub seconds_per_segment: u32,
     pub first_segment_seconds: u32,
     pub segments_per_ring: u32,
+    /// Only supported by `RingDeltaLoggerState`
+    pub pin: Option<RingPinConfig>,
+}
+
+/// Lower bound of `RingPinConfig::max_pinned_segments`
+pub const MIN_PINNED_SEGMENTS: u32 = 2;
+
+/// Pinned segments are excluded from the ring rotation, used to keep the
+/// segments around a flight. Pinning works on whole segments.
+///
+/// If the newest segment is pinned on boot (e.g. rebooted mid flight), new
+/// segments are still pinned for `seconds_after_unpin`.
+pub struct RingPinConfig {
+    /// Pinned segments are retyped to this file type
+    pub file_type: FileType,
+    /// Segments covering this long before `pin` are pinned as well
+    pub seconds_before_pin: u32,
+    /// New segments are still pinned this long after `unpin`
+    pub seconds_after_unpin: u32,
+    /// The oldest pinned segments are deleted past this, must be at least
+    /// `MIN_PINNED_SEGMENTS`
+    pub max_pinned_segments: u32,
+}
+
+/// Requests not yet handled by the runner, collapsed so an `unpin` right after
+/// a `pin` doesn't discard it
+#[derive(Default)]
+struct PendingPin {
+    /// The existing segments need to be pinned
+    pin: bool,
+    /// The last request is `unpin`
+    unpin: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PinState {
+    Unpinned,
+    Pinned,
+    /// boot time in ms
+    PinnedUntil(f64),
 }
 
 pub struct RingDeltaLoggerState<'a, D, C, F, FF, DL, CL>
@@ -43,7 +85,12 @@ where
     delay: DL,
     clock: CL,
     config: RingDeltaLoggerConfig,
+    serial_number: [u8; 12],
     current_ring_segments: RefCell<u32>,
+    current_pinned_segments: RefCell<u32>,
+    pin_signal: Signal<NoopRawMutex, ()>,
+    pending_pin: RefCell<PendingPin>,
+    pin_state: RefCell<PinState>,
 }
 
 impl<'a, D, C, F, FF, DL, CL> RingDeltaLoggerState<'a, D, C, F, FF, DL, CL>
@@ -62,43 +109,69 @@ where
         delay: DL,
         clock: CL,
         config: RingDeltaLoggerConfig,
+        serial_number: [u8; 12],
     ) -> Result<Self, VLFSError<F::Error>> {
-        let mut files_iter = fs.files_iter(config.file_type).await;
-        let mut files_count = 0;
-        while let Some(_) = files_iter.next().await? {
-            files_count += 1;
+        if let Some(pin_config) = &config.pin {
+            log_assert!(pin_config.max_pinned_segments >= MIN_PINNED_SEGMENTS);
         }
-        drop(files_iter);
+        let (files_count, last_ring_file) = count_files(fs, config.file_type).await?;
         log_info!("Found {} files", files_count);
+        let (pinned_files_count, last_pinned_file) = match &config.pin {
+            Some(pin_config) => count_files(fs, pin_config.file_type).await?,
+            None => (0, None),
+        };
+        let resume_pinned = last_pinned_file > last_ring_file;
 
-        let mut builder = fs.new_at_builder().await?;
+        // room for the new segment
+        let (new_ring_segments, new_pinned_segments) = if resume_pinned {
+            log_info!("Newest segment is pinned, resuming pinning");
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+        let kept_ring_segments = files_count.min(config.segments_per_ring - new_ring_segments);
+        let mut files_to_remove = files_count - kept_ring_segments;
+        let kept_pinned_segments = match &config.pin {
+            Some(pin_config) => {
+                pinned_files_count.min(pin_config.max_pinned_segments - new_pinned_segments)
+            }
+            None => 0,
+        };
+        let mut pinned_files_to_remove = pinned_files_count - kept_pinned_segments;
 
-        let (mut files_to_remove, current_ring_segments) =
-            if files_count > config.segments_per_ring - 1 {
-                (
-                    files_count - config.segments_per_ring + 1,
-                    config.segments_per_ring,
-                )
-            } else {
-                (0, files_count + 1)
-            };
+        let mut builder = fs.new_at_builder().await?;
 
-        log_info!("Removing {} extra files", files_to_remove);
+        log_info!(
+            "Removing {} extra files, {} extra pinned files",
+            files_to_remove,
+            pinned_files_to_remove
+        );
         while let Some(file_entry) = builder.read_next().await? {
+            let is_pinned = config.pin.as_ref().map(|p| p.file_type) == Some(file_entry.typ);
             if file_entry.typ == config.file_type && files_to_remove > 0 {
                 files_to_remove -= 1;
                 builder.release_file_sectors(&file_entry).await?;
+            } else if is_pinned && pinned_files_to_remove > 0 {
+                pinned_files_to_remove -= 1;
+                builder.release_file_sectors(&file_entry).await?;
             } else {
                 builder.write(&file_entry).await?;
             }
         }
 
+        let (new_file_type, pin_state) = match &config.pin {
+            Some(pin_config) if resume_pinned => {
+                let until = clock.now_ms() + pin_config.seconds_after_unpin as f64 * 1000.0;
+                (pin_config.file_type, PinState::PinnedUntil(until))
+            }
+            _ => (config.file_type, PinState::Unpinned),
+        };
         let writer = builder
-            .write_new_file_and_open_for_write(config.file_type)
+            .write_new_file_and_open_for_write(new_file_type)
             .await?;
         builder.commit().await?;
 
-        let delta_logger = DeltaLogger::new(writer);
+        let delta_logger = DeltaLogger::new(writer, serial_number);
 
         Ok(Self {
             fs,
@@ -107,10 +180,36 @@ where
             delta_logger: Mutex::new(Some(delta_logger)),
             close_signal: Signal::new(),
             config,
-            current_ring_segments: RefCell::new(current_ring_segments),
+            serial_number,
+            current_ring_segments: RefCell::new(kept_ring_segments + new_ring_segments),
+            current_pinned_segments: RefCell::new(kept_pinned_segments + new_pinned_segments),
+            pin_signal: Signal::new(),
+            pending_pin: RefCell::new(PendingPin::default()),
+            pin_state: RefCell::new(pin_state),
         })
     }
 
+    /// Pins the current segment, the segments covering `seconds_before_pin`
+    /// before it and every new segment until `unpin` is called.
+    /// Does nothing without a `RingPinConfig`.
+    pub fn pin(&self) {
+        if self.config.pin.is_some() {
+            *self.pending_pin.borrow_mut() = PendingPin {
+                pin: true,
+                unpin: false,
+            };
+            self.pin_signal.signal(());
+        }
+    }
+
+    /// New segments are no longer pinned after `seconds_after_unpin`
+    pub fn unpin(&self) {
+        if self.config.pin.is_some() {
+            self.pending_pin.borrow_mut().unpin = true;
+            self.pin_signal.signal(());
+        }
+    }
+
     pub fn log_stats(&self) {
         let readings_per_segment =
             (self.config.seconds_per_segment as f64 * 1000.0 / FF::min()) as u32;
@@ -233,23 +332,157 @@ where
             self.state.config.seconds_per_segment as f64 * 1000.0,
         );
         loop {
-            match select(ticker.next(), self.state.close_signal.wait()).await {
-                Either::First(_) => {
+            match select3(
+                ticker.next(),
+                self.state.close_signal.wait(),
+                self.state.pin_signal.wait(),
+            )
+            .await
+            {
+                Either3::First(_) => {
                     self.create_new_segment().await?;
                 }
-                Either::Second(_) => {
-                    let mut delta_logger = self.state.delta_logger.lock().await;
-                    let mut delta_logger = delta_logger.take().unwrap();
-                    delta_logger.flush().await.map_err(|e| e.0)?;
-                    let writer = delta_logger.into_inner().await.map_err(|e| e.0)?;
-                    writer.close().await?;
-                    return Ok(());
+                Either3::Third(_) => {
+                    self.handle_pin_requests().await?;
+                }
+                Either3::Second(_) => {
+                    return self.close().await;
                 }
             }
         }
     }
 
+    async fn close(&self) -> Result<(), VLFSError<F::Error>> {
+        let mut delta_logger = self.state.delta_logger.lock().await;
+        let mut delta_logger = delta_logger.take().unwrap();
+        delta_logger.flush().await.map_err(|e| e.0)?;
+        let writer = delta_logger.into_inner().await.map_err(|e| e.0)?;
+        writer.close().await?;
+        Ok(())
+    }
+
+    async fn handle_pin_requests(&self) -> Result<(), VLFSError<F::Error>> {
+        let pending_pin = self.state.pending_pin.take();
+        if pending_pin.pin {
+            self.pin_existing_segments().await?;
+        }
+        if pending_pin.unpin && *self.state.pin_state.borrow() == PinState::Pinned {
+            let seconds_after_unpin = self.state.config.pin.as_ref().unwrap().seconds_after_unpin;
+            let until = self.state.clock.now_ms() + seconds_after_unpin as f64 * 1000.0;
+            *self.state.pin_state.borrow_mut() = PinState::PinnedUntil(until);
+        }
+        Ok(())
+    }
+
+    async fn pin_existing_segments(&self) -> Result<(), VLFSError<F::Error>> {
+        let pin_state = *self.state.pin_state.borrow();
+        match pin_state {
+            // duplicated event
+            PinState::Pinned => return Ok(()),
+            // pinned again before the previous pin ended, the current segment is still pinned
+            PinState::PinnedUntil(_) => {
+                *self.state.pin_state.borrow_mut() = PinState::Pinned;
+                return Ok(());
+            }
+            PinState::Unpinned => {}
+        }
+
+        let config = &self.state.config;
+        let pin_config = config.pin.as_ref().unwrap();
+        // the current segment is the last one
+        let segments_to_pin = pin_config
+            .seconds_before_pin
+            .div_ceil(config.seconds_per_segment)
+            + 1;
+        let segments_to_pin = segments_to_pin.min(pin_config.max_pinned_segments);
+
+        let (ring_segments, _) = count_files(self.state.fs, config.file_type).await?;
+        let current_pinned_segments = *self.state.current_pinned_segments.borrow();
+
+        let mut segments_to_skip = ring_segments.saturating_sub(segments_to_pin);
+        // the segments pinned now are newer than the ones pinned before
+        let total_pinned_segments = current_pinned_segments + ring_segments.min(segments_to_pin);
+        let mut pinned_segments_to_remove =
+            total_pinned_segments.saturating_sub(pin_config.max_pinned_segments);
+        let mut pinned_segments = 0u32;
+        let mut removed_pinned_segments = 0u32;
+        let mut builder = self.state.fs.new_at_builder().await?;
+        while let Some(mut file_entry) = builder.read_next().await? {
+            if file_entry.typ == config.file_type {
+                if segments_to_skip > 0 {
+                    segments_to_skip -= 1;
+                } else {
+                    file_entry.typ = pin_config.file_type;
+                    pinned_segments += 1;
+                }
+            } else if file_entry.typ == pin_config.file_type && pinned_segments_to_remove > 0 {
+                pinned_segments_to_remove -= 1;
+                removed_pinned_segments += 1;
+                builder.release_file_sectors(&file_entry).await?;
+                continue;
+            }
+            builder.write(&file_entry).await?;
+        }
+        builder.commit().await?;
+
+        *self.state.current_ring_segments.borrow_mut() = ring_segments - pinned_segments;
+        *self.state.current_pinned_segments.borrow_mut() =
+            current_pinned_segments + pinned_segments - removed_pinned_segments;
+        *self.state.pin_state.borrow_mut() = PinState::Pinned;
+        log_info!(
+            "Pinned {} ring segments, deleted {} old pinned segments",
+            pinned_segments,
+            removed_pinned_segments
+        );
+        Ok(())
+    }
+
+    fn is_pinned(&self) -> bool {
+        let pin_state = *self.state.pin_state.borrow();
+        let pinned = match pin_state {
+            PinState::Unpinned => false,
+            PinState::Pinned => true,
+            PinState::PinnedUntil(until) => self.state.clock.now_ms() < until,
+        };
+        if !pinned {
+            *self.state.pin_state.borrow_mut() = PinState::Unpinned;
+        }
+        pinned
+    }
+
     async fn create_new_segment(&self) -> Result<(), VLFSError<F::Error>> {
+        if self.is_pinned() {
+            log_info!("Creating new pinned segment");
+            let pin_config = self.state.config.pin.as_ref().unwrap();
+            let mut builder = self.state.fs.new_at_builder().await?;
+            let new_pinned_segments =
+                if *self.state.current_pinned_segments.borrow() >= pin_config.max_pinned_segments {
+                    let mut first_segment_removed = false;
+                    while let Some(file_entry) = builder.read_next().await? {
+                        if file_entry.typ == pin_config.file_type && !first_segment_removed {
+                            log_info!("Deleting one pinned segment");
+                            first_segment_removed = true;
+                            builder.release_file_sectors(&file_entry).await?;
+                        } else {
+                            builder.write(&file_entry).await?;
+                        }
+                    }
+                    pin_config.max_pinned_segments
+                } else {
+                    while let Some(file_entry) = builder.read_next().await? {
+                        builder.write(&file_entry).await?;
+                    }
+                    *self.state.current_pinned_segments.borrow() + 1
+                };
+            let new_writer = builder
+                .write_new_file_and_open_for_write(pin_config.file_type)
+                .await?;
+            builder.commit().await?;
+            self.replace_segment(new_writer).await?;
+            *self.state.current_pinned_segments.borrow_mut() = new_pinned_segments;
+            return Ok(());
+        }
+
         log_info!("Creating new ring segment");
         let mut builder = self.state.fs.new_at_builder().await?;
         let new_ring_segments =
@@ -275,7 +508,16 @@ where
             .write_new_file_and_open_for_write(self.state.config.file_type)
             .await?;
         builder.commit().await?;
-        let new_delta_logger = DeltaLogger::new(new_writer);
+        self.replace_segment(new_writer).await?;
+        *self.state.current_ring_segments.borrow_mut() = new_ring_segments;
+        Ok(())
+    }
+
+    async fn replace_segment(
+        &self,
+        new_writer: vlfs::FileWriter<'a, F, C>,
+    ) -> Result<(), VLFSError<F::Error>> {
+        let new_delta_logger = DeltaLogger::new(new_writer, self.state.serial_number);
         let mut old_delta_logger = {
             let mut delta_logger = self.state.delta_logger.lock().await;
             let delta_logger = delta_logger.as_mut().unwrap();
@@ -285,13 +527,32 @@ where
         old_delta_logger.flush().await.map_err(|e| e.0)?;
         let old_writer = old_delta_logger.into_inner().await.map_err(|e| e.0)?;
         old_writer.close().await?;
-        *self.state.current_ring_segments.borrow_mut() = new_ring_segments;
 
         log_info!("Ring segment created");
         Ok(())
     }
 }
 
+/// Returns the number of files and the newest file id
+async fn count_files<F, C>(
+    fs: &VLFS<F, C>,
+    file_type: FileType,
+) -> Result<(u32, Option<FileID>), VLFSError<F::Error>>
+where
+    C: Crc,
+    F: Flash,
+    F::Error: defmt::Format,
+{
+    let mut files_iter = fs.files_iter(file_type).await;
+    let mut count = 0;
+    let mut newest = None;
+    while let Some(file_entry) = files_iter.next().await? {
+        count += 1;
+        newest = newest.max(Some(file_entry.id));
+    }
+    Ok((count, newest))
+}
+
 pub struct RingDeltaLoggerReader<'a, D, C, F, FF>
 where
     C: Crc,
@@ -353,7 +614,14 @@ where
         }
 
         if let Some(delta_logger_reader) = &mut self.delta_logger_reader {
-            let reading = delta_logger_reader.read().await?;
+            let reading = match delta_logger_reader.read().await {
+                Ok(reading) => reading,
+                Err(DeltaLoggerReaderError::Read(e)) => return Err(e),
+                Err(e) => {
+                    log_warn!("Skipping ring segment: {:?}", e);
+                    None
+                }
+            };
             if let Some(reading) = reading {
                 return Ok(DeltaLoggerReaderResult::Data(reading));
             } else {
@@ -388,3 +656,136 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::test_utils::{TokioClock, TokioDelay},
+        driver::adc::{ADCData, Volt},
+        fixed_point_factory,
+    };
+    use std::time::Duration;
+    use vlfs::{DummyCrc, MemoryFlash};
+
+    fixed_point_factory!(TestFF, f64, 0.0, 510.0, 0.5);
+
+    const RING_FILE_TYPE: FileType = FileType(1);
+    const PINNED_FILE_TYPE: FileType = FileType(2);
+
+    type TestState<'a> = RingDeltaLoggerState<
+        'a,
+        ADCData<Volt>,
+        DummyCrc,
+        MemoryFlash,
+        TestFF,
+        TokioDelay,
+        TokioClock,
+    >;
+
+    async fn new_fs() -> VLFS<MemoryFlash, DummyCrc> {
+        let mut fs = VLFS::new(MemoryFlash::new(None), DummyCrc {});
+        fs.init().await.unwrap();
+        fs
+    }
+
+    async fn new_state(fs: &VLFS<MemoryFlash, DummyCrc>) -> TestState<'_> {
+        let config = RingDeltaLoggerConfig {
+            file_type: RING_FILE_TYPE,
+            seconds_per_segment: 60,
+            first_segment_seconds: 60,
+            segments_per_ring: 3,
+            pin: Some(RingPinConfig {
+                file_type: PINNED_FILE_TYPE,
+                seconds_before_pin: 60,
+                seconds_after_unpin: 60,
+                max_pinned_segments: 3,
+            }),
+        };
+        RingDeltaLoggerState::new(fs, TokioDelay, TokioClock::new(), config, [0x42; 12])
+            .await
+            .unwrap()
+    }
+
+    /// Returns the number of ring and pinned segments
+    async fn segment_counts(fs: &VLFS<MemoryFlash, DummyCrc>) -> (u32, u32) {
+        (
+            count_files(fs, RING_FILE_TYPE).await.unwrap().0,
+            count_files(fs, PINNED_FILE_TYPE).await.unwrap().0,
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pin_and_unpin() {
+        let fs = new_fs().await;
+        let state = new_state(&fs).await;
+        let (_, runner) = state.get_logger_runner();
+        for _ in 0..3 {
+            runner.create_new_segment().await.unwrap();
+        }
+        assert_eq!(segment_counts(&fs).await, (3, 0));
+
+        // the current segment and the one before it
+        state.pin();
+        runner.handle_pin_requests().await.unwrap();
+        assert_eq!(segment_counts(&fs).await, (1, 2));
+
+        // the oldest pinned segment is deleted past max_pinned_segments
+        runner.create_new_segment().await.unwrap();
+        runner.create_new_segment().await.unwrap();
+        assert_eq!(segment_counts(&fs).await, (1, 3));
+
+        state.unpin();
+        runner.handle_pin_requests().await.unwrap();
+        runner.create_new_segment().await.unwrap();
+        assert_eq!(segment_counts(&fs).await, (1, 3));
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        runner.create_new_segment().await.unwrap();
+        assert_eq!(segment_counts(&fs).await, (2, 3));
+
+        runner.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unpin_right_after_pin() {
+        let fs = new_fs().await;
+        let state = new_state(&fs).await;
+        let (_, runner) = state.get_logger_runner();
+        runner.create_new_segment().await.unwrap();
+
+        state.pin();
+        state.unpin();
+        runner.handle_pin_requests().await.unwrap();
+        assert_eq!(segment_counts(&fs).await, (0, 2));
+        assert!(matches!(
+            *state.pin_state.borrow(),
+            PinState::PinnedUntil(_)
+        ));
+
+        runner.close().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resume_pinned_after_reboot() {
+        let fs = new_fs().await;
+        {
+            let state = new_state(&fs).await;
+            let (_, runner) = state.get_logger_runner();
+            state.pin();
+            runner.handle_pin_requests().await.unwrap();
+            runner.close().await.unwrap();
+        }
+        assert_eq!(segment_counts(&fs).await, (0, 1));
+
+        let state = new_state(&fs).await;
+        assert_eq!(segment_counts(&fs).await, (0, 2));
+        assert!(matches!(
+            *state.pin_state.borrow(),
+            PinState::PinnedUntil(_)
+        ));
+
+        let (_, runner) = state.get_logger_runner();
+        runner.close().await.unwrap();
+    }
+}
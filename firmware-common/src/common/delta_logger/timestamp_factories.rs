@@ -0,0 +1,15 @@
+//! Timestamp fixed point factories of every delta logger in the firmware.
+//!
+//! The parameters are recorded in the header of every delta log file, keep
+//! them here so the host can find the matching factory.
+
+use crate::fixed_point_factory;
+
+// sensors sampled every 5ms
+fixed_point_factory!(SensorsFF1, f64, 4.9, 7.0, 0.05);
+// sensors sampled every 200ms
+fixed_point_factory!(SensorsFF2, f64, 199.0, 210.0, 0.5);
+fixed_point_factory!(GPSFF1, f64, 99.0, 110.0, 0.5);
+fixed_point_factory!(GPSFF2, f64, 4999.0, 5010.0, 0.5);
+fixed_point_factory!(VacuumTestFF, f64, 4.0, 7.0, 0.05);
+fixed_point_factory!(BatteryFF, f64, 4999.0, 5010.0, 0.5);
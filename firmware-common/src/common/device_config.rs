@@ -1,18 +1,63 @@
 use rkyv::{Archive, Deserialize, Serialize};
 
 use crate::avionics::flight_profile::PyroSelection;
+use crate::common::schema_hash::SchemaHash;
+use crate::common::versioned::{migrate_from, Migration, Versioned};
 
-use super::rkyv_structs::RkyvString;
+use super::{rkyv_structs::RkyvString, vlp::packet_builder::vlp_device_id_from_serial_number};
 
-#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize, SchemaHash)]
+#[archive(check_bytes)]
 pub struct DeviceConfig {
     pub name: RkyvString<64>,
     pub mode: DeviceModeConfig,
     pub lora: LoraConfig,
     pub lora_key: [u8; 32],
+    /// Overrides the VLP device id derived from the serial number, needed when
+    /// two rockets talking to the same GCM derive the same id
+    pub vlp_device_id: Option<u8>,
 }
 
-#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+impl Versioned for DeviceConfig {
+    const VERSION: u16 = 1;
+    const MIGRATIONS: &'static [Migration<Self>] = &[Migration {
+        from_version: 0,
+        migrate: migrate_from::<DeviceConfigV0, DeviceConfig>,
+    }];
+}
+
+/// Layout before `vlp_device_id` was added, also what config files written
+/// before the envelope was introduced contain
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct DeviceConfigV0 {
+    pub name: RkyvString<64>,
+    pub mode: DeviceModeConfig,
+    pub lora: LoraConfig,
+    pub lora_key: [u8; 32],
+}
+
+impl From<DeviceConfigV0> for DeviceConfig {
+    fn from(old: DeviceConfigV0) -> Self {
+        Self {
+            name: old.name,
+            mode: old.mode,
+            lora: old.lora,
+            lora_key: old.lora_key,
+            vlp_device_id: None,
+        }
+    }
+}
+
+impl DeviceConfig {
+    pub fn vlp_device_id(&self, device_serial_number: &[u8; 12]) -> u8 {
+        self.vlp_device_id
+            .unwrap_or_else(|| vlp_device_id_from_serial_number(device_serial_number))
+    }
+}
+
+#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize, SchemaHash)]
+#[archive(check_bytes)]
 pub enum DeviceModeConfig {
     Avionics,
     GCM,
@@ -23,7 +68,8 @@ pub enum DeviceModeConfig {
     VacuumTest,
 }
 
-#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize, SchemaHash)]
+#[archive(check_bytes)]
 pub struct LoraConfig {
     pub frequency: u32,
     pub sf: u8,
@@ -0,0 +1,288 @@
+//! Ring buffer of the recent log records on the device, so the logs can be
+//! read over the USB console without a debug probe.
+//!
+//! `log_info!`, `log_warn!` and `log_error!` push into [`DEVICE_LOG`] when
+//! built with defmt and the `device-log` feature. Records are stamped with the
+//! clock passed to [`DeviceLog::with_timestamp_source`], or 0 outside of it.
+
+use core::{cell::RefCell, fmt::Write, future::Future};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    signal::Signal,
+};
+use heapless::{Deque, String};
+
+use crate::driver::clock::Clock;
+
+pub const DEVICE_LOG_CAPACITY: usize = 32;
+pub const DEVICE_LOG_MESSAGE_SIZE: usize = 96;
+
+pub static DEVICE_LOG: DeviceLog<DEVICE_LOG_CAPACITY> = DeviceLog::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum DeviceLogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl DeviceLogLevel {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Trace),
+            1 => Some(Self::Debug),
+            2 => Some(Self::Info),
+            3 => Some(Self::Warn),
+            4 => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trace => "TRACE",
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceLogRecord {
+    /// Increases by one for every record, a gap means the records in between
+    /// were overwritten before they were read
+    pub seq: u32,
+    pub timestamp: f64, // boot time in ms
+    pub level: DeviceLogLevel,
+    /// Truncated to `DEVICE_LOG_MESSAGE_SIZE` bytes
+    pub message: String<DEVICE_LOG_MESSAGE_SIZE>,
+}
+
+/// Writes as much as fits and drops the rest
+struct TruncatingWriter<'a>(&'a mut String<DEVICE_LOG_MESSAGE_SIZE>);
+
+impl<'a> Write for TruncatingWriter<'a> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                return Err(core::fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct DeviceLogBuffer<const N: usize> {
+    records: Deque<DeviceLogRecord, N>,
+    next_seq: u32,
+}
+
+impl<const N: usize> DeviceLogBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            records: Deque::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Overwrites the oldest record when full
+    pub fn push(
+        &mut self,
+        timestamp: f64,
+        level: DeviceLogLevel,
+        message: String<DEVICE_LOG_MESSAGE_SIZE>,
+    ) {
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        self.records
+            .push_back(DeviceLogRecord {
+                seq: self.next_seq,
+                timestamp,
+                level,
+                message,
+            })
+            .ok();
+        self.next_seq = self.next_seq.wrapping_add(1);
+    }
+
+    /// The oldest record with a seq not smaller than `seq`
+    pub fn read(&self, seq: u32) -> Option<DeviceLogRecord> {
+        // number of records logged since `seq`, seq wraps around
+        let behind = self.next_seq.wrapping_sub(seq) as i32;
+        if behind <= 0 {
+            return None;
+        }
+        if behind as usize > self.records.len() {
+            // already overwritten
+            return self.records.front().cloned();
+        }
+        self.records
+            .iter()
+            .nth(self.records.len() - behind as usize)
+            .cloned()
+    }
+
+    pub fn next_seq(&self) -> u32 {
+        self.next_seq
+    }
+}
+
+/// Clock of the running entry point with its type erased
+#[derive(Clone, Copy)]
+struct TimestampSource {
+    clock: *const (),
+    now_ms: unsafe fn(*const ()) -> f64,
+}
+
+// Safety: only created from a `&K` with `K: Sync`
+unsafe impl Send for TimestampSource {}
+
+unsafe fn erased_now_ms<K: Clock>(clock: *const ()) -> f64 {
+    (*(clock as *const K)).now_ms()
+}
+
+/// Clears the timestamp source when dropped, before the clock it points to
+struct TimestampSourceGuard<'a, const N: usize> {
+    log: &'a DeviceLog<N>,
+}
+
+impl<'a, const N: usize> Drop for TimestampSourceGuard<'a, N> {
+    fn drop(&mut self) {
+        self.log
+            .timestamp_source
+            .lock(|source| source.borrow_mut().take());
+    }
+}
+
+/// [`DeviceLogBuffer`] that can be shared between the log macros and the log
+/// stream
+pub struct DeviceLog<const N: usize> {
+    buffer: BlockingMutex<CriticalSectionRawMutex, RefCell<DeviceLogBuffer<N>>>,
+    timestamp_source: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<TimestampSource>>>,
+    new_record_signal: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<const N: usize> DeviceLog<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffer: BlockingMutex::new(RefCell::new(DeviceLogBuffer::new())),
+            timestamp_source: BlockingMutex::new(RefCell::new(None)),
+            new_record_signal: Signal::new(),
+        }
+    }
+
+    /// Runs `fut` with records stamped by `clock`, only one clock can be used
+    /// at a time
+    pub async fn with_timestamp_source<K: Clock + Sync, F: Future>(
+        &self,
+        clock: K,
+        fut: F,
+    ) -> F::Output {
+        // `clock` lives in the state of this future, which is pinned once
+        // polled, so it stays in place until the guard is dropped
+        self.timestamp_source.lock(|source| {
+            source.borrow_mut().replace(TimestampSource {
+                clock: &clock as *const K as *const (),
+                now_ms: erased_now_ms::<K>,
+            })
+        });
+        let _guard = TimestampSourceGuard { log: self };
+        fut.await
+    }
+
+    fn now_ms(&self) -> f64 {
+        // call the clock inside the lock so the guard can't clear it halfway
+        self.timestamp_source.lock(|source| match *source.borrow() {
+            Some(source) => unsafe { (source.now_ms)(source.clock) },
+            None => 0.0,
+        })
+    }
+
+    pub fn log(&self, level: DeviceLogLevel, args: core::fmt::Arguments) {
+        let mut message = String::new();
+        // a too long message is still useful, ignore the error
+        TruncatingWriter(&mut message).write_fmt(args).ok();
+        let timestamp = self.now_ms();
+
+        self.buffer
+            .lock(|buffer| buffer.borrow_mut().push(timestamp, level, message));
+        self.new_record_signal.signal(());
+    }
+
+    pub fn read(&self, seq: u32) -> Option<DeviceLogRecord> {
+        self.buffer.lock(|buffer| buffer.borrow().read(seq))
+    }
+
+    /// Waits until a record is pushed, only one task can wait at a time
+    pub async fn wait_new_record(&self) {
+        self.new_record_signal.wait().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device_log_buffer() {
+        let mut buffer = DeviceLogBuffer::<3>::new();
+        assert_eq!(buffer.read(0), None);
+
+        for i in 0..5 {
+            let mut message = String::new();
+            write!(message, "record {}", i).unwrap();
+            buffer.push(i as f64, DeviceLogLevel::Info, message);
+        }
+        assert_eq!(buffer.next_seq(), 5);
+
+        // record 0 and 1 are overwritten
+        let record = buffer.read(0).unwrap();
+        assert_eq!(record.seq, 2);
+        assert_eq!(record.message.as_str(), "record 2");
+        assert_eq!(record.timestamp, 2.0);
+
+        assert_eq!(buffer.read(4).unwrap().message.as_str(), "record 4");
+        assert_eq!(buffer.read(5), None);
+    }
+
+    #[test]
+    fn test_long_message_truncated() {
+        let log = DeviceLog::<1>::new();
+        let long = [b'a'; DEVICE_LOG_MESSAGE_SIZE + 10];
+        let long = core::str::from_utf8(&long).unwrap();
+        log.log(DeviceLogLevel::Warn, format_args!("{}", long));
+
+        let record = log.read(0).unwrap();
+        assert_eq!(record.level, DeviceLogLevel::Warn);
+        assert_eq!(record.timestamp, 0.0);
+        assert_eq!(record.message.len(), DEVICE_LOG_MESSAGE_SIZE);
+    }
+
+    #[derive(Clone)]
+    struct FixedClock(f64);
+
+    impl Clock for FixedClock {
+        fn now_ms(&self) -> f64 {
+            self.0
+        }
+    }
+
+    #[futures_test::test]
+    async fn test_timestamp_source() {
+        let log = DeviceLog::<2>::new();
+        log.with_timestamp_source(FixedClock(42.0), async {
+            log.log(DeviceLogLevel::Info, format_args!("stamped"));
+        })
+        .await;
+        log.log(DeviceLogLevel::Info, format_args!("not stamped"));
+
+        assert_eq!(log.read(0).unwrap().timestamp, 42.0);
+        assert_eq!(log.read(1).unwrap().timestamp, 0.0);
+    }
+}
@@ -1,29 +1,273 @@
 use vlfs::FileType;
 
-pub static DEVICE_CONFIG_FILE_TYPE: FileType = FileType(0);
-// pub static BEACON_SENDER_LOG_FILE_TYPE: FileType = FileType(1);
-pub static BENCHMARK_FILE_TYPE: FileType = FileType(2);
-pub static CALIBRATION_FILE_TYPE: FileType = FileType(3);
-pub static AVIONICS_SENSORS_FILE_TYPE: FileType = FileType(4);
-pub static AVIONICS_LOG_FILE_TYPE: FileType = FileType(5);
-pub static AVIONICS_UP_RIGHT_FILE_TYPE: FileType = FileType(6);
-pub static GROUND_TEST_LOG_FILE_TYPE: FileType = FileType(7);
-pub static FLIGHT_PROFILE_FILE_TYPE: FileType = FileType(8);
-pub static AVIONICS_GPS_LOGGER_TIER_1: FileType = FileType(9);
-pub static AVIONICS_GPS_LOGGER_TIER_2: FileType = FileType(10);
-pub static AVIONICS_LOW_G_IMU_LOGGER_TIER_1: FileType = FileType(9);
-pub static AVIONICS_LOW_G_IMU_LOGGER_TIER_2: FileType = FileType(10);
-pub static AVIONICS_HIGH_G_IMU_LOGGER_TIER_1: FileType = FileType(11);
-pub static AVIONICS_HIGH_G_IMU_LOGGER_TIER_2: FileType = FileType(12);
-pub static AVIONICS_BARO_LOGGER_TIER_1: FileType = FileType(13);
-pub static AVIONICS_BARO_LOGGER_TIER_2: FileType = FileType(14);
-pub static AVIONICS_MAG_LOGGER_TIER_1: FileType = FileType(15);
-pub static AVIONICS_MAG_LOGGER_TIER_2: FileType = FileType(16);
-pub static AVIONICS_BATTERY_LOGGER_TIER_1: FileType = FileType(17);
-pub static AVIONICS_BATTERY_LOGGER_TIER_2: FileType = FileType(18);
-pub static UPRIGHT_VECTOR_AND_GYRO_OFFSET_FILE_TYPE: FileType = FileType(19);
-pub static GROUND_TEST_BARO_FILE_TYPE: FileType = FileType(20);
-pub static VACUUM_TEST_LOG_FILE_TYPE: FileType = FileType(21);
-pub static VACUUM_TEST_BARO_LOGGER: FileType = FileType(22);
-pub static SG_READINGS: FileType = FileType(24);
-pub static SG_BATTERY_LOGGER: FileType = FileType(25);
\ No newline at end of file
+/// How the content of a file is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FileDecoderKind {
+    /// `DeltaLogger` readings, starts with a `DeltaLogHeader`
+    DeltaLog,
+    /// rkyv serialized enums, e.g. log messages
+    SerializedEnum,
+    /// `ConfigFile`
+    Config,
+    Raw,
+}
+
+impl FileDecoderKind {
+    /// Extension used by the host when saving the file
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            FileDecoderKind::DeltaLog => "vldr",
+            FileDecoderKind::SerializedEnum => "vle",
+            FileDecoderKind::Config => "vlc",
+            FileDecoderKind::Raw => "bin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum FileRetention {
+    /// Configs and calibrations, only removed when they are replaced
+    Persistent,
+    /// Flight data, removed by `DeleteLogsPacket`
+    Log,
+    /// Test data and recordings, kept by `DeleteLogsPacket`
+    Recording,
+    /// Can be removed at any time
+    Scratch,
+}
+
+impl FileRetention {
+    pub const fn removed_by_delete_logs(&self) -> bool {
+        matches!(self, FileRetention::Log | FileRetention::Scratch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileTypeDescription {
+    pub file_type: FileType,
+    pub name: &'static str,
+    pub decoder: FileDecoderKind,
+    pub retention: FileRetention,
+    /// Pulled files are saved as `<file id>.<file_name>.<extension>`
+    pub file_name: &'static str,
+    pub extension: &'static str,
+}
+
+pub const fn assert_unique_file_types(file_types: &[u16]) {
+    let mut i = 0;
+    while i < file_types.len() {
+        if file_types[i] == 0xFFFF {
+            panic!("File type 0xFFFF is reserved");
+        }
+        let mut j = i + 1;
+        while j < file_types.len() {
+            if file_types[i] == file_types[j] {
+                panic!("Duplicated file type");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+macro_rules! first_of {
+    ($first:expr $(, $rest:expr)*) => {
+        $first
+    };
+}
+
+/// Declares every file type once: the `FileType` constants, a compile time
+/// check that their ids are unique, and the list of file type descriptions.
+///
+/// The pulled file name and extension default to the name and the decoder
+/// extension, file types pulled before the registry keep their old names.
+macro_rules! create_file_types {
+    ($descriptions_name:ident, $(($id:literal, $const_name:ident, $name:literal, $decoder:ident, $retention:ident $(, $file_name:literal, $extension:literal)?)),* $(,)?) => {
+        $(
+            pub const $const_name: FileType = FileType($id);
+        )*
+
+        const _: () = assert_unique_file_types(&[$($id),*]);
+
+        pub const $descriptions_name: &[FileTypeDescription] = &[
+            $(
+                FileTypeDescription {
+                    file_type: $const_name,
+                    name: $name,
+                    decoder: FileDecoderKind::$decoder,
+                    retention: FileRetention::$retention,
+                    file_name: first_of!($($file_name,)? $name),
+                    extension: first_of!($($extension,)? FileDecoderKind::$decoder.extension()),
+                },
+            )*
+        ];
+    };
+}
+
+// ids are stored in the flash, never change or reuse them
+// 1: beacon sender log, removed
+// 23: never used
+create_file_types! {
+    FILE_TYPES,
+    (0, DEVICE_CONFIG_FILE_TYPE, "device_config", Config, Persistent),
+    (2, BENCHMARK_FILE_TYPE, "benchmark", Raw, Scratch),
+    (3, CALIBRATION_FILE_TYPE, "calibration", Config, Persistent),
+    (4, AVIONICS_SENSORS_FILE_TYPE, "avionics_sensors", Raw, Log),
+    (5, AVIONICS_LOG_FILE_TYPE, "avionics_log", SerializedEnum, Log),
+    (6, AVIONICS_UP_RIGHT_FILE_TYPE, "avionics_up_right", Config, Persistent),
+    (7, GROUND_TEST_LOG_FILE_TYPE, "ground_test_log", SerializedEnum, Log),
+    (8, FLIGHT_PROFILE_FILE_TYPE, "flight_profile", Config, Persistent),
+    (9, AVIONICS_LOW_G_IMU_LOGGER_TIER_1, "low_g_imu_tier_1", DeltaLog, Log, "low_g_imu", "vldr"),
+    (10, AVIONICS_LOW_G_IMU_LOGGER_TIER_2, "low_g_imu_tier_2", DeltaLog, Log),
+    (11, AVIONICS_HIGH_G_IMU_LOGGER_TIER_1, "high_g_imu_tier_1", DeltaLog, Log),
+    (12, AVIONICS_HIGH_G_IMU_LOGGER_TIER_2, "high_g_imu_tier_2", DeltaLog, Log),
+    (13, AVIONICS_BARO_LOGGER_TIER_1, "baro_tier_1", DeltaLog, Log),
+    (14, AVIONICS_BARO_LOGGER_TIER_2, "baro_tier_2", DeltaLog, Log),
+    (15, AVIONICS_MAG_LOGGER_TIER_1, "mag_tier_1", DeltaLog, Log),
+    (16, AVIONICS_MAG_LOGGER_TIER_2, "mag_tier_2", DeltaLog, Log),
+    (17, AVIONICS_BATTERY_LOGGER_TIER_1, "battery_tier_1", DeltaLog, Log),
+    (18, AVIONICS_BATTERY_LOGGER_TIER_2, "battery_tier_2", DeltaLog, Log),
+    (19, UPRIGHT_VECTOR_AND_GYRO_OFFSET_FILE_TYPE, "upright_vector_and_gyro_offset", Config, Persistent),
+    (20, GROUND_TEST_BARO_FILE_TYPE, "ground_test_baro", DeltaLog, Recording),
+    (21, VACUUM_TEST_LOG_FILE_TYPE, "vacuum_test_log", SerializedEnum, Recording),
+    (22, VACUUM_TEST_BARO_LOGGER, "vacuum_test_baro", DeltaLog, Recording, "baro", "vldr"),
+    (24, SG_READINGS, "sg", SerializedEnum, Recording),
+    (25, SG_BATTERY_LOGGER, "sg_battery", DeltaLog, Recording, "battery", "vldr"),
+    (26, GCM_TELEMETRY_RECORDING_FILE_TYPE, "gcm_telemetry_recording", SerializedEnum, Recording, "gcm_recording", "vlr"),
+    // used to share 9 and 10 with the low g imu loggers, see `legacy_file_type`
+    (27, AVIONICS_GPS_LOGGER_TIER_1, "gps_tier_1", DeltaLog, Log),
+    (28, AVIONICS_GPS_LOGGER_TIER_2, "gps_tier_2", DeltaLog, Log),
+    // ring segments pinned around a flight, see `RingPinConfig`
+    (29, AVIONICS_GPS_LOGGER_TIER_1_PINNED, "gps_tier_1_pinned", DeltaLog, Log),
+    (30, AVIONICS_GPS_LOGGER_TIER_2_PINNED, "gps_tier_2_pinned", DeltaLog, Log),
+    (31, AVIONICS_LOW_G_IMU_LOGGER_TIER_1_PINNED, "low_g_imu_tier_1_pinned", DeltaLog, Log),
+    (32, AVIONICS_LOW_G_IMU_LOGGER_TIER_2_PINNED, "low_g_imu_tier_2_pinned", DeltaLog, Log),
+    (33, AVIONICS_BARO_LOGGER_TIER_1_PINNED, "baro_tier_1_pinned", DeltaLog, Log),
+    (34, AVIONICS_BARO_LOGGER_TIER_2_PINNED, "baro_tier_2_pinned", DeltaLog, Log),
+    // last applied over the air config update, see `ConfigUpdateState`
+    (35, CONFIG_UPDATE_STATE_FILE_TYPE, "config_update_state", Config, Persistent),
+}
+
+const _: () = {
+    let mut i = 0;
+    while i < FILE_TYPES.len() {
+        let mut j = i + 1;
+        while j < FILE_TYPES.len() {
+            if const_str_eq(FILE_TYPES[i].name, FILE_TYPES[j].name) {
+                panic!("Duplicated file type name");
+            }
+            if const_str_eq(FILE_TYPES[i].file_name, FILE_TYPES[j].file_name) {
+                panic!("Duplicated pulled file name");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+const fn const_str_eq(a: &str, b: &str) -> bool {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+pub fn file_type_description(file_type: FileType) -> Option<&'static FileTypeDescription> {
+    FILE_TYPES.iter().find(|d| d.file_type == file_type)
+}
+
+pub fn file_type_by_name(name: &str) -> Option<&'static FileTypeDescription> {
+    FILE_TYPES.iter().find(|d| d.name == name)
+}
+
+/// Looks up the file type of a pulled `<file id>.<file_name>.<extension>` file
+pub fn file_type_by_file_name(
+    file_name: &str,
+    extension: &str,
+) -> Option<&'static FileTypeDescription> {
+    FILE_TYPES
+        .iter()
+        .find(|d| d.file_name == file_name && d.extension == extension)
+}
+
+/// The file type the files of `file_type` were written with before it got
+/// its own id. The GPS loggers used to share 9 and 10 with the low g imu
+/// loggers, their files can only be told apart by the delta log header.
+pub fn legacy_file_type(file_type: FileType) -> Option<FileType> {
+    if file_type == AVIONICS_GPS_LOGGER_TIER_1 {
+        Some(AVIONICS_LOW_G_IMU_LOGGER_TIER_1)
+    } else if file_type == AVIONICS_GPS_LOGGER_TIER_2 {
+        Some(AVIONICS_LOW_G_IMU_LOGGER_TIER_2)
+    } else {
+        None
+    }
+}
+
+/// Unknown file types are kept
+pub fn removed_by_delete_logs(file_type: FileType) -> bool {
+    file_type_description(file_type).map_or(false, |d| d.retention.removed_by_delete_logs())
+}
+
+/// File types of a delta logger table, e.g.
+/// `avionics_delta_loggers!(delta_logger_file_types!())`
+///
+/// Delta logger tables list the `(file type, sensor data type, timestamp fixed
+/// point factory)` of the delta loggers of a device mode, the firmware checks
+/// its loggers against them and the host pulls the files with them.
+#[macro_export]
+macro_rules! delta_logger_file_types {
+    ([$(($file_type:expr, $data:ty, $ff:ty)),* $(,)?]) => {
+        &[$($file_type),*]
+    };
+}
+
+pub const fn contains_file_type(file_types: &[FileType], file_type: FileType) -> bool {
+    let mut i = 0;
+    while i < file_types.len() {
+        if file_types[i].0 == file_type.0 {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let gps = file_type_by_name("gps_tier_1").unwrap();
+        assert_eq!(gps.file_type, AVIONICS_GPS_LOGGER_TIER_1);
+        assert_ne!(gps.file_type, AVIONICS_LOW_G_IMU_LOGGER_TIER_1);
+        assert_eq!(gps.decoder.extension(), "vldr");
+
+        assert!(removed_by_delete_logs(AVIONICS_BARO_LOGGER_TIER_2));
+        assert!(removed_by_delete_logs(AVIONICS_GPS_LOGGER_TIER_1));
+        assert!(!removed_by_delete_logs(FLIGHT_PROFILE_FILE_TYPE));
+        assert!(!removed_by_delete_logs(VACUUM_TEST_BARO_LOGGER));
+        assert!(!removed_by_delete_logs(GCM_TELEMETRY_RECORDING_FILE_TYPE));
+        assert!(!removed_by_delete_logs(FileType(0xFFFE)));
+
+        let battery = file_type_by_file_name("battery", "vldr").unwrap();
+        assert_eq!(battery.file_type, SG_BATTERY_LOGGER);
+        assert_eq!(
+            file_type_by_file_name("baro_tier_1", "vldr").unwrap().name,
+            "baro_tier_1"
+        );
+        assert_eq!(
+            legacy_file_type(AVIONICS_GPS_LOGGER_TIER_2),
+            Some(AVIONICS_LOW_G_IMU_LOGGER_TIER_2)
+        );
+    }
+}
@@ -9,6 +9,7 @@ pub trait F32FixedPointFactory: Clone {
     fn to_float(value: <Self::VI as VariableIntTrait>::Packed) -> f32;
     fn min() -> f32;
     fn max() -> f32;
+    fn max_error() -> f32;
 }
 
 pub trait F64FixedPointFactory: Clone {
@@ -18,6 +19,7 @@ pub trait F64FixedPointFactory: Clone {
     fn to_float(value: <Self::VI as VariableIntTrait>::Packed) -> f64;
     fn min() -> f64;
     fn max() -> f64;
+    fn max_error() -> f64;
 }
 
 #[macro_export]
@@ -96,6 +98,10 @@ macro_rules! fixed_point_factory {
                 fn min() -> $source {
                     calculate_required_bits::calculate_min!($mode, $min, $max, $max_error) as $source
                 }
+
+                fn max_error() -> $source {
+                    $max_error as $source
+                }
             }
 
             impl $name {
@@ -5,6 +5,7 @@ pub mod config_file;
 pub mod console;
 pub mod delta_logger;
 pub mod device_config;
+pub mod device_log;
 pub mod vl_device_manager;
 pub mod file_types;
 pub mod fixed_point;
@@ -14,6 +15,7 @@ pub mod moving_average;
 mod multi_waker;
 pub mod rkyv_structs;
 pub mod rpc_channel;
+pub mod schema_hash;
 pub mod sensor_reading;
 pub mod sensor_snapshot;
 pub mod serialized_enum;
@@ -22,6 +24,7 @@ pub(crate) mod test_utils;
 pub mod ticker;
 pub mod unix_clock;
 pub mod variable_int;
+pub mod versioned;
 pub mod vlp;
 pub mod debounced_signal;
 pub mod zerocopy_channel;
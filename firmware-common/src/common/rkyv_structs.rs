@@ -2,7 +2,10 @@ use core::ops::{Index, IndexMut};
 
 use rkyv::{Archive, Deserialize, Serialize};
 
-#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+use super::schema_hash::SchemaHash;
+
+#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize, SchemaHash)]
+#[archive(check_bytes)]
 pub struct RkyvVec<const N: usize, T: Copy + Default> {
     pub data: [T; N],
     pub len: usize,
@@ -52,7 +55,8 @@ impl<const N: usize, T: Copy + Default> IndexMut<usize> for RkyvVec<N, T> {
     }
 }
 
-#[derive(Default, Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, defmt::Format, Archive, Serialize, Deserialize, SchemaHash)]
+#[archive(check_bytes)]
 pub struct RkyvString<const N: usize> {
     pub vec: RkyvVec<N, u8>,
 }
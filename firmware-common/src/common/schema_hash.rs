@@ -0,0 +1,171 @@
+//! Hash of the layout of the types sent over the rpc, the host compares it
+//! with the one reported by the firmware to detect incompatible builds.
+
+pub use bitslice_serialize_derive::SchemaHash;
+
+/// Derive it with `#[derive(SchemaHash)]`, which requires every field type to
+/// implement `SchemaHash` too
+pub trait SchemaHash {
+    const SCHEMA_HASH: u32;
+}
+
+/// FNV-1a
+#[derive(Clone, Copy)]
+pub struct SchemaHasher(u32);
+
+impl SchemaHasher {
+    pub const fn new() -> Self {
+        Self(0x811C_9DC5)
+    }
+
+    pub const fn write_bytes(self, bytes: &[u8]) -> Self {
+        const FNV_PRIME: u32 = 0x0100_0193;
+        let mut hash = self.0;
+        let mut i = 0;
+        while i < bytes.len() {
+            hash = (hash ^ bytes[i] as u32).wrapping_mul(FNV_PRIME);
+            i += 1;
+        }
+        Self(hash)
+    }
+
+    pub const fn write_u32(self, value: u32) -> Self {
+        self.write_bytes(&value.to_le_bytes())
+    }
+
+    /// Prefixed with the length, so "ab", "c" and "a", "bc" hash differently
+    pub const fn write_str(self, value: &str) -> Self {
+        self.write_u32(value.len() as u32)
+            .write_bytes(value.as_bytes())
+    }
+
+    pub const fn finish(self) -> u32 {
+        self.0
+    }
+}
+
+macro_rules! impl_primitive_schema_hash {
+    ($($ty:ty),*) => {
+        $(
+            impl SchemaHash for $ty {
+                const SCHEMA_HASH: u32 = SchemaHasher::new().write_str(stringify!($ty)).finish();
+            }
+        )*
+    };
+}
+
+impl_primitive_schema_hash!(
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    f32,
+    f64,
+    bool,
+    ()
+);
+
+impl<T: SchemaHash> SchemaHash for Option<T> {
+    const SCHEMA_HASH: u32 = SchemaHasher::new()
+        .write_str("Option")
+        .write_u32(T::SCHEMA_HASH)
+        .finish();
+}
+
+impl<T: SchemaHash, const N: usize> SchemaHash for [T; N] {
+    const SCHEMA_HASH: u32 = SchemaHasher::new()
+        .write_str("array")
+        .write_u32(T::SCHEMA_HASH)
+        .write_u32(N as u32)
+        .finish();
+}
+
+macro_rules! impl_tuple_schema_hash {
+    ($($name:ident),*) => {
+        impl<$($name: SchemaHash),*> SchemaHash for ($($name,)*) {
+            const SCHEMA_HASH: u32 = SchemaHasher::new()
+                .write_str("tuple")
+                $(.write_u32($name::SCHEMA_HASH))*
+                .finish();
+        }
+    };
+}
+
+impl_tuple_schema_hash!(A);
+impl_tuple_schema_hash!(A, B);
+impl_tuple_schema_hash!(A, B, C);
+impl_tuple_schema_hash!(A, B, C, D);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    struct Request {
+        file_id: u64,
+        file_type: Option<u16>,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    struct RenamedRequest {
+        file_id: u64,
+        file_type: Option<u16>,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    struct WiderRequest {
+        file_id: u64,
+        file_type: Option<u32>,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    struct Nested {
+        request: Request,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    struct SignedRequest {
+        file_id: i64,
+        file_type: Option<u16>,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    struct SignedNested {
+        request: SignedRequest,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    enum Status {
+        Ok = 0,
+        Error = 1,
+    }
+
+    #[derive(SchemaHash)]
+    #[allow(unused)]
+    enum RenumberedStatus {
+        Ok = 0,
+        Error = 2,
+    }
+
+    #[test]
+    fn test_schema_hash_follows_the_fields() {
+        assert_eq!(Request::SCHEMA_HASH, RenamedRequest::SCHEMA_HASH);
+        assert_ne!(Request::SCHEMA_HASH, WiderRequest::SCHEMA_HASH);
+        // same size, different nested type
+        assert_ne!(Nested::SCHEMA_HASH, SignedNested::SCHEMA_HASH);
+        assert_ne!(Status::SCHEMA_HASH, RenumberedStatus::SCHEMA_HASH);
+        assert_ne!(<[u8; 12]>::SCHEMA_HASH, <[u8; 16]>::SCHEMA_HASH);
+    }
+}
@@ -7,6 +7,8 @@ use super::{
     unix_clock::UnixClock,
 };
 
+pub use bitslice_serialize_derive::SensorData;
+
 pub trait SensorData:
     BitArraySerializable
     + Deltable<DeltaType: BitArraySerializable>
@@ -14,6 +16,10 @@ pub trait SensorData:
     + core::fmt::Debug
     + Clone
 {
+    /// Identifies the type in delta log headers, see `delta_logger::header::DELTA_LOG_SCHEMAS`
+    const SCHEMA_ID: u8;
+    /// Bump when the serialized format of the type changes
+    const SCHEMA_VERSION: u8 = 0;
 }
 
 #[derive(defmt::Format, Debug, Clone)]
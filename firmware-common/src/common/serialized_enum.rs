@@ -31,40 +31,40 @@ macro_rules! create_serialized_enum {
         )*
 
         impl $enum_name {
+            pub const MAX_RECORD_SIZE: usize = $crate::common::versioned::ENVELOPE_HEADER_SIZE
+                + core::mem::size_of::<<$enum_name as rkyv::Archive>::Archived>();
+
             pub fn write_to_buffer(&self, buffer: &mut [u8]) -> usize {
                 use rkyv::ser::Serializer;
                 use rkyv::{ser::serializers::BufferSerializer};
                 use core::mem::size_of;
+                use $crate::common::versioned::{EnvelopeHeader, Versioned, ENVELOPE_HEADER_SIZE};
 
                 match self {
                     $(
                         $enum_name::$log_type(log) => {
-                            buffer[0] = $log_type_i;
-                            let mut serializer = BufferSerializer::new(&mut buffer[1..]);
+                            let size = size_of::<<$log_type as rkyv::Archive>::Archived>();
+                            let (header_buffer, payload_buffer) = buffer.split_at_mut(ENVELOPE_HEADER_SIZE);
+                            let mut serializer = BufferSerializer::new(&mut payload_buffer[..size]);
                             serializer.serialize_value(log).unwrap();
-                            return size_of::<<$log_type as rkyv::Archive>::Archived>() + 1;
+                            let header = EnvelopeHeader::new($log_type_i, <$log_type as Versioned>::VERSION, &payload_buffer[..size]);
+                            header_buffer.copy_from_slice(&header.to_bytes());
+                            return size + ENVELOPE_HEADER_SIZE;
                         }
                     )*
                 };
             }
 
-            pub fn from_buffer(buffer: &[u8]) -> Option<Self> {
-                use rkyv::{archived_root, AlignedBytes};
-                use core::mem::size_of;
-                use paste::paste;
+            fn decode(header: &$crate::common::versioned::EnvelopeHeader, payload: &[u8]) -> Option<Self> {
+                use $crate::common::versioned::decode_versioned;
 
-                match buffer[0] {
+                if !header.matches(payload) {
+                    return None;
+                }
+                match header.type_tag {
                     $(
                         $log_type_i => {
-                            let size = size_of::<<$log_type as rkyv::Archive>::Archived>();
-                            if buffer.len() < size+1 {
-                                return None;
-                            }
-                            let mut aligned_buffer: AlignedBytes<{ size_of::<<$log_type as rkyv::Archive>::Archived>() }> = Default::default();
-                            aligned_buffer.as_mut().copy_from_slice(&buffer[1..size+1]);
-                            let archived = unsafe { archived_root::<$log_type>(aligned_buffer.as_ref()) };
-                            let deserialized = <paste!{ [<Archived $log_type>] } as rkyv::Deserialize<$log_type, rkyv::Infallible>>::deserialize(archived, &mut rkyv::Infallible).unwrap();
-                            Some($enum_name::$log_type(deserialized))
+                            decode_versioned::<$log_type>(header.version, payload).map($enum_name::$log_type)
                         }
                     )*
                     _ => {
@@ -72,16 +72,63 @@ macro_rules! create_serialized_enum {
                     }
                 }
             }
+
+            /// Decodes a record written before the envelope was introduced:
+            /// `[variant index: u8][archived bytes of version 0]`
+            fn decode_legacy(tag: u8, payload: &[u8]) -> Option<Self> {
+                use $crate::common::versioned::decode_versioned;
+
+                match tag {
+                    $(
+                        $log_type_i => {
+                            decode_versioned::<$log_type>(0, payload).map($enum_name::$log_type)
+                        }
+                    )*
+                    _ => {
+                        None
+                    }
+                }
+            }
+
+            /// Size of a legacy record with the variant index `tag`, including
+            /// the tag
+            fn legacy_record_size(tag: u8) -> Option<usize> {
+                use core::mem::size_of;
+
+                match tag {
+                    $(
+                        $log_type_i => Some(1 + size_of::<<$log_type as rkyv::Archive>::Archived>()),
+                    )*
+                    _ => None,
+                }
+            }
+
+            pub fn from_buffer(buffer: &[u8]) -> Option<Self> {
+                use $crate::common::versioned::{EnvelopeHeader, ENVELOPE_HEADER_SIZE};
+
+                if buffer.len() >= ENVELOPE_HEADER_SIZE {
+                    let header = EnvelopeHeader::from_bytes(buffer[..ENVELOPE_HEADER_SIZE].try_into().unwrap());
+                    if let Some(payload) = buffer.get(ENVELOPE_HEADER_SIZE..ENVELOPE_HEADER_SIZE + header.length as usize) {
+                        if let Some(log) = Self::decode(&header, payload) {
+                            return Some(log);
+                        }
+                    }
+                }
+
+                let tag = *buffer.first()?;
+                let size = Self::legacy_record_size(tag)?;
+                Self::decode_legacy(tag, buffer.get(1..size)?)
+            }
         }
 
         pub struct $writer_struct_name {
-            buffer: [u8; core::mem::size_of::<<$enum_name as rkyv::Archive>::Archived>()],
+            buffer: [u8; $enum_name::MAX_RECORD_SIZE],
         }
 
         impl $writer_struct_name {
             pub fn new() -> Self {
                 Self {
-                    buffer: [0; core::mem::size_of::<<$enum_name as rkyv::Archive>::Archived>()],
+                    buffer: [0; $enum_name::MAX_RECORD_SIZE],
                 }
             }
 
@@ -92,54 +139,125 @@ macro_rules! create_serialized_enum {
             }
         }
 
-        #[repr(C, align(16))]
         pub struct $reader_struct_name<R: embedded_io_async::Read> {
-            buffer: [u8; core::mem::size_of::<<$enum_name as rkyv::Archive>::Archived>()],
+            buffer: [u8; $enum_name::MAX_RECORD_SIZE],
+            /// Bytes at the start of `buffer` read from `reader` but not
+            /// consumed yet
+            filled: usize,
+            /// `None` until the first record is read
+            legacy: Option<bool>,
             reader: R,
         }
 
+        impl<R: embedded_io_async::Read> $reader_struct_name<R> {
+            /// Reads until `buffer[..size]` is filled, returns false on EOF
+            async fn fill(&mut self, size: usize) -> Result<bool, R::Error> {
+                while self.filled < size {
+                    let read = self.reader.read(&mut self.buffer[self.filled..size]).await?;
+                    if read == 0 {
+                        return Ok(false);
+                    }
+                    self.filled += read;
+                }
+                Ok(true)
+            }
+
+            fn consume(&mut self, size: usize) {
+                self.buffer.copy_within(size..self.filled, 0);
+                self.filled -= size;
+            }
+
+            /// Returns `Ok(None)` when the record can't be decoded, `Err(None)`
+            /// when the end of the records is reached
+            async fn read_enveloped(&mut self) -> Result<Option<$enum_name>, Option<R::Error>> {
+                use $crate::common::versioned::{EnvelopeHeader, ENVELOPE_HEADER_SIZE};
+
+                if !self.fill(ENVELOPE_HEADER_SIZE).await.map_err(Some)? {
+                    return Err(None);
+                }
+                let header = EnvelopeHeader::from_bytes(self.buffer[..ENVELOPE_HEADER_SIZE].try_into().unwrap());
+
+                // a corrupted length means the following records can't be located
+                let size = ENVELOPE_HEADER_SIZE + header.length as usize;
+                if size > self.buffer.len() || !self.fill(size).await.map_err(Some)? {
+                    return Err(None);
+                }
+                let log = $enum_name::decode(&header, &self.buffer[ENVELOPE_HEADER_SIZE..size]);
+                if log.is_none() {
+                    log_warn!("Skipping invalid record, type tag: {}, version: {}", header.type_tag, header.version);
+                }
+                self.consume(size);
+                Ok(log)
+            }
+
+            async fn read_legacy(&mut self) -> Result<Option<$enum_name>, Option<R::Error>> {
+                if !self.fill(1).await.map_err(Some)? {
+                    return Err(None);
+                }
+                // legacy records have no length, an unknown tag means the
+                // following records can't be located
+                let tag = self.buffer[0];
+                let Some(size) = $enum_name::legacy_record_size(tag) else {
+                    return Err(None);
+                };
+                if !self.fill(size).await.map_err(Some)? {
+                    return Err(None);
+                }
+                let log = $enum_name::decode_legacy(tag, &self.buffer[1..size]);
+                self.consume(size);
+                Ok(log)
+            }
+
+            /// Files written before the envelope was introduced start with a
+            /// legacy record, try the envelope first and fall back to it
+            async fn detect_format(&mut self) -> Result<Option<$enum_name>, Option<R::Error>> {
+                use $crate::common::versioned::{EnvelopeHeader, ENVELOPE_HEADER_SIZE};
+
+                let envelope_filled = self.fill(ENVELOPE_HEADER_SIZE).await.map_err(Some)?;
+                if envelope_filled {
+                    let header = EnvelopeHeader::from_bytes(self.buffer[..ENVELOPE_HEADER_SIZE].try_into().unwrap());
+                    let size = ENVELOPE_HEADER_SIZE + header.length as usize;
+                    if size <= self.buffer.len() && self.fill(size).await.map_err(Some)? {
+                        if let Some(log) = $enum_name::decode(&header, &self.buffer[ENVELOPE_HEADER_SIZE..size]) {
+                            self.legacy = Some(false);
+                            self.consume(size);
+                            return Ok(Some(log));
+                        }
+                    }
+                }
+
+                let log = self.read_legacy().await?;
+                if log.is_some() {
+                    self.legacy = Some(true);
+                }
+                Ok(log)
+            }
+        }
+
         impl<R: embedded_io_async::Read> $crate::common::serialized_enum::SerializedEnumReader<R> for $reader_struct_name<R> {
             type Output = $enum_name;
 
             fn new(reader: R) -> Self {
                 Self {
                     reader,
-                    buffer: [0; core::mem::size_of::<<$enum_name as rkyv::Archive>::Archived>()],
+                    buffer: [0; $enum_name::MAX_RECORD_SIZE],
+                    filled: 0,
+                    legacy: None,
                 }
             }
 
             async fn read_next(&mut self) -> Result<Option<$enum_name>, R::Error> {
-                use paste::paste;
-                use rkyv::archived_root;
-                match self.reader.read_exact(&mut self.buffer[..1]).await {
-                    Ok(_) => {},
-                    Err(embedded_io_async::ReadExactError::UnexpectedEof) => {
-                        return Ok(None)
-                    }
-                    Err(embedded_io_async::ReadExactError::Other(e)) =>{
-                        return Err(e)
-                    }
-                }
-                match self.buffer[0] {
-                    $(
-                        $log_type_i => {
-                            let size = core::mem::size_of::<<$log_type as rkyv::Archive>::Archived>();
-                            match self.reader.read_exact(&mut self.buffer[..size]).await {
-                                Ok(_) => {},
-                                Err(embedded_io_async::ReadExactError::UnexpectedEof) => {
-                                    return Ok(None)
-                                }
-                                Err(embedded_io_async::ReadExactError::Other(e)) =>{
-                                    return Err(e)
-                                }
-                            }
-                            let archived = unsafe { archived_root::<$log_type>(&self.buffer[..size]) };
-                            let deserialized = <paste!{ [<Archived $log_type>] } as rkyv::Deserialize<$log_type, rkyv::Infallible>>::deserialize(archived, &mut rkyv::Infallible).unwrap();
-                            Ok(Some($enum_name::$log_type(deserialized)))
-                        }
-                    )*
-                    _ => {
-                        Ok(None)
+                loop {
+                    let result = match self.legacy {
+                        None => self.detect_format().await,
+                        Some(false) => self.read_enveloped().await,
+                        Some(true) => self.read_legacy().await,
+                    };
+                    match result {
+                        Ok(Some(log)) => return Ok(Some(log)),
+                        Ok(None) => {}
+                        Err(None) => return Ok(None),
+                        Err(Some(e)) => return Err(e),
                     }
                 }
             }
@@ -154,21 +272,29 @@ macro_rules! create_serialized_enum {
 
 #[cfg(test)]
 mod file_logger_test {
-    use crate::common::{serialized_enum::SerializedEnumReader, test_utils::BufferWriter};
+    use crate::common::{
+        serialized_enum::SerializedEnumReader, test_utils::BufferWriter, versioned::Versioned,
+    };
     use core::assert_matches::assert_matches;
     use embedded_io_async::Write;
     use rkyv::{Archive, Deserialize, Serialize};
 
     #[derive(Archive, Deserialize, Serialize, Debug, Clone, defmt::Format)]
+    #[archive(check_bytes)]
     pub struct LogType1 {
         pub fielda: u32,
     }
 
     #[derive(Archive, Deserialize, Serialize, Debug, Clone, defmt::Format)]
+    #[archive(check_bytes)]
     pub struct LogType2 {
         pub fieldb: f32,
     }
 
+    impl Versioned for LogType1 {}
+
+    impl Versioned for LogType2 {}
+
     create_serialized_enum!(
         FileLogger, // this is the name of the struct
         FileLoggerReader,
@@ -205,4 +331,68 @@ mod file_logger_test {
             Ok(Some(Log::LogType2(LogType2 { fieldb: 1234.0 })))
         );
     }
+
+    #[futures_test::test]
+    async fn skip_corrupted_record() {
+        let mut buffer = [0u8; 4096];
+        let mut writer = BufferWriter::new(&mut buffer);
+
+        let mut logger = FileLogger::new();
+        for fielda in 0..3 {
+            logger
+                .write(&mut writer, &Log::LogType1(LogType1 { fielda }))
+                .await
+                .unwrap();
+        }
+
+        let record_size = writer.offset / 3;
+        writer.buffer[record_size * 2 - 1] ^= 0xFF;
+        let reader = writer.into_reader();
+
+        let mut logger_reader = FileLoggerReader::new(reader);
+        assert_matches!(
+            logger_reader.read_next().await,
+            Ok(Some(Log::LogType1(LogType1 { fielda: 0 })))
+        );
+        assert_matches!(
+            logger_reader.read_next().await,
+            Ok(Some(Log::LogType1(LogType1 { fielda: 2 })))
+        );
+        assert_matches!(logger_reader.read_next().await, Ok(None));
+    }
+
+    #[futures_test::test]
+    async fn read_legacy_records() {
+        use rkyv::ser::{serializers::BufferSerializer, Serializer};
+
+        // records written before the envelope: [variant index][archived bytes]
+        let mut buffer = [0u8; 4096];
+        let mut writer = BufferWriter::new(&mut buffer);
+        for fielda in 0..2 {
+            let mut serializer = BufferSerializer::new([0u8; 4]);
+            serializer.serialize_value(&LogType1 { fielda }).unwrap();
+            writer.write_all(&[0]).await.unwrap();
+            writer.write_all(&serializer.into_inner()).await.unwrap();
+        }
+        let mut serializer = BufferSerializer::new([0u8; 4]);
+        serializer.serialize_value(&LogType2 { fieldb: 1234.0 }).unwrap();
+        writer.write_all(&[1]).await.unwrap();
+        writer.write_all(&serializer.into_inner()).await.unwrap();
+        let reader = writer.into_reader();
+
+        let mut logger_reader = FileLoggerReader::new(reader);
+        assert_matches!(
+            logger_reader.read_next().await,
+            Ok(Some(Log::LogType1(LogType1 { fielda: 0 })))
+        );
+        assert_matches!(
+            logger_reader.read_next().await,
+            Ok(Some(Log::LogType1(LogType1 { fielda: 1 })))
+        );
+        assert_matches!(
+            logger_reader.read_next().await,
+            Ok(Some(Log::LogType2(LogType2 { fieldb: 1234.0 })))
+        );
+        assert_matches!(logger_reader.read_next().await, Ok(None));
+    }
 }
@@ -1,5 +1,9 @@
 use core::convert::Infallible;
 
+use embedded_hal_async::delay::DelayNs;
+
+use crate::driver::{clock::Clock, delay::Delay};
+
 pub(crate) struct BufferWriter<'a, const N: usize> {
     pub buffer: &'a mut [u8; N],
     pub offset: usize,
@@ -67,3 +71,39 @@ impl<'b, const N: usize> embedded_io_async::Read for BufferReader<'b, N> {
         }
     }
 }
+
+/// Clock backed by tokio time, use with `#[tokio::test(start_paused = true)]`
+/// so time only moves when every task is waiting or the test advances it
+#[derive(Clone)]
+pub(crate) struct TokioClock {
+    start: tokio::time::Instant,
+}
+
+impl TokioClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Clock for TokioClock {
+    fn now_ms(&self) -> f64 {
+        self.start.elapsed().as_secs_f64() * 1000.0
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct TokioDelay;
+
+impl DelayNs for TokioDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        tokio::time::sleep(std::time::Duration::from_nanos(ns as u64)).await;
+    }
+}
+
+impl Delay for TokioDelay {
+    async fn delay_ms(&self, ms: f64) {
+        tokio::time::sleep(std::time::Duration::from_secs_f64(ms / 1000.0)).await;
+    }
+}
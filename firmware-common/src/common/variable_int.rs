@@ -9,6 +9,7 @@ use rkyv::{
 };
 
 use super::delta_logger::bitslice_primitive::BitSlicePrimitive;
+use super::schema_hash::{SchemaHash, SchemaHasher};
 
 pub trait VariableIntTrait {
     type Base;
@@ -42,6 +43,14 @@ macro_rules! impl_variable_int {
             }
         }
 
+        impl SchemaHash for Integer<$base_type, packed_bits::Bits<$bits>> {
+            const SCHEMA_HASH: u32 = SchemaHasher::new()
+                .write_str("Integer")
+                .write_u32(<$base_type>::SCHEMA_HASH)
+                .write_u32($bits)
+                .finish();
+        }
+
         impl<S: Fallible + ?Sized> SerializeWith<Integer<$base_type, packed_bits::Bits<$bits>>, S>
             for VariableIntRkyvWrapper
         {
@@ -0,0 +1,216 @@
+use core::mem::size_of;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use rkyv::{
+    validation::{check_archived_root_with_context, validators::ArchiveValidator},
+    AlignedBytes, Archive, CheckBytes, Deserialize,
+};
+
+pub const ENVELOPE_HEADER_SIZE: usize = 12;
+
+/// Precedes every archived config and serialized enum record, so readers can
+/// reject corrupted data and decode older versions of a type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct EnvelopeHeader {
+    /// File type id for configs, variant index for serialized enums
+    pub type_tag: u16,
+    /// `Versioned::VERSION` of the payload
+    pub version: u16,
+    /// Length of the payload in bytes
+    pub length: u32,
+    /// CRC of the payload
+    pub crc: u32,
+}
+
+impl EnvelopeHeader {
+    pub fn new(type_tag: u16, version: u16, payload: &[u8]) -> Self {
+        Self {
+            type_tag,
+            version,
+            length: payload.len() as u32,
+            crc: envelope_crc(payload),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; ENVELOPE_HEADER_SIZE] {
+        let mut bytes = [0u8; ENVELOPE_HEADER_SIZE];
+        bytes[0..2].copy_from_slice(&self.type_tag.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.version.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; ENVELOPE_HEADER_SIZE]) -> Self {
+        Self {
+            type_tag: u16::from_le_bytes([bytes[0], bytes[1]]),
+            version: u16::from_le_bytes([bytes[2], bytes[3]]),
+            length: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            crc: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+        }
+    }
+
+    pub fn matches(&self, payload: &[u8]) -> bool {
+        self.length as usize == payload.len() && self.crc == envelope_crc(payload)
+    }
+}
+
+pub fn envelope_crc(payload: &[u8]) -> u32 {
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    crc.checksum(payload)
+}
+
+/// Converts the payload of an older version
+pub struct Migration<T> {
+    pub from_version: u16,
+    pub migrate: fn(&[u8]) -> Option<T>,
+}
+
+/// A type stored in an envelope.
+///
+/// Bump `VERSION` whenever the archived layout changes, keep the old
+/// definition around and register `migrate_from::<OldType, Self>` for the
+/// old version in `MIGRATIONS`.
+pub trait Versioned: Archive + Sized {
+    const VERSION: u16 = 0;
+    const MIGRATIONS: &'static [Migration<Self>] = &[];
+}
+
+/// Validates the archived bytes with `check_archived_root` before
+/// deserializing them. Only the archive validator is used, the shared
+/// pointer validator needs an allocator and none of our types use shared
+/// pointers.
+pub fn decode_archived<T>(payload: &[u8]) -> Option<T>
+where
+    T: Archive,
+    T::Archived: Deserialize<T, rkyv::Infallible> + for<'a> CheckBytes<ArchiveValidator<'a>>,
+    [(); size_of::<T::Archived>()]:,
+{
+    if payload.len() != size_of::<T::Archived>() {
+        return None;
+    }
+    let mut buffer: AlignedBytes<{ size_of::<T::Archived>() }> = Default::default();
+    buffer.as_mut().copy_from_slice(payload);
+
+    let mut validator = ArchiveValidator::new(buffer.as_ref());
+    let archived =
+        check_archived_root_with_context::<T, _>(buffer.as_ref(), &mut validator).ok()?;
+    Some(
+        <T::Archived as Deserialize<T, rkyv::Infallible>>::deserialize(
+            archived,
+            &mut rkyv::Infallible,
+        )
+        .unwrap(),
+    )
+}
+
+/// Decodes a payload of `version`, running the registered migration if it
+/// is an older version
+pub fn decode_versioned<T>(version: u16, payload: &[u8]) -> Option<T>
+where
+    T: Versioned,
+    T::Archived: Deserialize<T, rkyv::Infallible> + for<'a> CheckBytes<ArchiveValidator<'a>>,
+    [(); size_of::<T::Archived>()]:,
+{
+    if version == T::VERSION {
+        return decode_archived::<T>(payload);
+    }
+
+    if let Some(migration) = T::MIGRATIONS.iter().find(|m| m.from_version == version) {
+        (migration.migrate)(payload)
+    } else {
+        log_warn!("No migration from version {} to {}", version, T::VERSION);
+        None
+    }
+}
+
+/// Migration from the archived layout of `Old`
+pub fn migrate_from<Old, T>(payload: &[u8]) -> Option<T>
+where
+    Old: Archive + Into<T>,
+    Old::Archived: Deserialize<Old, rkyv::Infallible> + for<'a> CheckBytes<ArchiveValidator<'a>>,
+    [(); size_of::<Old::Archived>()]:,
+{
+    decode_archived::<Old>(payload).map(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use rkyv::{
+        ser::{serializers::BufferSerializer, Serializer},
+        Serialize,
+    };
+
+    use super::*;
+
+    #[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[archive(check_bytes)]
+    struct ConfigV0 {
+        a: u32,
+    }
+
+    #[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[archive(check_bytes)]
+    struct Config {
+        a: u32,
+        b: bool,
+    }
+
+    impl From<ConfigV0> for Config {
+        fn from(old: ConfigV0) -> Self {
+            Self { a: old.a, b: true }
+        }
+    }
+
+    impl Versioned for Config {
+        const VERSION: u16 = 1;
+        const MIGRATIONS: &'static [Migration<Self>] = &[Migration {
+            from_version: 0,
+            migrate: migrate_from::<ConfigV0, Config>,
+        }];
+    }
+
+    fn archive<T>(value: &T) -> [u8; size_of::<T::Archived>()]
+    where
+        T: Archive + Serialize<BufferSerializer<[u8; size_of::<T::Archived>()]>>,
+        [(); size_of::<T::Archived>()]:,
+    {
+        let mut serializer = BufferSerializer::new([0u8; size_of::<T::Archived>()]);
+        serializer.serialize_value(value).unwrap();
+        serializer.into_inner()
+    }
+
+    #[test]
+    fn test_envelope_header() {
+        let payload = [1u8, 2, 3, 4, 5];
+        let header = EnvelopeHeader::new(8, 3, &payload);
+        let decoded = EnvelopeHeader::from_bytes(&header.to_bytes());
+        assert_eq!(decoded, header);
+        assert!(decoded.matches(&payload));
+        assert!(!decoded.matches(&[1, 2, 3, 4, 6]));
+        assert!(!decoded.matches(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_decode_versioned() {
+        let config = Config { a: 42, b: false };
+        let bytes = archive(&config);
+        assert_eq!(decode_versioned::<Config>(1, &bytes), Some(config));
+
+        let old_bytes = archive(&ConfigV0 { a: 7 });
+        assert_eq!(
+            decode_versioned::<Config>(0, &old_bytes),
+            Some(Config { a: 7, b: true })
+        );
+        assert_eq!(decode_versioned::<Config>(2, &bytes), None);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut bytes = archive(&Config { a: 42, b: false });
+        let bool_offset = bytes.len() - 4;
+        bytes[bool_offset] = 2;
+        assert_eq!(decode_archived::<Config>(&bytes), None);
+        assert_eq!(decode_archived::<Config>(&bytes[1..]), None);
+    }
+}
@@ -307,7 +307,7 @@ macro_rules! vl_device_manager_type {
 (mut) => { &mut VLDeviceManager<
     impl Debugger,
     impl SysReset,
-    impl Clock,
+    impl Clock + Sync,
     impl Delay,
     impl Flash,
     impl Crc,
@@ -0,0 +1,464 @@
+use core::mem::size_of;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use hmac::{Hmac, Mac};
+use libm::fabs;
+use rkyv::{
+    ser::{serializers::BufferSerializer, Serializer},
+    validation::validators::ArchiveValidator,
+    Archive, CheckBytes, Deserialize, Serialize,
+};
+use sha2::Sha256;
+
+use crate::{
+    avionics::flight_profile::FlightProfile,
+    common::{
+        device_config::{DeviceConfig, LoraConfig},
+        versioned::{decode_archived, Versioned},
+    },
+};
+
+use super::packet::{
+    ConfigChunkPacket, ConfigCommitPacket, ConfigUpdateStatus, ConfigUpdateType,
+    CONFIG_CHUNK_SIZE,
+};
+
+/// The part of `DeviceConfig` that can be changed over the air,
+/// takes effect after the rocket reboots
+#[derive(Clone, Debug, defmt::Format, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct DeviceConfigUpdate {
+    pub lora: LoraConfig,
+    pub vlp_device_id: Option<u8>,
+}
+
+impl DeviceConfigUpdate {
+    pub fn apply(&self, config: &mut DeviceConfig) {
+        config.lora = self.lora.clone();
+        config.vlp_device_id = self.vlp_device_id;
+    }
+}
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+pub const MAX_CONFIG_UPDATE_LENGTH: usize = max(
+    size_of::<<FlightProfile as Archive>::Archived>(),
+    size_of::<<DeviceConfigUpdate as Archive>::Archived>(),
+);
+const MAX_CONFIG_UPDATE_CHUNKS: usize = MAX_CONFIG_UPDATE_LENGTH.div_ceil(CONFIG_CHUNK_SIZE);
+
+impl ConfigUpdateType {
+    /// Length of the serialized config
+    pub fn length(&self) -> usize {
+        match self {
+            ConfigUpdateType::FlightProfile => size_of::<<FlightProfile as Archive>::Archived>(),
+            ConfigUpdateType::DeviceConfig => {
+                size_of::<<DeviceConfigUpdate as Archive>::Archived>()
+            }
+        }
+    }
+}
+
+pub const CONFIG_UPDATE_MAC_SIZE: usize = 16;
+/// Commits further than this from the unix time of the rocket are rejected,
+/// only checked once the rocket has unix time
+pub const CONFIG_UPDATE_MAX_CLOCK_OFFSET_MS: f64 = 5.0 * 60.0 * 1000.0;
+
+/// HMAC-SHA256 of a config update keyed with the LoRa key, truncated to
+/// `CONFIG_UPDATE_MAC_SIZE` bytes. Covers the commit timestamp, so a recorded
+/// update can not be replayed as a newer one.
+pub fn config_update_mac(
+    config_type: ConfigUpdateType,
+    transfer_id: u8,
+    timestamp: f64,
+    data: &[u8],
+    key: &[u8; 32],
+) -> [u8; CONFIG_UPDATE_MAC_SIZE] {
+    let mac = config_update_hmac(config_type, transfer_id, timestamp, data, key);
+    let mut truncated = [0u8; CONFIG_UPDATE_MAC_SIZE];
+    truncated.copy_from_slice(&mac.finalize().into_bytes()[..CONFIG_UPDATE_MAC_SIZE]);
+    truncated
+}
+
+fn config_update_hmac(
+    config_type: ConfigUpdateType,
+    transfer_id: u8,
+    timestamp: f64,
+    data: &[u8],
+    key: &[u8; 32],
+) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(&[config_type as u8, transfer_id]);
+    mac.update(&timestamp.to_le_bytes());
+    mac.update(&(data.len() as u32).to_le_bytes());
+    mac.update(data);
+    mac
+}
+
+/// Persisted by the rocket so an update can not be replayed after a reboot
+#[derive(Clone, Debug, Default, defmt::Format, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct ConfigUpdateState {
+    /// Timestamp of the last applied `ConfigCommitPacket`
+    pub last_applied_timestamp: f64,
+}
+
+impl Versioned for ConfigUpdateState {}
+
+/// Hash of the applied config the rocket reports back
+pub fn config_hash(data: &[u8]) -> u32 {
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    crc.checksum(data)
+}
+
+/// Serializes the config into the archived bytes `ConfigFile` stores after
+/// its envelope header
+pub fn serialize_config<T>(config: &T) -> [u8; size_of::<T::Archived>()]
+where
+    T: Archive + Serialize<BufferSerializer<[u8; size_of::<T::Archived>()]>>,
+    [(); size_of::<T::Archived>()]:,
+{
+    let buffer = [0u8; size_of::<T::Archived>()];
+    let mut serializer = BufferSerializer::new(buffer);
+    serializer.serialize_value(config).unwrap();
+    serializer.into_inner()
+}
+
+/// `data` should have been validated by `ConfigUpdateStager::validate`,
+/// returns `None` if it is not a valid archive of `T`
+pub fn deserialize_config<T>(data: &[u8]) -> Option<T>
+where
+    T: Archive,
+    T::Archived: Deserialize<T, rkyv::Infallible> + for<'a> CheckBytes<ArchiveValidator<'a>>,
+    [(); size_of::<T::Archived>()]:,
+{
+    decode_archived::<T>(data)
+}
+
+/// Splits a serialized config into chunk packets
+pub fn create_config_chunks(
+    config_type: ConfigUpdateType,
+    transfer_id: u8,
+    data: &[u8],
+) -> impl Iterator<Item = ConfigChunkPacket> + '_ {
+    data.chunks(CONFIG_CHUNK_SIZE)
+        .enumerate()
+        .map(move |(i, chunk)| {
+            let mut chunk_data = [0u8; CONFIG_CHUNK_SIZE];
+            chunk_data[..chunk.len()].copy_from_slice(chunk);
+            ConfigChunkPacket {
+                config_type,
+                transfer_id,
+                chunk_index: i as u8,
+                length: chunk.len() as u8,
+                data: chunk_data,
+            }
+        })
+}
+
+/// Collects the chunks of a config update on the rocket
+pub struct ConfigUpdateStager {
+    config_type: ConfigUpdateType,
+    transfer_id: Option<u8>,
+    buffer: [u8; MAX_CONFIG_UPDATE_LENGTH],
+    received_chunks: [bool; MAX_CONFIG_UPDATE_CHUNKS],
+    /// The last applied commit and the hash of its config, a retried commit
+    /// whose result got lost is answered with the same result
+    applied: Option<(ConfigCommitPacket, u32)>,
+    last_applied_timestamp: f64,
+}
+
+impl ConfigUpdateStager {
+    /// `state` is the persisted `ConfigUpdateState`
+    pub fn new(state: &ConfigUpdateState) -> Self {
+        Self {
+            config_type: ConfigUpdateType::FlightProfile,
+            transfer_id: None,
+            buffer: [0; MAX_CONFIG_UPDATE_LENGTH],
+            received_chunks: [false; MAX_CONFIG_UPDATE_CHUNKS],
+            applied: None,
+            last_applied_timestamp: state.last_applied_timestamp,
+        }
+    }
+
+    /// A chunk from a different transfer discards the chunks staged so far
+    pub fn add_chunk(&mut self, chunk: &ConfigChunkPacket) {
+        if self.transfer_id != Some(chunk.transfer_id) || self.config_type != chunk.config_type {
+            self.clear();
+            self.transfer_id = Some(chunk.transfer_id);
+            self.config_type = chunk.config_type;
+        }
+
+        let offset = chunk.chunk_index as usize * CONFIG_CHUNK_SIZE;
+        let length = chunk.length as usize;
+        if chunk.chunk_index as usize >= MAX_CONFIG_UPDATE_CHUNKS
+            || length > CONFIG_CHUNK_SIZE
+            || offset + length > MAX_CONFIG_UPDATE_LENGTH
+        {
+            log_warn!("Config chunk {} out of range", chunk.chunk_index);
+            return;
+        }
+
+        self.buffer[offset..(offset + length)].copy_from_slice(&chunk.data[..length]);
+        self.received_chunks[chunk.chunk_index as usize] = true;
+    }
+
+    /// Hash of the applied config if `commit` is a retry of the last applied
+    /// commit
+    pub fn applied_hash(&self, commit: &ConfigCommitPacket) -> Option<u32> {
+        match &self.applied {
+            Some((applied, hash)) if applied == commit => Some(*hash),
+            _ => None,
+        }
+    }
+
+    /// Returns the staged config if all of its chunks are received, the MAC
+    /// matches and the commit is newer than the last applied one.
+    /// `unix_now` is the unix time of the rocket if known.
+    pub fn validate(
+        &self,
+        commit: &ConfigCommitPacket,
+        key: &[u8; 32],
+        unix_now: Option<f64>,
+    ) -> Result<&[u8], ConfigUpdateStatus> {
+        if self.transfer_id != Some(commit.transfer_id) || self.config_type != commit.config_type {
+            return Err(ConfigUpdateStatus::Incomplete);
+        }
+
+        let length = commit.length as usize;
+        if length != commit.config_type.length() {
+            return Err(ConfigUpdateStatus::Invalid);
+        }
+
+        let chunks = length.div_ceil(CONFIG_CHUNK_SIZE);
+        if !self.received_chunks[..chunks].iter().all(|received| *received) {
+            return Err(ConfigUpdateStatus::Incomplete);
+        }
+
+        let data = &self.buffer[..length];
+        let mac = config_update_hmac(
+            commit.config_type,
+            commit.transfer_id,
+            commit.timestamp,
+            data,
+            key,
+        );
+        // constant time comparison
+        if mac.verify_truncated_left(&commit.mac).is_err() {
+            return Err(ConfigUpdateStatus::Invalid);
+        }
+
+        if commit.timestamp <= self.last_applied_timestamp {
+            return Err(ConfigUpdateStatus::Replayed);
+        }
+        if let Some(unix_now) = unix_now
+            && fabs(unix_now - commit.timestamp) > CONFIG_UPDATE_MAX_CLOCK_OFFSET_MS
+        {
+            return Err(ConfigUpdateStatus::Replayed);
+        }
+
+        Ok(data)
+    }
+
+    /// Call after the config of `commit` is written, returns the state to
+    /// persist
+    pub fn mark_applied(&mut self, commit: &ConfigCommitPacket, hash: u32) -> ConfigUpdateState {
+        self.clear();
+        self.applied = Some((commit.clone(), hash));
+        self.last_applied_timestamp = commit.timestamp;
+        ConfigUpdateState {
+            last_applied_timestamp: commit.timestamp,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.transfer_id = None;
+        self.received_chunks = [false; MAX_CONFIG_UPDATE_CHUNKS];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        avionics::flight_profile::PyroSelection,
+        common::{
+            delta_logger::prelude::{BitArraySerializable, BitSliceReader, BitSliceWriter},
+            vlp::packet::ConfigUpdateResultPacket,
+        },
+    };
+
+    use super::*;
+
+    fn create_flight_profile() -> FlightProfile {
+        FlightProfile {
+            drogue_pyro: PyroSelection::Pyro1,
+            drogue_chute_minimum_time_ms: 1000.0,
+            drogue_chute_minimum_altitude_agl: 100.0,
+            drogue_chute_delay_ms: 500.0,
+            main_pyro: PyroSelection::Pyro2,
+            main_chute_altitude_agl: 300.0,
+            main_chute_delay_ms: 0.0,
+            drouge_to_main_ms: 107000.0,
+            main_to_landed_ms: 76000.0,
+        }
+    }
+
+    fn create_commit(
+        data: &[u8],
+        transfer_id: u8,
+        timestamp: f64,
+        key: &[u8; 32],
+    ) -> ConfigCommitPacket {
+        ConfigCommitPacket {
+            timestamp,
+            config_type: ConfigUpdateType::FlightProfile,
+            transfer_id,
+            length: data.len() as u32,
+            mac: config_update_mac(
+                ConfigUpdateType::FlightProfile,
+                transfer_id,
+                timestamp,
+                data,
+                key,
+            ),
+        }
+    }
+
+    fn stage(stager: &mut ConfigUpdateStager, transfer_id: u8, data: &[u8]) {
+        for chunk in create_config_chunks(ConfigUpdateType::FlightProfile, transfer_id, data) {
+            stager.add_chunk(&chunk);
+        }
+    }
+
+    #[test]
+    fn test_stage_and_validate() {
+        let key = [0x69u8; 32];
+        let data = serialize_config(&create_flight_profile());
+        let commit = create_commit(&data, 1, 1000.0, &key);
+
+        let mut stager = ConfigUpdateStager::new(&ConfigUpdateState::default());
+        let mut chunks = create_config_chunks(ConfigUpdateType::FlightProfile, 1, &data)
+            .collect::<std::vec::Vec<_>>();
+        let last_chunk = chunks.pop().unwrap();
+        for chunk in chunks.iter().rev() {
+            stager.add_chunk(chunk);
+        }
+        assert_eq!(
+            stager.validate(&commit, &key, None),
+            Err(ConfigUpdateStatus::Incomplete)
+        );
+
+        stager.add_chunk(&last_chunk);
+        assert_eq!(stager.validate(&commit, &key, None), Ok(&data[..]));
+        assert_eq!(
+            stager.validate(&commit, &[0x42u8; 32], None),
+            Err(ConfigUpdateStatus::Invalid)
+        );
+
+        let mut forged = commit.clone();
+        forged.mac[0] ^= 1;
+        assert_eq!(
+            stager.validate(&forged, &key, None),
+            Err(ConfigUpdateStatus::Invalid)
+        );
+
+        let profile: FlightProfile =
+            deserialize_config(stager.validate(&commit, &key, None).unwrap()).unwrap();
+        assert_eq!(profile.main_chute_altitude_agl, 300.0);
+        assert_eq!(profile.main_pyro, PyroSelection::Pyro2);
+    }
+
+    #[test]
+    fn test_replay_rejected() {
+        let key = [0x69u8; 32];
+        let data = serialize_config(&create_flight_profile());
+        let commit = create_commit(&data, 1, 1000.0, &key);
+
+        let mut stager = ConfigUpdateStager::new(&ConfigUpdateState::default());
+        stage(&mut stager, 1, &data);
+        assert_eq!(
+            stager.validate(
+                &commit,
+                &key,
+                Some(1000.0 + CONFIG_UPDATE_MAX_CLOCK_OFFSET_MS * 2.0)
+            ),
+            Err(ConfigUpdateStatus::Replayed)
+        );
+        let hash = config_hash(stager.validate(&commit, &key, Some(2000.0)).unwrap());
+        let state = stager.mark_applied(&commit, hash);
+        assert_eq!(state.last_applied_timestamp, 1000.0);
+
+        // retried commit whose result got lost
+        assert_eq!(stager.applied_hash(&commit), Some(hash));
+
+        // replayed after a reboot
+        let mut stager = ConfigUpdateStager::new(&state);
+        assert_eq!(stager.applied_hash(&commit), None);
+        stage(&mut stager, 1, &data);
+        assert_eq!(
+            stager.validate(&commit, &key, None),
+            Err(ConfigUpdateStatus::Replayed)
+        );
+
+        let newer_commit = create_commit(&data, 2, 3000.0, &key);
+        stage(&mut stager, 2, &data);
+        assert_eq!(stager.validate(&newer_commit, &key, None), Ok(&data[..]));
+    }
+
+    #[test]
+    fn test_new_transfer_discards_staged_chunks() {
+        let key = [0x69u8; 32];
+        let data = serialize_config(&create_flight_profile());
+
+        let mut stager = ConfigUpdateStager::new(&ConfigUpdateState::default());
+        stage(&mut stager, 1, &data);
+        let first_chunk = create_config_chunks(ConfigUpdateType::FlightProfile, 2, &data)
+            .next()
+            .unwrap();
+        stager.add_chunk(&first_chunk);
+
+        assert_eq!(
+            stager.validate(&create_commit(&data, 1, 1000.0, &key), &key, None),
+            Err(ConfigUpdateStatus::Incomplete)
+        );
+        assert_eq!(
+            stager.validate(&create_commit(&data, 2, 1000.0, &key), &key, None),
+            Err(ConfigUpdateStatus::Incomplete)
+        );
+    }
+
+    #[test]
+    fn test_write_failed() {
+        let key = [0x69u8; 32];
+        let data = serialize_config(&create_flight_profile());
+        let commit = create_commit(&data, 1, 1000.0, &key);
+
+        let mut stager = ConfigUpdateStager::new(&ConfigUpdateState::default());
+        stage(&mut stager, 1, &data);
+        assert_eq!(stager.validate(&commit, &key, None), Ok(&data[..]));
+
+        // the write failed so the commit is not marked applied, a retry is
+        // validated again from the staged chunks
+        assert_eq!(stager.applied_hash(&commit), None);
+        assert_eq!(stager.validate(&commit, &key, None), Ok(&data[..]));
+
+        let result = ConfigUpdateResultPacket {
+            config_type: ConfigUpdateType::FlightProfile,
+            transfer_id: 1,
+            status: ConfigUpdateStatus::WriteFailed,
+            hash: 0,
+        };
+        let mut writer = BitSliceWriter::<32>::default();
+        result.serialize(&mut writer);
+        let mut reader = BitSliceReader::<32>::default();
+        reader.replenish_bytes(writer.view_all_data_slice());
+        let decoded = ConfigUpdateResultPacket::deserialize(&mut reader);
+        assert_eq!(decoded.status, ConfigUpdateStatus::WriteFailed);
+    }
+}
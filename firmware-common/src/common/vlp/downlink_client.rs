@@ -10,15 +10,15 @@ use lora_phy::{
 };
 
 use super::{
-    lora_phy::LoraPhy,
+    lora_phy::{LoraPhy, VLPPhy},
     packet::{AckPacket, VLPDownlinkPacket, VLPUplinkPacket},
-    packet_builder::{VLPPacketBuilder, MAX_VLP_PACKET_SIZE},
+    packet_builder::{VLPPacketBuilder, MAX_VLP_PACKET_SIZE, VLP_BROADCAST_DEVICE_ID},
 };
 
 // VLP client running on the GCM
 pub struct VLPDownlinkClient {
-    tx_signal: Signal<NoopRawMutex, VLPUplinkPacket>,
-    rx_signal: Signal<NoopRawMutex, (VLPDownlinkPacket, PacketStatus)>,
+    tx_signal: Signal<NoopRawMutex, (u8, VLPUplinkPacket)>,
+    rx_signal: Signal<NoopRawMutex, (u8, VLPDownlinkPacket, PacketStatus)>,
     send_success_signal: Signal<NoopRawMutex, Option<PacketStatus>>,
 }
 
@@ -32,13 +32,15 @@ impl VLPDownlinkClient {
     }
 
     /// Calling send multiple times concurrently is not supported
+    /// `device_id` is the destination rocket, or `VLP_BROADCAST_DEVICE_ID`
     /// Returns the packet status of the ack message
-    pub async fn send(&self, packet: VLPUplinkPacket) -> Option<PacketStatus> {
-        self.tx_signal.signal(packet);
+    pub async fn send(&self, device_id: u8, packet: VLPUplinkPacket) -> Option<PacketStatus> {
+        self.tx_signal.signal((device_id, packet));
         self.send_success_signal.wait().await
     }
 
-    pub async fn wait_receive(&self) -> (VLPDownlinkPacket, PacketStatus) {
+    /// Returns the source device id, the packet and its status
+    pub async fn wait_receive(&self) -> (u8, VLPDownlinkPacket, PacketStatus) {
         self.rx_signal.wait().await
     }
 
@@ -50,8 +52,20 @@ impl VLPDownlinkClient {
         unix_clock: UnixClock<'a, impl Clock>,
         key: &[u8; 32],
     ) {
-        let mut packet_builder = VLPPacketBuilder::new(unix_clock.clone(), lora_config.into(), key);
         let mut lora = LoraPhy::new(lora, lora_config);
+        self.run_with_phy(delay, &mut lora, lora_config, unix_clock, key)
+            .await
+    }
+
+    pub async fn run_with_phy<'a>(
+        &self,
+        delay: impl Delay,
+        lora: &mut impl VLPPhy,
+        lora_config: &LoraConfig,
+        unix_clock: UnixClock<'a, impl Clock>,
+        key: &[u8; 32],
+    ) {
+        let mut packet_builder = VLPPacketBuilder::new(unix_clock.clone(), lora_config.into(), key);
         let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
 
         loop {
@@ -60,8 +74,8 @@ impl VLPDownlinkClient {
                     Ok(packet_status) => {
                         // try to deserialize the packet
                         match packet_builder.deserialize_downlink(&buffer) {
-                            Ok(packet) => {
-                                self.rx_signal.signal((packet, packet_status));
+                            Ok((source, packet)) => {
+                                self.rx_signal.signal((source, packet, packet_status));
                             }
                             Err(_) => {
                                 // deserialize error
@@ -73,13 +87,13 @@ impl VLPDownlinkClient {
                 }
 
                 if self.tx_signal.signaled() {
-                    let tx_packet = self.tx_signal.wait().await;
-                    log_info!("Sending message: {:?}", tx_packet);
+                    let (destination, tx_packet) = self.tx_signal.wait().await;
+                    log_info!("Sending message to device {}: {:?}", destination, tx_packet);
 
                     let mut ack_packet_status: Option<PacketStatus> = None;
                     for i in 0..5 {
                         packet_builder
-                            .serialize_uplink(&mut buffer, &tx_packet)
+                            .serialize_uplink(&mut buffer, destination, &tx_packet)
                             .unwrap();
                         lora.tx(&buffer).await?;
 
@@ -87,24 +101,28 @@ impl VLPDownlinkClient {
                             Ok(packet_status) => {
                                 // try to deserialize the packet
                                 match packet_builder.deserialize_downlink(&buffer) {
-                                    Ok(packet) => {
-                                        if matches!(
+                                    Ok((source, VLPDownlinkPacket::AckPacket(AckPacket { .. })))
+                                        if destination == VLP_BROADCAST_DEVICE_ID
+                                            || destination == source =>
+                                    {
+                                        log_info!(
+                                            "Ack received from device {}: rssi: {}, snr: {}",
+                                            source,
+                                            packet_status.rssi,
+                                            packet_status.snr
+                                        );
+                                        ack_packet_status = Some(packet_status);
+                                        break;
+                                    }
+                                    Ok((source, packet)) => {
+                                        log_warn!(
+                                            "Expected AckPacket from device {}, but received {:?} from device {}",
+                                            destination,
                                             packet,
-                                            VLPDownlinkPacket::AckPacket(AckPacket { .. })
-                                        ) {
-                                            log_info!(
-                                                "Ack received: rssi: {}, snr: {}",
-                                                packet_status.rssi,
-                                                packet_status.snr
-                                            );
-                                            ack_packet_status = Some(packet_status);
-                                            break;
-                                        } else {
-                                            log_warn!(
-                                                "Expected AckPacket, but received {:?}",
-                                                packet
-                                            );
-                                        }
+                                            source
+                                        );
+                                        // don't drop telemetry from other devices
+                                        self.rx_signal.signal((source, packet, packet_status));
                                     }
                                     Err(_) => {
                                         // deserialize error
@@ -7,6 +7,18 @@ use lora_phy::{
     LoRa, RxMode,
 };
 
+/// The radio operations the VLP clients need, implemented by `LoraPhy` for
+/// real hardware and by `VirtualLoraPhy` for testing
+pub trait VLPPhy {
+    async fn tx<const N: usize>(&mut self, buffer: &Vec<u8, N>) -> Result<(), RadioError>;
+
+    async fn rx<const N: usize>(
+        &mut self,
+        listen_mode: RxMode,
+        buffer: &mut Vec<u8, N>,
+    ) -> Result<PacketStatus, RadioError>;
+}
+
 pub struct LoraPhy<'a, 'b, LK: RadioKind, DL: DelayNs> {
     lora: &'a mut LoRa<LK, DL>,
     lora_config: &'b LoraConfig,
@@ -16,8 +28,10 @@ impl<'a, 'b, LK: RadioKind, DL: DelayNs> LoraPhy<'a, 'b, LK, DL> {
     pub fn new(lora: &'a mut LoRa<LK, DL>, lora_config: &'b LoraConfig) -> Self {
         LoraPhy { lora, lora_config }
     }
+}
 
-    pub async fn tx<const N: usize>(&mut self, buffer: &Vec<u8, N>) -> Result<(), RadioError> {
+impl<'a, 'b, LK: RadioKind, DL: DelayNs> VLPPhy for LoraPhy<'a, 'b, LK, DL> {
+    async fn tx<const N: usize>(&mut self, buffer: &Vec<u8, N>) -> Result<(), RadioError> {
         let modulation_params = self.lora.create_modulation_params(
             self.lora_config.sf_phy(),
             self.lora_config.bw_phy(),
@@ -40,7 +54,7 @@ impl<'a, 'b, LK: RadioKind, DL: DelayNs> LoraPhy<'a, 'b, LK, DL> {
         Ok(())
     }
 
-    pub async fn rx<const N: usize>(
+    async fn rx<const N: usize>(
         &mut self,
         listen_mode: RxMode,
         buffer: &mut Vec<u8, N>,
@@ -1,6 +1,9 @@
+pub mod config_update;
 pub mod downlink_client;
 pub mod lora_phy;
 pub mod packet;
 pub mod packet_builder;
 pub mod telemetry_packet;
 pub mod uplink_client;
+#[cfg(any(test, feature = "std"))]
+pub mod virtual_lora;
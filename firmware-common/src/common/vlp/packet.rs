@@ -1,165 +1,229 @@
-use crate::{avionics::flight_profile::PyroSelection, common::delta_logger::prelude::*};
-
+use crate::{
+    avionics::flight_profile::PyroSelection,
+    common::{
+        can_bus::messages::{NodeCommand, NodeCommandStatus},
+        delta_logger::prelude::*,
+        schema_hash::SchemaHash,
+    },
+};
+
+use super::config_update::CONFIG_UPDATE_MAC_SIZE;
 use super::telemetry_packet::TelemetryPacket;
+use int_enum::IntEnum;
 use rkyv::{Archive, Deserialize, Serialize};
 
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct VerticalCalibrationPacket {
     pub timestamp: f64,
 }
 
-impl BitArraySerializable for VerticalCalibrationPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        64
-    }
-}
-
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct SoftArmPacket {
     pub timestamp: f64,
     pub armed: bool,
 }
 
-impl BitArraySerializable for SoftArmPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-        writer.write(self.armed);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-            armed: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        64 + 1
-    }
-}
-
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct LowPowerModePacket {
     pub timestamp: f64,
     pub enabled: bool,
 }
 
-impl BitArraySerializable for LowPowerModePacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-        writer.write(self.enabled);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-            enabled: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        64 + 1
-    }
-}
-
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct ResetPacket {
     pub timestamp: f64,
 }
 
-impl BitArraySerializable for ResetPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        64
-    }
-}
-
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct DeleteLogsPacket {
     pub timestamp: f64,
 }
 
-impl BitArraySerializable for DeleteLogsPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        64
-    }
-}
-
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct GroundTestDeployPacket {
     pub timestamp: f64,
+    #[bits(8, int_enum)]
     pub pyro: PyroSelection,
 }
 
-impl BitArraySerializable for GroundTestDeployPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-        writer.write::<u8>(self.pyro.into());
-    }
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct ManualTriggerDeplotmentPacket {
+    pub timestamp: f64,
+}
 
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-            pyro: PyroSelection::try_from(reader.read::<u8>().unwrap()).unwrap(),
-        }
-    }
+#[repr(u8)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    defmt::Format,
+    PartialEq,
+    Archive,
+    Serialize,
+    Deserialize,
+    IntEnum,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub enum ConfigUpdateType {
+    FlightProfile = 0,
+    DeviceConfig = 1,
+}
 
-    fn len_bits() -> usize {
-        64 + 8
-    }
+/// Bytes of config carried by each `ConfigChunkPacket`
+pub const CONFIG_CHUNK_SIZE: usize = 28;
+
+/// One chunk of a config update, the rocket stages the chunks until it
+/// receives the matching `ConfigCommitPacket`
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct ConfigChunkPacket {
+    #[bits(1, int_enum)]
+    pub config_type: ConfigUpdateType,
+    pub transfer_id: u8,
+    pub chunk_index: u8,
+    #[bits(5)]
+    pub length: u8,
+    pub data: [u8; CONFIG_CHUNK_SIZE],
 }
 
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
-pub struct ManualTriggerDeplotmentPacket {
+/// Asks the rocket to validate the staged chunks and write the config
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct ConfigCommitPacket {
     pub timestamp: f64,
+    #[bits(1, int_enum)]
+    pub config_type: ConfigUpdateType,
+    pub transfer_id: u8,
+    pub length: u32,
+    /// See `config_update_mac`
+    pub mac: [u8; CONFIG_UPDATE_MAC_SIZE],
 }
 
-impl BitArraySerializable for ManualTriggerDeplotmentPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        64
-    }
+/// Relayed by the avionics to the CAN bus as a `NodeCommandMessage`
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct CanCommandPacket {
+    pub timestamp: f64,
+    #[bits(4, primitive_enum)]
+    pub command: NodeCommand,
+    #[bits(6)]
+    pub target_node_type: u8,
+    /// `CAN_COMMAND_BROADCAST_NODE_ID` to address every node of `target_node_type`
+    #[bits(12)]
+    pub target_node_id: u16,
+    pub argument: u32,
 }
 
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize, SchemaHash)]
+#[archive(check_bytes)]
 pub enum VLPUplinkPacket {
     VerticalCalibrationPacket(VerticalCalibrationPacket),
     SoftArmPacket(SoftArmPacket),
@@ -168,6 +232,9 @@ pub enum VLPUplinkPacket {
     DeleteLogsPacket(DeleteLogsPacket),
     GroundTestDeployPacket(GroundTestDeployPacket),
     ManualTriggerDeplotmentPacket(ManualTriggerDeplotmentPacket),
+    ConfigChunkPacket(ConfigChunkPacket),
+    ConfigCommitPacket(ConfigCommitPacket),
+    CanCommandPacket(CanCommandPacket),
 }
 
 impl From<VerticalCalibrationPacket> for VLPUplinkPacket {
@@ -212,31 +279,124 @@ impl From<ManualTriggerDeplotmentPacket> for VLPUplinkPacket {
     }
 }
 
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
-pub struct AckPacket {
-    pub timestamp: f64,
+impl From<ConfigChunkPacket> for VLPUplinkPacket {
+    fn from(packet: ConfigChunkPacket) -> Self {
+        Self::ConfigChunkPacket(packet)
+    }
 }
 
-impl BitArraySerializable for AckPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
+impl From<ConfigCommitPacket> for VLPUplinkPacket {
+    fn from(packet: ConfigCommitPacket) -> Self {
+        Self::ConfigCommitPacket(packet)
     }
+}
 
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-        }
+impl From<CanCommandPacket> for VLPUplinkPacket {
+    fn from(packet: CanCommandPacket) -> Self {
+        Self::CanCommandPacket(packet)
     }
+}
 
-    fn len_bits() -> usize {
-        64
-    }
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct AckPacket {
+    pub timestamp: f64,
+}
+
+#[repr(u8)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    defmt::Format,
+    PartialEq,
+    Archive,
+    Serialize,
+    Deserialize,
+    IntEnum,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub enum ConfigUpdateStatus {
+    Applied = 0,
+    /// The rocket only writes configs while disarmed
+    RejectedArmed = 1,
+    /// Some chunks are missing
+    Incomplete = 2,
+    /// MAC or length mismatch
+    Invalid = 3,
+    /// Not newer than the last applied update, or too far from the unix time
+    /// of the rocket
+    Replayed = 4,
+    /// Valid, but writing it to flash failed. The chunks stay staged so the
+    /// commit can be retried
+    WriteFailed = 5,
+}
+
+/// Sent by the rocket after processing a `ConfigCommitPacket`
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct ConfigUpdateResultPacket {
+    #[bits(1, int_enum)]
+    pub config_type: ConfigUpdateType,
+    pub transfer_id: u8,
+    #[bits(3, int_enum)]
+    pub status: ConfigUpdateStatus,
+    /// `config_hash` of the applied config, 0 if not applied
+    pub hash: u32,
 }
 
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+/// Response of a CAN node to a relayed `CanCommandPacket`, one per responding node
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub struct CanCommandResultPacket {
+    #[bits(6)]
+    pub node_type: u8,
+    #[bits(12)]
+    pub node_id: u16,
+    #[bits(4, primitive_enum)]
+    pub command: NodeCommand,
+    #[bits(3, primitive_enum)]
+    pub status: NodeCommandStatus,
+}
+
+#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize, SchemaHash)]
+#[archive(check_bytes)]
 pub enum VLPDownlinkPacket {
     AckPacket(AckPacket),
     TelemetryPacket(TelemetryPacket),
+    ConfigUpdateResultPacket(ConfigUpdateResultPacket),
+    CanCommandResultPacket(CanCommandResultPacket),
 }
 
 impl From<AckPacket> for VLPDownlinkPacket {
@@ -250,3 +410,15 @@ impl From<TelemetryPacket> for VLPDownlinkPacket {
         Self::TelemetryPacket(packet)
     }
 }
+
+impl From<ConfigUpdateResultPacket> for VLPDownlinkPacket {
+    fn from(packet: ConfigUpdateResultPacket) -> Self {
+        Self::ConfigUpdateResultPacket(packet)
+    }
+}
+
+impl From<CanCommandResultPacket> for VLPDownlinkPacket {
+    fn from(packet: CanCommandResultPacket) -> Self {
+        Self::CanCommandResultPacket(packet)
+    }
+}
@@ -17,6 +17,17 @@ use super::packet::{VLPDownlinkPacket, VLPUplinkPacket};
 
 pub const MAX_VLP_PACKET_SIZE: usize = 49;
 
+/// Uplink packets addressed to this device id are accepted by every rocket
+pub const VLP_BROADCAST_DEVICE_ID: u8 = 0xFF;
+
+/// Derive a VLP device id from the serial number, never returns `VLP_BROADCAST_DEVICE_ID`.
+/// Only 255 ids, so two boards can collide. The GCM can't tell them apart,
+/// set `vlp_device_id` in the device config of one of them when they fly together.
+pub fn vlp_device_id_from_serial_number(serial_number: &[u8]) -> u8 {
+    let crc = Crc::<u8>::new(&CRC_8_SMBUS);
+    crc.checksum(serial_number) % VLP_BROADCAST_DEVICE_ID
+}
+
 const TIME_BASED_NONCE_ENABLED: bool = false;
 
 pub struct VLPPacketBuilder<'a, 'b, CL: Clock> {
@@ -93,9 +104,12 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
         result
     }
 
+    /// `destination` is the device id of the rocket this packet is addressed to,
+    /// or `VLP_BROADCAST_DEVICE_ID`
     pub fn serialize_uplink(
         &mut self,
         buffer: &mut Vec<u8, MAX_VLP_PACKET_SIZE>,
+        destination: u8,
         packet: &VLPUplinkPacket,
     ) -> Result<(), ()> {
         buffer.clear();
@@ -110,10 +124,14 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
             VLPUplinkPacket::DeleteLogsPacket(_) => 4,
             VLPUplinkPacket::GroundTestDeployPacket(_) => 5,
             VLPUplinkPacket::ManualTriggerDeplotmentPacket(_) => 6,
+            VLPUplinkPacket::ConfigChunkPacket(_) => 7,
+            VLPUplinkPacket::ConfigCommitPacket(_) => 8,
+            VLPUplinkPacket::CanCommandPacket(_) => 9,
         };
         let packet_type: Integer<u8, packed_bits::Bits<4>> = packet_type.into();
 
         self.bit_slice_writer.write(packet_type);
+        self.bit_slice_writer.write(destination);
         match packet {
             VLPUplinkPacket::VerticalCalibrationPacket(packet) => {
                 packet.serialize(&mut self.bit_slice_writer)
@@ -132,6 +150,15 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
             VLPUplinkPacket::ManualTriggerDeplotmentPacket(packet) => {
                 packet.serialize(&mut self.bit_slice_writer)
             }
+            VLPUplinkPacket::ConfigChunkPacket(packet) => {
+                packet.serialize(&mut self.bit_slice_writer)
+            }
+            VLPUplinkPacket::ConfigCommitPacket(packet) => {
+                packet.serialize(&mut self.bit_slice_writer)
+            }
+            VLPUplinkPacket::CanCommandPacket(packet) => {
+                packet.serialize(&mut self.bit_slice_writer)
+            }
         };
 
         let data = self.bit_slice_writer.view_all_data_slice();
@@ -155,10 +182,11 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
         Ok(())
     }
 
+    /// Returns the destination device id and the packet
     pub fn deserialize_uplink(
         &mut self,
         buffer: &Vec<u8, MAX_VLP_PACKET_SIZE>,
-    ) -> Result<VLPUplinkPacket, ()> {
+    ) -> Result<(u8, VLPUplinkPacket), ()> {
         if buffer.len() <= 8 {
             log_info!("Received Lora message too short");
             return Err(());
@@ -191,6 +219,7 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
                 let packet_type: Integer<u8, packed_bits::Bits<4>> =
                     self.bit_slice_reader.read().unwrap();
                 let packet_type: u8 = packet_type.into();
+                let destination: u8 = self.bit_slice_reader.read().unwrap();
                 let packet = match packet_type {
                     0 => VLPUplinkPacket::VerticalCalibrationPacket(
                         VerticalCalibrationPacket::deserialize(&mut self.bit_slice_reader),
@@ -213,12 +242,21 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
                     6 => VLPUplinkPacket::ManualTriggerDeplotmentPacket(
                         ManualTriggerDeplotmentPacket::deserialize(&mut self.bit_slice_reader),
                     ),
+                    7 => VLPUplinkPacket::ConfigChunkPacket(ConfigChunkPacket::deserialize(
+                        &mut self.bit_slice_reader,
+                    )),
+                    8 => VLPUplinkPacket::ConfigCommitPacket(ConfigCommitPacket::deserialize(
+                        &mut self.bit_slice_reader,
+                    )),
+                    9 => VLPUplinkPacket::CanCommandPacket(CanCommandPacket::deserialize(
+                        &mut self.bit_slice_reader,
+                    )),
                     _ => {
                         continue;
                     }
                 };
                 log_info!("Received Lora message with offset {}ms", offset);
-                return Ok(packet);
+                return Ok((destination, packet));
             } else {
                 continue;
             }
@@ -227,9 +265,11 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
         Err(())
     }
 
+    /// `source` is the device id of the rocket sending this packet
     pub fn serialize_downlink(
         &mut self,
         buffer: &mut Vec<u8, MAX_VLP_PACKET_SIZE>,
+        source: u8,
         packet: &VLPDownlinkPacket,
     ) -> Result<(), ()> {
         buffer.clear();
@@ -239,15 +279,24 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
         let packet_type: u8 = match packet {
             VLPDownlinkPacket::AckPacket(_) => 0,
             VLPDownlinkPacket::TelemetryPacket(_) => 1,
+            VLPDownlinkPacket::ConfigUpdateResultPacket(_) => 2,
+            VLPDownlinkPacket::CanCommandResultPacket(_) => 3,
         };
-        let packet_type: Integer<u8, packed_bits::Bits<1>> = packet_type.into();
+        let packet_type: Integer<u8, packed_bits::Bits<2>> = packet_type.into();
 
         self.bit_slice_writer.write(packet_type);
+        self.bit_slice_writer.write(source);
         match packet {
             VLPDownlinkPacket::AckPacket(packet) => packet.serialize(&mut self.bit_slice_writer),
             VLPDownlinkPacket::TelemetryPacket(packet) => {
                 packet.serialize(&mut self.bit_slice_writer)
             }
+            VLPDownlinkPacket::ConfigUpdateResultPacket(packet) => {
+                packet.serialize(&mut self.bit_slice_writer)
+            }
+            VLPDownlinkPacket::CanCommandResultPacket(packet) => {
+                packet.serialize(&mut self.bit_slice_writer)
+            }
         }
 
         let data = self.bit_slice_writer.view_all_data_slice();
@@ -269,10 +318,11 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
         Ok(())
     }
 
+    /// Returns the source device id and the packet
     pub fn deserialize_downlink(
         &mut self,
         buffer: &Vec<u8, MAX_VLP_PACKET_SIZE>,
-    ) -> Result<VLPDownlinkPacket, ()> {
+    ) -> Result<(u8, VLPDownlinkPacket), ()> {
         if buffer.len() <= 8 {
             log_info!("Received Lora message too short");
             return Err(());
@@ -302,9 +352,10 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
                 buffer.pop();
                 self.bit_slice_reader.clear();
                 self.bit_slice_reader.replenish_bytes(buffer.as_slice());
-                let packet_type: Integer<u8, packed_bits::Bits<1>> =
+                let packet_type: Integer<u8, packed_bits::Bits<2>> =
                     self.bit_slice_reader.read().unwrap();
                 let packet_type: u8 = packet_type.into();
+                let source: u8 = self.bit_slice_reader.read().unwrap();
                 let packet = match packet_type {
                     0 => VLPDownlinkPacket::AckPacket(AckPacket::deserialize(
                         &mut self.bit_slice_reader,
@@ -312,12 +363,18 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
                     1 => VLPDownlinkPacket::TelemetryPacket(TelemetryPacket::deserialize(
                         &mut self.bit_slice_reader,
                     )),
+                    2 => VLPDownlinkPacket::ConfigUpdateResultPacket(
+                        ConfigUpdateResultPacket::deserialize(&mut self.bit_slice_reader),
+                    ),
+                    3 => VLPDownlinkPacket::CanCommandResultPacket(
+                        CanCommandResultPacket::deserialize(&mut self.bit_slice_reader),
+                    ),
                     _ => {
                         continue;
                     }
                 };
                 log_info!("Received Lora message with offset {}ms", offset);
-                return Ok(packet);
+                return Ok((source, packet));
             } else {
                 continue;
             }
@@ -327,17 +384,24 @@ impl<'a, 'b, CL: Clock> VLPPacketBuilder<'a, 'b, CL> {
     }
 }
 
-fn calculate_ecc_length_from_data_length(data_length: usize) -> usize {
+pub fn calculate_ecc_length_from_data_length(data_length: usize) -> usize {
     data_length / 4
 }
 
-fn calculate_ecc_length_from_total_length(total_length: usize) -> usize {
+pub fn calculate_ecc_length_from_total_length(total_length: usize) -> usize {
     total_length / 5
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{avionics::flight_core_event::FlightCoreState, common::unix_clock::UnixClockTask};
+    use crate::{
+        avionics::flight_core_event::FlightCoreState,
+        common::{
+            can_bus::messages::{NodeCommand, NodeCommandStatus},
+            unix_clock::UnixClockTask,
+        },
+        driver::can_bus::CanBusState,
+    };
 
     use super::*;
 
@@ -382,17 +446,17 @@ mod test {
             armed: true,
         });
         packet_builder
-            .serialize_uplink(&mut buffer, &packet)
+            .serialize_uplink(&mut buffer, 42, &packet)
             .unwrap();
 
         println!("serialized package len: {} {:02X?}", buffer.len(), buffer);
 
         let deserialized_packet = packet_builder.deserialize_uplink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[4] = 0xFF;
         let deserialized_packet = packet_builder.deserialize_uplink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[6] = 0xFF;
         packet_builder.deserialize_uplink(&buffer).unwrap_err();
@@ -437,34 +501,140 @@ mod test {
             FlightCoreState::Armed,
             false,
             false,
+            2,
+            1,
+            CanBusState::ErrorPassive,
+            3,
         ));
         packet_builder
-            .serialize_downlink(&mut buffer, &packet)
+            .serialize_downlink(&mut buffer, 42, &packet)
             .unwrap();
 
         println!("serialized package len: {} {:02X?}", buffer.len(), buffer);
 
         let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[4] = 0xFF;
         let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[6] = 0xFF;
         let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[39] = 0xFF;
         let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[20] = 0xFF;
         let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
-        assert_eq!(packet, deserialized_packet);
+        assert_eq!((42, packet.clone()), deserialized_packet);
 
         buffer[30] = 0xFF;
         buffer[31] = 0xFF;
         packet_builder.deserialize_downlink(&buffer).unwrap_err();
     }
+
+    #[test]
+    fn test_serialize_deserialize_config_chunk() {
+        let lora_config = BaseBandModulationParams::new(
+            lora_modulation::SpreadingFactor::_10,
+            lora_modulation::Bandwidth::_250KHz,
+            lora_modulation::CodingRate::_4_5,
+        );
+
+        let clock = MockClock {};
+        let unix_clock_task = UnixClockTask::new(clock);
+        let unix_clock = unix_clock_task.get_clock();
+
+        let key = [0x69u8; 32];
+
+        let mut packet_builder = VLPPacketBuilder::new(unix_clock, lora_config, &key);
+
+        let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
+        let packet = VLPUplinkPacket::ConfigChunkPacket(ConfigChunkPacket {
+            config_type: ConfigUpdateType::DeviceConfig,
+            transfer_id: 3,
+            chunk_index: 1,
+            length: CONFIG_CHUNK_SIZE as u8,
+            data: [0xA5; CONFIG_CHUNK_SIZE],
+        });
+        packet_builder
+            .serialize_uplink(&mut buffer, 42, &packet)
+            .unwrap();
+
+        println!("serialized package len: {} {:02X?}", buffer.len(), buffer);
+
+        let deserialized_packet = packet_builder.deserialize_uplink(&buffer).unwrap();
+        assert_eq!((42, packet.clone()), deserialized_packet);
+
+        let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
+        let packet = VLPDownlinkPacket::ConfigUpdateResultPacket(ConfigUpdateResultPacket {
+            config_type: ConfigUpdateType::FlightProfile,
+            transfer_id: 3,
+            status: ConfigUpdateStatus::Applied,
+            hash: 0xDEADBEEF,
+        });
+        packet_builder
+            .serialize_downlink(&mut buffer, 42, &packet)
+            .unwrap();
+        let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
+        assert_eq!((42, packet.clone()), deserialized_packet);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_can_command() {
+        let lora_config = BaseBandModulationParams::new(
+            lora_modulation::SpreadingFactor::_10,
+            lora_modulation::Bandwidth::_250KHz,
+            lora_modulation::CodingRate::_4_5,
+        );
+
+        let clock = MockClock {};
+        let unix_clock_task = UnixClockTask::new(clock);
+        let unix_clock = unix_clock_task.get_clock();
+
+        let key = [0x69u8; 32];
+
+        let mut packet_builder = VLPPacketBuilder::new(unix_clock, lora_config, &key);
+
+        let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
+        let packet = VLPUplinkPacket::CanCommandPacket(CanCommandPacket {
+            timestamp: 12345.67,
+            command: NodeCommand::SetSampleRate,
+            target_node_type: 20,
+            target_node_id: 0xFFF,
+            argument: 1000,
+        });
+        packet_builder
+            .serialize_uplink(&mut buffer, 42, &packet)
+            .unwrap();
+        let deserialized_packet = packet_builder.deserialize_uplink(&buffer).unwrap();
+        assert_eq!((42, packet.clone()), deserialized_packet);
+
+        let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
+        let packet = VLPDownlinkPacket::CanCommandResultPacket(CanCommandResultPacket {
+            node_type: 20,
+            node_id: 0x123,
+            command: NodeCommand::SetSampleRate,
+            status: NodeCommandStatus::Unsupported,
+        });
+        packet_builder
+            .serialize_downlink(&mut buffer, 42, &packet)
+            .unwrap();
+        let deserialized_packet = packet_builder.deserialize_downlink(&buffer).unwrap();
+        assert_eq!((42, packet.clone()), deserialized_packet);
+    }
+
+    #[test]
+    fn test_vlp_device_id_from_serial_number() {
+        for i in 0..=255u8 {
+            let serial_number = [i; 12];
+            assert_ne!(
+                vlp_device_id_from_serial_number(&serial_number),
+                VLP_BROADCAST_DEVICE_ID
+            );
+        }
+    }
 }
@@ -1,7 +1,9 @@
 use crate::avionics::flight_core_event::FlightCoreState;
 use crate::common::delta_logger::prelude::*;
+use crate::common::schema_hash::SchemaHash;
 use crate::common::unix_clock::UnixClock;
 use crate::common::variable_int::VariableIntRkyvWrapper;
+use crate::driver::can_bus::CanBusState;
 use crate::driver::clock::Clock;
 use crate::driver::gps::GPSData;
 use crate::{common::fixed_point::F32FixedPointFactory, fixed_point_factory};
@@ -16,7 +18,18 @@ fixed_point_factory!(FreeSpaceFac, f32, 0.0, 67108864.0, 40960.0);
 fixed_point_factory!(AltitudeFac, f32, -100.0, 5000.0, 5.0);
 fixed_point_factory!(AirSpeedFac, f32, -400.0, 400.0, 2.0);
 
-#[derive(defmt::Format, Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    PartialEq,
+    Archive,
+    Deserialize,
+    Serialize,
+    BitArraySerializable,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
 pub struct TelemetryPacket {
     unix_clock_ready: bool,
     timestamp: u32, // seconds since unix epoch / seconds since boot
@@ -70,6 +83,19 @@ pub struct TelemetryPacket {
 
     drogue_deployed: bool,
     main_deployed: bool,
+
+    #[defmt(Debug2Format)]
+    #[with(VariableIntRkyvWrapper)]
+    can_nodes_online: Integer<u8, packed_bits::Bits<3>>,
+    #[defmt(Debug2Format)]
+    #[with(VariableIntRkyvWrapper)]
+    can_nodes_faulty: Integer<u8, packed_bits::Bits<3>>,
+    #[defmt(Debug2Format)]
+    #[with(VariableIntRkyvWrapper)]
+    can_bus_state: Integer<u8, packed_bits::Bits<2>>,
+    #[defmt(Debug2Format)]
+    #[with(VariableIntRkyvWrapper)]
+    can_bus_tx_lost: Integer<u8, packed_bits::Bits<4>>,
 }
 
 impl TelemetryPacket {
@@ -104,6 +130,11 @@ impl TelemetryPacket {
 
         drogue_deployed: bool,
         main_deployed: bool,
+
+        can_nodes_online: u8,
+        can_nodes_faulty: u8,
+        can_bus_state: CanBusState,
+        can_bus_tx_lost: u32,
     ) -> Self {
         Self {
             unix_clock_ready,
@@ -127,6 +158,10 @@ impl TelemetryPacket {
             backup_flight_core_state: (backup_flight_core_state as u8).into(),
             drogue_deployed,
             main_deployed,
+            can_nodes_online: can_nodes_online.min(7).into(),
+            can_nodes_faulty: can_nodes_faulty.min(7).into(),
+            can_bus_state: (can_bus_state as u8).into(),
+            can_bus_tx_lost: (can_bus_tx_lost.min(15) as u8).into(),
         }
     }
 
@@ -229,81 +264,29 @@ impl TelemetryPacket {
     pub fn main_deployed(&self) -> bool {
         self.main_deployed
     }
-}
 
-impl BitArraySerializable for TelemetryPacket {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.unix_clock_ready);
-        writer.write(self.timestamp);
-        writer.write(self.num_of_fix_satellites);
-        writer.write(self.lat_lon);
-        writer.write(self.battery_v);
-        writer.write(self.temperature);
-        writer.write(self.hardware_armed);
-        writer.write(self.software_armed);
-        writer.write(self.disk_free_space);
-        writer.write(self.pyro_main_continuity);
-        writer.write(self.pyro_drogue_continuity);
-        writer.write(self.altitude);
-        writer.write(self.max_altitude);
-        writer.write(self.backup_max_altitude);
-        writer.write(self.air_speed);
-        writer.write(self.max_air_speed);
-        writer.write(self.backup_max_air_speed);
-        writer.write(self.flight_core_state);
-        writer.write(self.backup_flight_core_state);
-        writer.write(self.drogue_deployed);
-        writer.write(self.main_deployed);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            unix_clock_ready: reader.read().unwrap(),
-            timestamp: reader.read().unwrap(),
-            num_of_fix_satellites: reader.read().unwrap(),
-            lat_lon: reader.read().unwrap(),
-            battery_v: reader.read().unwrap(),
-            temperature: reader.read().unwrap(),
-            hardware_armed: reader.read().unwrap(),
-            software_armed: reader.read().unwrap(),
-            disk_free_space: reader.read().unwrap(),
-            pyro_main_continuity: reader.read().unwrap(),
-            pyro_drogue_continuity: reader.read().unwrap(),
-            altitude: reader.read().unwrap(),
-            max_altitude: reader.read().unwrap(),
-            backup_max_altitude: reader.read().unwrap(),
-            air_speed: reader.read().unwrap(),
-            max_air_speed: reader.read().unwrap(),
-            backup_max_air_speed: reader.read().unwrap(),
-            flight_core_state: reader.read().unwrap(),
-            backup_flight_core_state: reader.read().unwrap(),
-            drogue_deployed: reader.read().unwrap(),
-            main_deployed: reader.read().unwrap(),
+    /// Number of CAN nodes sending heartbeats, capped at 7
+    pub fn can_nodes_online(&self) -> u8 {
+        self.can_nodes_online.into()
+    }
+
+    /// Number of missing, unhealthy or colliding CAN nodes, capped at 7
+    pub fn can_nodes_faulty(&self) -> u8 {
+        self.can_nodes_faulty.into()
+    }
+
+    pub fn can_bus_state(&self) -> CanBusState {
+        match u8::from(self.can_bus_state) {
+            0 => CanBusState::ErrorActive,
+            1 => CanBusState::ErrorPassive,
+            _ => CanBusState::BusOff,
         }
     }
 
-    fn len_bits() -> usize {
-        bool::len_bits()
-            + u32::len_bits()
-            + <Integer<u8, packed_bits::Bits<5>>>::len_bits()
-            + <(f64, f64)>::len_bits()
-            + BatteryVFacPacked::len_bits()
-            + TemperatureFacPacked::len_bits()
-            + bool::len_bits()
-            + bool::len_bits()
-            + FreeSpaceFacPacked::len_bits()
-            + bool::len_bits()
-            + bool::len_bits()
-            + AltitudeFacPacked::len_bits()
-            + AltitudeFacPacked::len_bits()
-            + AltitudeFacPacked::len_bits()
-            + AirSpeedFacPacked::len_bits()
-            + AirSpeedFacPacked::len_bits()
-            + AirSpeedFacPacked::len_bits()
-            + <Integer<u8, packed_bits::Bits<3>>>::len_bits()
-            + <Integer<u8, packed_bits::Bits<3>>>::len_bits()
-            + bool::len_bits()
-            + bool::len_bits()
+    /// Number of CAN frames dropped or failed to send since the previous
+    /// telemetry packet, capped at 15
+    pub fn can_bus_tx_lost(&self) -> u8 {
+        self.can_bus_tx_lost.into()
     }
 }
 
@@ -330,6 +313,15 @@ pub struct TelemetryPacketBuilderState {
 
     pub drogue_deployed: bool,
     pub main_deployed: bool,
+
+    pub can_nodes_online: u8,
+    pub can_nodes_faulty: u8,
+
+    pub can_bus_state: CanBusState,
+    /// frames dropped or failed to send since boot
+    pub can_bus_tx_lost: u32,
+    /// `can_bus_tx_lost` when the previous packet was created
+    reported_can_bus_tx_lost: u32,
 }
 
 pub struct TelemetryPacketBuilder<'a, K: Clock> {
@@ -362,13 +354,22 @@ impl<'a, K: Clock> TelemetryPacketBuilder<'a, K> {
                 disk_free_space: 0,
                 drogue_deployed: false,
                 main_deployed: false,
+                can_nodes_online: 0,
+                can_nodes_faulty: 0,
+                can_bus_state: CanBusState::ErrorActive,
+                can_bus_tx_lost: 0,
+                reported_can_bus_tx_lost: 0,
             })),
         }
     }
 
     pub fn create_packet(&self) -> TelemetryPacket {
         self.state.lock(|state| {
-            let state = state.borrow();
+            let mut state = state.borrow_mut();
+            let can_bus_tx_lost = state
+                .can_bus_tx_lost
+                .wrapping_sub(state.reported_can_bus_tx_lost);
+            state.reported_can_bus_tx_lost = state.can_bus_tx_lost;
 
             TelemetryPacket::new(
                 self.unix_clock.ready(),
@@ -395,6 +396,10 @@ impl<'a, K: Clock> TelemetryPacketBuilder<'a, K> {
                 state.backup_flight_core_state,
                 state.drogue_deployed,
                 state.main_deployed,
+                state.can_nodes_online,
+                state.can_nodes_faulty,
+                state.can_bus_state,
+                can_bus_tx_lost,
             )
         })
     }
@@ -416,10 +421,86 @@ impl<'a, K: Clock> TelemetryPacketBuilder<'a, K> {
 
 #[cfg(test)]
 mod test {
+    use crate::common::unix_clock::UnixClockTask;
+
     use super::*;
 
+    #[derive(Debug, Clone)]
+    struct MockClock {}
+
+    impl Clock for MockClock {
+        fn now_ms(&self) -> f64 {
+            1000.0
+        }
+    }
+
+    #[test]
+    fn test_can_bus_tx_lost_since_last_packet() {
+        let unix_clock_task = UnixClockTask::new(MockClock {});
+        let builder = TelemetryPacketBuilder::new(unix_clock_task.get_clock());
+
+        builder.update(|b| b.can_bus_tx_lost = 3);
+        assert_eq!(builder.create_packet().can_bus_tx_lost(), 3);
+        assert_eq!(builder.create_packet().can_bus_tx_lost(), 0);
+
+        builder.update(|b| b.can_bus_tx_lost = 100);
+        assert_eq!(builder.create_packet().can_bus_tx_lost(), 15);
+        builder.update(|b| b.can_bus_tx_lost = 105);
+        assert_eq!(builder.create_packet().can_bus_tx_lost(), 5);
+    }
+
     #[test]
     fn print_telemetry_packet_length() {
         println!("Telemetry Packet Length: {}", TelemetryPacket::len_bits());
     }
+
+    /// Written by the hand-written `BitArraySerializable` impl before the derive,
+    /// the CAN bus fields are appended after it
+    const BASELINE_GOLDEN_VECTOR: [u8; 34] = [
+        0x01, 0xE2, 0xA7, 0xCA, 0xD2, 0xCC, 0xCC, 0xCC, 0xCC, 0xCC, 0x74, 0x11, 0x10, 0xAE, 0x47,
+        0xE1, 0x7A, 0x14, 0xF6, 0x14, 0x30, 0x71, 0xF2, 0x2A, 0xF4, 0xC5, 0x12, 0x80, 0xFF, 0x21,
+        0x63, 0xB9, 0x72, 0x0C,
+    ];
+
+    #[test]
+    fn test_golden_vector() {
+        let packet = TelemetryPacket {
+            unix_clock_ready: true,
+            timestamp: 1_700_000_000,
+            num_of_fix_satellites: 9.into(),
+            lat_lon: (43.65, -79.38),
+            battery_v: 2500.into(),
+            temperature: 700.into(),
+            hardware_armed: true,
+            software_armed: false,
+            disk_free_space: 1000.into(),
+            pyro_main_continuity: true,
+            pyro_drogue_continuity: false,
+            altitude: 300.into(),
+            max_altitude: 512.into(),
+            backup_max_altitude: 511.into(),
+            air_speed: 200.into(),
+            max_air_speed: 300.into(),
+            backup_max_air_speed: 299.into(),
+            flight_core_state: 3.into(),
+            backup_flight_core_state: 4.into(),
+            drogue_deployed: true,
+            main_deployed: false,
+            can_nodes_online: 0.into(),
+            can_nodes_faulty: 0.into(),
+            can_bus_state: 0.into(),
+            can_bus_tx_lost: 0.into(),
+        };
+        assert_eq!(TelemetryPacket::len_bits(), 269 + 12);
+
+        let mut writer = BitSliceWriter::<64>::default();
+        packet.serialize(&mut writer);
+        let mut expected = BASELINE_GOLDEN_VECTOR.to_vec();
+        expected.extend_from_slice(&[0, 0]);
+        assert_eq!(writer.view_all_data_slice(), expected.as_slice());
+
+        let mut reader = BitSliceReader::<64>::default();
+        reader.replenish_bytes(&expected);
+        assert_eq!(TelemetryPacket::deserialize(&mut reader), packet);
+    }
 }
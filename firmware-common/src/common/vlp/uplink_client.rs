@@ -1,3 +1,4 @@
+use embassy_futures::select::{select, Either};
 use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
 use heapless::Vec;
 use lora_phy::{
@@ -11,21 +12,27 @@ use crate::{
 };
 
 use super::{
-    lora_phy::LoraPhy,
+    lora_phy::{LoraPhy, VLPPhy},
     packet::{AckPacket, LowPowerModePacket, VLPDownlinkPacket, VLPUplinkPacket},
-    packet_builder::{VLPPacketBuilder, MAX_VLP_PACKET_SIZE},
+    packet_builder::{VLPPacketBuilder, MAX_VLP_PACKET_SIZE, VLP_BROADCAST_DEVICE_ID},
 };
 
 // VLP client running on the rocket
 pub struct VLPUplinkClient {
+    device_id: u8,
     tx_signal: Signal<NoopRawMutex, VLPDownlinkPacket>,
+    priority_tx_signal: Signal<NoopRawMutex, VLPDownlinkPacket>,
     rx_signal: Signal<NoopRawMutex, (VLPUplinkPacket, PacketStatus)>,
 }
 
 impl VLPUplinkClient {
-    pub fn new() -> Self {
+    /// `device_id` is used as the source of downlink packets, uplink packets
+    /// addressed to other devices are ignored
+    pub fn new(device_id: u8) -> Self {
         VLPUplinkClient {
+            device_id,
             tx_signal: Signal::new(),
+            priority_tx_signal: Signal::new(),
             rx_signal: Signal::new(),
         }
     }
@@ -34,6 +41,12 @@ impl VLPUplinkClient {
         self.tx_signal.signal(packet);
     }
 
+    /// Sent before the packet from `send`, and won't be replaced by it.
+    /// In low power mode the packet is sent after the next received uplink packet.
+    pub fn send_priority(&self, packet: VLPDownlinkPacket) {
+        self.priority_tx_signal.signal(packet);
+    }
+
     pub async fn wait_receive(&self) -> (VLPUplinkPacket, PacketStatus) {
         self.rx_signal.wait().await
     }
@@ -46,17 +59,38 @@ impl VLPUplinkClient {
         unix_clock: UnixClock<'a, impl Clock>,
         key: &[u8; 32],
     ) {
-        let mut packet_builder = VLPPacketBuilder::new(unix_clock.clone(), lora_config.into(), key);
         let mut lora = LoraPhy::new(lora, lora_config);
+        self.run_with_phy(delay, &mut lora, lora_config, unix_clock, key)
+            .await
+    }
+
+    pub async fn run_with_phy<'a>(
+        &self,
+        delay: impl Delay,
+        lora: &mut impl VLPPhy,
+        lora_config: &LoraConfig,
+        unix_clock: UnixClock<'a, impl Clock>,
+        key: &[u8; 32],
+    ) {
+        let mut packet_builder = VLPPacketBuilder::new(unix_clock.clone(), lora_config.into(), key);
         let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
         let mut low_power_mode = false;
 
         loop {
             let result: Result<(), RadioError> = try {
-                if !low_power_mode {
-                    let tx_packet = self.tx_signal.wait().await;
+                let tx_packet = if low_power_mode {
+                    self.priority_tx_signal.try_take()
+                } else if let Some(packet) = self.priority_tx_signal.try_take() {
+                    Some(packet)
+                } else {
+                    match select(self.priority_tx_signal.wait(), self.tx_signal.wait()).await {
+                        Either::First(packet) => Some(packet),
+                        Either::Second(packet) => Some(packet),
+                    }
+                };
+                if let Some(tx_packet) = tx_packet {
                     packet_builder
-                        .serialize_downlink(&mut buffer, &tx_packet)
+                        .serialize_downlink(&mut buffer, self.device_id, &tx_packet)
                         .unwrap();
                     lora.tx(&buffer).await?;
                 }
@@ -74,7 +108,13 @@ impl VLPUplinkClient {
                     Ok(packet_status) => {
                         // try to deserialize the packet
                         match packet_builder.deserialize_uplink(&buffer) {
-                            Ok(packet) => {
+                            Ok((destination, _))
+                                if destination != self.device_id
+                                    && destination != VLP_BROADCAST_DEVICE_ID =>
+                            {
+                                log_info!("Ignoring packet addressed to device {}", destination);
+                            }
+                            Ok((_, packet)) => {
                                 if let VLPUplinkPacket::LowPowerModePacket(LowPowerModePacket {
                                     enabled,
                                     ..
@@ -86,6 +126,7 @@ impl VLPUplinkClient {
                                 packet_builder
                                     .serialize_downlink(
                                         &mut buffer,
+                                        self.device_id,
                                         &AckPacket {
                                             timestamp: unix_clock.now_ms(),
                                         }
@@ -0,0 +1,621 @@
+use core::cell::RefCell;
+use std::vec::Vec as StdVec;
+
+use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex as BlockingMutex};
+use heapless::Vec;
+use lora_modulation::BaseBandModulationParams;
+use lora_phy::{
+    mod_params::{PacketStatus, RadioError},
+    RxMode,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    common::device_config::LoraConfig,
+    driver::{clock::Clock, delay::Delay},
+};
+
+use super::lora_phy::VLPPhy;
+
+// same as LoraPhy
+const PREAMBLE_LENGTH: u8 = 8;
+const POLL_INTERVAL_MS: f64 = 1.0;
+// transmissions older than this can't affect any receiver
+const TRANSMISSION_RETENTION_MS: f64 = 10_000.0;
+
+#[derive(Debug, Clone)]
+pub struct VirtualLoraChannelConfig {
+    /// Probability of a packet not reaching a receiver
+    pub packet_loss: f64,
+    pub rssi: i16,
+    pub snr: i16,
+    /// RSSI and SNR of received packets are randomly offset by up to this amount
+    pub jitter: i16,
+    pub seed: u64,
+}
+
+impl Default for VirtualLoraChannelConfig {
+    fn default() -> Self {
+        Self {
+            packet_loss: 0.0,
+            rssi: -80,
+            snr: 10,
+            jitter: 2,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Transmission {
+    id: u64,
+    sender: usize,
+    frequency: u32,
+    start_ms: f64,
+    preamble_end_ms: f64,
+    end_ms: f64,
+    data: StdVec<u8>,
+}
+
+impl Transmission {
+    fn overlaps(&self, other: &Transmission) -> bool {
+        self.frequency == other.frequency
+            && self.start_ms < other.end_ms
+            && other.start_ms < self.end_ms
+    }
+}
+
+struct VirtualLoraChannelState {
+    config: VirtualLoraChannelConfig,
+    rng: SmallRng,
+    node_count: usize,
+    next_transmission_id: u64,
+    transmissions: StdVec<Transmission>,
+    // indexed by node id
+    drop_next_packets: StdVec<usize>,
+}
+
+/// Shared medium of the virtual LoRa radios, simulates air time, packet loss,
+/// RSSI / SNR and collisions between the nodes.
+///
+/// Collisions destroy all the packets involved, there is no capture effect.
+pub struct VirtualLoraChannel<C: Clock> {
+    clock: C,
+    state: BlockingMutex<NoopRawMutex, RefCell<VirtualLoraChannelState>>,
+}
+
+impl<C: Clock> VirtualLoraChannel<C> {
+    pub fn new(clock: C, config: VirtualLoraChannelConfig) -> Self {
+        Self {
+            clock,
+            state: BlockingMutex::new(RefCell::new(VirtualLoraChannelState {
+                rng: SmallRng::seed_from_u64(config.seed),
+                config,
+                node_count: 0,
+                next_transmission_id: 0,
+                transmissions: StdVec::new(),
+                drop_next_packets: StdVec::new(),
+            })),
+        }
+    }
+
+    /// Creates a radio attached to this channel
+    pub fn create_phy<'a, D: Delay>(
+        &'a self,
+        delay: D,
+        lora_config: &LoraConfig,
+    ) -> VirtualLoraPhy<'a, C, D> {
+        let id = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state.node_count += 1;
+            state.drop_next_packets.push(0);
+            state.node_count - 1
+        });
+        VirtualLoraPhy {
+            channel: self,
+            id,
+            delay,
+            lora_config: lora_config.clone(),
+            last_handled_transmission_id: None,
+        }
+    }
+
+    pub fn set_packet_loss(&self, packet_loss: f64) {
+        self.state
+            .lock(|state| state.borrow_mut().config.packet_loss = packet_loss);
+    }
+
+    /// The next `n` packets received by `node_id` are lost regardless of the
+    /// packet loss probability
+    pub fn drop_next_packets(&self, node_id: usize, n: usize) {
+        self.state
+            .lock(|state| state.borrow_mut().drop_next_packets[node_id] = n);
+    }
+
+    fn add_transmission(&self, sender: usize, lora_config: &LoraConfig, data: &[u8]) -> f64 {
+        let modulation: BaseBandModulationParams = lora_config.into();
+        let air_time_ms =
+            modulation.time_on_air_us(Some(PREAMBLE_LENGTH), true, data.len() as u8) as f64
+                / 1000.0;
+        let preamble_ms = (PREAMBLE_LENGTH as f64 + 4.25) * symbol_time_ms(lora_config);
+        let now = self.clock.now_ms();
+
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            state
+                .transmissions
+                .retain(|t| now - t.end_ms < TRANSMISSION_RETENTION_MS);
+            let id = state.next_transmission_id;
+            state.next_transmission_id += 1;
+            state.transmissions.push(Transmission {
+                id,
+                sender,
+                frequency: lora_config.frequency,
+                start_ms: now,
+                preamble_end_ms: now + preamble_ms,
+                end_ms: now + air_time_ms,
+                data: data.to_vec(),
+            });
+        });
+
+        air_time_ms
+    }
+}
+
+fn symbol_time_ms(lora_config: &LoraConfig) -> f64 {
+    (1u32 << lora_config.sf) as f64 / lora_config.bw as f64 * 1000.0
+}
+
+/// Whether a receiver that started listening at `rx_start_ms` can detect the
+/// preamble of `transmission`
+fn can_detect(listen_mode: &RxMode, rx_start_ms: f64, transmission: &Transmission) -> bool {
+    // the preamble must not be over when the receiver starts listening
+    if transmission.preamble_end_ms < rx_start_ms {
+        return false;
+    }
+
+    match listen_mode {
+        RxMode::DutyCycle(params) => {
+            // receiver is on for rx_time, then off for sleep_time, detection
+            // needs one of the on windows to overlap with the preamble
+            let rx_ms = params.rx_time as f64 / 1000.0;
+            let period_ms = (params.rx_time + params.sleep_time) as f64 / 1000.0;
+            let relative_start = (transmission.start_ms - rx_start_ms).max(0.0);
+            let relative_end = transmission.preamble_end_ms - rx_start_ms;
+            let window_start = (relative_start / period_ms).floor() * period_ms;
+            relative_start - window_start <= rx_ms || window_start + period_ms <= relative_end
+        }
+        _ => true,
+    }
+}
+
+pub struct VirtualLoraPhy<'a, C: Clock, D: Delay> {
+    channel: &'a VirtualLoraChannel<C>,
+    id: usize,
+    delay: D,
+    lora_config: LoraConfig,
+    last_handled_transmission_id: Option<u64>,
+}
+
+impl<'a, C: Clock, D: Delay> VirtualLoraPhy<'a, C, D> {
+    pub fn node_id(&self) -> usize {
+        self.id
+    }
+}
+
+impl<'a, C: Clock, D: Delay> VLPPhy for VirtualLoraPhy<'a, C, D> {
+    async fn tx<const N: usize>(&mut self, buffer: &Vec<u8, N>) -> Result<(), RadioError> {
+        let air_time_ms =
+            self.channel
+                .add_transmission(self.id, &self.lora_config, buffer.as_slice());
+        self.delay.delay_ms(air_time_ms).await;
+        Ok(())
+    }
+
+    async fn rx<const N: usize>(
+        &mut self,
+        listen_mode: RxMode,
+        buffer: &mut Vec<u8, N>,
+    ) -> Result<PacketStatus, RadioError> {
+        let rx_start_ms = self.channel.clock.now_ms();
+        let timeout_ms = match listen_mode {
+            RxMode::Single(symbols) => {
+                Some(rx_start_ms + symbols as f64 * symbol_time_ms(&self.lora_config))
+            }
+            _ => None,
+        };
+
+        loop {
+            let now = self.channel.clock.now_ms();
+
+            // find the first transmission this radio can lock on to
+            let transmission = self.channel.state.lock(|state| {
+                let state = state.borrow();
+                state
+                    .transmissions
+                    .iter()
+                    .filter(|t| t.sender != self.id)
+                    .filter(|t| t.frequency == self.lora_config.frequency)
+                    .filter(|t| Some(t.id) > self.last_handled_transmission_id)
+                    .filter(|t| timeout_ms.map_or(true, |timeout_ms| t.start_ms <= timeout_ms))
+                    .filter(|t| t.start_ms <= now)
+                    .find(|t| can_detect(&listen_mode, rx_start_ms, t))
+                    .cloned()
+            });
+
+            if let Some(transmission) = transmission {
+                if now < transmission.end_ms {
+                    self.delay.delay_ms(transmission.end_ms - now).await;
+                    continue;
+                }
+
+                let result = self.channel.state.lock(|state| {
+                    let mut state = state.borrow_mut();
+                    let colliding_ids = state
+                        .transmissions
+                        .iter()
+                        .filter(|t| t.id != transmission.id && t.sender != self.id)
+                        .filter(|t| t.overlaps(&transmission))
+                        .map(|t| t.id)
+                        .collect::<StdVec<_>>();
+                    self.last_handled_transmission_id = colliding_ids
+                        .iter()
+                        .copied()
+                        .chain(core::iter::once(transmission.id))
+                        .max();
+
+                    if !colliding_ids.is_empty() {
+                        log_info!("Virtual LoRa node {}: collision", self.id);
+                        return None;
+                    }
+                    if state.drop_next_packets[self.id] > 0 {
+                        state.drop_next_packets[self.id] -= 1;
+                        return None;
+                    }
+                    let packet_loss = state.config.packet_loss;
+                    if state.rng.gen_bool(packet_loss) {
+                        return None;
+                    }
+
+                    let jitter = state.config.jitter;
+                    let rssi = state.config.rssi + state.rng.gen_range(-jitter..=jitter);
+                    let snr = state.config.snr + state.rng.gen_range(-jitter..=jitter);
+                    Some(PacketStatus { rssi, snr })
+                });
+
+                if let Some(status) = result {
+                    buffer.clear();
+                    let len = transmission.data.len().min(N);
+                    buffer
+                        .extend_from_slice(&transmission.data[..len])
+                        .unwrap();
+                    return Ok(status);
+                }
+                continue;
+            }
+
+            if let Some(timeout_ms) = timeout_ms {
+                if now > timeout_ms {
+                    return Err(RadioError::ReceiveTimeout);
+                }
+            }
+            self.delay.delay_ms(POLL_INTERVAL_MS).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::future::Future;
+    use std::time::Duration;
+
+    use embassy_futures::{
+        join::{join, join3},
+        select::{select, Either},
+    };
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{
+        avionics::flight_core_event::FlightCoreState,
+        common::{
+            test_utils::{TokioClock, TokioDelay},
+            unix_clock::UnixClockTask,
+            vlp::{
+                downlink_client::VLPDownlinkClient,
+                packet::{LowPowerModePacket, SoftArmPacket, VLPDownlinkPacket, VLPUplinkPacket},
+                telemetry_packet::TelemetryPacket,
+                uplink_client::VLPUplinkClient,
+            },
+        },
+        driver::can_bus::CanBusState,
+    };
+
+    const ROCKET_KEY: [u8; 32] = [0x69; 32];
+    const ROCKET_ID: u8 = 42;
+
+    fn lora_config() -> LoraConfig {
+        LoraConfig {
+            frequency: 903_000_000,
+            sf: 12,
+            bw: 250000,
+            cr: 8,
+            power: 22,
+        }
+    }
+
+    fn create_telemetry_packet() -> VLPDownlinkPacket {
+        TelemetryPacket::new(
+            false,
+            0.0,
+            0,
+            None,
+            8.0,
+            25.0,
+            false,
+            false,
+            0,
+            false,
+            false,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            0.0,
+            FlightCoreState::DisArmed,
+            FlightCoreState::DisArmed,
+            false,
+            false,
+            0,
+            0,
+            CanBusState::ErrorActive,
+            0,
+        )
+        .into()
+    }
+
+    async fn with_timeout<F: Future>(ms: f64, future: F) -> Option<F::Output> {
+        timeout(Duration::from_secs_f64(ms / 1000.0), future)
+            .await
+            .ok()
+    }
+
+    async fn run_until<F: Future>(nodes_fut: impl Future, test_fut: F) -> F::Output {
+        match select(nodes_fut, test_fut).await {
+            Either::First(_) => unreachable!(),
+            Either::Second(result) => result,
+        }
+    }
+
+    async fn send_telemetry(rocket: &VLPUplinkClient) {
+        loop {
+            rocket.send(create_telemetry_packet());
+            TokioDelay.delay_ms(2000.0).await;
+        }
+    }
+
+    async fn enter_low_power_mode(gcm: &VLPDownlinkClient, rocket: &VLPUplinkClient) {
+        let packet: VLPUplinkPacket = LowPowerModePacket {
+            timestamp: 0.0,
+            enabled: true,
+        }
+        .into();
+        let (status, received) = join(gcm.send(ROCKET_ID, packet.clone()), rocket.wait_receive()).await;
+        assert!(status.is_some());
+        assert_eq!(received.0, packet);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_air_time_and_collision() {
+        let clock = TokioClock::new();
+        let channel = VirtualLoraChannel::new(clock.clone(), VirtualLoraChannelConfig::default());
+        let lora_config = lora_config();
+        let mut node_a = channel.create_phy(TokioDelay, &lora_config);
+        let mut node_b = channel.create_phy(TokioDelay, &lora_config);
+        let mut node_c = channel.create_phy(TokioDelay, &lora_config);
+        let buffer = Vec::<u8, 32>::from_slice(&[0x42; 32]).unwrap();
+
+        // single packet
+        let start = clock.now_ms();
+        let (tx_result, rx_result) = join(
+            node_a.tx(&buffer),
+            node_c.rx(RxMode::Single(100), &mut Vec::<u8, 32>::new()),
+        )
+        .await;
+        tx_result.unwrap();
+        let status = rx_result.unwrap();
+        let modulation: BaseBandModulationParams = (&lora_config).into();
+        let air_time_ms = modulation.time_on_air_us(Some(PREAMBLE_LENGTH), true, 32) as f64 / 1000.0;
+        assert!(clock.now_ms() - start >= air_time_ms);
+        assert!((-82..=-78).contains(&status.rssi));
+        assert!((8..=12).contains(&status.snr));
+
+        // two nodes transmitting at the same time
+        let mut rx_buffer = Vec::<u8, 32>::new();
+        let (tx_a_result, tx_b_result, rx_result) = join3(
+            node_a.tx(&buffer),
+            node_b.tx(&buffer),
+            node_c.rx(RxMode::Single(100), &mut rx_buffer),
+        )
+        .await;
+        tx_a_result.unwrap();
+        tx_b_result.unwrap();
+        assert!(matches!(rx_result, Err(RadioError::ReceiveTimeout)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_telemetry_and_command() {
+        let clock = TokioClock::new();
+        let unix_clock_task = UnixClockTask::new(clock.clone());
+        let channel = VirtualLoraChannel::new(clock.clone(), VirtualLoraChannelConfig::default());
+        let lora_config = lora_config();
+        let mut rocket_phy = channel.create_phy(TokioDelay, &lora_config);
+        let mut gcm_phy = channel.create_phy(TokioDelay, &lora_config);
+
+        let rocket = VLPUplinkClient::new(ROCKET_ID);
+        let gcm = VLPDownlinkClient::new();
+        let nodes_fut = join3(
+            rocket.run_with_phy(TokioDelay, &mut rocket_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            gcm.run_with_phy(TokioDelay, &mut gcm_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            send_telemetry(&rocket),
+        );
+
+        run_until(nodes_fut, async {
+            let (source, packet, status) = with_timeout(10_000.0, gcm.wait_receive()).await.unwrap();
+            assert_eq!(source, ROCKET_ID);
+            assert!(matches!(packet, VLPDownlinkPacket::TelemetryPacket(_)));
+            assert!((-82..=-78).contains(&status.rssi));
+
+            let packet: VLPUplinkPacket = SoftArmPacket {
+                timestamp: 0.0,
+                armed: true,
+            }
+            .into();
+            let (status, received) = with_timeout(
+                20_000.0,
+                join(gcm.send(ROCKET_ID, packet.clone()), rocket.wait_receive()),
+            )
+            .await
+            .unwrap();
+            assert!(status.is_some());
+            assert_eq!(received.0, packet);
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_lost_uplink_and_ack() {
+        let clock = TokioClock::new();
+        let unix_clock_task = UnixClockTask::new(clock.clone());
+        let channel = VirtualLoraChannel::new(clock.clone(), VirtualLoraChannelConfig::default());
+        let lora_config = lora_config();
+        let mut rocket_phy = channel.create_phy(TokioDelay, &lora_config);
+        let mut gcm_phy = channel.create_phy(TokioDelay, &lora_config);
+        let rocket_node_id = rocket_phy.node_id();
+        let gcm_node_id = gcm_phy.node_id();
+
+        let rocket = VLPUplinkClient::new(ROCKET_ID);
+        let gcm = VLPDownlinkClient::new();
+        let nodes_fut = join3(
+            rocket.run_with_phy(TokioDelay, &mut rocket_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            gcm.run_with_phy(TokioDelay, &mut gcm_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            send_telemetry(&rocket),
+        );
+
+        run_until(nodes_fut, async {
+            // in low power mode the rocket stops sending telemetry and keeps a duty cycled
+            // receiver on, its 110ms period is shorter than the SF12 preamble so every
+            // retry can be heard
+            with_timeout(60_000.0, enter_low_power_mode(&gcm, &rocket))
+                .await
+                .unwrap();
+
+            let packet: VLPUplinkPacket = SoftArmPacket {
+                timestamp: 0.0,
+                armed: true,
+            }
+            .into();
+
+            channel.drop_next_packets(rocket_node_id, 1);
+            let (status, received) = with_timeout(
+                60_000.0,
+                join(gcm.send(ROCKET_ID, packet.clone()), rocket.wait_receive()),
+            )
+            .await
+            .unwrap();
+            assert!(status.is_some());
+            assert_eq!(received.0, packet);
+
+            channel.drop_next_packets(gcm_node_id, 1);
+            let status = with_timeout(60_000.0, gcm.send(ROCKET_ID, packet.clone()))
+                .await
+                .unwrap();
+            assert!(status.is_some());
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_low_power_mode() {
+        let clock = TokioClock::new();
+        let unix_clock_task = UnixClockTask::new(clock.clone());
+        let channel = VirtualLoraChannel::new(clock.clone(), VirtualLoraChannelConfig::default());
+        let lora_config = lora_config();
+        let mut rocket_phy = channel.create_phy(TokioDelay, &lora_config);
+        let mut gcm_phy = channel.create_phy(TokioDelay, &lora_config);
+
+        let rocket = VLPUplinkClient::new(ROCKET_ID);
+        let gcm = VLPDownlinkClient::new();
+        let nodes_fut = join3(
+            rocket.run_with_phy(TokioDelay, &mut rocket_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            gcm.run_with_phy(TokioDelay, &mut gcm_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            send_telemetry(&rocket),
+        );
+
+        run_until(nodes_fut, async {
+            with_timeout(60_000.0, enter_low_power_mode(&gcm, &rocket))
+                .await
+                .unwrap();
+
+            // drain telemetry received before entering low power mode
+            with_timeout(100.0, gcm.wait_receive()).await;
+            // no telemetry is sent in low power mode
+            assert!(with_timeout(20_000.0, gcm.wait_receive()).await.is_none());
+
+            // duty-cycle rx still catches uplink packets
+            let packet: VLPUplinkPacket = LowPowerModePacket {
+                timestamp: 0.0,
+                enabled: false,
+            }
+            .into();
+            let (status, received) = with_timeout(
+                60_000.0,
+                join(gcm.send(ROCKET_ID, packet.clone()), rocket.wait_receive()),
+            )
+            .await
+            .unwrap();
+            assert!(status.is_some());
+            assert_eq!(received.0, packet);
+
+            // telemetry resumes
+            let (source, _, _) = with_timeout(20_000.0, gcm.wait_receive()).await.unwrap();
+            assert_eq!(source, ROCKET_ID);
+        })
+        .await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_key_mismatch() {
+        let clock = TokioClock::new();
+        let unix_clock_task = UnixClockTask::new(clock.clone());
+        let channel = VirtualLoraChannel::new(clock.clone(), VirtualLoraChannelConfig::default());
+        let lora_config = lora_config();
+        let mut rocket_phy = channel.create_phy(TokioDelay, &lora_config);
+        let mut gcm_phy = channel.create_phy(TokioDelay, &lora_config);
+        let gcm_key = [0x42u8; 32];
+
+        let rocket = VLPUplinkClient::new(ROCKET_ID);
+        let gcm = VLPDownlinkClient::new();
+        let nodes_fut = join3(
+            rocket.run_with_phy(TokioDelay, &mut rocket_phy, &lora_config, unix_clock_task.get_clock(), &ROCKET_KEY),
+            gcm.run_with_phy(TokioDelay, &mut gcm_phy, &lora_config, unix_clock_task.get_clock(), &gcm_key),
+            send_telemetry(&rocket),
+        );
+
+        run_until(nodes_fut, async {
+            assert!(with_timeout(10_000.0, gcm.wait_receive()).await.is_none());
+
+            let packet: VLPUplinkPacket = SoftArmPacket {
+                timestamp: 0.0,
+                armed: true,
+            }
+            .into();
+            let status = with_timeout(60_000.0, gcm.send(ROCKET_ID, packet))
+                .await
+                .unwrap();
+            assert!(status.is_none());
+        })
+        .await;
+    }
+}
@@ -2,6 +2,7 @@ use core::marker::PhantomData;
 
 use embedded_hal_async::delay::DelayNs;
 
+use crate::common::delta_logger::header::{ADC_AMPERE_DATA_SCHEMA_ID, ADC_VOLT_DATA_SCHEMA_ID};
 use crate::common::delta_logger::prelude::*;
 use crate::common::fixed_point::F32FixedPointFactory;
 use crate::common::sensor_reading::{SensorData, SensorReading};
@@ -9,17 +10,24 @@ use crate::fixed_point_factory_slope;
 
 use super::timestamp::BootTimestamp;
 
-pub trait UnitType: Clone + defmt::Format + core::fmt::Debug {}
+pub trait UnitType: Clone + defmt::Format + core::fmt::Debug {
+    /// Schema id of `ADCData` with this unit
+    const SCHEMA_ID: u8;
+}
 
 #[derive(defmt::Format, Debug, Clone, PartialEq)]
 pub struct Volt;
 
-impl UnitType for Volt {}
+impl UnitType for Volt {
+    const SCHEMA_ID: u8 = ADC_VOLT_DATA_SCHEMA_ID;
+}
 
 #[derive(defmt::Format, Debug, Clone, PartialEq)]
 pub struct Ampere;
 
-impl UnitType for Ampere {}
+impl UnitType for Ampere {
+    const SCHEMA_ID: u8 = ADC_AMPERE_DATA_SCHEMA_ID;
+}
 
 #[derive(defmt::Format, Debug, Clone, PartialEq)]
 pub struct ADCData<U: UnitType> {
@@ -94,7 +102,9 @@ impl<U: UnitType> Deltable for ADCData<U> {
     }
 }
 
-impl<U: UnitType> SensorData for ADCData<U> {}
+impl<U: UnitType> SensorData for ADCData<U> {
+    const SCHEMA_ID: u8 = U::SCHEMA_ID;
+}
 
 pub trait ADC<U: UnitType> {
     type Error: defmt::Format + core::fmt::Debug;
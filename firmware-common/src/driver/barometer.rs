@@ -1,7 +1,10 @@
+use crate::common::delta_logger::header::{
+    BARO_DATA_SCHEMA_ID, QUANTIZED_BARO_TIER_1_SCHEMA_ID, QUANTIZED_BARO_TIER_2_SCHEMA_ID,
+};
+use crate::common::delta_logger::quantized::{QuantizableSensorData, MAX_QUANTIZED_FIELDS};
 use crate::common::delta_logger::prelude::*;
-use crate::common::fixed_point::F32FixedPointFactory;
 use crate::common::sensor_reading::{SensorData, SensorReading};
-use crate::fixed_point_factory_slope;
+use crate::{fixed_point_factory_slope, sensor_quantizer};
 
 use core::{fmt::Debug, ops::DerefMut as _};
 use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::MutexGuard};
@@ -11,78 +14,52 @@ use icao_units::si::Pascals;
 
 use super::timestamp::BootTimestamp;
 
-#[derive(defmt::Format, Debug, Clone)]
-pub struct BaroData {
-    pub temperature: f32, // C
-    pub pressure: f32,    // Pa
-}
-
-impl BitArraySerializable for BaroData {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.temperature);
-        writer.write(self.pressure);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            temperature: reader.read().unwrap(),
-            pressure: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        32 + 32
-    }
-}
-
 fixed_point_factory_slope!(TemperatureFac, 20.0, 5.0, 0.05);
 fixed_point_factory_slope!(PressureFac, 4000.0, 5.0, 1.0);
 
-#[derive(defmt::Format, Debug, Clone)]
-pub struct BaroDataDelta {
-    #[defmt(Debug2Format)]
-    pub temperature: TemperatureFacPacked,
-    #[defmt(Debug2Format)]
-    pub pressure: PressureFacPacked,
+#[derive(defmt::Format, Debug, Clone, BitArraySerializable, Deltable, SensorData)]
+#[sensor_data(schema_id = BARO_DATA_SCHEMA_ID)]
+pub struct BaroData {
+    #[delta(TemperatureFac)]
+    pub temperature: f32, // C
+    #[delta(PressureFac)]
+    pub pressure: f32, // Pa
 }
 
-impl BitArraySerializable for BaroDataDelta {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.temperature);
-        writer.write(self.pressure);
+impl QuantizableSensorData for BaroData {
+    const FIELD_NAMES: &'static [&'static str] = &["temperature", "pressure"];
+
+    fn to_fields(&self) -> [f32; MAX_QUANTIZED_FIELDS] {
+        let mut fields = [0.0; MAX_QUANTIZED_FIELDS];
+        fields[0] = self.temperature;
+        fields[1] = self.pressure;
+        fields
     }
 
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
+    fn from_fields(fields: &[f32; MAX_QUANTIZED_FIELDS]) -> Self {
         Self {
-            temperature: reader.read().unwrap(),
-            pressure: reader.read().unwrap(),
+            temperature: fields[0],
+            pressure: fields[1],
         }
     }
-
-    fn len_bits() -> usize {
-        TemperatureFacPacked::len_bits() + PressureFacPacked::len_bits()
-    }
-}
-
-impl Deltable for BaroData {
-    type DeltaType = BaroDataDelta;
-
-    fn add_delta(&self, delta: &Self::DeltaType) -> Option<Self> {
-        Some(Self {
-            temperature: self.temperature + TemperatureFac::to_float(delta.temperature),
-            pressure: self.pressure + PressureFac::to_float(delta.pressure),
-        })
-    }
-
-    fn subtract(&self, other: &Self) -> Option<Self::DeltaType> {
-        Some(Self::DeltaType {
-            temperature: TemperatureFac::to_fixed_point(self.temperature - other.temperature)?,
-            pressure: PressureFac::to_fixed_point(self.pressure - other.pressure)?,
-        })
-    }
 }
 
-impl SensorData for BaroData {}
+// (field index, factory, min, max, max error, delta bits)
+sensor_quantizer!(
+    BaroQuantizerTier1,
+    BaroData,
+    QUANTIZED_BARO_TIER_1_SCHEMA_ID,
+    (0, BaroT1TemperatureFac, -40.0, 85.0, 0.01, 4),
+    (1, BaroT1PressureFac, 0.0, 120000.0, 1.0, 6),
+);
+
+sensor_quantizer!(
+    BaroQuantizerTier2,
+    BaroData,
+    QUANTIZED_BARO_TIER_2_SCHEMA_ID,
+    (0, BaroT2TemperatureFac, -40.0, 85.0, 0.1, 3),
+    (1, BaroT2PressureFac, 0.0, 120000.0, 4.0, 5),
+);
 
 impl BaroData {
     pub fn altitude(&self) -> f32 {
@@ -148,3 +125,56 @@ where
         self.deref_mut().read().await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Written by the hand-written `BitArraySerializable` impl before the derive
+    const GOLDEN_VECTOR: [u8; 8] = [0x00, 0x00, 0xAC, 0x41, 0x80, 0xE6, 0xC5, 0x47];
+
+    #[test]
+    fn test_golden_vector() {
+        let data = BaroData {
+            temperature: 21.5,
+            pressure: 101325.0,
+        };
+        assert_eq!(BaroData::len_bits(), 64);
+
+        let mut writer = BitSliceWriter::<16>::default();
+        data.serialize(&mut writer);
+        assert_eq!(writer.view_all_data_slice(), &GOLDEN_VECTOR);
+
+        let mut reader = BitSliceReader::<16>::default();
+        reader.replenish_bytes(&GOLDEN_VECTOR);
+        let deserialized = BaroData::deserialize(&mut reader);
+        assert_eq!(deserialized.temperature, data.temperature);
+        assert_eq!(deserialized.pressure, data.pressure);
+    }
+
+    #[test]
+    fn test_delta_matches_baseline_layout() {
+        let a = BaroData {
+            temperature: 21.5,
+            pressure: 101325.0,
+        };
+        let b = BaroData {
+            temperature: 21.55,
+            pressure: 101321.0,
+        };
+        let delta = b.subtract(&a).unwrap();
+        assert_eq!(
+            BaroDataDelta::len_bits(),
+            TemperatureFacPacked::len_bits() + PressureFacPacked::len_bits()
+        );
+
+        let mut writer = BitSliceWriter::<16>::default();
+        delta.serialize(&mut writer);
+
+        // the hand-written impl wrote temperature then pressure
+        let mut expected = BitSliceWriter::<16>::default();
+        expected.write(delta.temperature);
+        expected.write(delta.pressure);
+        assert_eq!(writer.view_all_data_slice(), expected.view_all_data_slice());
+    }
+}
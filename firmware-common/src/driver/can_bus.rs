@@ -2,7 +2,10 @@ use core::cell::RefCell;
 use core::convert::Infallible;
 use core::marker::PhantomData;
 
+use rkyv::{Archive, Deserialize, Serialize};
+
 use crate::common::can_bus::message::CanBusMessage;
+use crate::common::schema_hash::SchemaHash;
 
 use super::delay::Delay;
 
@@ -25,11 +28,57 @@ pub trait SplitableCanBus {
     fn split(&mut self) -> (Self::TX<'_>, Self::RX<'_>);
 }
 
+/// Error state of the CAN controller, see the fault confinement rules in the CAN spec
+#[derive(
+    defmt::Format,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Archive,
+    Deserialize,
+    Serialize,
+    SchemaHash,
+)]
+#[archive(check_bytes)]
+pub enum CanBusState {
+    #[default]
+    ErrorActive,
+    /// Error counter above 127, the controller can still send but only passive error flags
+    ErrorPassive,
+    /// TX error counter above 255, the controller is disconnected from the bus
+    /// until `CanBusTX::recover_from_bus_off` is called
+    BusOff,
+}
+
+/// Snapshot of the health counters of the CAN controller
+#[derive(defmt::Format, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CanBusStatus {
+    pub state: CanBusState,
+    pub tx_error_count: u8,
+    pub rx_error_count: u8,
+    /// total since the driver is created
+    pub lost_arbitration_count: u32,
+    /// frames dropped by the controller because the RX buffer is full,
+    /// total since the driver is created
+    pub rx_overrun_count: u32,
+}
+
 pub trait CanBusTX {
     type Error: defmt::Format + core::fmt::Debug;
 
     fn configure_self_node(&mut self, node_type: u8, node_id: u16);
 
+    /// Drivers that can't read the controller state should report an error
+    /// active bus
+    fn status(&self) -> CanBusStatus;
+
+    /// Rejoin the bus after bus-off, should be a no-op for drivers that
+    /// recover on their own
+    async fn recover_from_bus_off(&mut self) -> Result<(), Self::Error>;
+
     /// priority can be 0 - 7, 7 being the highest priority
     async fn send<T: CanBusMessage>(
         &mut self,
@@ -41,6 +90,21 @@ pub trait CanBusTX {
     async fn send_remote<T: CanBusMessage>(&mut self, priority: u8) -> Result<(), Self::Error>;
 }
 
+/// Implemented by drivers that can send frames with an arbitrary message
+/// type, needed by transports built on top of the bus (e.g. multi-frame
+/// transfers)
+pub trait CanBusRawTX: CanBusTX {
+    /// Sends a frame with up to 8 bytes of data as the configured node
+    ///
+    /// priority can be 0 - 7, 7 being the highest priority
+    async fn send_raw(
+        &mut self,
+        message_type: u8,
+        data: &[u8],
+        priority: u8,
+    ) -> Result<(), Self::Error>;
+}
+
 pub trait CanBusRX {
     type Error: defmt::Format + core::fmt::Debug;
     type Message: CanBusRawMessage;
@@ -53,6 +117,13 @@ pub fn can_node_id_from_serial_number(serial_number: &[u8]) -> u16 {
     crc.checksum(serial_number) & 0xFFF
 }
 
+/// Independent from `can_node_id_from_serial_number`, two nodes with the same
+/// node id are very unlikely to also have the same serial hash
+pub fn can_node_serial_hash(serial_number: &[u8]) -> u16 {
+    let crc = crc::Crc::<u16>::new(&crc::CRC_16_ARC);
+    crc.checksum(serial_number)
+}
+
 pub struct SplitableCanBusWrapper<
     T: CanBusTX<Error = E>,
     R: CanBusRX<Error = E>,
@@ -112,6 +183,16 @@ impl<'a, T: CanBusTX<Error = E>, R: CanBusRX<Error = E>, E: defmt::Format + core
         tx.configure_self_node(node_type, node_id);
     }
 
+    fn status(&self) -> CanBusStatus {
+        let tx = self.wrapper.tx.borrow();
+        tx.status()
+    }
+
+    async fn recover_from_bus_off(&mut self) -> Result<(), Self::Error> {
+        let mut tx = self.wrapper.tx.borrow_mut();
+        tx.recover_from_bus_off().await
+    }
+
     async fn send<M: CanBusMessage>(
         &mut self,
         message: &M,
@@ -127,6 +208,24 @@ impl<'a, T: CanBusTX<Error = E>, R: CanBusRX<Error = E>, E: defmt::Format + core
     }
 }
 
+impl<
+        'a,
+        T: CanBusRawTX<Error = E>,
+        R: CanBusRX<Error = E>,
+        E: defmt::Format + core::fmt::Debug,
+    > CanBusRawTX for TXGuard<'a, T, R, E>
+{
+    async fn send_raw(
+        &mut self,
+        message_type: u8,
+        data: &[u8],
+        priority: u8,
+    ) -> Result<(), Self::Error> {
+        let mut tx = self.wrapper.tx.borrow_mut();
+        tx.send_raw(message_type, data, priority).await
+    }
+}
+
 pub struct RXGuard<
     'a,
     T: CanBusTX<Error = E>,
@@ -182,6 +281,14 @@ impl<D: Delay> CanBusTX for DummyCanBus<D> {
         // noop
     }
 
+    fn status(&self) -> CanBusStatus {
+        CanBusStatus::default()
+    }
+
+    async fn recover_from_bus_off(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     async fn send<T: CanBusMessage>(
         &mut self,
         _message: &T,
@@ -195,6 +302,17 @@ impl<D: Delay> CanBusTX for DummyCanBus<D> {
     }
 }
 
+impl<D: Delay> CanBusRawTX for DummyCanBus<D> {
+    async fn send_raw(
+        &mut self,
+        _message_type: u8,
+        _data: &[u8],
+        _priority: u8,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 impl<D: Delay> CanBusRX for DummyCanBus<D> {
     type Error = Infallible;
     type Message = DummyCanBusMessage;
@@ -1,3 +1,4 @@
+use crate::common::delta_logger::header::GPS_DATA_SCHEMA_ID;
 use crate::common::delta_logger::prelude::*;
 use crate::common::fixed_point::F32FixedPointFactory;
 use crate::common::sensor_reading::{SensorData, SensorReading};
@@ -15,7 +16,8 @@ use super::clock::Clock;
 use super::delay::Delay;
 use super::timestamp::BootTimestamp;
 
-#[derive(defmt::Format, Debug, Clone)]
+#[derive(defmt::Format, Debug, Clone, BitArraySerializable, SensorData)]
+#[sensor_data(schema_id = GPS_DATA_SCHEMA_ID)]
 pub struct GPSData {
     pub timestamp: Option<i64>, // in seconds
     pub lat_lon: Option<(f64, f64)>,
@@ -55,45 +57,11 @@ impl From<&Nmea> for GPSData {
     }
 }
 
-impl BitArraySerializable for GPSData {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-        writer.write(self.lat_lon);
-        writer.write(self.altitude);
-        writer.write(self.num_of_fix_satellites);
-        writer.write(self.hdop);
-        writer.write(self.vdop);
-        writer.write(self.pdop);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        GPSData {
-            timestamp: reader.read().unwrap(),
-            lat_lon: reader.read().unwrap(),
-            altitude: reader.read().unwrap(),
-            num_of_fix_satellites: reader.read().unwrap(),
-            hdop: reader.read().unwrap(),
-            vdop: reader.read().unwrap(),
-            pdop: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        <Option<i64> as BitSlicePrimitive>::len_bits()
-            + <Option<(f64, f64)> as BitSlicePrimitive>::len_bits()
-            + <Option<f32> as BitSlicePrimitive>::len_bits()
-            + u8::len_bits()
-            + <Option<f32> as BitSlicePrimitive>::len_bits()
-            + <Option<f32> as BitSlicePrimitive>::len_bits()
-            + <Option<f32> as BitSlicePrimitive>::len_bits()
-    }
-}
-
 fixed_point_factory_slope!(LatLonFac, 0.01, 100.0, 0.000005);
 fixed_point_factory_slope!(AltitudeFac, 400.0, 100.0, 0.5);
 fixed_point_factory!(DoPFac, f32, 0.0, 1.0, 0.1);
 
-#[derive(defmt::Format, Debug, Clone)]
+#[derive(defmt::Format, Debug, Clone, BitArraySerializable)]
 pub struct GPSDataDelta {
     pub timestamp: u8,
     #[defmt(Debug2Format)]
@@ -110,40 +78,6 @@ pub struct GPSDataDelta {
     pub pdop: DoPFacPacked,
 }
 
-impl BitArraySerializable for GPSDataDelta {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.timestamp);
-        writer.write(self.lat_lon);
-        writer.write(self.altitude);
-        writer.write(self.num_of_fix_satellites);
-        writer.write(self.hdop);
-        writer.write(self.vdop);
-        writer.write(self.pdop);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            timestamp: reader.read().unwrap(),
-            lat_lon: reader.read().unwrap(),
-            altitude: reader.read().unwrap(),
-            num_of_fix_satellites: reader.read().unwrap(),
-            hdop: reader.read().unwrap(),
-            vdop: reader.read().unwrap(),
-            pdop: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        u8::len_bits()
-            + <(LatLonFacPacked, LatLonFacPacked)>::len_bits()
-            + AltitudeFacPacked::len_bits()
-            + <VariableInt<2> as VariableIntTrait>::Packed::len_bits()
-            + DoPFacPacked::len_bits()
-            + DoPFacPacked::len_bits()
-            + DoPFacPacked::len_bits()
-    }
-}
-
 impl Deltable for GPSData {
     type DeltaType = GPSDataDelta;
 
@@ -216,8 +150,6 @@ impl Deltable for GPSData {
     }
 }
 
-impl SensorData for GPSData {}
-
 pub trait GPS {
     type Error: defmt::Format + Debug;
 
@@ -3,105 +3,76 @@ use core::fmt::Debug;
 use embedded_hal_async::delay::DelayNs;
 use ferraris_calibration::IMUReadingTrait;
 
+use crate::common::delta_logger::header::{
+    IMU_DATA_SCHEMA_ID, QUANTIZED_IMU_TIER_1_SCHEMA_ID, QUANTIZED_IMU_TIER_2_SCHEMA_ID,
+};
+use crate::common::delta_logger::quantized::{QuantizableSensorData, MAX_QUANTIZED_FIELDS};
 use crate::{
     common::{
-        delta_logger::{delta_factory::Deltable, prelude::*},
-        fixed_point::F32FixedPointFactory,
+        delta_logger::prelude::*,
         sensor_reading::{SensorData, SensorReading},
     },
-    fixed_point_factory_slope,
+    fixed_point_factory_slope, sensor_quantizer,
 };
 
 use super::timestamp::{BootTimestamp, TimestampType};
 
-#[derive(defmt::Format, Debug, Clone)]
-pub struct IMUData {
-    pub acc: [f32; 3],  // m/s^2
-    pub gyro: [f32; 3], // deg/s
-}
-
-impl BitArraySerializable for IMUData {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.acc);
-        writer.write(self.gyro);
-    }
-
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
-        Self {
-            acc: reader.read().unwrap(),
-            gyro: reader.read().unwrap(),
-        }
-    }
-
-    fn len_bits() -> usize {
-        <[f32; 3]>::len_bits() + <[f32; 3]>::len_bits()
-    }
-}
-
 fixed_point_factory_slope!(AccFac, 100.0, 5.0, 0.01);
 fixed_point_factory_slope!(GyroFac, 100.0, 5.0, 0.01);
 
-#[derive(defmt::Format, Debug, Clone)]
-pub struct IMUDataDelta {
-    #[defmt(Debug2Format)]
-    pub acc: [AccFacPacked; 3],
-    #[defmt(Debug2Format)]
-    pub gyro: [GyroFacPacked; 3],
+#[derive(defmt::Format, Debug, Clone, BitArraySerializable, Deltable, SensorData)]
+#[sensor_data(schema_id = IMU_DATA_SCHEMA_ID)]
+pub struct IMUData {
+    #[delta(AccFac)]
+    pub acc: [f32; 3], // m/s^2
+    #[delta(GyroFac)]
+    pub gyro: [f32; 3], // deg/s
 }
 
-impl BitArraySerializable for IMUDataDelta {
-    fn serialize<const N: usize>(&self, writer: &mut BitSliceWriter<N>) {
-        writer.write(self.acc);
-        writer.write(self.gyro);
+impl QuantizableSensorData for IMUData {
+    const FIELD_NAMES: &'static [&'static str] =
+        &["acc_x", "acc_y", "acc_z", "gyro_x", "gyro_y", "gyro_z"];
+
+    fn to_fields(&self) -> [f32; MAX_QUANTIZED_FIELDS] {
+        let mut fields = [0.0; MAX_QUANTIZED_FIELDS];
+        fields[0..3].copy_from_slice(&self.acc);
+        fields[3..6].copy_from_slice(&self.gyro);
+        fields
     }
 
-    fn deserialize<const N: usize>(reader: &mut BitSliceReader<N>) -> Self {
+    fn from_fields(fields: &[f32; MAX_QUANTIZED_FIELDS]) -> Self {
         Self {
-            acc: reader.read().unwrap(),
-            gyro: reader.read().unwrap(),
+            acc: [fields[0], fields[1], fields[2]],
+            gyro: [fields[3], fields[4], fields[5]],
         }
     }
-
-    fn len_bits() -> usize {
-        <[AccFacPacked; 3]>::len_bits() + <[GyroFacPacked; 3]>::len_bits()
-    }
 }
 
-impl Deltable for IMUData {
-    type DeltaType = IMUDataDelta;
-
-    fn add_delta(&self, delta: &Self::DeltaType) -> Option<Self> {
-        Some(Self {
-            acc: [
-                self.acc[0] + AccFac::to_float(delta.acc[0]),
-                self.acc[1] + AccFac::to_float(delta.acc[1]),
-                self.acc[2] + AccFac::to_float(delta.acc[2]),
-            ],
-            gyro: [
-                self.gyro[0] + GyroFac::to_float(delta.gyro[0]),
-                self.gyro[1] + GyroFac::to_float(delta.gyro[1]),
-                self.gyro[2] + GyroFac::to_float(delta.gyro[2]),
-            ],
-        })
-    }
-
-    fn subtract(&self, other: &Self) -> Option<Self::DeltaType> {
-        Some(IMUDataDelta {
-            acc: [
-                AccFac::to_fixed_point(self.acc[0] - other.acc[0])?,
-                AccFac::to_fixed_point(self.acc[1] - other.acc[1])?,
-                AccFac::to_fixed_point(self.acc[2] - other.acc[2])?,
-            ],
-            gyro: [
-                GyroFac::to_fixed_point(self.gyro[0] - other.gyro[0])?,
-                GyroFac::to_fixed_point(self.gyro[1] - other.gyro[1])?,
-                GyroFac::to_fixed_point(self.gyro[2] - other.gyro[2])?,
-            ],
-        })
-    }
-}
-
-impl SensorData for IMUData {}
+// (field index, factory, min, max, max error, delta bits)
+// covers the range of both the low g and the high g imu
+sensor_quantizer!(
+    IMUQuantizerTier1,
+    IMUData,
+    QUANTIZED_IMU_TIER_1_SCHEMA_ID,
+    (0, IMUT1AccXFac, -1600.0, 1600.0, 0.02, 9),
+    (1, IMUT1AccYFac, -1600.0, 1600.0, 0.02, 9),
+    (2, IMUT1AccZFac, -1600.0, 1600.0, 0.02, 9),
+    (3, IMUT1GyroXFac, -2000.0, 2000.0, 0.05, 9),
+    (4, IMUT1GyroYFac, -2000.0, 2000.0, 0.05, 9),
+    (5, IMUT1GyroZFac, -2000.0, 2000.0, 0.05, 9),
+);
+
+sensor_quantizer!(
+    IMUQuantizerTier2,
+    IMUData,
+    QUANTIZED_IMU_TIER_2_SCHEMA_ID,
+    (0, IMUT2AccXFac, -1600.0, 1600.0, 0.1, 7),
+    (1, IMUT2AccYFac, -1600.0, 1600.0, 0.1, 7),
+    (2, IMUT2AccZFac, -1600.0, 1600.0, 0.1, 7),
+    (3, IMUT2GyroXFac, -2000.0, 2000.0, 0.5, 6),
+    (4, IMUT2GyroYFac, -2000.0, 2000.0, 0.5, 6),
+    (5, IMUT2GyroZFac, -2000.0, 2000.0, 0.5, 6),
+);
 
 impl<T: TimestampType> IMUReadingTrait for SensorReading<T, IMUData> {
     fn timestamp(&self) -> f64 {
@@ -160,3 +131,59 @@ impl<D: DelayNs> IMU for DummyIMU<D> {
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Written by the hand-written `BitArraySerializable` impl before the derive
+    const GOLDEN_VECTOR: [u8; 24] = [
+        0x00, 0x00, 0xC0, 0x3F, 0x00, 0x00, 0x10, 0xC0, 0xC3, 0xF5, 0x1C, 0x41, 0xCD, 0xCC, 0xCC,
+        0x3D, 0xCD, 0xCC, 0x4C, 0xBE, 0x00, 0x00, 0xB4, 0x43,
+    ];
+
+    #[test]
+    fn test_golden_vector() {
+        let data = IMUData {
+            acc: [1.5, -2.25, 9.81],
+            gyro: [0.1, -0.2, 360.0],
+        };
+        assert_eq!(IMUData::len_bits(), 192);
+
+        let mut writer = BitSliceWriter::<32>::default();
+        data.serialize(&mut writer);
+        assert_eq!(writer.view_all_data_slice(), &GOLDEN_VECTOR);
+
+        let mut reader = BitSliceReader::<32>::default();
+        reader.replenish_bytes(&GOLDEN_VECTOR);
+        let deserialized = IMUData::deserialize(&mut reader);
+        assert_eq!(deserialized.acc, data.acc);
+        assert_eq!(deserialized.gyro, data.gyro);
+    }
+
+    #[test]
+    fn test_delta_matches_baseline_layout() {
+        let a = IMUData {
+            acc: [1.5, -2.25, 9.81],
+            gyro: [0.1, -0.2, 360.0],
+        };
+        let b = IMUData {
+            acc: [1.62, -2.5, 9.7],
+            gyro: [0.3, -0.25, 359.7],
+        };
+        let delta = b.subtract(&a).unwrap();
+        assert_eq!(
+            IMUDataDelta::len_bits(),
+            <[AccFacPacked; 3]>::len_bits() + <[GyroFacPacked; 3]>::len_bits()
+        );
+
+        let mut writer = BitSliceWriter::<32>::default();
+        delta.serialize(&mut writer);
+
+        // the hand-written impl wrote acc then gyro
+        let mut expected = BitSliceWriter::<32>::default();
+        expected.write(delta.acc);
+        expected.write(delta.gyro);
+        assert_eq!(writer.view_all_data_slice(), expected.view_all_data_slice());
+    }
+}
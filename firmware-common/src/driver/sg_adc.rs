@@ -24,4 +24,9 @@ pub trait SGAdc<T: RawSGReadingsTrait> {
 
 pub trait SGAdcController {
     async fn set_enable(&mut self, enable: bool);
+
+    /// Returns false if the sample rate is not supported
+    async fn set_sample_rate(&mut self, _samples_per_second: u32) -> bool {
+        false
+    }
 }
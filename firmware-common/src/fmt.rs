@@ -25,6 +25,31 @@ macro_rules! log_debug {
     };
 }
 
+// defmt keeps its deferred formatting, the device log ring buffer needs the
+// formatted message. Every argument is bound to a reference once so both sinks
+// see the same value, each recursion step gets its own hygienic `arg`
+macro_rules! log_to_device {
+    (@bind $defmt_macro:ident, $level:ident, $s:literal, [$($arg:ident)*], $x:expr $(, $rest:expr)*) => {
+        match &$x {
+            arg => log_to_device!(@bind $defmt_macro, $level, $s, [$($arg)* arg] $(, $rest)*),
+        }
+    };
+    (@bind $defmt_macro:ident, $level:ident, $s:literal, [$($arg:ident)*]) => {
+        {
+            ::defmt::$defmt_macro!($s $(, *$arg)*);
+
+            #[cfg(feature = "device-log")]
+            $crate::common::device_log::DEVICE_LOG.log(
+                $crate::common::device_log::DeviceLogLevel::$level,
+                format_args!($s $(, *$arg)*),
+            );
+        }
+    };
+    ($defmt_macro:ident, $level:ident, $s:literal $(, $x:expr)*) => {
+        log_to_device!(@bind $defmt_macro, $level, $s, [] $(, $x)*)
+    };
+}
+
 macro_rules! log_info {
     ($s:literal $(, $x:expr)* $(,)?) => {
         {
@@ -32,7 +57,7 @@ macro_rules! log_info {
             ::log::info!($s $(, $x)*);
 
             #[cfg(feature = "defmt")]
-            ::defmt::info!($s $(, $x)*);
+            log_to_device!(info, Info, $s $(, $x)*);
         }
     };
 }
@@ -44,7 +69,7 @@ macro_rules! log_warn {
             ::log::warn!($s $(, $x)*);
 
             #[cfg(feature = "defmt")]
-            ::defmt::warn!($s $(, $x)*);
+            log_to_device!(warn, Warn, $s $(, $x)*);
         }
     };
 }
@@ -56,7 +81,7 @@ macro_rules! log_error {
             ::log::error!($s $(, $x)*);
 
             #[cfg(feature = "defmt")]
-            ::defmt::error!($s $(, $x)*);
+            log_to_device!(error, Error, $s $(, $x)*);
         }
     };
 }
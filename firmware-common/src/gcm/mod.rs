@@ -1,36 +1,144 @@
+use core::cell::RefCell;
+
 use embassy_futures::select::select;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Sender};
+use embassy_sync::{
+    blocking_mutex::{raw::NoopRawMutex, Mutex as BlockingMutex},
+    channel::{Channel, Sender},
+};
 use futures::join;
+use heapless::LinearMap;
 use lora_phy::mod_params::PacketStatus;
+use rkyv::{Archive, Deserialize, Serialize};
 use vlfs::{Crc, Flash};
 
 use crate::{
     claim_devices,
     common::{
         device_config::DeviceConfig,
+        file_types::GCM_TELEMETRY_RECORDING_FILE_TYPE,
         vl_device_manager::prelude::*,
         rpc_channel::RpcChannelServer,
+        schema_hash::SchemaHash,
+        versioned::Versioned,
         vlp::{
             downlink_client::VLPDownlinkClient,
             packet::{VLPDownlinkPacket, VLPUplinkPacket},
+            packet_builder::VLP_BROADCAST_DEVICE_ID,
         },
     },
-    vl_device_manager_type,
+    create_serialized_enum, try_or_warn, utils::run_with_timeout, vl_device_manager_type,
     driver::indicator::Indicator,
 };
 
+pub const MAX_GCM_DEVICES: usize = 8;
+// flushing claims a new sector, so batch the received packets
+const RECORDING_FLUSH_INTERVAL_MS: f64 = 10_000.0;
+// waiting for space would stall the receive loop and lose packets in the
+// downlink client, so buffer enough packets to cover the slowest flash write
+const RECORDING_CHANNEL_SIZE: usize = 32;
+
+/// A downlink packet received by the GCM, as it was received
+#[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize)]
+#[archive(check_bytes)]
+pub struct ReceivedVLPPacket {
+    pub unix_timestamp: f64, // ms
+    pub device_id: u8,
+    pub rssi: i16,
+    pub snr: i16,
+    pub packet: VLPDownlinkPacket,
+}
+
+impl Versioned for ReceivedVLPPacket {}
+
+create_serialized_enum!(
+    GCMRecordingLogger,
+    GCMRecordingLoggerReader,
+    GCMRecordingLog,
+    (0, ReceivedVLPPacket)
+);
+
+/// State of the telemetry recording, read by the `GCMGetRecordingStatus` rpc
+#[derive(Debug, Clone, Default, defmt::Format)]
+pub struct GCMRecordingStatus {
+    /// `None` until the recording file is created, stays `None` if creating
+    /// it failed
+    pub file_id: Option<u64>,
+    pub recorded_packets: u32,
+}
+
+/// What the GCM knows about a rocket it has heard from
+#[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize, SchemaHash)]
+#[archive(check_bytes)]
+pub struct GCMDeviceState {
+    /// Boot timestamp of the last packet from this device
+    pub last_seen_timestamp: f64,
+    pub last_rssi: i16,
+    pub last_snr: i16,
+    pub received_packets: u32,
+}
+
+/// Rockets the GCM has heard from, keyed by VLP device id. Two rockets whose
+/// serial numbers hash to the same id show up as one device, give one of them
+/// a `vlp_device_id` in its device config.
+pub struct GCMDeviceTable {
+    devices: LinearMap<u8, GCMDeviceState, MAX_GCM_DEVICES>,
+}
+
+impl GCMDeviceTable {
+    pub fn new() -> Self {
+        Self {
+            devices: LinearMap::new(),
+        }
+    }
+
+    pub fn get(&self, device_id: u8) -> Option<&GCMDeviceState> {
+        self.devices.get(&device_id)
+    }
+
+    pub fn packet_received(&mut self, device_id: u8, status: &PacketStatus, now: f64) {
+        if let Some(state) = self.devices.get_mut(&device_id) {
+            state.last_seen_timestamp = now;
+            state.last_rssi = status.rssi;
+            state.last_snr = status.snr;
+            state.received_packets += 1;
+        } else {
+            log_info!("New device found: {}", device_id);
+            let state = GCMDeviceState {
+                last_seen_timestamp: now,
+                last_rssi: status.rssi,
+                last_snr: status.snr,
+                received_packets: 1,
+            };
+            if self.devices.insert(device_id, state).is_err() {
+                log_warn!("Too many devices, not tracking device {}", device_id);
+            }
+        }
+    }
+
+    /// Fixed size device table for the console RPC
+    pub fn table(&self) -> [Option<(u8, GCMDeviceState)>; MAX_GCM_DEVICES] {
+        let mut table: [Option<(u8, GCMDeviceState)>; MAX_GCM_DEVICES] = Default::default();
+        for (entry, (device_id, state)) in table.iter_mut().zip(self.devices.iter()) {
+            *entry = Some((*device_id, state.clone()));
+        }
+        table
+    }
+}
+
 #[inline(never)]
 pub async fn gcm_main(
     device_manager: vl_device_manager_type!(),
     services: system_services_type!(),
     config: &DeviceConfig,
-    downlink_package_sender: Sender<'_, NoopRawMutex, (VLPDownlinkPacket, PacketStatus), 1>,
+    downlink_package_sender: Sender<'_, NoopRawMutex, (u8, VLPDownlinkPacket, PacketStatus), 1>,
     mut send_uplink_packet_rpc_server: RpcChannelServer<
         '_,
         NoopRawMutex,
-        VLPUplinkPacket,
+        (u8, VLPUplinkPacket),
         Option<PacketStatus>,
     >,
+    recording_status: &BlockingMutex<NoopRawMutex, RefCell<GCMRecordingStatus>>,
+    devices: &BlockingMutex<NoopRawMutex, RefCell<GCMDeviceTable>>,
 ) {
     claim_devices!(device_manager, lora, indicators);
 
@@ -39,6 +147,8 @@ pub async fn gcm_main(
     // select(indicators_fut, wait_gps_fut).await;
     let indictors_fut = indicators.run([], [50, 950], []);
 
+    let recording_channel = Channel::<NoopRawMutex, ReceivedVLPPacket, RECORDING_CHANNEL_SIZE>::new();
+
     let vlp_client = VLPDownlinkClient::new();
     let vlp_client_fut = vlp_client.run(
         services.delay(),
@@ -50,21 +160,102 @@ pub async fn gcm_main(
 
     let vlp_send_fut = async {
         loop {
-            let packet = send_uplink_packet_rpc_server.get_request().await;
-            let response = vlp_client.send(packet).await;
+            let (device_id, packet) = send_uplink_packet_rpc_server.get_request().await;
+            if device_id != VLP_BROADCAST_DEVICE_ID {
+                let state = devices.lock(|devices| devices.borrow().get(device_id).cloned());
+                if let Some(state) = state {
+                    log_info!(
+                        "Sending packet to device {}, last heard {}ms ago with RSSI {} SNR {}, {} packets received",
+                        device_id,
+                        services.clock.now_ms() - state.last_seen_timestamp,
+                        state.last_rssi,
+                        state.last_snr,
+                        state.received_packets,
+                    );
+                } else {
+                    log_warn!("Sending packet to device {} which has not been heard from", device_id);
+                }
+            }
+            let response = vlp_client.send(device_id, packet).await;
             send_uplink_packet_rpc_server.send_response(response).await;
         }
     };
 
     let vlp_receive_fut = async {
         loop {
-            let received = vlp_client.wait_receive().await;
-            downlink_package_sender.try_send(received).ok();
+            let (device_id, packet, status) = vlp_client.wait_receive().await;
+            devices.lock(|devices| {
+                devices
+                    .borrow_mut()
+                    .packet_received(device_id, &status, services.clock.now_ms())
+            });
+            let received = ReceivedVLPPacket {
+                unix_timestamp: services.unix_clock.now_ms(),
+                device_id,
+                rssi: status.rssi,
+                snr: status.snr,
+                packet: packet.clone(),
+            };
+            if recording_channel.try_send(received).is_err() {
+                log_warn!("Recording channel full, packet not recorded");
+            }
+            downlink_package_sender.try_send((device_id, packet, status)).ok();
+        }
+    };
+
+    let recording_fut = async {
+        log_info!("Creating telemetry recording");
+        let mut recording_file_writer = match services
+            .fs
+            .create_file_and_open_for_write(GCM_TELEMETRY_RECORDING_FILE_TYPE)
+            .await
+        {
+            Ok(writer) => writer,
+            Err(e) => {
+                // keep the radio link up, drop the packets instead of recording them
+                log_error!("Failed to create telemetry recording, not recording: {:?}", e);
+                loop {
+                    recording_channel.receive().await;
+                }
+            }
+        };
+        recording_status.lock(|status| {
+            status.borrow_mut().file_id = Some(recording_file_writer.file_id.0);
+        });
+        let mut logger = GCMRecordingLogger::new();
+        let mut delay = services.delay();
+        let mut last_flush_time = services.clock.now_ms();
+        let mut unflushed = false;
+
+        loop {
+            let receive_fut = recording_channel.receive();
+            if let Ok(received) = run_with_timeout(&mut delay, RECORDING_FLUSH_INTERVAL_MS, receive_fut).await {
+                try_or_warn!(
+                    logger
+                        .write(&mut recording_file_writer, &GCMRecordingLog::ReceivedVLPPacket(received))
+                        .await
+                );
+                recording_status.lock(|status| status.borrow_mut().recorded_packets += 1);
+                unflushed = true;
+            }
+
+            let now = services.clock.now_ms();
+            if unflushed && now - last_flush_time >= RECORDING_FLUSH_INTERVAL_MS {
+                try_or_warn!(recording_file_writer.flush().await);
+                last_flush_time = now;
+                unflushed = false;
+            }
         }
     };
 
     #[allow(unreachable_code)]
     {
-        join!(vlp_client_fut, vlp_send_fut, vlp_receive_fut, indictors_fut);
+        join!(
+            vlp_client_fut,
+            vlp_send_fut,
+            vlp_receive_fut,
+            recording_fut,
+            indictors_fut
+        );
     }
 }
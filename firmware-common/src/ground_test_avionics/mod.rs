@@ -4,25 +4,28 @@ use crate::{
     avionics::{arming_state::ArmingStateManager, flight_profile::PyroSelection},
     claim_devices,
     common::{
-        can_bus::{messages as can_messages, node_types::VOID_LAKE_NODE_TYPE}, delta_logger::{buffered_logger::BufferedLoggerState, delta_logger::DeltaLogger, prelude::DeltaLoggerTrait}, device_config::{DeviceConfig, DeviceModeConfig}, file_types::{GROUND_TEST_BARO_FILE_TYPE, GROUND_TEST_LOG_FILE_TYPE}, ticker::Ticker, vl_device_manager::prelude::*, vlp::{
+        can_bus::{messages as can_messages, node_types::VOID_LAKE_NODE_TYPE}, delta_logger::{buffered_logger::BufferedLoggerState, delta_logger::DeltaLogger, prelude::DeltaLoggerTrait, timestamp_factories::SensorsFF1}, device_config::{DeviceConfig, DeviceModeConfig}, file_types::{contains_file_type, GROUND_TEST_BARO_FILE_TYPE, GROUND_TEST_LOG_FILE_TYPE}, ticker::Ticker, vl_device_manager::prelude::*, versioned::Versioned, vlp::{
             packet::{GroundTestDeployPacket, VLPDownlinkPacket, VLPUplinkPacket},
             telemetry_packet::TelemetryPacketBuilder,
             uplink_client::VLPUplinkClient,
         }
     },
-    create_serialized_enum,
+    create_serialized_enum, delta_logger_file_types,
     driver::{barometer::BaroData, can_bus::{can_node_id_from_serial_number, CanBusTX}, indicator::Indicator},
-    fixed_point_factory, pyro, try_or_warn, vl_device_manager_type,
+    pyro, try_or_warn, vl_device_manager_type,
 };
 use embassy_sync::{blocking_mutex::{raw::NoopRawMutex, Mutex as BlockingMutex}, mutex::Mutex};
 use futures::join;
 use rkyv::{Archive, Deserialize, Serialize};
 
 #[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize)]
+#[archive(check_bytes)]
 pub struct FireEvent {
     pub timestamp: f64, // ms
 }
 
+impl Versioned for FireEvent {}
+
 create_serialized_enum!(
     GroundTestLogger,
     GroundTestLoggerReader,
@@ -30,6 +33,21 @@ create_serialized_enum!(
     (0, FireEvent)
 );
 
+/// Delta loggers of `ground_test_avionics`, see `delta_logger_file_types`
+#[macro_export]
+macro_rules! ground_test_delta_loggers {
+    ($callback:ident!($($args:expr),* $(,)?)) => {
+        $callback!($($args,)* [
+            ($crate::common::file_types::GROUND_TEST_BARO_FILE_TYPE, $crate::driver::barometer::BaroData, $crate::common::delta_logger::timestamp_factories::SensorsFF1),
+        ])
+    };
+}
+
+const _: () = assert!(contains_file_type(
+    ground_test_delta_loggers!(delta_logger_file_types!()),
+    GROUND_TEST_BARO_FILE_TYPE
+));
+
 #[inline(never)]
 pub async fn ground_test_avionics(
     device_manager: vl_device_manager_type!(),
@@ -58,13 +76,13 @@ pub async fn ground_test_avionics(
     let mut logger = GroundTestLogger::new();
 
     log_info!("Creating baro logger");
-    fixed_point_factory!(BaroFF, f64, 4.9, 7.0, 0.05);
     let baro_log_file_writer = services
         .fs
         .create_file_and_open_for_write(GROUND_TEST_BARO_FILE_TYPE)
         .await
         .unwrap();
-    let baro_logger = DeltaLogger::<BaroData, _, BaroFF>::new(baro_log_file_writer);
+    let baro_logger =
+        DeltaLogger::<BaroData, _, SensorsFF1>::new(baro_log_file_writer, *device_serial_number);
     let buffered_baro_logger_state = BufferedLoggerState::<_, _, _, 100>::new(baro_logger);
     let (mut buffered_baro_logger, mut buffered_baro_logger_runner) =
         buffered_baro_logger_state.get_logger_runner();
@@ -116,7 +134,9 @@ pub async fn ground_test_avionics(
     };
 
     let telemetry_packet_builder = TelemetryPacketBuilder::new(services.unix_clock());
-    let vlp = VLPUplinkClient::new();
+    let vlp_device_id = config.vlp_device_id(device_serial_number);
+    log_info!("VLP device id: {}", vlp_device_id);
+    let vlp = VLPUplinkClient::new(vlp_device_id);
     let vlp_tx_fut = async {
         let mut update_ticker = Ticker::every(services.clock(), services.delay(), 1000.0);
         loop {
@@ -15,7 +15,7 @@ pub mod utils;
 pub mod avionics;
 pub mod common;
 pub mod driver;
-mod gcm;
+pub mod gcm;
 mod ground_test_avionics;
 pub mod strain_gauges;
 pub mod vacuum_test;
@@ -25,6 +25,7 @@ pub use common::console::vl_rpc;
 pub use common::console::sg_rpc;
 pub use common::console::common_rpc_trait::CommonRPCTrait;
 pub use common::vl_device_manager::VLDeviceManager;
+pub use ground_test_avionics::{FireEvent, GroundTestLog, GroundTestLoggerReader};
 pub use vl_main::vl_main;
 pub use strain_gauges::high_prio::sg_high_prio_main;
 pub use strain_gauges::mid_prio::sg_mid_prio_main;
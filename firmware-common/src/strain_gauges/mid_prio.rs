@@ -8,19 +8,21 @@ use embassy_sync::mutex::Mutex;
 use futures::join;
 use vlfs::{Crc, Flash, VLFS};
 
-use crate::common::can_bus::id::CanBusExtendedId;
-use crate::common::can_bus::message::CanBusMessage as _;
+use crate::common::can_bus::clock_sync::{ClockSyncEvent, ClockSyncSlave, SyncedClock};
+use crate::common::can_bus::command::{CanCommandServer, CanCommandServerEvent};
 use crate::common::can_bus::messages::{
-    AvionicsStatusMessage, FlightEvent, FlightEventMessage, HealthMessage, HealthState,
-    ResetMessage, UnixTimeMessage,
+    DecodedCanMessage, FlightEvent, HealthState, NodeCommand, NodeCommandStatus,
 };
+use crate::common::can_bus::node_registry::{create_heartbeat_message, CAN_HEARTBEAT_INTERVAL_MS};
 use crate::common::can_bus::node_types::STRAIN_GAUGES_NODE_TYPE;
 use crate::common::console::sg_rpc::run_rpc_server;
 use crate::common::delta_logger::buffered_logger::BufferedLoggerState;
 use crate::common::delta_logger::delta_logger::{ArchivedUnixTimestampLog, UnixTimestampLog};
 use crate::common::delta_logger::prelude::RingFileWriter;
 use crate::common::delta_logger::ring_delta_logger::{RingDeltaLoggerConfig, RingDeltaLoggerState};
-use crate::common::file_types::{SG_BATTERY_LOGGER, SG_READINGS};
+use crate::common::delta_logger::timestamp_factories::BatteryFF;
+use crate::common::device_log::DEVICE_LOG;
+use crate::common::file_types::{contains_file_type, SG_BATTERY_LOGGER, SG_READINGS};
 use crate::common::ticker::Ticker;
 use crate::driver::adc::{ADCData, Volt, ADC};
 use crate::driver::can_bus::{
@@ -32,7 +34,7 @@ use crate::driver::sg_adc::{RawSGReadingsTrait, SGAdcController};
 use crate::driver::sys_reset::SysReset;
 use crate::driver::usb::SplitableUSB;
 use crate::driver::{can_bus::SplitableCanBus, indicator::Indicator};
-use crate::{create_serialized_enum, fixed_point_factory, try_or_warn};
+use crate::{create_serialized_enum, delta_logger_file_types, try_or_warn};
 
 use super::global_states::SGGlobalStates;
 use super::{ArchivedProcessedSGReading, ProcessedSGReading};
@@ -45,9 +47,57 @@ create_serialized_enum!(
     (1, UnixTimestampLog)
 );
 
-fixed_point_factory!(BatteryFF, f64, 4999.0, 5010.0, 0.5);
+/// Delta loggers of `sg_mid_prio_main`, see `delta_logger_file_types`
+#[macro_export]
+macro_rules! sg_delta_loggers {
+    ($callback:ident!($($args:expr),* $(,)?)) => {
+        $callback!($($args,)* [
+            ($crate::common::file_types::SG_BATTERY_LOGGER, $crate::driver::adc::ADCData<$crate::driver::adc::Volt>, $crate::common::delta_logger::timestamp_factories::BatteryFF),
+        ])
+    };
+}
+
+const _: () = assert!(contains_file_type(
+    sg_delta_loggers!(delta_logger_file_types!()),
+    SG_BATTERY_LOGGER
+));
 
 pub async fn sg_mid_prio_main(
+    states: &SGGlobalStates<impl RawMutex, impl RawSGReadingsTrait>,
+    device_serial_number: &[u8; 12],
+    indicator: impl Indicator,
+    sg_adc_controller: impl SGAdcController,
+    flash: impl Flash,
+    crc: impl Crc,
+    can: impl SplitableCanBus,
+    clock: impl Clock + Sync,
+    delay: impl Delay,
+    sys_reset: impl SysReset,
+    usb: impl SplitableUSB,
+    battery_adc: impl ADC<Volt>,
+) {
+    DEVICE_LOG
+        .with_timestamp_source(
+            clock.clone(),
+            run_sg_mid_prio(
+                states,
+                device_serial_number,
+                indicator,
+                sg_adc_controller,
+                flash,
+                crc,
+                can,
+                clock,
+                delay,
+                sys_reset,
+                usb,
+                battery_adc,
+            ),
+        )
+        .await
+}
+
+async fn run_sg_mid_prio(
     states: &SGGlobalStates<impl RawMutex, impl RawSGReadingsTrait>,
     device_serial_number: &[u8; 12],
     mut indicator: impl Indicator,
@@ -74,10 +124,14 @@ pub async fn sg_mid_prio_main(
 
     log_info!("Initializing CAN Bus");
     let (mut can_tx, mut can_rx) = can.split();
-    can_tx.configure_self_node(
+    let can_node_id = can_node_id_from_serial_number(device_serial_number);
+    can_tx.configure_self_node(STRAIN_GAUGES_NODE_TYPE, can_node_id);
+    let can_tx = Mutex::<NoopRawMutex, _>::new(can_tx);
+    let clock_sync = BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(ClockSyncSlave::new(
         STRAIN_GAUGES_NODE_TYPE,
-        can_node_id_from_serial_number(device_serial_number),
-    );
+        can_node_id,
+    )));
+    let synced_clock = SyncedClock::new(&clock_sync, clock.clone());
 
     let usb_connected = {
         log_info!("Waiting for USB connection");
@@ -103,6 +157,7 @@ pub async fn sg_mid_prio_main(
                 &mut usb,
                 &fs,
                 &sys_reset,
+                delay.clone(),
                 device_serial_number,
                 &sg_adc_controller,
                 states,
@@ -115,6 +170,11 @@ pub async fn sg_mid_prio_main(
         let delay = delay.clone();
         let mut armed = false;
         let mut landed = false;
+        // set by the avionics through CAN commands, overrides armed and landed
+        // until the avionics disarms or lands
+        let mut high_rate_logging: Option<bool> = None;
+        let mut low_power = false;
+        let mut command_server = CanCommandServer::new(STRAIN_GAUGES_NODE_TYPE, can_node_id);
         loop {
             match can_rx.receive().await {
                 Ok(message) => {
@@ -126,47 +186,137 @@ pub async fn sg_mid_prio_main(
                     if message.rtr() {
                         continue;
                     }
-                    let id = CanBusExtendedId::from_raw(message.id());
-
-                    if id.message_type == AvionicsStatusMessage::message_type() {
-                        // if armed -> going to launch soon, start recording adc
-                        let message =
-                            AvionicsStatusMessage::from_data(message.data().try_into().unwrap());
-                        log_info!("Received CAN message: AvionicsStatusMessage");
-                        armed = message.armed;
-                    } else if id.message_type == FlightEventMessage::message_type() {
-                        // if landed -> stop recording adc
-                        let message =
-                            FlightEventMessage::from_data(message.data().try_into().unwrap());
-                        log_info!("Received CAN message: FlightEventMessage");
-                        landed = message.event == FlightEvent::Landed;
-                    } else if id.message_type == UnixTimeMessage::message_type() {
-                        // sync time
-                        let boot_timestamp = message.timestamp();
-                        let message =
-                            UnixTimeMessage::from_data(message.data().try_into().unwrap());
-                        log_info!("Received CAN message: UnixTimeMessage");
-                        let unix_timestamp: u64 = message.timestamp.into();
-                        unix_timestamp_log_mutex.lock(|m| {
-                            m.borrow_mut().replace((
-                                UnixTimestampLog {
-                                    boot_timestamp,
-                                    unix_timestamp: unix_timestamp as f64,
-                                },
-                                true,
-                            ));
-                        });
-                    } else if id.message_type == ResetMessage::message_type() {
-                        sys_reset.reset();
+                    match clock_sync.lock(|s| s.borrow_mut().process_message(&message)) {
+                        Some(ClockSyncEvent::SendDelayRequest(request)) => {
+                            let mut can_tx = can_tx.lock().await;
+                            let send_start = clock.now_ms();
+                            if can_tx.send(&request, 6).await.is_ok() {
+                                let send_end = clock.now_ms();
+                                clock_sync.lock(|s| {
+                                    s.borrow_mut().delay_request_sent(
+                                        request.sequence,
+                                        send_start,
+                                        send_end,
+                                    )
+                                });
+                            }
+                            drop(can_tx);
+                            continue;
+                        }
+                        Some(ClockSyncEvent::Synced) => {
+                            // boot and unix timestamp of the same instant, so
+                            // the readings line up with the avionics
+                            let boot_timestamp = clock.now_ms();
+                            if let Some(unix_timestamp) =
+                                synced_clock.convert_to_master(boot_timestamp)
+                            {
+                                unix_timestamp_log_mutex.lock(|m| {
+                                    m.borrow_mut().replace((
+                                        UnixTimestampLog {
+                                            boot_timestamp,
+                                            unix_timestamp,
+                                        },
+                                        true,
+                                    ));
+                                });
+                            }
+                            continue;
+                        }
+                        None => {}
+                    }
+                    match command_server.process_message(&message) {
+                        Some(CanCommandServerEvent::Execute(command)) => {
+                            let status = match command.command {
+                                NodeCommand::SetHighRateLogging => {
+                                    high_rate_logging = Some(command.argument != 0);
+                                    NodeCommandStatus::Ok
+                                }
+                                NodeCommand::SetSampleRate => {
+                                    if command.argument == 0 {
+                                        NodeCommandStatus::InvalidArgument
+                                    } else if sg_adc_controller
+                                        .lock()
+                                        .await
+                                        .set_sample_rate(command.argument)
+                                        .await
+                                    {
+                                        NodeCommandStatus::Ok
+                                    } else {
+                                        NodeCommandStatus::Unsupported
+                                    }
+                                }
+                                NodeCommand::ClearData => {
+                                    if states.error_states.lock(|s| s.borrow().recording) {
+                                        NodeCommandStatus::Busy
+                                    } else if fs.remove_files(()).await.is_ok() {
+                                        NodeCommandStatus::Ok
+                                    } else {
+                                        NodeCommandStatus::Failed
+                                    }
+                                }
+                                NodeCommand::SetLowPower => {
+                                    low_power = command.argument != 0;
+                                    NodeCommandStatus::Ok
+                                }
+                            };
+                            log_info!("CAN command {:?}: {:?}", command.command, status);
+                            let response = command_server.respond(&command, status);
+                            can_tx.lock().await.send(&response, 4).await.ok();
+                        }
+                        Some(CanCommandServerEvent::Resend(response)) => {
+                            can_tx.lock().await.send(&response, 4).await.ok();
+                        }
+                        None => {}
+                    }
+                    match DecodedCanMessage::decode(message.id(), message.data()) {
+                        Some(DecodedCanMessage::AvionicsStatusMessage(status)) => {
+                            // if armed -> going to launch soon, start recording adc
+                            log_info!("Received CAN message: AvionicsStatusMessage");
+                            if armed && !status.armed {
+                                // the override only lasts until the flight ends
+                                high_rate_logging = None;
+                            }
+                            armed = status.armed;
+                        }
+                        Some(DecodedCanMessage::FlightEventMessage(event)) => {
+                            // if landed -> stop recording adc
+                            log_info!("Received CAN message: FlightEventMessage");
+                            landed = event.event == FlightEvent::Landed;
+                            if landed {
+                                high_rate_logging = None;
+                            }
+                        }
+                        Some(DecodedCanMessage::UnixTimeMessage(unix_time))
+                            if !synced_clock.synced() =>
+                        {
+                            // coarse time sync for avionics without clock sync
+                            let boot_timestamp = message.timestamp();
+                            log_info!("Received CAN message: UnixTimeMessage");
+                            let unix_timestamp: u64 = unix_time.timestamp.into();
+                            unix_timestamp_log_mutex.lock(|m| {
+                                m.borrow_mut().replace((
+                                    UnixTimestampLog {
+                                        boot_timestamp,
+                                        unix_timestamp: unix_timestamp as f64,
+                                    },
+                                    true,
+                                ));
+                            });
+                        }
+                        Some(DecodedCanMessage::ResetMessage(_)) => {
+                            sys_reset.reset();
+                        }
+                        _ => {}
                     }
 
+                    let recording = !low_power && high_rate_logging.unwrap_or(armed && !landed);
                     sg_adc_controller
                         .lock()
                         .await
-                        .set_enable(armed && !landed)
+                        .set_enable(recording)
                         .await;
                     states.error_states.lock(|s| {
-                        s.borrow_mut().recording = armed && !landed;
+                        s.borrow_mut().recording = recording;
                     });
                 }
                 Err(e) => {
@@ -181,17 +331,17 @@ pub async fn sg_mid_prio_main(
     };
 
     let can_tx_fut = async {
-        let mut ticker = Ticker::every(clock.clone(), delay.clone(), 1000.0);
+        let mut ticker = Ticker::every(clock.clone(), delay.clone(), CAN_HEARTBEAT_INTERVAL_MS);
         loop {
             let error_states = states.error_states.lock(|s| s.borrow().clone());
-            let health_message = HealthMessage {
-                state: if error_states.can_bus_error || error_states.sg_adc_error {
-                    HealthState::UnHealthy
-                } else {
-                    HealthState::Healthy
-                },
+            let health = if error_states.can_bus_error || error_states.sg_adc_error {
+                HealthState::UnHealthy
+            } else {
+                HealthState::Healthy
             };
-            can_tx.send(&health_message, 3).await.ok();
+            let heartbeat_message =
+                create_heartbeat_message(health, clock.now_ms(), device_serial_number);
+            can_tx.lock().await.send(&heartbeat_message, 3).await.ok();
             ticker.next().await;
         }
     };
@@ -245,6 +395,7 @@ pub async fn sg_mid_prio_main(
                 seconds_per_segment: 60 * 10,
                 first_segment_seconds: 60 * 10,
                 segments_per_ring: 60, // 10 hours of data
+                pin: None,
             },
             delay.clone(),
             clock.clone(),
@@ -265,7 +416,9 @@ pub async fn sg_mid_prio_main(
                     seconds_per_segment: 1800,
                     first_segment_seconds: 60,
                     segments_per_ring: 20, // 10 hours
+                    pin: None,
                 },
+                *device_serial_number,
             )
             .await
             .unwrap();
@@ -1,5 +1,7 @@
 use rkyv::{Archive, Deserialize, Serialize};
 
+use crate::common::versioned::Versioned;
+
 pub mod global_states;
 pub mod high_prio;
 pub mod low_prio;
@@ -24,6 +26,7 @@ pub const READS_PER_SECOND: usize = 200;
 
 // one per 200ms per channel
 #[derive(defmt::Format, Debug, Clone, Archive, Deserialize, Serialize)]
+#[archive(check_bytes)]
 pub struct ProcessedSGReading {
     pub start_time: f64, // boot time in ms
     pub sg_i: u8,
@@ -31,6 +34,8 @@ pub struct ProcessedSGReading {
     pub samples: [u8; 80],      // [half::f16; 40]
 }
 
+impl Versioned for ProcessedSGReading {}
+
 impl Default for ProcessedSGReading {
     fn default() -> Self {
         Self {
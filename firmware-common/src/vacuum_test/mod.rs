@@ -10,21 +10,27 @@ use crate::avionics::flight_profile::FlightProfile;
 use crate::common::config_file::ConfigFile;
 use crate::common::delta_logger::buffered_logger::BufferedLoggerState;
 use crate::common::delta_logger::ring_delta_logger::{RingDeltaLoggerConfig, RingDeltaLoggerState};
+use crate::common::delta_logger::timestamp_factories::VacuumTestFF;
 use crate::common::file_types::{
-    FLIGHT_PROFILE_FILE_TYPE, VACUUM_TEST_BARO_LOGGER, VACUUM_TEST_LOG_FILE_TYPE,
+    contains_file_type, FLIGHT_PROFILE_FILE_TYPE, VACUUM_TEST_BARO_LOGGER,
+    VACUUM_TEST_LOG_FILE_TYPE,
 };
 use crate::common::ticker::Ticker;
+use crate::common::versioned::Versioned;
 use crate::common::vl_device_manager::prelude::*;
 use crate::driver::barometer::BaroData;
-use crate::{claim_devices, create_serialized_enum, fixed_point_factory};
+use crate::{claim_devices, create_serialized_enum, delta_logger_file_types};
 use crate::{system_services_type, vl_device_manager_type};
 
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone, defmt::Format)]
+#[archive(check_bytes)]
 pub struct FlightCoreEventLog {
-    timestamp: f64,
-    event: FlightCoreEvent,
+    pub timestamp: f64,
+    pub event: FlightCoreEvent,
 }
 
+impl Versioned for FlightCoreEventLog {}
+
 create_serialized_enum!(
     VacuumTestLogger,
     VacuumTestLoggerReader,
@@ -32,12 +38,26 @@ create_serialized_enum!(
     (0, FlightCoreEventLog)
 );
 
-fixed_point_factory!(SensorsFF1, f64, 4.0, 7.0, 0.05);
+/// Delta loggers of `vacuum_test_main`, see `delta_logger_file_types`
+#[macro_export]
+macro_rules! vacuum_test_delta_loggers {
+    ($callback:ident!($($args:expr),* $(,)?)) => {
+        $callback!($($args,)* [
+            ($crate::common::file_types::VACUUM_TEST_BARO_LOGGER, $crate::driver::barometer::BaroData, $crate::common::delta_logger::timestamp_factories::VacuumTestFF),
+        ])
+    };
+}
+
+const _: () = assert!(contains_file_type(
+    vacuum_test_delta_loggers!(delta_logger_file_types!()),
+    VACUUM_TEST_BARO_LOGGER
+));
 
 #[inline(never)]
 pub async fn vacuum_test_main(
     device_manager: vl_device_manager_type!(),
     services: system_services_type!(),
+    device_serial_number: &[u8; 12],
 ) -> ! {
     claim_devices!(device_manager, indicators, barometer);
 
@@ -64,7 +84,7 @@ pub async fn vacuum_test_main(
     let mut logger = VacuumTestLogger::new();
 
     log_info!("Creating baro logger");
-    let baro_logger_state = RingDeltaLoggerState::<BaroData, _, _, SensorsFF1, _, _>::new(
+    let baro_logger_state = RingDeltaLoggerState::<BaroData, _, _, VacuumTestFF, _, _>::new(
         services.fs,
         services.delay.clone(),
         services.clock.clone(),
@@ -73,7 +93,9 @@ pub async fn vacuum_test_main(
             seconds_per_segment: 60,
             first_segment_seconds: 30,
             segments_per_ring: 30, // 30 min
+            pin: None,
         },
+        *device_serial_number,
     )
     .await
     .unwrap();
@@ -1,19 +1,26 @@
 use crate::avionics::avionics_main;
 use crate::claim_devices;
+use crate::common::can_bus::node_registry::CanNodeRegistry;
+use crate::common::can_bus::node_types::VOID_LAKE_NODE_TYPE;
+use crate::common::can_bus::statistics::CanBusStatistics;
 use crate::common::config_file::ConfigFile;
 use crate::common::console::vl_rpc::run_rpc_server;
 use crate::common::device_config::{DeviceConfig, DeviceModeConfig};
+use crate::common::device_log::DEVICE_LOG;
 use crate::common::file_types::DEVICE_CONFIG_FILE_TYPE;
 use crate::common::rpc_channel::RpcChannel;
 use crate::common::sensor_reading::SensorReading;
 use crate::common::{buzzer_queue::BuzzerQueueRunner, unix_clock::UnixClockTask};
+use crate::driver::can_bus::can_node_id_from_serial_number;
 use crate::driver::clock::VLFSTimerWrapper;
 use crate::driver::gps::GPSData;
 use crate::driver::timestamp::BootTimestamp;
 use crate::vacuum_test::vacuum_test_main;
 use embassy_futures::select::{select, Either};
 use embassy_futures::yield_now;
+use core::cell::RefCell;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::pubsub::{PubSubBehavior, PubSubChannel};
 use futures::join;
@@ -21,7 +28,7 @@ use futures::join;
 use crate::common::vl_device_manager::prelude::*;
 use vlfs::{StatFlash, VLFS};
 
-use crate::gcm::gcm_main;
+use crate::gcm::{gcm_main, GCMDeviceTable, GCMRecordingStatus};
 use crate::ground_test_avionics::ground_test_avionics;
 
 pub async fn vl_main(
@@ -32,6 +39,19 @@ pub async fn vl_main(
     #[cfg(all(feature = "defmt", feature = "log"))]
     compile_error!("Feature defmt and log are mutually exclusive and cannot be enabled together");
 
+    DEVICE_LOG
+        .with_timestamp_source(
+            device_manager.clock(),
+            run_vl(device_manager, device_serial_number, device_config),
+        )
+        .await
+}
+
+async fn run_vl(
+    device_manager: vl_device_manager_type!(mut),
+    device_serial_number: &[u8; 12],
+    device_config: Option<DeviceConfig>,
+) -> ! {
     claim_devices!(
         device_manager,
         flash,
@@ -130,6 +150,18 @@ pub async fn vl_main(
         }
     };
 
+    let can_node_registry = BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(
+        CanNodeRegistry::new(
+            VOID_LAKE_NODE_TYPE,
+            can_node_id_from_serial_number(device_serial_number),
+        ),
+    ));
+    let can_bus_statistics =
+        BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(CanBusStatistics::new()));
+    let gcm_recording_status =
+        BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(GCMRecordingStatus::default()));
+    let gcm_devices = BlockingMutex::<NoopRawMutex, _>::new(RefCell::new(GCMDeviceTable::new()));
+
     log_info!("Initializing RPC Server");
     let gcm_downlink_package_channel = Channel::new();
     let gcm_send_uplink_packet_rpc = RpcChannel::new();
@@ -140,6 +172,10 @@ pub async fn vl_main(
         device_serial_number,
         gcm_downlink_package_channel.receiver(),
         gcm_send_uplink_packet_rpc.client(),
+        &can_node_registry,
+        &can_bus_statistics,
+        &gcm_recording_status,
+        &gcm_devices,
     );
     let usb_console_fut = async {
         loop {
@@ -151,6 +187,10 @@ pub async fn vl_main(
                 device_serial_number,
                 gcm_downlink_package_channel.receiver(),
                 gcm_send_uplink_packet_rpc.client(),
+                &can_node_registry,
+                &can_bus_statistics,
+                &gcm_recording_status,
+                &gcm_devices,
             )
             .await;
         }
@@ -183,6 +223,8 @@ pub async fn vl_main(
                     &services,
                     &device_config,
                     device_serial_number,
+                    &can_node_registry,
+                    &can_bus_statistics,
                 )
                 .await
             }
@@ -193,6 +235,8 @@ pub async fn vl_main(
                     &device_config,
                     gcm_downlink_package_channel.sender(),
                     gcm_send_uplink_packet_rpc.server(),
+                    &gcm_recording_status,
+                    &gcm_devices,
                 )
                 .await
             }
@@ -208,7 +252,7 @@ pub async fn vl_main(
             }
             DeviceModeConfig::VacuumTest => {
                 stop_if_usb_connected().await;
-                vacuum_test_main(device_manager, &services).await
+                vacuum_test_main(device_manager, &services, device_serial_number).await
             }
         };
     };
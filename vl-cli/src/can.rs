@@ -0,0 +1,114 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use clap_num::maybe_hex;
+use firmware_common::common::can_bus::id::CanBusExtendedId;
+use firmware_common::common::can_bus::messages::HealthState;
+use firmware_common::common::can_bus::node_registry::{
+    create_heartbeat_message, CAN_HEARTBEAT_INTERVAL_MS,
+};
+use firmware_common::driver::can_bus::{
+    can_node_id_from_serial_number, CanBusRX, CanBusRawMessage, CanBusTX,
+};
+use tokio::time::{sleep, Duration};
+use vl_host_lib::can_bus::{open_socket_can, CanFrameDecoder, CanFrameFilter};
+
+#[derive(clap::Parser)]
+pub struct CanCli {
+    /// SocketCAN interface, e.g. can0 or vcan0
+    interface: String,
+
+    #[clap(subcommand)]
+    command: CanCommands,
+}
+
+#[derive(Subcommand)]
+enum CanCommands {
+    #[command(about = "Decode and print the frames on the bus")]
+    Monitor(CanMonitorArgs),
+
+    #[command(about = "Act as a CAN node that only sends heartbeats")]
+    Heartbeat(CanHeartbeatArgs),
+}
+
+#[derive(clap::Args)]
+struct CanMonitorArgs {
+    /// Only show frames from this node type, can be repeated
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    node_type: Vec<u8>,
+
+    /// Only show frames with this message type, can be repeated
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    message_type: Vec<u8>,
+
+    /// Also append the decoded frames to this file
+    #[arg(long)]
+    log: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct CanHeartbeatArgs {
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    node_type: u8,
+
+    /// Serial number in hex, the node id is derived from it
+    serial_number: String,
+}
+
+pub async fn run_can_cli(cli: CanCli) -> Result<()> {
+    let (mut tx, mut rx) = open_socket_can(&cli.interface)?;
+
+    match cli.command {
+        CanCommands::Monitor(args) => {
+            let filter = CanFrameFilter {
+                node_types: args.node_type,
+                message_types: args.message_type,
+            };
+            let mut log_file = match args.log {
+                Some(path) => Some(LineWriter::new(
+                    File::options().create(true).append(true).open(path)?,
+                )),
+                None => None,
+            };
+            let mut decoder = CanFrameDecoder::new();
+
+            loop {
+                let frame = rx
+                    .receive()
+                    .await
+                    .map_err(|e| anyhow!("Error receiving CAN frame: {:?}", e))?;
+                // decode every frame so multi-frame transfers are reassembled
+                let line = decoder.decode(&frame);
+                if !filter.matches(&CanBusExtendedId::from_raw(frame.id())) {
+                    continue;
+                }
+                println!("{}", line);
+                if let Some(log_file) = &mut log_file {
+                    writeln!(log_file, "{}", line)?;
+                }
+            }
+        }
+        CanCommands::Heartbeat(args) => {
+            let serial_number = crate::parse_hex(&args.serial_number)?;
+            let node_id = can_node_id_from_serial_number(&serial_number);
+            tx.configure_self_node(args.node_type, node_id);
+            println!("Sending heartbeats as node type {} id {:03X}", args.node_type, node_id);
+
+            let start = Instant::now();
+            loop {
+                let message = create_heartbeat_message(
+                    HealthState::Healthy,
+                    start.elapsed().as_secs_f64() * 1000.0,
+                    &serial_number,
+                );
+                tx.send(&message, 3)
+                    .await
+                    .map_err(|e| anyhow!("Error sending CAN frame: {:?}", e))?;
+                sleep(Duration::from_secs_f64(CAN_HEARTBEAT_INTERVAL_MS / 1000.0)).await;
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
 #![feature(generic_const_exprs)]
 
+#[cfg(target_os = "linux")]
+mod can;
+
 use std::cmp::Ordering;
 use std::time::Duration;
 
@@ -8,7 +11,16 @@ use anyhow::Result;
 use clap::{Parser, Subcommand};
 use clap_num::maybe_hex;
 use embedded_hal_async::delay::DelayNs;
+use firmware_common::common::can_bus::command::CAN_COMMAND_BROADCAST_NODE_ID;
+use firmware_common::common::can_bus::messages::NodeCommand;
+use firmware_common::common::can_bus::node_types::STRAIN_GAUGES_NODE_TYPE;
+use firmware_common::common::file_types::file_type_by_name;
+use firmware_common::common::file_types::FileTypeDescription;
 use firmware_common::common::console::vl_rpc::GCMPollDownlinkPacketResponse;
+use firmware_common::common::vlp::config_update::serialize_config;
+use firmware_common::common::vlp::config_update::DeviceConfigUpdate;
+use firmware_common::common::vlp::packet::CanCommandPacket;
+use firmware_common::common::vlp::packet::ConfigUpdateType;
 use firmware_common::common::vlp::packet::DeleteLogsPacket;
 use firmware_common::common::vlp::packet::LowPowerModePacket;
 use firmware_common::common::vlp::packet::ManualTriggerDeplotmentPacket;
@@ -17,25 +29,49 @@ use firmware_common::common::vlp::packet::SoftArmPacket;
 use firmware_common::common::vlp::packet::VLPDownlinkPacket;
 use firmware_common::common::vlp::packet::VLPUplinkPacket;
 use firmware_common::common::vlp::packet::VerticalCalibrationPacket;
+use firmware_common::common::vlp::packet_builder::VLP_BROADCAST_DEVICE_ID;
 use firmware_common::common::vlp::telemetry_packet::TelemetryPacket;
 use firmware_common::sg_rpc;
 use firmware_common::vl_rpc;
 use firmware_common::vl_rpc::RpcPacketStatus;
+use futures_util::{pin_mut, StreamExt};
 use log::LevelFilter;
 use tokio::fs::read_to_string;
 use tokio::time::sleep;
 use tokio_serial::available_ports;
-use vl_host_lib::common::list_files;
+use vl_host_lib::can_bus::can_bus_registry_json;
+use vl_host_lib::common::decode_delta_log_to_csv;
+use vl_host_lib::common::describe_delta_log_header;
+use vl_host_lib::common::export::ExportFormat;
+use vl_host_lib::common::export::ExportManifest;
+use vl_host_lib::common::list_files_with_type;
+use vl_host_lib::common::print_device_logs;
 use vl_host_lib::common::probe_device_type;
+use vl_host_lib::common::read_delta_log_header_or_legacy;
 use vl_host_lib::common::pull_file;
+use vl_host_lib::common::quantization_report;
 use vl_host_lib::create_serial;
+use vl_host_lib::timeline::build_timeline;
+use vl_host_lib::timeline::write_timeline_csv;
+use vl_host_lib::timeline::ResampleMethod;
+use vl_host_lib::timeline::ResampleOptions;
 use vl_host_lib::ozys::pull_ozys_data;
 use vl_host_lib::vl::format_lora_key;
+use vl_host_lib::vl::gcm_push_config;
 use vl_host_lib::vl::gen_lora_key;
+use vl_host_lib::vl::device_config_to_json;
+use vl_host_lib::vl::diff_device_config;
+use vl_host_lib::vl::diff_flight_profile;
+use vl_host_lib::vl::flight_profile_to_json;
 use vl_host_lib::vl::json_to_device_config;
 use vl_host_lib::vl::json_to_flight_profile;
 use vl_host_lib::vl::pull_flight_data;
+use vl_host_lib::vl::pull_gcm_recording;
+use vl_host_lib::vl::pull_ground_test;
+use vl_host_lib::vl::replay_gcm_recording;
 use vl_host_lib::vl::pull_vacuum_test;
+use vl_host_lib::vl::DecodedVLPPacket;
+use vl_host_lib::vl::VLPCodec;
 use vlfs::FileID;
 use vlfs::FileType;
 
@@ -68,6 +104,77 @@ enum ModeSelect {
 
     #[command(about = "Generate a new Lora key")]
     GenLoraKey,
+
+    #[command(about = "Replay telemetry recorded by the GCM")]
+    GCMReplay(GCMReplayArgs),
+
+    #[command(about = "Encode / decode raw VLP LoRa payloads")]
+    VLP(VLPCli),
+
+    #[cfg(target_os = "linux")]
+    #[command(about = "Monitor the CAN bus through SocketCAN")]
+    CAN(can::CanCli),
+
+    #[command(about = "Print the CAN bus message and node type registry as JSON")]
+    CANRegistry,
+
+    #[command(about = "Decode a delta log file (.vldr) to csv, the decoder is picked from the file header")]
+    DecodeLog(DecodeLogArgs),
+
+    #[command(
+        about = "Compare the size and the max error of the quantized delta log modes on recorded delta logs"
+    )]
+    QuantizationReport(QuantizationReportArgs),
+
+    #[command(about = "Merge the files pulled from one or more devices into one csv on a unix time base")]
+    Timeline(TimelineArgs),
+}
+
+#[derive(clap::Args)]
+struct DecodeLogArgs {
+    file: std::path::PathBuf,
+
+    /// Only print the file header
+    #[arg(long)]
+    header_only: bool,
+
+    /// Defaults to the input file path with the extension replaced by csv
+    #[arg(long)]
+    csv: Option<std::path::PathBuf>,
+
+    /// File type id or name of files without a header, defaults to the one
+    /// in the pulled file name
+    #[arg(long, value_parser=file_type_parser)]
+    file_type: Option<FileType>,
+}
+
+#[derive(clap::Args)]
+struct QuantizationReportArgs {
+    /// Delta log files (.vldr) with unquantized imu or baro readings
+    #[arg(required = true)]
+    files: Vec<std::path::PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct TimelineArgs {
+    /// Folders created by the pull commands, one per device
+    #[arg(required = true)]
+    folders: Vec<std::path::PathBuf>,
+
+    #[arg(long, default_value = "timeline.csv")]
+    output: std::path::PathBuf,
+
+    /// Resample every channel to this interval, keeps the raw samples if not set
+    #[arg(long, value_parser=positive_f64_parser)]
+    resample_ms: Option<f64>,
+
+    /// hold, nearest or linear
+    #[arg(long, default_value = "linear")]
+    method: ResampleMethod,
+
+    /// Channels are left empty when the closest sample is further than this
+    #[arg(long, default_value_t = 1000.0)]
+    max_gap_ms: f64,
 }
 
 #[derive(Parser)]
@@ -86,10 +193,35 @@ struct OZYSCli {
     command: SGCommands,
 }
 
+#[derive(Parser)]
+struct VLPCli {
+    /// Device config json to take the LoRa config and key from
+    #[arg(long)]
+    config: std::path::PathBuf,
+
+    #[clap(subcommand)]
+    command: VLPCommands,
+}
+
+#[derive(Subcommand)]
+enum VLPCommands {
+    #[command(about = "Decode a raw LoRa payload, tries both uplink and downlink")]
+    Decode(VLPDecodeArgs),
+
+    #[command(about = "Encode an uplink packet into a raw LoRa payload")]
+    Encode(GCMSendUplinkArgs),
+}
+
+#[derive(clap::Args)]
+struct VLPDecodeArgs {
+    /// Payload in hex, spaces and a leading 0x are ignored
+    hex: String,
+}
+
 #[derive(Subcommand)]
 enum SGCommands {
     #[command(about = "Pull all the strain gauges readings from device")]
-    PullData(PullDataArgs),
+    PullData(PullSensorDataArgs),
 
     #[command(about = "Clear all the data on the device")]
     ClearData,
@@ -99,6 +231,7 @@ enum SGCommands {
 
     LS(LSArgs),
     PullFile(PullArgs),
+    Logs(LogsArgs),
 
     #[command(about = "Reset device")]
     Reset,
@@ -106,28 +239,67 @@ enum SGCommands {
 
 #[derive(Subcommand)]
 enum VLCommands {
-    #[clap(subcommand)]
-    GCMSendUplink(GCMUplinkPacket),
+    GCMSendUplink(GCMSendUplinkArgs),
     GCMListen(GCMArgs),
+
+    #[command(about = "Pull telemetry recordings from the GCM")]
+    GCMPullRecording(PullDataArgs),
+
+    #[command(about = "List the rockets the GCM has heard from")]
+    GCMDevices,
+
+    #[command(about = "Push a flight profile to a rocket over the air through the GCM")]
+    GCMPushProfile(GCMPushConfigArgs),
+
+    #[command(about = "Push the LoRa settings and VLP device id of a device config to a rocket over the air through the GCM, takes effect after reboot")]
+    GCMPushDeviceConfig(GCMPushConfigArgs),
+
     SetFlightProfile(FlightProfileArgs),
     SetDeviceConfig(DeviceConfigArgs),
 
+    #[command(about = "Print the flight profile stored on the device as json")]
+    GetFlightProfile,
+
+    #[command(about = "Print the device config stored on the device as json")]
+    GetDeviceConfig,
+
+    Verify(VerifyArgs),
+
     #[command(about = "Pull flight data from device")]
-    PullFlight(PullDataArgs),
+    PullFlight(PullFlightArgs),
 
     #[command(about = "Pull vacuum test data from device")]
-    PullVacuumTest(PullDataArgs),
+    PullVacuumTest(PullSensorDataArgs),
 
     #[command(about = "Pull ground test data from device")]
-    PullGroundTest(PullDataArgs),
+    PullGroundTest(PullSensorDataArgs),
 
     LS(LSArgs),
     PullFile(PullArgs),
 
+    #[command(about = "List the CAN nodes seen by the avionics")]
+    CanNodes,
+
+    #[command(about = "Show the CAN bus error counters and statistics of the avionics")]
+    CanBusStats,
+
+    Logs(LogsArgs),
+
     #[command(about = "Reset device")]
     Reset,
 }
 
+#[derive(clap::Args)]
+#[command(about = "Send a VLP Uplink packet")]
+struct GCMSendUplinkArgs {
+    /// Device id of the target rocket, sends to all rockets if not specified
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    device: Option<u8>,
+
+    #[clap(subcommand)]
+    packet: GCMUplinkPacket,
+}
+
 #[derive(Subcommand)]
 enum GCMUplinkPacket {
     VerticalCalibration,
@@ -138,15 +310,50 @@ enum GCMUplinkPacket {
     Reset,
     DeleteLogs,
     ManualTriggerDeployment,
+    CanCommand(CanCommandArgs),
 }
 
+#[derive(clap::Args)]
+#[command(about = "Relay a command to the CAN nodes through the avionics")]
+struct CanCommandArgs {
+    /// high-rate-logging, sample-rate, clear-data or low-power
+    #[arg(value_parser=can_command_parser)]
+    command: NodeCommand,
+
+    /// 1 / 0 to turn high-rate-logging and low-power on / off, samples per second for sample-rate
+    #[arg(default_value_t = 0)]
+    argument: u32,
+
+    #[arg(long, default_value_t = STRAIN_GAUGES_NODE_TYPE)]
+    node_type: u8,
+
+    /// Sends to every node of the node type if not specified
+    #[arg(long, value_parser=maybe_hex::<u16>)]
+    node_id: Option<u16>,
+}
+
+fn can_command_parser(s: &str) -> Result<NodeCommand, String> {
+    match s {
+        "high-rate-logging" => Ok(NodeCommand::SetHighRateLogging),
+        "sample-rate" => Ok(NodeCommand::SetSampleRate),
+        "clear-data" => Ok(NodeCommand::ClearData),
+        "low-power" => Ok(NodeCommand::SetLowPower),
+        _ => Err(format!("Unknown CAN command: {}", s)),
+    }
+}
+
+/// Accepts a file type id or a registered file type name
 fn file_type_parser(s: &str) -> Result<FileType, String> {
+    if let Some(description) = file_type_by_name(s) {
+        return Ok(description.file_type);
+    }
     maybe_hex(s).map(FileType)
 }
 
 #[derive(clap::Args)]
 #[command(about = "List files on the device")]
 struct LSArgs {
+    /// File type id or name, e.g. baro_tier_1
     #[arg(value_parser=file_type_parser)]
     file_type: Option<FileType>,
 }
@@ -163,9 +370,58 @@ struct PullArgs {
     host_path: std::path::PathBuf,
 }
 
+#[derive(clap::Args)]
+#[command(about = "Print the recent logs buffered on the device")]
+struct LogsArgs {
+    /// Keep printing new logs until ctrl-c
+    #[arg(long, short)]
+    follow: bool,
+}
+
 #[derive(clap::Args)]
 #[command(about = "Listen on VLP Downlink packet")]
-struct GCMArgs {}
+struct GCMArgs {
+    /// Only print packets from this device id
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    device: Option<u8>,
+}
+
+#[derive(clap::Args)]
+struct GCMReplayArgs {
+    /// Recording files pulled from the GCM, replayed in the given order
+    #[arg(required = true)]
+    recording_paths: Vec<std::path::PathBuf>,
+
+    /// Only print packets from this device id
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    device: Option<u8>,
+
+    /// Playback speed multiplier
+    #[arg(long, default_value_t = 1.0, value_parser=positive_f64_parser)]
+    speed: f64,
+}
+
+fn positive_f64_parser(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        Ok(_) => Err(format!("{} is not a positive number", s)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(clap::Args)]
+struct GCMPushConfigArgs {
+    /// Device id of the target rocket
+    #[arg(long, value_parser=maybe_hex::<u8>)]
+    device: u8,
+
+    /// Device config of the GCM, the LoRa key in it authenticates the update
+    #[arg(long)]
+    gcm_config: std::path::PathBuf,
+
+    /// Flight profile or device config json to push
+    config_path: std::path::PathBuf,
+}
 
 #[derive(clap::Args)]
 #[command(about = "Set flight profile")]
@@ -179,11 +435,40 @@ struct DeviceConfigArgs {
     config_path: std::path::PathBuf,
 }
 
+#[derive(clap::Args)]
+#[command(about = "Compare the configs stored on the device with local json files")]
+struct VerifyArgs {
+    #[arg(long)]
+    flight_profile: Option<std::path::PathBuf>,
+
+    #[arg(long)]
+    device_config: Option<std::path::PathBuf>,
+}
+
 #[derive(clap::Args)]
 struct PullDataArgs {
     save_folder: std::path::PathBuf,
 }
 
+#[derive(clap::Args)]
+struct PullSensorDataArgs {
+    save_folder: std::path::PathBuf,
+
+    /// Export format of the sensor readings: csv, parquet or arrow
+    #[arg(long, default_value_t = ExportFormat::CSV)]
+    format: ExportFormat,
+}
+
+#[derive(clap::Args)]
+struct PullFlightArgs {
+    #[command(flatten)]
+    pull: PullSensorDataArgs,
+
+    /// Only pull the log segments pinned around launch and landing
+    #[arg(long)]
+    flight_only: bool,
+}
+
 #[derive(clap::Args)]
 struct RealTimeArgs {
     channel: Option<usize>,
@@ -222,51 +507,26 @@ async fn main() -> Result<()> {
         ModeSelect::VL(VLCli { serial, command }) => {
             let mut serial = create_serial(serial)?;
             let mut client = vl_rpc::RpcClient::new(&mut serial, Delay);
-            client.reset().await.map_err(|_| anyhow!("reset Error"))?;
+            client.reset().await.map_err(|e| anyhow!("reset error: {:?}", e))?;
 
             let timestamp = chrono::Utc::now().timestamp_micros() as f64 / 1000.0;
             match command {
-                VLCommands::GCMSendUplink(uplink_packet) => {
-                    let packet: VLPUplinkPacket = match uplink_packet {
-                        GCMUplinkPacket::VerticalCalibration => {
-                            VerticalCalibrationPacket { timestamp }.into()
-                        }
-                        GCMUplinkPacket::SoftArm => SoftArmPacket {
-                            timestamp,
-                            armed: true,
-                        }
-                        .into(),
-                        GCMUplinkPacket::SoftDisarm => SoftArmPacket {
-                            timestamp,
-                            armed: false,
-                        }
-                        .into(),
-                        GCMUplinkPacket::LowPowerModeOn => LowPowerModePacket {
-                            timestamp,
-                            enabled: true,
-                        }
-                        .into(),
-                        GCMUplinkPacket::LowPowerModeOff => LowPowerModePacket {
-                            timestamp,
-                            enabled: false,
-                        }
-                        .into(),
-                        GCMUplinkPacket::Reset => ResetPacket { timestamp }.into(),
-                        GCMUplinkPacket::DeleteLogs => DeleteLogsPacket { timestamp }.into(),
-                        GCMUplinkPacket::ManualTriggerDeployment => {
-                            ManualTriggerDeplotmentPacket { timestamp }.into()
-                        }
-                    };
+                VLCommands::GCMSendUplink(args) => {
+                    let packet = create_uplink_packet(args.packet, timestamp);
 
-                    let result = client.g_c_m_send_uplink_packet(packet).await.unwrap();
+                    let device_id = args.device.unwrap_or(VLP_BROADCAST_DEVICE_ID);
+                    let result = client
+                        .g_c_m_send_uplink_packet(device_id, packet)
+                        .await
+                        .unwrap();
                     println!("{:?}", result);
                     loop {
                         match client.g_c_m_poll_downlink_packet().await {
                             Ok(GCMPollDownlinkPacketResponse {
-                                packet: Some((packet, status)),
+                                packet: Some((source, packet, status)),
                             }) => {
-                                if let VLPDownlinkPacket::TelemetryPacket(packet) = packet {
-                                    print_telemetry_packet(&packet, &status);
+                                if args.device.map_or(true, |device| device == source) {
+                                    print_downlink_packet(source, &packet, &status);
                                 }
                             }
                             Err(e) => {
@@ -277,13 +537,13 @@ async fn main() -> Result<()> {
                         sleep(Duration::from_millis(100)).await;
                     }
                 }
-                VLCommands::GCMListen(_) => loop {
+                VLCommands::GCMListen(args) => loop {
                     match client.g_c_m_poll_downlink_packet().await {
                         Ok(GCMPollDownlinkPacketResponse {
-                            packet: Some((packet, status)),
+                            packet: Some((source, packet, status)),
                         }) => {
-                            if let VLPDownlinkPacket::TelemetryPacket(packet) = packet {
-                                print_telemetry_packet(&packet, &status);
+                            if args.device.map_or(true, |device| device == source) {
+                                print_downlink_packet(source, &packet, &status);
                             }
                         }
                         Err(e) => {
@@ -293,6 +553,38 @@ async fn main() -> Result<()> {
                     }
                     sleep(Duration::from_millis(100)).await;
                 },
+                VLCommands::GCMPushProfile(args) => {
+                    let gcm_config = json_to_device_config(read_to_string(args.gcm_config).await?)?;
+                    let profile = json_to_flight_profile(read_to_string(args.config_path).await?)?;
+                    let data = serialize_config(&profile);
+                    let result = gcm_push_config(
+                        &mut client,
+                        args.device,
+                        ConfigUpdateType::FlightProfile,
+                        &data,
+                        &gcm_config.lora_key,
+                    )
+                    .await?;
+                    println!("{:?}, hash: {:08X}", result.status, result.hash);
+                }
+                VLCommands::GCMPushDeviceConfig(args) => {
+                    let gcm_config = json_to_device_config(read_to_string(args.gcm_config).await?)?;
+                    let device_config = json_to_device_config(read_to_string(args.config_path).await?)?;
+                    let update = DeviceConfigUpdate {
+                        lora: device_config.lora,
+                        vlp_device_id: device_config.vlp_device_id,
+                    };
+                    let data = serialize_config(&update);
+                    let result = gcm_push_config(
+                        &mut client,
+                        args.device,
+                        ConfigUpdateType::DeviceConfig,
+                        &data,
+                        &gcm_config.lora_key,
+                    )
+                    .await?;
+                    println!("{:?}, hash: {:08X}", result.status, result.hash);
+                }
                 VLCommands::SetFlightProfile(args) => {
                     let json = read_to_string(args.profile_path).await?;
                     let profile = json_to_flight_profile(json)?;
@@ -303,26 +595,126 @@ async fn main() -> Result<()> {
                     let device_config = json_to_device_config(json)?;
                     client.set_device_config(device_config).await.unwrap();
                 }
+                VLCommands::GetFlightProfile => {
+                    let profile = client.get_flight_profile().await.unwrap().flight_profile;
+                    let profile = profile.ok_or(anyhow!("No flight profile on the device"))?;
+                    println!("{}", flight_profile_to_json(profile)?);
+                }
+                VLCommands::GetDeviceConfig => {
+                    let config = client.get_device_config().await.unwrap().device_config;
+                    let config = config.ok_or(anyhow!("No device config on the device"))?;
+                    println!("{}", device_config_to_json(config)?);
+                }
+                VLCommands::Verify(args) => {
+                    if args.flight_profile.is_none() && args.device_config.is_none() {
+                        return Err(anyhow!("Nothing to verify, pass --flight-profile and / or --device-config"));
+                    }
+                    let mut matched = true;
+                    if let Some(path) = args.flight_profile {
+                        let local = json_to_flight_profile(read_to_string(&path).await?)?;
+                        let device = client.get_flight_profile().await.unwrap().flight_profile;
+                        matched &= print_config_diff(&path, device.map(|device| diff_flight_profile(local, device)));
+                    }
+                    if let Some(path) = args.device_config {
+                        let local = json_to_device_config(read_to_string(&path).await?)?;
+                        let device = client.get_device_config().await.unwrap().device_config;
+                        matched &= print_config_diff(&path, device.map(|device| diff_device_config(local, device)));
+                    }
+                    if !matched {
+                        return Err(anyhow!("Device does not match the local configs"));
+                    }
+                }
                 VLCommands::PullFlight(args) => {
-                    pull_flight_data(&mut client, &args.save_folder).await.unwrap();
+                    let manifest = pull_flight_data(
+                        &mut client,
+                        &args.pull.save_folder,
+                        args.pull.format,
+                        args.flight_only,
+                    )
+                    .await
+                    .unwrap();
+                    print_export_manifest(&manifest);
                 },
+                VLCommands::GCMPullRecording(args) => {
+                    let files = pull_gcm_recording(&mut client, &args.save_folder)
+                        .await
+                        .unwrap();
+                    println!("Pulled {} recordings", files.len());
+                }
+                VLCommands::GCMDevices => {
+                    let response = client.g_c_m_get_devices().await.unwrap();
+                    // rockets are told apart by VLP device id only, boards whose
+                    // serial numbers hash to the same id show up as one device
+                    for (device_id, state) in response.devices.into_iter().flatten() {
+                        println!(
+                            "[{:02X}] packets: {}, RSSI: {}, SNR: {}, last seen: {}s",
+                            device_id,
+                            state.received_packets,
+                            state.last_rssi,
+                            state.last_snr,
+                            state.last_seen_timestamp / 1000.0,
+                        );
+                    }
+                }
                 VLCommands::PullVacuumTest(args) => {
-                    pull_vacuum_test(&mut client, &args.save_folder)
+                    let manifest = pull_vacuum_test(&mut client, &args.save_folder, args.format)
                         .await
                         .unwrap();
+                    print_export_manifest(&manifest);
+                }
+                VLCommands::PullGroundTest(args) => {
+                    let manifest = pull_ground_test(&mut client, &args.save_folder, args.format)
+                        .await
+                        .unwrap();
+                    print_export_manifest(&manifest);
                 }
-                VLCommands::PullGroundTest(_) => todo!(),
                 VLCommands::LS(args) => {
-                    let files = list_files(&mut client, args.file_type).await.unwrap();
-                    for file in files {
-                        println!("{:?}", file);
-                    }
+                    print_files(&list_files_with_type(&mut client, args.file_type).await?);
                 }
                 VLCommands::PullFile(args) => {
                     pull_file(&mut client, args.file_id, args.host_path)
                         .await
                         .unwrap();
                 }
+                VLCommands::CanNodes => {
+                    let response = client.get_can_nodes().await.unwrap();
+                    if response.self_id_collision {
+                        println!("Another node is using the node id of this device!");
+                    }
+                    for (node, status) in response.nodes.into_iter().flatten() {
+                        println!(
+                            "type {:2} id {:03X}: {:?}, health: {:?}, fw: {}, uptime: {}s, last seen: {}s",
+                            node.node_type,
+                            node.node_id,
+                            status,
+                            node.health,
+                            node.firmware_version
+                                .map(|[major, minor, patch]| format!("{}.{}.{}", major, minor, patch))
+                                .unwrap_or("unknown".into()),
+                            node.uptime_s,
+                            node.last_seen / 1000.0,
+                        );
+                    }
+                }
+                VLCommands::CanBusStats => {
+                    let s = client.get_can_bus_statistics().await.unwrap().statistics;
+                    println!(
+                        "state: {:?}, TX error count: {}, RX error count: {}",
+                        s.state, s.tx_error_count, s.rx_error_count
+                    );
+                    println!(
+                        "TX frames: {}, failed: {}, dropped: {}, lost arbitration: {}",
+                        s.tx_frames, s.tx_failed, s.tx_dropped, s.lost_arbitration
+                    );
+                    println!(
+                        "RX frames: {}, errors: {}, overruns: {}",
+                        s.rx_frames, s.rx_errors, s.rx_overruns
+                    );
+                    println!("bus off: {} times", s.bus_off_count);
+                }
+                VLCommands::Logs(args) => {
+                    print_device_logs(&mut client, args.follow).await?;
+                }
                 VLCommands::Reset => {
                     client.reset_device().await.unwrap();
                 }
@@ -331,13 +723,14 @@ async fn main() -> Result<()> {
         ModeSelect::OZYS(OZYSCli { serial, command }) => {
             let mut serial = create_serial(serial)?;
             let mut client = sg_rpc::RpcClient::new(&mut serial, Delay);
-            client.reset().await.map_err(|_| anyhow!("reset Error"))?;
+            client.reset().await.map_err(|e| anyhow!("reset error: {:?}", e))?;
 
             match command {
                 SGCommands::PullData(args) => {
-                    pull_ozys_data(&mut client, &args.save_folder)
+                    let manifest = pull_ozys_data(&mut client, &args.save_folder, args.format)
                         .await
                         .unwrap();
+                    print_export_manifest(&manifest);
                 }
                 SGCommands::ClearData => {
                     client.clear_data().await.unwrap();
@@ -356,16 +749,16 @@ async fn main() -> Result<()> {
                     }
                 }
                 SGCommands::LS(args) => {
-                    let files = list_files(&mut client, args.file_type).await.unwrap();
-                    for file in files {
-                        println!("{:?}", file);
-                    }
+                    print_files(&list_files_with_type(&mut client, args.file_type).await?);
                 }
                 SGCommands::PullFile(args) => {
                     pull_file(&mut client, args.file_id, args.host_path)
                         .await
                         .unwrap();
                 }
+                SGCommands::Logs(args) => {
+                    print_device_logs(&mut client, args.follow).await?;
+                }
                 SGCommands::Reset => {
                     client.reset_device().await.unwrap();
                 }
@@ -375,18 +768,355 @@ async fn main() -> Result<()> {
             let key = gen_lora_key();
             println!("{}", format_lora_key(&key));
         }
+        #[cfg(target_os = "linux")]
+        ModeSelect::CAN(cli) => {
+            can::run_can_cli(cli).await?;
+        }
+        ModeSelect::CANRegistry => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&can_bus_registry_json())?
+            );
+        }
+        ModeSelect::DecodeLog(args) => {
+            let header = read_delta_log_header_or_legacy(&args.file, args.file_type).await?;
+            println!("{}", describe_delta_log_header(&header));
+            if !args.header_only {
+                let csv_path = args.csv.unwrap_or(args.file.with_extension("csv"));
+                decode_delta_log_to_csv(&args.file, &csv_path, args.file_type).await?;
+                println!("Decoded to {:?}", csv_path);
+            }
+        }
+        ModeSelect::QuantizationReport(args) => {
+            for file in &args.files {
+                println!("{:?}", file);
+                for report in quantization_report(file).await? {
+                    println!(
+                        "  {}: {} -> {} bytes ({:.2}x), {} / {} readings logged as full values",
+                        report.quantizer,
+                        report.original_bytes,
+                        report.quantized_bytes,
+                        report.compression_ratio(),
+                        report.full_readings,
+                        report.readings,
+                    );
+                    for field in &report.fields {
+                        println!(
+                            "    {}: max error {} (bound {})",
+                            field.name, field.max_error, field.max_error_bound
+                        );
+                    }
+                }
+            }
+        }
+        ModeSelect::Timeline(args) => {
+            let timeline = build_timeline(&args.folders).await?;
+            let resample = args.resample_ms.map(|interval_ms| ResampleOptions {
+                interval_ms,
+                method: args.method,
+                max_gap_ms: args.max_gap_ms,
+            });
+            let rows = write_timeline_csv(&timeline, &args.output, resample.as_ref())?;
+            println!(
+                "{} files, {} boot sessions, {} channels, {} samples, {} events",
+                timeline.files,
+                timeline.sessions,
+                timeline.channels.len(),
+                timeline.entries.len(),
+                timeline.events.len()
+            );
+            if timeline.unmapped > 0 {
+                println!(
+                    "{} samples / events skipped, their boot sessions have no unix time",
+                    timeline.unmapped
+                );
+            }
+            println!("Wrote {} rows to {:?}", rows, args.output);
+        }
+        ModeSelect::VLP(VLPCli { config, command }) => {
+            let config = json_to_device_config(read_to_string(config).await?)?;
+            let codec = VLPCodec::new(config.lora, config.lora_key);
+            match command {
+                VLPCommands::Decode(args) => {
+                    let data = parse_hex(&args.hex)?;
+                    if let Ok(decoded) = codec.decode_uplink(&data) {
+                        println!("Uplink packet");
+                        print_vlp_frame(&decoded);
+                        println!("Destination: {:02X}", decoded.device_id);
+                        print_uplink_fields(&decoded.packet);
+                    } else if let Ok(decoded) = codec.decode_downlink(&data) {
+                        println!("Downlink packet");
+                        print_vlp_frame(&decoded);
+                        println!("Source: {:02X}", decoded.device_id);
+                        if let VLPDownlinkPacket::TelemetryPacket(packet) = &decoded.packet {
+                            print_telemetry_fields(packet);
+                        } else {
+                            println!("{:#?}", decoded.packet);
+                        }
+                    } else {
+                        return Err(anyhow!(
+                            "Not a valid VLP packet, wrong key or too many corrupted bytes"
+                        ));
+                    }
+                }
+                VLPCommands::Encode(args) => {
+                    let timestamp = chrono::Utc::now().timestamp_micros() as f64 / 1000.0;
+                    let packet = create_uplink_packet(args.packet, timestamp);
+                    let device_id = args.device.unwrap_or(VLP_BROADCAST_DEVICE_ID);
+                    let data = codec.encode_uplink(device_id, &packet)?;
+                    print_uplink_fields(&packet);
+                    println!("Destination: {:02X}", device_id);
+                    println!("Length: {} bytes", data.len());
+                    println!("{}", format_hex(&data));
+                }
+            }
+        }
+        ModeSelect::GCMReplay(args) => {
+            let stream = replay_gcm_recording(args.recording_paths, args.speed);
+            pin_mut!(stream);
+            while let Some(received) = stream.next().await.transpose()? {
+                if let VLPDownlinkPacket::TelemetryPacket(packet) = received.packet {
+                    if args.device.map_or(true, |device| device == received.device_id) {
+                        let status = RpcPacketStatus {
+                            rssi: received.rssi,
+                            snr: received.snr,
+                        };
+                        print_telemetry_packet(received.device_id, &packet, &status);
+                    }
+                }
+            }
+        }
     }
 
     println!("Done");
     Ok(())
 }
 
-fn print_telemetry_packet(packet: &TelemetryPacket, status: &RpcPacketStatus) {
+fn create_uplink_packet(packet: GCMUplinkPacket, timestamp: f64) -> VLPUplinkPacket {
+    match packet {
+        GCMUplinkPacket::VerticalCalibration => {
+            VerticalCalibrationPacket { timestamp }.into()
+        }
+        GCMUplinkPacket::SoftArm => SoftArmPacket {
+            timestamp,
+            armed: true,
+        }
+        .into(),
+        GCMUplinkPacket::SoftDisarm => SoftArmPacket {
+            timestamp,
+            armed: false,
+        }
+        .into(),
+        GCMUplinkPacket::LowPowerModeOn => LowPowerModePacket {
+            timestamp,
+            enabled: true,
+        }
+        .into(),
+        GCMUplinkPacket::LowPowerModeOff => LowPowerModePacket {
+            timestamp,
+            enabled: false,
+        }
+        .into(),
+        GCMUplinkPacket::Reset => ResetPacket { timestamp }.into(),
+        GCMUplinkPacket::DeleteLogs => DeleteLogsPacket { timestamp }.into(),
+        GCMUplinkPacket::ManualTriggerDeployment => {
+            ManualTriggerDeplotmentPacket { timestamp }.into()
+        }
+        GCMUplinkPacket::CanCommand(args) => CanCommandPacket {
+            timestamp,
+            command: args.command,
+            target_node_type: args.node_type,
+            target_node_id: args.node_id.unwrap_or(CAN_COMMAND_BROADCAST_NODE_ID),
+            argument: args.argument,
+        }
+        .into(),
+    }
+}
+
+fn print_export_manifest(manifest: &ExportManifest) {
+    for file in &manifest.files {
+        println!(
+            "Exported {} ({} rows from {} files)",
+            file.path,
+            file.rows.map_or("unknown".into(), |rows| rows.to_string()),
+            file.source_files.len()
+        );
+    }
+}
+
+/// `diffs` is `None` when the config is missing on the device, returns whether they match
+fn print_config_diff(path: &std::path::Path, diffs: Option<Vec<String>>) -> bool {
+    match diffs {
+        None => {
+            println!("{:?}: missing on the device", path);
+            false
+        }
+        Some(diffs) if diffs.is_empty() => {
+            println!("{:?}: matches", path);
+            true
+        }
+        Some(diffs) => {
+            println!("{:?}: {} fields differ", path, diffs.len());
+            for diff in diffs {
+                println!("  {}", diff);
+            }
+            false
+        }
+    }
+}
+
+fn print_files(files: &[(FileID, Option<&FileTypeDescription>)]) {
+    for (file_id, description) in files {
+        if let Some(description) = description {
+            println!(
+                "{:>8} {} (type {}, {:?})",
+                file_id.0, description.name, description.file_type.0, description.decoder
+            );
+        } else {
+            println!("{:>8} unknown type", file_id.0);
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let s = s.strip_prefix("0x").unwrap_or(&s);
+    if !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Hex string contains a non hex digit"));
+    }
+    if s.len() % 2 != 0 {
+        return Err(anyhow!("Hex string has an odd number of digits"));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| anyhow!(e)))
+        .collect()
+}
+
+fn format_hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn print_vlp_frame<P>(decoded: &DecodedVLPPacket<P>) {
+    println!(
+        "Length: {} bytes ({} data + crc, {} ecc)",
+        decoded.total_length,
+        decoded.total_length - decoded.ecc_length,
+        decoded.ecc_length
+    );
+}
+
+fn print_uplink_fields(packet: &VLPUplinkPacket) {
+    match packet {
+        VLPUplinkPacket::VerticalCalibrationPacket(packet) => {
+            println!("Type: VerticalCalibration");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+        }
+        VLPUplinkPacket::SoftArmPacket(packet) => {
+            println!("Type: SoftArm");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+            println!("Armed: {}", packet.armed);
+        }
+        VLPUplinkPacket::LowPowerModePacket(packet) => {
+            println!("Type: LowPowerMode");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+            println!("Enabled: {}", packet.enabled);
+        }
+        VLPUplinkPacket::ResetPacket(packet) => {
+            println!("Type: Reset");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+        }
+        VLPUplinkPacket::DeleteLogsPacket(packet) => {
+            println!("Type: DeleteLogs");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+        }
+        VLPUplinkPacket::GroundTestDeployPacket(packet) => {
+            println!("Type: GroundTestDeploy");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+            println!("Pyro: {:?}", packet.pyro);
+        }
+        VLPUplinkPacket::ManualTriggerDeplotmentPacket(packet) => {
+            println!("Type: ManualTriggerDeployment");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+        }
+        VLPUplinkPacket::ConfigChunkPacket(packet) => {
+            println!("Type: ConfigChunk");
+            println!("Config type: {:?}", packet.config_type);
+            println!("Transfer id: {}", packet.transfer_id);
+            println!("Chunk index: {}", packet.chunk_index);
+            println!("Length: {} bytes", packet.length);
+            println!("Data: {}", format_hex(&packet.data));
+        }
+        VLPUplinkPacket::ConfigCommitPacket(packet) => {
+            println!("Type: ConfigCommit");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+            println!("Config type: {:?}", packet.config_type);
+            println!("Transfer id: {}", packet.transfer_id);
+            println!("Length: {} bytes", packet.length);
+            println!("MAC: {}", format_hex(&packet.mac));
+        }
+        VLPUplinkPacket::CanCommandPacket(packet) => {
+            println!("Type: CanCommand");
+            println!("Timestamp: {}s", packet.timestamp / 1000.0);
+            println!("Command: {:?}", packet.command);
+            println!("Target node type: {}", packet.target_node_type);
+            println!("Target node id: {:03X}", packet.target_node_id);
+            println!("Argument: {}", packet.argument);
+        }
+    }
+}
+
+fn print_telemetry_fields(packet: &TelemetryPacket) {
+    println!("Unix clock ready: {}", packet.unix_clock_ready());
+    println!("Timestamp: {}s", packet.timestamp() / 1000.0);
+    println!("Satellites: {}", packet.num_of_fix_satellites());
+    println!("Lat lon: {:?}", packet.lat_lon());
+    println!("Battery: {}V", packet.battery_v());
+    println!("Temperature: {}C", packet.temperature());
+    println!("Hardware armed: {}", packet.hardware_armed());
+    println!("Software armed: {}", packet.software_armed());
+    println!("Free space: {}MiB", packet.free_space() / 1024.0 / 1024.0);
+    println!("Main continuity: {}", packet.pyro_main_continuity());
+    println!("Drogue continuity: {}", packet.pyro_drogue_continuity());
+    println!("Altitude: {}m", packet.altitude());
+    println!("Max altitude: {}m", packet.max_altitude());
+    println!("Backup max altitude: {}m", packet.backup_max_altitude());
+    println!("Air speed: {}m/s", packet.air_speed());
+    println!("Max air speed: {}m/s", packet.max_air_speed());
+    println!("Backup max air speed: {}m/s", packet.backup_max_air_speed());
+    println!("Flight core state: {:?}", packet.flight_core_state());
+    println!("Backup flight core state: {:?}", packet.backup_flight_core_state());
+    println!("Drogue deployed: {}", packet.drogue_deployed());
+    println!("Main deployed: {}", packet.main_deployed());
+    println!("CAN nodes online: {}", packet.can_nodes_online());
+    println!("CAN nodes faulty: {}", packet.can_nodes_faulty());
+}
+
+fn print_downlink_packet(device_id: u8, packet: &VLPDownlinkPacket, status: &RpcPacketStatus) {
+    match packet {
+        VLPDownlinkPacket::TelemetryPacket(packet) => {
+            print_telemetry_packet(device_id, packet, status)
+        }
+        VLPDownlinkPacket::CanCommandResultPacket(result) => {
+            println!(
+                "[{:02X}] CAN node type {} id {:03X} {:?}: {:?}",
+                device_id, result.node_type, result.node_id, result.command, result.status
+            );
+        }
+        _ => {}
+    }
+}
+
+fn print_telemetry_packet(device_id: u8, packet: &TelemetryPacket, status: &RpcPacketStatus) {
     if let Some((lat, lon)) = packet.lat_lon() {
-        println!("GPS: {}, {}", lat, lon);
+        println!("[{:02X}] GPS: {}, {}", device_id, lat, lon);
     }
     println!(
-        "{} ({:?}) Altitude: {}/{}, Speed: {}/{}, Temp: {}, Main Cont: {}, Drogue Cont: {}, H Armed: {}, S Armed: {}, Free space: {}MiB, RSSI: {}, SNR: {}{}{}",
+        "[{:02X}] {} ({:?}) Altitude: {}/{}, Speed: {}/{}, Temp: {}, Main Cont: {}, Drogue Cont: {}, H Armed: {}, S Armed: {}, Free space: {}MiB, RSSI: {}, SNR: {}{}{}",
+        device_id,
         packet.timestamp() / 1000.0,
         packet.backup_flight_core_state(),
         packet.altitude(),
@@ -404,4 +1134,12 @@ fn print_telemetry_packet(packet: &TelemetryPacket, status: &RpcPacketStatus) {
         if packet.drogue_deployed() { " Drogue Deployed" } else { "" },
         if packet.main_deployed() { " Main Deployed" } else { "" },
     );
+    println!(
+        "[{:02X}] CAN nodes online: {}, faulty: {}, bus: {:?}, lost frames since last packet: {}",
+        device_id,
+        packet.can_nodes_online(),
+        packet.can_nodes_faulty(),
+        packet.can_bus_state(),
+        packet.can_bus_tx_lost(),
+    );
 }
@@ -0,0 +1,153 @@
+use firmware_common::{
+    common::can_bus::{
+        id::CanBusExtendedId,
+        messages::{DecodedCanMessage, DecodedCanMultiFrameMessage},
+        multi_frame::CanBusMultiFrameDecoder,
+        node_types::node_type_name,
+    },
+    driver::can_bus::CanBusRawMessage,
+};
+
+/// Only show frames from these node types and with these message types,
+/// an empty list matches everything
+#[derive(Debug, Clone, Default)]
+pub struct CanFrameFilter {
+    pub node_types: Vec<u8>,
+    pub message_types: Vec<u8>,
+}
+
+impl CanFrameFilter {
+    pub fn matches(&self, id: &CanBusExtendedId) -> bool {
+        (self.node_types.is_empty() || self.node_types.contains(&id.node_type.into()))
+            && (self.message_types.is_empty() || self.message_types.contains(&id.message_type))
+    }
+}
+
+/// Turns raw frames into human readable lines, reassembling multi-frame transfers
+pub struct CanFrameDecoder {
+    multi_frame_decoder: CanBusMultiFrameDecoder<16>,
+}
+
+impl CanFrameDecoder {
+    pub fn new() -> Self {
+        Self {
+            multi_frame_decoder: CanBusMultiFrameDecoder::new(1000.0),
+        }
+    }
+
+    pub fn decode(&mut self, frame: &impl CanBusRawMessage) -> String {
+        let id = CanBusExtendedId::from_raw(frame.id());
+        let node_type: u8 = id.node_type.into();
+        let node_id: u16 = id.node_id.into();
+        let priority: u8 = id.priority.into();
+        let node = match node_type_name(node_type) {
+            Some(name) => format!("{}({})", name, node_type),
+            None => format!("Unknown({})", node_type),
+        };
+        let header = format!(
+            "[{:10.3}] {:08X} {} #{:03X} p{}",
+            frame.timestamp() / 1000.0,
+            frame.id(),
+            node,
+            node_id,
+            priority,
+        );
+
+        if frame.rtr() {
+            return format!(
+                "{} remote request for message type {}",
+                header, id.message_type
+            );
+        }
+
+        if let Some(transfer_id) = id.transfer_id() {
+            let data = frame.data();
+            let mut line = format!(
+                "{} multi-frame transfer {} frame {}: {:02X?}",
+                header,
+                transfer_id,
+                data.first().copied().unwrap_or(0),
+                data,
+            );
+            if let Some(transfer) = self.multi_frame_decoder.process_frame(frame) {
+                let message = match DecodedCanMultiFrameMessage::decode(&transfer) {
+                    Some(message) => format!("{:?}", message),
+                    None => format!(
+                        "Unknown message type {}, {} bytes: {:02X?}",
+                        transfer.message_type,
+                        transfer.data.len(),
+                        transfer.data.as_slice(),
+                    ),
+                };
+                line.push_str(&format!(
+                    "\n{} multi-frame transfer {} complete: {}",
+                    header, transfer_id, message
+                ));
+            }
+            return line;
+        }
+
+        let data = frame.data();
+        let message = match DecodedCanMessage::decode(frame.id(), data) {
+            Some(message) => format!("{:?}", message),
+            None => format!("Unknown message type {}: {:02X?}", id.message_type, data),
+        };
+        format!("{} {}", header, message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use firmware_common::common::can_bus::{
+        message::CanBusMessage,
+        messages::{HealthMessage, HealthState},
+        node_types::STRAIN_GAUGES_NODE_TYPE,
+    };
+
+    use super::*;
+
+    struct TestFrame {
+        id: u32,
+        data: Vec<u8>,
+    }
+
+    impl CanBusRawMessage for TestFrame {
+        fn timestamp(&self) -> f64 {
+            1234.0
+        }
+
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn rtr(&self) -> bool {
+            false
+        }
+
+        fn data(&self) -> &[u8] {
+            &self.data
+        }
+    }
+
+    #[test]
+    fn test_decode_health_message() {
+        let message = HealthMessage {
+            state: HealthState::Degraded,
+        };
+        let id = HealthMessage::create_id(3, STRAIN_GAUGES_NODE_TYPE, 0x123);
+        let frame = TestFrame {
+            id: id.into(),
+            data: message.to_data().to_vec(),
+        };
+
+        let filter = CanFrameFilter {
+            node_types: vec![STRAIN_GAUGES_NODE_TYPE],
+            message_types: vec![],
+        };
+        assert!(filter.matches(&id));
+
+        let line = CanFrameDecoder::new().decode(&frame);
+        assert!(line.contains("StrainGauges(20) #123 p3"));
+        assert!(line.contains("Degraded"));
+    }
+}
@@ -0,0 +1,9 @@
+mod decode;
+mod registry;
+#[cfg(target_os = "linux")]
+mod socket_can;
+
+pub use decode::{CanFrameDecoder, CanFrameFilter};
+pub use registry::can_bus_registry_json;
+#[cfg(target_os = "linux")]
+pub use socket_can::{open_socket_can, SocketCanError, SocketCanMessage, SocketCanRX, SocketCanTX};
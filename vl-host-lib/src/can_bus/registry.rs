@@ -0,0 +1,48 @@
+use firmware_common::common::can_bus::{
+    id::{
+        CAN_BUS_ID_MESSAGE_TYPE_FIELD, CAN_BUS_ID_NODE_ID_FIELD, CAN_BUS_ID_NODE_TYPE_FIELD,
+        CAN_BUS_ID_PRIORITY_FIELD,
+    },
+    messages::CAN_BUS_MESSAGE_DESCRIPTIONS,
+    multi_frame::{MAX_MULTI_FRAME_LENGTH, MULTI_FRAME_MESSAGE_TYPE_FLAG},
+    node_types::CAN_BUS_NODE_TYPES,
+};
+use serde_json::{json, Value};
+
+fn id_field_json((shift, bits): (u32, u32)) -> Value {
+    json!({ "shift": shift, "bits": bits })
+}
+
+/// Machine readable description of the CAN bus protocol, for nodes not
+/// written in Rust
+pub fn can_bus_registry_json() -> Value {
+    let messages: Vec<Value> = CAN_BUS_MESSAGE_DESCRIPTIONS
+        .iter()
+        .map(|description| {
+            json!({
+                "name": description.name,
+                "message_type": description.message_type,
+                "length": description.length,
+            })
+        })
+        .collect();
+    let node_types: Vec<Value> = CAN_BUS_NODE_TYPES
+        .iter()
+        .map(|(node_type, name)| json!({ "name": name, "node_type": node_type }))
+        .collect();
+
+    json!({
+        "extended_id": {
+            "priority": id_field_json(CAN_BUS_ID_PRIORITY_FIELD),
+            "message_type": id_field_json(CAN_BUS_ID_MESSAGE_TYPE_FIELD),
+            "node_type": id_field_json(CAN_BUS_ID_NODE_TYPE_FIELD),
+            "node_id": id_field_json(CAN_BUS_ID_NODE_ID_FIELD),
+        },
+        "multi_frame": {
+            "message_type_flag": MULTI_FRAME_MESSAGE_TYPE_FLAG,
+            "max_length": MAX_MULTI_FRAME_LENGTH,
+        },
+        "messages": messages,
+        "node_types": node_types,
+    })
+}
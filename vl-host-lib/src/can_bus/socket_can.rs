@@ -0,0 +1,258 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use firmware_common::{
+    common::can_bus::{id::CanBusExtendedId, message::CanBusMessage},
+    driver::can_bus::{
+        CanBusRX, CanBusRawMessage, CanBusRawTX, CanBusState, CanBusStatus, CanBusTX,
+    },
+};
+use socketcan::{
+    CanError, CanErrorFrame, CanFrame, CanSocket, ControllerProblem, EmbeddedFrame, ExtendedId, Id,
+    Socket, SocketOptions,
+};
+use tokio::{
+    sync::mpsc::{channel, Receiver},
+    task::spawn_blocking,
+};
+
+#[derive(Debug, defmt::Format)]
+pub enum SocketCanError {
+    Io {
+        #[defmt(Debug2Format)]
+        kind: io::ErrorKind,
+    },
+    InvalidFrame,
+    Closed,
+}
+
+impl From<io::Error> for SocketCanError {
+    fn from(e: io::Error) -> Self {
+        SocketCanError::Io { kind: e.kind() }
+    }
+}
+
+/// A frame received from SocketCAN
+#[derive(Debug, Clone)]
+pub struct SocketCanMessage {
+    /// ms since the interface was opened
+    pub timestamp: f64,
+    pub id: u32,
+    pub rtr: bool,
+    pub data: Vec<u8>,
+}
+
+impl CanBusRawMessage for SocketCanMessage {
+    fn timestamp(&self) -> f64 {
+        self.timestamp
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn rtr(&self) -> bool {
+        self.rtr
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Frames buffered between the reader thread and `SocketCanRX::receive`, the
+/// thread blocks when it is full so the kernel socket buffer takes over
+const RX_CHANNEL_SIZE: usize = 256;
+
+/// How often the reader thread checks whether `SocketCanRX` has been dropped
+const RX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct SocketCanTX {
+    socket: Arc<CanSocket>,
+    self_node: Option<(u8, u16)>,
+    status: Arc<Mutex<CanBusStatus>>,
+}
+
+pub struct SocketCanRX {
+    receiver: Receiver<Result<SocketCanMessage, io::ErrorKind>>,
+}
+
+/// Open a SocketCAN interface (e.g. `can0` or `vcan0`) so firmware node logic
+/// can run on the host. Wrap the halves with `SplitableCanBusWrapper` to get a
+/// `SplitableCanBus`.
+///
+/// Both halves share one socket, so frames sent by the TX half are not
+/// received by the RX half. Frames from other nodes using our node id are still
+/// received.
+///
+/// The reader thread stops once `SocketCanRX` is dropped.
+///
+/// The bus status is decoded from the error frames of the interface. Bus-off
+/// recovery is left to the kernel, configure it with `ip link set <interface>
+/// type can restart-ms <ms>`.
+pub fn open_socket_can(interface: &str) -> Result<(SocketCanTX, SocketCanRX)> {
+    let socket = Arc::new(CanSocket::open(interface)?);
+    socket.set_error_filter_accept_all()?;
+    socket.set_read_timeout(RX_POLL_INTERVAL)?;
+    let status = Arc::new(Mutex::new(CanBusStatus::default()));
+    let start = Instant::now();
+
+    let (sender, receiver) = channel(RX_CHANNEL_SIZE);
+    let rx_socket = socket.clone();
+    let rx_status = status.clone();
+    thread::spawn(move || loop {
+        if sender.is_closed() {
+            break;
+        }
+        let result = match rx_socket.read_frame() {
+            Ok(CanFrame::Error(frame)) => {
+                update_status(&mut rx_status.lock().unwrap(), frame);
+                continue;
+            }
+            Ok(frame) => {
+                let id = match frame.id() {
+                    Id::Standard(id) => id.as_raw() as u32,
+                    Id::Extended(id) => id.as_raw(),
+                };
+                Ok(SocketCanMessage {
+                    timestamp: start.elapsed().as_secs_f64() * 1000.0,
+                    id,
+                    rtr: frame.is_remote_frame(),
+                    data: frame.data().to_vec(),
+                })
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => Err(e.kind()),
+        };
+        if sender.blocking_send(result).is_err() {
+            break;
+        }
+    });
+
+    Ok((
+        SocketCanTX {
+            socket,
+            self_node: None,
+            status,
+        },
+        SocketCanRX { receiver },
+    ))
+}
+
+fn update_status(status: &mut CanBusStatus, frame: CanErrorFrame) {
+    // error counters are in the last two bytes when the driver reports them
+    if let [_, _, _, _, _, _, tx_error_count, rx_error_count] = *frame.data() {
+        if (tx_error_count, rx_error_count) != (0, 0) {
+            status.tx_error_count = tx_error_count;
+            status.rx_error_count = rx_error_count;
+        }
+    }
+
+    match CanError::from(frame) {
+        CanError::LostArbitration(_) => status.lost_arbitration_count += 1,
+        CanError::BusOff => status.state = CanBusState::BusOff,
+        CanError::Restarted => {
+            *status = CanBusStatus {
+                lost_arbitration_count: status.lost_arbitration_count,
+                rx_overrun_count: status.rx_overrun_count,
+                ..Default::default()
+            }
+        }
+        CanError::ControllerProblem(problem) => match problem {
+            ControllerProblem::ReceiveBufferOverflow => status.rx_overrun_count += 1,
+            ControllerProblem::ReceiveErrorPassive | ControllerProblem::TransmitErrorPassive => {
+                status.state = CanBusState::ErrorPassive
+            }
+            ControllerProblem::Active => status.state = CanBusState::ErrorActive,
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+impl SocketCanTX {
+    fn create_id(&self, priority: u8, message_type: u8) -> Result<ExtendedId, SocketCanError> {
+        let (node_type, node_id) = self.self_node.unwrap_or((0, 0));
+        let id: u32 = CanBusExtendedId::new(priority, message_type, node_type, node_id).into();
+        ExtendedId::new(id).ok_or(SocketCanError::InvalidFrame)
+    }
+
+    /// `write_frame` blocks while the interface tx queue is full, so it runs
+    /// on the blocking thread pool
+    async fn write(&self, frame: Option<CanFrame>) -> Result<(), SocketCanError> {
+        let frame = frame.ok_or(SocketCanError::InvalidFrame)?;
+        let socket = self.socket.clone();
+        spawn_blocking(move || socket.write_frame(&frame))
+            .await
+            .map_err(|_| SocketCanError::Closed)??;
+        Ok(())
+    }
+}
+
+impl CanBusTX for SocketCanTX {
+    type Error = SocketCanError;
+
+    fn configure_self_node(&mut self, node_type: u8, node_id: u16) {
+        self.self_node = Some((node_type, node_id));
+    }
+
+    fn status(&self) -> CanBusStatus {
+        *self.status.lock().unwrap()
+    }
+
+    /// The kernel restarts the controller, see `open_socket_can`
+    async fn recover_from_bus_off(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn send<T: CanBusMessage>(
+        &mut self,
+        message: &T,
+        priority: u8,
+    ) -> Result<(), Self::Error> {
+        let id = self.create_id(priority, T::message_type())?;
+        self.write(CanFrame::new(id, &message.to_data())).await
+    }
+
+    async fn send_remote<T: CanBusMessage>(&mut self, priority: u8) -> Result<(), Self::Error> {
+        let id = self.create_id(priority, T::message_type())?;
+        self.write(CanFrame::new_remote(id, T::len())).await
+    }
+}
+
+impl CanBusRawTX for SocketCanTX {
+    async fn send_raw(
+        &mut self,
+        message_type: u8,
+        data: &[u8],
+        priority: u8,
+    ) -> Result<(), Self::Error> {
+        let id = self.create_id(priority, message_type)?;
+        self.write(CanFrame::new(id, data)).await
+    }
+}
+
+impl CanBusRX for SocketCanRX {
+    type Error = SocketCanError;
+    type Message = SocketCanMessage;
+
+    async fn receive(&mut self) -> Result<Self::Message, Self::Error> {
+        self.receiver
+            .recv()
+            .await
+            .ok_or(SocketCanError::Closed)?
+            .map_err(|kind| SocketCanError::Io { kind })
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use firmware_common::{
+    common::{
+        delta_logger::{header::DeltaLogHeader, timestamp_factories::*},
+        file_types::*,
+        fixed_point::F64FixedPointFactory,
+        sensor_reading::SensorData,
+    },
+    driver::{
+        adc::{ADCData, Volt},
+        barometer::BaroData,
+        imu::IMUData,
+    },
+};
+use tokio::io::AsyncReadExt;
+use vlfs::FileType;
+
+use super::{
+    parse_delta_readings::parse_delta_readings, sensor_data_columns::SensorDataColumns,
+    SensorReadingCSVWriter,
+};
+use crate::timeline::source::parse_pulled_file_name;
+
+/// Returns None for legacy files written before the header existed
+pub async fn read_delta_log_header(file_path: &PathBuf) -> Result<Option<DeltaLogHeader>> {
+    let mut file = tokio::fs::File::open(file_path).await?;
+    let mut buffer = [0u8; DeltaLogHeader::SIZE];
+    let mut length = 0;
+    while length < buffer.len() {
+        let read_bytes = file.read(&mut buffer[length..]).await?;
+        if read_bytes == 0 {
+            break;
+        }
+        length += read_bytes;
+    }
+    if length < buffer.len() || !DeltaLogHeader::has_magic(&buffer) {
+        return Ok(None);
+    }
+    DeltaLogHeader::deserialize(&buffer)
+        .map(Some)
+        .ok_or(anyhow!(
+            "{:?} is written by an unsupported firmware",
+            file_path
+        ))
+}
+
+fn legacy_header<D: SensorData, FF: F64FixedPointFactory>() -> DeltaLogHeader {
+    DeltaLogHeader {
+        firmware_version: [0; 3],
+        ..DeltaLogHeader::new::<D, FF>([0; 12])
+    }
+}
+
+/// Sensor data and timestamp fixed point factory of the files written before
+/// the header existed. 9 and 10 were shared by the gps and low g imu loggers,
+/// their headerless files are read as imu readings.
+const LEGACY_DELTA_LOGS: &[(FileType, fn() -> DeltaLogHeader)] = &[
+    (
+        AVIONICS_LOW_G_IMU_LOGGER_TIER_1,
+        legacy_header::<IMUData, SensorsFF1>,
+    ),
+    (
+        AVIONICS_LOW_G_IMU_LOGGER_TIER_2,
+        legacy_header::<IMUData, SensorsFF2>,
+    ),
+    (
+        AVIONICS_BARO_LOGGER_TIER_1,
+        legacy_header::<BaroData, SensorsFF1>,
+    ),
+    (
+        AVIONICS_BARO_LOGGER_TIER_2,
+        legacy_header::<BaroData, SensorsFF2>,
+    ),
+    (
+        GROUND_TEST_BARO_FILE_TYPE,
+        legacy_header::<BaroData, SensorsFF1>,
+    ),
+    (
+        VACUUM_TEST_BARO_LOGGER,
+        legacy_header::<BaroData, VacuumTestFF>,
+    ),
+    (SG_BATTERY_LOGGER, legacy_header::<ADCData<Volt>, BatteryFF>),
+];
+
+/// The header a legacy file of `file_type` would have, with unknown firmware
+/// version and serial number
+pub fn legacy_delta_log_header(file_type: FileType) -> Option<DeltaLogHeader> {
+    LEGACY_DELTA_LOGS
+        .iter()
+        .find(|(legacy_file_type, _)| *legacy_file_type == file_type)
+        .map(|(_, header)| header())
+}
+
+/// Like `read_delta_log_header`, but legacy files are decoded as written by
+/// the logger of `file_type`. The file type is taken from the pulled file
+/// name if it is not given.
+pub async fn read_delta_log_header_or_legacy(
+    file_path: &PathBuf,
+    file_type: Option<FileType>,
+) -> Result<DeltaLogHeader> {
+    if let Some(header) = read_delta_log_header(file_path).await? {
+        return Ok(header);
+    }
+    let file_type = file_type.or(parse_pulled_file_name(file_path).map(|(_, d)| d.file_type));
+    file_type.and_then(legacy_delta_log_header).ok_or(anyhow!(
+        "{:?} has no header, the file type is needed to decode it",
+        file_path
+    ))
+}
+
+pub fn describe_delta_log_header(header: &DeltaLogHeader) -> String {
+    format!(
+        "{} v{} (timestamp {}..{} ±{}), firmware {}.{}.{}, serial {}",
+        header
+            .schema_name()
+            .map_or(format!("unknown schema {}", header.schema_id), String::from),
+        header.schema_version,
+        header.timestamp_min,
+        header.timestamp_max,
+        header.timestamp_max_error,
+        header.firmware_version[0],
+        header.firmware_version[1],
+        header.firmware_version[2],
+        header
+            .serial_number
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<String>(),
+    )
+}
+
+// Tries every timestamp fixed point factory in the list, evaluates to the
+// result of the first one matching the header
+macro_rules! try_delta_log_decoders {
+    ($header:expr, $func:ident($($arg:expr),*), $data:ty, [$($ff:ty),*]) => {
+        'found: {
+            $(
+                if $header.matches::<$data, $ff>() {
+                    break 'found Some($func::<$data, $ff>($($arg),*).await);
+                }
+            )*
+            None
+        }
+    };
+}
+
+/// Awaits `$func::<D, FF>(args...)` with the `SensorData` type and timestamp
+/// fixed point factory matching `$header`, evaluates to None if none of the
+/// known decoders matches.
+///
+/// New fixed point factories used by the firmware must be added here.
+macro_rules! with_delta_log_decoder {
+    ($header:expr, $func:ident($($arg:expr),*)) => {{
+        use firmware_common::{
+            common::delta_logger::{header::*, quantized::Quantized, timestamp_factories::*},
+            driver::{
+                adc::{ADCData, Ampere, Volt},
+                barometer::{BaroData, BaroQuantizerTier1, BaroQuantizerTier2},
+                gps::GPSData,
+                imu::{IMUData, IMUQuantizerTier1, IMUQuantizerTier2},
+                mag::MagData,
+            },
+        };
+        use $crate::common::delta_log_decoder::try_delta_log_decoders;
+
+        match $header.schema_id {
+            BARO_DATA_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                BaroData,
+                [SensorsFF1, SensorsFF2, VacuumTestFF]
+            ),
+            IMU_DATA_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                IMUData,
+                [SensorsFF1, SensorsFF2]
+            ),
+            GPS_DATA_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                GPSData,
+                [GPSFF1, GPSFF2]
+            ),
+            MAG_DATA_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                MagData,
+                [SensorsFF1, SensorsFF2]
+            ),
+            ADC_VOLT_DATA_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                ADCData<Volt>,
+                [BatteryFF, SensorsFF1, SensorsFF2]
+            ),
+            ADC_AMPERE_DATA_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                ADCData<Ampere>,
+                [BatteryFF, SensorsFF1, SensorsFF2]
+            ),
+            QUANTIZED_IMU_TIER_1_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                Quantized<IMUQuantizerTier1>,
+                [SensorsFF1, SensorsFF2]
+            ),
+            QUANTIZED_IMU_TIER_2_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                Quantized<IMUQuantizerTier2>,
+                [SensorsFF1, SensorsFF2]
+            ),
+            QUANTIZED_BARO_TIER_1_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                Quantized<BaroQuantizerTier1>,
+                [SensorsFF1, SensorsFF2, VacuumTestFF]
+            ),
+            QUANTIZED_BARO_TIER_2_SCHEMA_ID => try_delta_log_decoders!(
+                $header,
+                $func($($arg),*),
+                Quantized<BaroQuantizerTier2>,
+                [SensorsFF1, SensorsFF2, VacuumTestFF]
+            ),
+            _ => None,
+        }
+    }};
+}
+
+pub(crate) use try_delta_log_decoders;
+pub(crate) use with_delta_log_decoder;
+
+async fn decode_to_csv<D, FF>(file_path: &PathBuf, csv_path: &PathBuf) -> Result<()>
+where
+    D: SensorDataColumns,
+    FF: F64FixedPointFactory,
+    [(); size_of::<D>() + 10]:,
+{
+    let mut csv_writer = SensorReadingCSVWriter::<D, _>::from_sensor_data(csv_path)?;
+    let stream = parse_delta_readings::<D, FF>(file_path.clone()).await?;
+    csv_writer.write_all(stream).await?;
+    csv_writer.flush()
+}
+
+/// Picks the decoder from the file header and writes the readings to a csv file,
+/// see `read_delta_log_header_or_legacy` for files without a header
+pub async fn decode_delta_log_to_csv(
+    file_path: &PathBuf,
+    csv_path: &PathBuf,
+    file_type: Option<FileType>,
+) -> Result<DeltaLogHeader> {
+    let header = read_delta_log_header_or_legacy(file_path, file_type).await?;
+    match with_delta_log_decoder!(header, decode_to_csv(file_path, csv_path)) {
+        Some(result) => result.map(|_| header),
+        None => bail!(
+            "No decoder for {:?}: {}",
+            file_path,
+            describe_delta_log_header(&header)
+        ),
+    }
+}
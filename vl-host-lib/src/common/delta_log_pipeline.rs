@@ -0,0 +1,172 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use firmware_common::{
+    common::{
+        file_types::{
+            file_type_description, legacy_file_type, FileDecoderKind, FileTypeDescription,
+            FILE_TYPES,
+        },
+        fixed_point::F64FixedPointFactory,
+        serialized_enum::SerializedEnumReader,
+    },
+    driver::serial::SplitableSerial,
+    CommonRPCTrait,
+};
+use futures_util::{pin_mut, StreamExt};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+};
+use vlfs::FileType;
+
+use super::{
+    delta_log_decoder::read_delta_log_header,
+    export::{DataExport, ExportedFile},
+    extend_path,
+    parse_delta_readings::parse_delta_readings,
+    parse_serialized_enums::parse_serialized_enums,
+    pull_file::pull_files,
+    readers::BufReaderWrapper,
+    sensor_data_columns::SensorDataColumns,
+};
+
+fn registered_file_type(
+    file_type: FileType,
+    decoder: FileDecoderKind,
+) -> Result<&'static FileTypeDescription> {
+    let description = file_type_description(file_type)
+        .ok_or(anyhow!("File type {} is not registered", file_type.0))?;
+    if description.decoder != decoder {
+        bail!("{} is not a {:?} file type", description.name, decoder);
+    }
+    Ok(description)
+}
+
+/// Whether the delta log file is written with `schema_id`, `headerless` for
+/// files written before the header existed
+async fn has_schema(file_path: &PathBuf, schema_id: u8, headerless: bool) -> bool {
+    match read_delta_log_header(file_path).await {
+        Ok(Some(header)) => header.schema_id == schema_id,
+        Ok(None) => headerless,
+        Err(_) => false,
+    }
+}
+
+/// Pulls the delta log files of `file_type`, including the files written
+/// under its legacy file type. Files sharing an id are told apart by the
+/// schema in their header, headerless files belong to the current owner of
+/// the id.
+async fn pull_delta_log_files<S, D>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_type: FileType,
+    save_folder: &PathBuf,
+) -> Result<Vec<PathBuf>>
+where
+    S: SplitableSerial,
+    D: SensorDataColumns,
+{
+    let shared = FILE_TYPES
+        .iter()
+        .any(|d| legacy_file_type(d.file_type) == Some(file_type));
+    let mut file_paths = vec![];
+    for file_path in pull_files(rpc, file_type, save_folder).await? {
+        if !shared || has_schema(&file_path, D::SCHEMA_ID, true).await {
+            file_paths.push(file_path);
+        }
+    }
+
+    if let Some(legacy_file_type) = legacy_file_type(file_type) {
+        for file_path in pull_files(rpc, legacy_file_type, save_folder).await? {
+            if has_schema(&file_path, D::SCHEMA_ID, false).await {
+                file_paths.push(file_path);
+            }
+        }
+    }
+
+    Ok(file_paths)
+}
+
+/// Pulls all the files of `file_type` and exports them into `<pulled file name>.<format extension>`,
+/// e.g. `low_g_imu.csv`
+pub async fn pull_delta_logs<S, D, FF>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_type: FileType,
+    export: &mut DataExport,
+) -> Result<()>
+where
+    S: SplitableSerial,
+    D: SensorDataColumns + 'static,
+    FF: F64FixedPointFactory,
+    [(); size_of::<D>() + 10]:,
+{
+    let description = registered_file_type(file_type, FileDecoderKind::DeltaLog)?;
+
+    let file_paths = pull_delta_log_files::<S, D>(rpc, file_type, export.save_folder()).await?;
+    let mut readings = export.create_readings::<D>(description.file_name, description.name)?;
+    for file_path in file_paths {
+        let stream = parse_delta_readings::<D, FF>(file_path.clone()).await?;
+        readings.write_all(&file_path, stream).await?;
+    }
+    export.finish_readings(readings)?;
+
+    Ok(())
+}
+
+/// Pulls all the files of `file_type` and writes the logs into `<pulled file name>.log`
+pub async fn pull_serialized_enum_logs<S, SR>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_type: FileType,
+    export: &mut DataExport,
+) -> Result<()>
+where
+    S: SplitableSerial,
+    SR: SerializedEnumReader<BufReaderWrapper<File>>,
+{
+    let description = registered_file_type(file_type, FileDecoderKind::SerializedEnum)?;
+
+    let file_paths = pull_files(rpc, file_type, export.save_folder()).await?;
+    let log_file_name = format!("{}.log", description.file_name);
+    let mut exported_file = ExportedFile::other(log_file_name.clone(), "log", description.name);
+    let mut logs_writer =
+        BufWriter::new(File::create(extend_path(export.save_folder(), &log_file_name)).await?);
+    let mut rows = 0;
+    for file_path in file_paths {
+        exported_file.add_source_file(&file_path);
+        let stream = parse_serialized_enums::<SR>(file_path).await?;
+        pin_mut!(stream);
+        while let Some(log) = stream.next().await {
+            logs_writer
+                .write_all(format!("{:?}\n", log).as_bytes())
+                .await?;
+            rows += 1;
+        }
+    }
+    logs_writer.flush().await?;
+    exported_file.rows = Some(rows);
+    export.add_file(exported_file);
+
+    Ok(())
+}
+
+/// Pulls and exports a delta logger table in order, returns early from the
+/// enclosing function on error, e.g.
+/// `avionics_delta_loggers!(delta_log_pipeline!(rpc, &mut export))`.
+/// See `firmware_common::delta_logger_file_types` for the tables.
+///
+/// A wrong sensor data type or fixed point factory is refused by the file
+/// header instead of producing garbage.
+macro_rules! delta_log_pipeline {
+    ($rpc:expr, $export:expr, [$(($file_type:expr, $data:ty, $ff:ty)),* $(,)?]) => {
+        $(
+            $crate::common::delta_log_pipeline::pull_delta_logs::<_, $data, $ff>(
+                $rpc,
+                $file_type,
+                $export,
+            )
+            .await?;
+        )*
+    };
+}
+
+pub(crate) use delta_log_pipeline;
@@ -0,0 +1,58 @@
+use anyhow::anyhow;
+use anyhow::Result;
+use firmware_common::{
+    common::device_log::DeviceLogRecord, driver::serial::SplitableSerial, CommonRPCTrait,
+};
+
+/// Prints the logs buffered on the device, keeps printing the new logs until
+/// ctrl-c if `follow` is true
+pub async fn print_device_logs<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    follow: bool,
+) -> Result<()> {
+    let mut next_seq: Option<u32> = None;
+    let stop = async {
+        tokio::signal::ctrl_c().await.ok();
+    };
+    rpc.read_device_logs(follow, stop, &mut |record| {
+        if let Some(next_seq) = next_seq {
+            if record.seq != next_seq {
+                println!("... {} records dropped", record.seq.wrapping_sub(next_seq));
+            }
+        }
+        next_seq = Some(record.seq.wrapping_add(1));
+        println!("{}", format_device_log_record(&record));
+    })
+    .await
+    .map_err(|e| anyhow!("Failed to stream device logs: {:?}", e))
+}
+
+fn format_device_log_record(record: &DeviceLogRecord) -> String {
+    format!(
+        "[{:>10.3}s] {:<5} {}",
+        record.timestamp / 1000.0,
+        record.level.as_str(),
+        record.message
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use firmware_common::common::device_log::DeviceLogLevel;
+
+    use super::*;
+
+    #[test]
+    fn test_format_device_log_record() {
+        let record = DeviceLogRecord {
+            seq: 0,
+            timestamp: 12345.6,
+            level: DeviceLogLevel::Info,
+            message: "Initializing VLFS".try_into().unwrap(),
+        };
+        assert_eq!(
+            format_device_log_record(&record),
+            "[    12.346s] INFO  Initializing VLFS"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::{fs::File, marker::PhantomData, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Result};
+use arrow::{
+    array::{ArrayBuilder, ArrayRef, Float32Builder, Float64Builder, Int64Builder, UInt8Builder},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use firmware_common::{common::sensor_reading::SensorReading, driver::timestamp::BootTimestamp};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+use crate::common::sensor_data_columns::{Column, ColumnType, ColumnValue, SensorDataColumns};
+
+use super::{
+    manifest::{BOOT_TIMESTAMP_COLUMN, UNIX_TIMESTAMP_COLUMN},
+    SensorReadingExporter,
+};
+
+/// Rows are buffered and written as a record batch (parquet row group) of this size
+const BATCH_SIZE: usize = 64 * 1024;
+
+enum ColumnBuilder {
+    F32(Float32Builder),
+    F64(Float64Builder),
+    I64(Int64Builder),
+    U8(UInt8Builder),
+}
+
+impl ColumnBuilder {
+    fn new(typ: ColumnType) -> Self {
+        match typ {
+            ColumnType::F32 => Self::F32(Float32Builder::with_capacity(BATCH_SIZE)),
+            ColumnType::F64 => Self::F64(Float64Builder::with_capacity(BATCH_SIZE)),
+            ColumnType::I64 => Self::I64(Int64Builder::with_capacity(BATCH_SIZE)),
+            ColumnType::U8 => Self::U8(UInt8Builder::with_capacity(BATCH_SIZE)),
+        }
+    }
+
+    fn append(&mut self, value: ColumnValue) -> Result<()> {
+        match (self, value) {
+            (Self::F32(builder), ColumnValue::F32(v)) => builder.append_option(v),
+            (Self::F64(builder), ColumnValue::F64(v)) => builder.append_option(v),
+            (Self::I64(builder), ColumnValue::I64(v)) => builder.append_option(v),
+            (Self::U8(builder), ColumnValue::U8(v)) => builder.append_option(v),
+            (_, value) => bail!("Column value {:?} doesn't match the column type", value),
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::F32(builder) => Arc::new(builder.finish()),
+            Self::F64(builder) => Arc::new(builder.finish()),
+            Self::I64(builder) => Arc::new(builder.finish()),
+            Self::U8(builder) => Arc::new(builder.finish()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::F32(builder) => builder.len(),
+            Self::F64(builder) => builder.len(),
+            Self::I64(builder) => builder.len(),
+            Self::U8(builder) => builder.len(),
+        }
+    }
+}
+
+fn column_field(column: &Column, nullable: bool) -> Field {
+    let data_type = match column.typ {
+        ColumnType::F32 => DataType::Float32,
+        ColumnType::F64 => DataType::Float64,
+        ColumnType::I64 => DataType::Int64,
+        ColumnType::U8 => DataType::UInt8,
+    };
+    Field::new(column.name, data_type, nullable)
+}
+
+enum BatchWriter {
+    Parquet(ArrowWriter<File>),
+    IPC(FileWriter<File>),
+}
+
+/// Writes rows with a boot timestamp column, a unix timestamp column and
+/// `columns` into Apache Parquet or Arrow IPC files
+pub struct ArrowTableWriter {
+    schema: SchemaRef,
+    builders: Vec<ColumnBuilder>,
+    writer: BatchWriter,
+}
+
+impl ArrowTableWriter {
+    fn schema(columns: &[Column]) -> SchemaRef {
+        let mut fields = vec![
+            column_field(&BOOT_TIMESTAMP_COLUMN, false),
+            column_field(&UNIX_TIMESTAMP_COLUMN, true),
+        ];
+        fields.extend(columns.iter().map(|column| column_field(column, true)));
+        Arc::new(Schema::new(fields))
+    }
+
+    fn new(columns: &[Column], schema: SchemaRef, writer: BatchWriter) -> Self {
+        let mut builders = vec![
+            ColumnBuilder::new(BOOT_TIMESTAMP_COLUMN.typ),
+            ColumnBuilder::new(UNIX_TIMESTAMP_COLUMN.typ),
+        ];
+        builders.extend(columns.iter().map(|column| ColumnBuilder::new(column.typ)));
+        Self {
+            schema,
+            builders,
+            writer,
+        }
+    }
+
+    pub fn new_parquet(file_path: &PathBuf, columns: &[Column]) -> Result<Self> {
+        let schema = Self::schema(columns);
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer =
+            ArrowWriter::try_new(File::create(file_path)?, schema.clone(), Some(properties))?;
+        Ok(Self::new(columns, schema, BatchWriter::Parquet(writer)))
+    }
+
+    pub fn new_ipc(file_path: &PathBuf, columns: &[Column]) -> Result<Self> {
+        let schema = Self::schema(columns);
+        let writer = FileWriter::try_new(File::create(file_path)?, &schema)?;
+        Ok(Self::new(columns, schema, BatchWriter::IPC(writer)))
+    }
+
+    /// `values` in the same order and types as `columns`
+    pub fn write(
+        &mut self,
+        boot_timestamp: f64,
+        unix_timestamp: Option<f64>,
+        values: impl IntoIterator<Item = ColumnValue>,
+    ) -> Result<()> {
+        let timestamps = [
+            ColumnValue::F64(Some(boot_timestamp)),
+            ColumnValue::F64(unix_timestamp),
+        ];
+        let values: Vec<ColumnValue> = timestamps.into_iter().chain(values).collect();
+        if values.len() != self.builders.len() {
+            bail!(
+                "Expected {} column values, got {}",
+                self.builders.len(),
+                values.len()
+            );
+        }
+        for (builder, value) in self.builders.iter_mut().zip(values) {
+            builder.append(value)?;
+        }
+
+        if self.builders[0].len() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if self.builders[0].len() == 0 {
+            return Ok(());
+        }
+        let columns: Vec<ArrayRef> = self.builders.iter_mut().map(|b| b.finish()).collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns)?;
+        match &mut self.writer {
+            BatchWriter::Parquet(writer) => writer.write(&batch)?,
+            BatchWriter::IPC(writer) => writer.write(&batch)?,
+        }
+        Ok(())
+    }
+
+    /// Flushes and closes the file
+    pub fn finish(mut self) -> Result<()> {
+        self.write_batch()?;
+        match self.writer {
+            BatchWriter::Parquet(writer) => {
+                writer.close()?;
+            }
+            BatchWriter::IPC(mut writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/// Exports to Apache Parquet or Arrow IPC files with typed columns
+pub struct ArrowExporter<D: SensorDataColumns> {
+    table: ArrowTableWriter,
+    phantom: PhantomData<D>,
+}
+
+impl<D: SensorDataColumns> ArrowExporter<D> {
+    pub fn new_parquet(file_path: &PathBuf) -> Result<Self> {
+        Ok(Self {
+            table: ArrowTableWriter::new_parquet(file_path, D::COLUMNS)?,
+            phantom: PhantomData,
+        })
+    }
+
+    pub fn new_ipc(file_path: &PathBuf) -> Result<Self> {
+        Ok(Self {
+            table: ArrowTableWriter::new_ipc(file_path, D::COLUMNS)?,
+            phantom: PhantomData,
+        })
+    }
+}
+
+impl<D: SensorDataColumns> SensorReadingExporter<D> for ArrowExporter<D> {
+    fn write(
+        &mut self,
+        reading: SensorReading<BootTimestamp, D>,
+        unix_timestamp: Option<f64>,
+    ) -> Result<()> {
+        self.table.write(
+            reading.timestamp,
+            unix_timestamp,
+            reading.data.column_values(),
+        )
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.table.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use arrow::array::{Array, Float32Array, Float64Array};
+    use firmware_common::driver::barometer::BaroData;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let file_path = std::env::temp_dir().join("vl_host_lib_test_baro.parquet");
+        let mut exporter: Box<dyn SensorReadingExporter<BaroData>> =
+            Box::new(ArrowExporter::<BaroData>::new_parquet(&file_path).unwrap());
+        for i in 0..3 {
+            let reading = SensorReading::new(
+                i as f64 * 5.0,
+                BaroData {
+                    temperature: 20.0,
+                    pressure: 101325.0 + i as f32,
+                },
+            );
+            exporter
+                .write(reading, if i == 0 { None } else { Some(1000.0) })
+                .unwrap();
+        }
+        exporter.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&file_path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(batch.num_columns(), 5);
+
+        let unix_timestamps = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        assert!(unix_timestamps.is_null(0));
+        assert_eq!(unix_timestamps.value(1), 1000.0);
+
+        let pressures = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(pressures.value(2), 101327.0);
+
+        std::fs::remove_file(file_path).ok();
+    }
+
+    #[test]
+    fn test_column_type_mismatch() {
+        let file_path = std::env::temp_dir().join("vl_host_lib_test_mismatch.arrow");
+        let mut table =
+            ArrowTableWriter::new_ipc(&file_path, &[Column::new("value", ColumnType::F32)])
+                .unwrap();
+        assert!(table
+            .write(0.0, None, [ColumnValue::F64(Some(1.0))])
+            .is_err());
+        assert!(table.write(0.0, None, []).is_err());
+
+        std::fs::remove_file(file_path).ok();
+    }
+}
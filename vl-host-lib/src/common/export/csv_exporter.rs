@@ -0,0 +1,22 @@
+use anyhow::Result;
+use firmware_common::{common::sensor_reading::SensorReading, driver::timestamp::BootTimestamp};
+
+use crate::common::{sensor_data_columns::SensorDataColumns, SensorReadingCSVWriter};
+
+use super::SensorReadingExporter;
+
+impl<D: SensorDataColumns> SensorReadingExporter<D>
+    for SensorReadingCSVWriter<D, fn(D) -> Vec<String>>
+{
+    fn write(
+        &mut self,
+        reading: SensorReading<BootTimestamp, D>,
+        unix_timestamp: Option<f64>,
+    ) -> Result<()> {
+        SensorReadingCSVWriter::write(self, reading, unix_timestamp)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush()
+    }
+}
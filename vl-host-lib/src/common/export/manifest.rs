@@ -0,0 +1,153 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use firmware_common::common::delta_logger::header::delta_log_schema_name;
+use serde::Serialize;
+
+use crate::common::sensor_data_columns::{Column, ColumnType, SensorDataColumns};
+
+use super::ExportFormat;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+pub const BOOT_TIMESTAMP_COLUMN: Column = Column::new("boot timestamp", ColumnType::F64);
+pub const UNIX_TIMESTAMP_COLUMN: Column = Column::new("unix timestamp", ColumnType::F64);
+
+/// Describes every file exported by a pull, written as `manifest.json`
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportManifest {
+    pub vl_host_lib_version: &'static str,
+    /// ms since unix epoch
+    pub created_at: u64,
+    pub files: Vec<ExportedFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedFile {
+    /// Relative to the save folder
+    pub path: String,
+    pub format: String,
+    /// Name in the file type registry
+    pub file_type: &'static str,
+    /// `SensorData` type, None for files that are not sensor readings
+    pub sensor_data: Option<&'static str>,
+    pub columns: Vec<Column>,
+    /// None if unknown
+    pub rows: Option<usize>,
+    /// (first, last) in ms
+    pub boot_timestamp_range: Option<(f64, f64)>,
+    /// (first, last) in ms
+    pub unix_timestamp_range: Option<(f64, f64)>,
+    /// Raw files pulled from the device, relative to the save folder
+    pub source_files: Vec<String>,
+}
+
+impl ExportedFile {
+    pub fn new<D: SensorDataColumns>(
+        path: String,
+        format: ExportFormat,
+        file_type: &'static str,
+    ) -> Self {
+        let mut columns = vec![BOOT_TIMESTAMP_COLUMN, UNIX_TIMESTAMP_COLUMN];
+        columns.extend_from_slice(D::COLUMNS);
+        Self {
+            path,
+            format: format.to_string(),
+            file_type,
+            sensor_data: delta_log_schema_name(D::SCHEMA_ID),
+            columns,
+            rows: Some(0),
+            boot_timestamp_range: None,
+            unix_timestamp_range: None,
+            source_files: vec![],
+        }
+    }
+
+    /// A file written without an exporter, e.g. text logs
+    pub fn other(path: String, format: &str, file_type: &'static str) -> Self {
+        Self {
+            path,
+            format: format.into(),
+            file_type,
+            sensor_data: None,
+            columns: vec![],
+            rows: None,
+            boot_timestamp_range: None,
+            unix_timestamp_range: None,
+            source_files: vec![],
+        }
+    }
+
+    pub fn add_source_file(&mut self, source_file: &PathBuf) {
+        self.source_files.push(
+            source_file
+                .file_name()
+                .map_or(source_file.to_string_lossy(), |name| name.to_string_lossy())
+                .into_owned(),
+        );
+    }
+
+    pub fn add_row(&mut self, boot_timestamp: f64, unix_timestamp: Option<f64>) {
+        self.rows = Some(self.rows.unwrap_or(0) + 1);
+        extend_range(&mut self.boot_timestamp_range, boot_timestamp);
+        if let Some(unix_timestamp) = unix_timestamp {
+            extend_range(&mut self.unix_timestamp_range, unix_timestamp);
+        }
+    }
+}
+
+fn extend_range(range: &mut Option<(f64, f64)>, value: f64) {
+    *range = Some(match *range {
+        Some((first, last)) => (first.min(value), last.max(value)),
+        None => (value, value),
+    });
+}
+
+impl ExportManifest {
+    pub fn new() -> Self {
+        Self {
+            vl_host_lib_version: env!("CARGO_PKG_VERSION"),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            files: vec![],
+        }
+    }
+
+    pub fn write(&self, file_path: &PathBuf) -> Result<()> {
+        std::fs::write(file_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+impl Default for ExportManifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use firmware_common::driver::barometer::BaroData;
+
+    use super::*;
+
+    #[test]
+    fn test_exported_file() {
+        let mut file =
+            ExportedFile::new::<BaroData>("baro.csv".into(), ExportFormat::CSV, "baro_tier_1");
+        file.add_source_file(&PathBuf::from("/tmp/flight/3.baro_tier_1.vldr"));
+        file.add_row(20.0, None);
+        file.add_row(10.0, Some(1000.0));
+
+        assert_eq!(file.rows, Some(2));
+        assert_eq!(file.boot_timestamp_range, Some((10.0, 20.0)));
+        assert_eq!(file.unix_timestamp_range, Some((1000.0, 1000.0)));
+        assert_eq!(file.source_files, vec!["3.baro_tier_1.vldr".to_string()]);
+        assert_eq!(file.sensor_data, Some("BaroData"));
+        assert_eq!(file.columns.len(), 5);
+    }
+}
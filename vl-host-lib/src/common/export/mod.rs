@@ -0,0 +1,168 @@
+mod arrow_exporter;
+mod csv_exporter;
+mod manifest;
+
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use anyhow::{bail, Result};
+use firmware_common::{common::sensor_reading::SensorReading, driver::timestamp::BootTimestamp};
+use futures_core::Stream;
+use futures_util::{pin_mut, StreamExt};
+use serde::Serialize;
+
+use super::{extend_path, sensor_data_columns::SensorDataColumns, SensorReadingCSVWriter};
+
+pub use arrow_exporter::{ArrowExporter, ArrowTableWriter};
+pub use manifest::{
+    ExportManifest, ExportedFile, BOOT_TIMESTAMP_COLUMN, MANIFEST_FILE_NAME, UNIX_TIMESTAMP_COLUMN,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    CSV,
+    /// Apache Parquet, zstd compressed
+    Parquet,
+    /// Apache Arrow IPC file
+    Arrow,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::CSV => "csv",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Arrow => "arrow",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::CSV),
+            "parquet" => Ok(ExportFormat::Parquet),
+            "arrow" => Ok(ExportFormat::Arrow),
+            _ => bail!(
+                "Unknown export format {}, expected csv, parquet or arrow",
+                s
+            ),
+        }
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Writes sensor readings into a file, with a boot timestamp column, a unix
+/// timestamp column and the columns of `D`
+pub trait SensorReadingExporter<D: SensorDataColumns> {
+    fn write(
+        &mut self,
+        reading: SensorReading<BootTimestamp, D>,
+        unix_timestamp: Option<f64>,
+    ) -> Result<()>;
+
+    /// Flushes and closes the file
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+pub fn create_exporter<D: SensorDataColumns + 'static>(
+    format: ExportFormat,
+    file_path: &PathBuf,
+) -> Result<Box<dyn SensorReadingExporter<D>>> {
+    Ok(match format {
+        ExportFormat::CSV => Box::new(SensorReadingCSVWriter::<D, _>::from_sensor_data(file_path)?),
+        ExportFormat::Parquet => Box::new(ArrowExporter::<D>::new_parquet(file_path)?),
+        ExportFormat::Arrow => Box::new(ArrowExporter::<D>::new_ipc(file_path)?),
+    })
+}
+
+pub struct ReadingsExport<D: SensorDataColumns> {
+    exporter: Box<dyn SensorReadingExporter<D>>,
+    file: ExportedFile,
+}
+
+impl<D: SensorDataColumns> ReadingsExport<D> {
+    pub async fn write_all(
+        &mut self,
+        source_file: &PathBuf,
+        readings: impl Stream<Item = Result<(SensorReading<BootTimestamp, D>, Option<f64>)>>,
+    ) -> Result<()> {
+        self.file.add_source_file(source_file);
+        pin_mut!(readings);
+        while let Some((reading, unix_timestamp)) = readings.next().await.transpose()? {
+            self.file.add_row(reading.timestamp, unix_timestamp);
+            self.exporter.write(reading, unix_timestamp)?;
+        }
+        Ok(())
+    }
+}
+
+/// Exports of a single pull, every exported file is recorded in the manifest
+pub struct DataExport {
+    save_folder: PathBuf,
+    format: ExportFormat,
+    manifest: ExportManifest,
+}
+
+impl DataExport {
+    pub fn new(save_folder: &PathBuf, format: ExportFormat) -> Self {
+        Self {
+            save_folder: save_folder.clone(),
+            format,
+            manifest: ExportManifest::new(),
+        }
+    }
+
+    pub fn save_folder(&self) -> &PathBuf {
+        &self.save_folder
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Creates `<name>.<format extension>`, readings from multiple source files
+    /// can be written into it before passing it to `finish_readings`
+    pub fn create_readings<D: SensorDataColumns + 'static>(
+        &self,
+        name: &str,
+        file_type: &'static str,
+    ) -> Result<ReadingsExport<D>> {
+        let file_name = format!("{}.{}", name, self.format.extension());
+        let exporter =
+            create_exporter::<D>(self.format, &extend_path(&self.save_folder, &file_name))?;
+        Ok(ReadingsExport {
+            exporter,
+            file: ExportedFile::new::<D>(file_name, self.format, file_type),
+        })
+    }
+
+    pub fn finish_readings<D: SensorDataColumns>(
+        &mut self,
+        readings: ReadingsExport<D>,
+    ) -> Result<()> {
+        readings.exporter.finish()?;
+        self.manifest.files.push(readings.file);
+        Ok(())
+    }
+
+    /// Records a file written without an exporter
+    pub fn add_file(&mut self, file: ExportedFile) {
+        self.manifest.files.push(file);
+    }
+
+    /// Writes `manifest.json` into the save folder
+    pub fn finish(self) -> Result<ExportManifest> {
+        self.manifest
+            .write(&extend_path(&self.save_folder, MANIFEST_FILE_NAME))?;
+        Ok(self.manifest)
+    }
+}
@@ -1,18 +1,22 @@
 use anyhow::Result;
-use firmware_common::{driver::serial::SplitableSerial, CommonRPCTrait};
+use firmware_common::{
+    common::file_types::{file_type_description, FileTypeDescription},
+    driver::serial::SplitableSerial,
+    CommonRPCTrait,
+};
 use vlfs::{FileID, FileType};
 
-pub async fn list_files<S: SplitableSerial>(
+async fn list_files_and_types<S: SplitableSerial>(
     rpc: &mut impl CommonRPCTrait<S>,
     file_type: Option<FileType>,
-) -> Result<Vec<FileID>> {
+) -> Result<Vec<(FileID, FileType)>> {
     let mut result = Vec::new();
 
     rpc.start_list_files(file_type).await.unwrap();
     loop {
         let response = rpc.get_listed_file().await.unwrap();
-        if let Some(file_id) = response {
-            result.push(file_id);
+        if let Some(file) = response {
+            result.push(file);
         } else {
             break;
         }
@@ -20,3 +24,24 @@ pub async fn list_files<S: SplitableSerial>(
 
     Ok(result)
 }
+
+pub async fn list_files<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_type: Option<FileType>,
+) -> Result<Vec<FileID>> {
+    let files = list_files_and_types(rpc, file_type).await?;
+    Ok(files.into_iter().map(|(file_id, _)| file_id).collect())
+}
+
+/// Lists the files with their registered file type, `None` for files with
+/// an unregistered file type
+pub async fn list_files_with_type<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_type: Option<FileType>,
+) -> Result<Vec<(FileID, Option<&'static FileTypeDescription>)>> {
+    let files = list_files_and_types(rpc, file_type).await?;
+    Ok(files
+        .into_iter()
+        .map(|(file_id, file_type)| (file_id, file_type_description(file_type)))
+        .collect())
+}
@@ -1,17 +1,31 @@
+pub(crate) mod delta_log_decoder;
+pub(crate) mod delta_log_pipeline;
+mod device_logs;
+pub mod export;
 mod list_files;
 pub(crate) mod parse_serialized_enums;
 mod probe_device_type;
 pub(crate) mod pull_file;
 pub(crate) mod parse_delta_readings;
+mod quantization_report;
 pub(crate) mod readers;
+pub(crate) mod sensor_data_columns;
 mod sensor_reading_csv_writer;
 pub(crate) mod unix_timestamp_lut;
 
 use std::path::PathBuf;
 
-pub use list_files::list_files;
+pub use delta_log_decoder::{
+    decode_delta_log_to_csv, describe_delta_log_header, legacy_delta_log_header,
+    read_delta_log_header, read_delta_log_header_or_legacy,
+};
+pub use delta_log_pipeline::pull_delta_logs;
+pub use device_logs::print_device_logs;
+pub use list_files::{list_files, list_files_with_type};
 pub use probe_device_type::probe_device_type;
 pub use pull_file::pull_file;
+pub use quantization_report::{quantization_report, FieldError, QuantizationReport};
+pub use sensor_data_columns::SensorDataColumns;
 pub use sensor_reading_csv_writer::SensorReadingCSVWriter;
 
 pub fn extend_path(path: &PathBuf, extend: &str) -> PathBuf {
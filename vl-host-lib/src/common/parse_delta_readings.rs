@@ -1,10 +1,13 @@
-use std::path::PathBuf;
+use std::{fmt::Debug, path::PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use either::Either;
 use firmware_common::{
     common::{
-        delta_logger::delta_logger::DeltaLoggerReader,
+        delta_logger::{
+            delta_logger::{DeltaLoggerReader, DeltaLoggerReaderError},
+            header::DeltaLogHeader,
+        },
         fixed_point::F64FixedPointFactory,
         sensor_reading::{SensorData, SensorReading},
     },
@@ -12,13 +15,35 @@ use firmware_common::{
 };
 use tokio::{fs::File, io::BufReader};
 
-use super::{readers::BufReaderWrapper, unix_timestamp_lut::UnixTimestampLUT};
-use async_stream::stream;
+use super::{
+    delta_log_decoder::describe_delta_log_header, readers::BufReaderWrapper,
+    unix_timestamp_lut::UnixTimestampLUT,
+};
+use async_stream::try_stream;
 use futures_core::stream::Stream;
 
+fn reader_error<D: SensorData, FF: F64FixedPointFactory, E: Debug>(
+    file_path: &PathBuf,
+    e: DeltaLoggerReaderError<E>,
+) -> anyhow::Error {
+    match e {
+        DeltaLoggerReaderError::Read(e) => anyhow!("Failed to read {:?}: {:?}", file_path, e),
+        DeltaLoggerReaderError::InvalidHeader => {
+            anyhow!("{:?} is written by an unsupported firmware", file_path)
+        }
+        DeltaLoggerReaderError::SchemaMismatch(header) => anyhow!(
+            "{:?} is {}, expected {}",
+            file_path,
+            describe_delta_log_header(&header),
+            describe_delta_log_header(&DeltaLogHeader::new::<D, FF>(header.serial_number)),
+        ),
+    }
+}
+
+/// Files without a header are assumed to be written with `D` and `FF`
 pub async fn parse_delta_readings<D: SensorData, FF: F64FixedPointFactory>(
     file_path: PathBuf,
-) -> Result<impl Stream<Item = (SensorReading<BootTimestamp, D>, Option<f64>)>>
+) -> Result<impl Stream<Item = Result<(SensorReading<BootTimestamp, D>, Option<f64>)>>>
 where
     [(); size_of::<D>() + 10]:,
 {
@@ -28,7 +53,11 @@ where
     let reader = BufReaderWrapper(reader);
     let mut reader = DeltaLoggerReader::<D, _, FF>::new(reader);
 
-    while let Some(reading) = reader.read().await.unwrap() {
+    while let Some(reading) = reader
+        .read()
+        .await
+        .map_err(|e| reader_error::<D, FF, _>(&file_path, e))?
+    {
         if let Either::Right(unix_time_log) = reading {
             timestamp_lut.add_timestamp(unix_time_log.boot_timestamp, unix_time_log.unix_timestamp)
         }
@@ -36,11 +65,15 @@ where
     timestamp_lut.sort_timestamps();
 
     // Pass 2: read all the readings and convert timestamps
-    let stream = stream! {
-        let reader = BufReader::new(File::open(file_path).await.unwrap());
+    let stream = try_stream! {
+        let reader = BufReader::new(File::open(&file_path).await?);
         let reader = BufReaderWrapper(reader);
         let mut reader = DeltaLoggerReader::<D, _, FF>::new(reader);
-        while let Some(reading) = reader.read().await.unwrap() {
+        while let Some(reading) = reader
+            .read()
+            .await
+            .map_err(|e| reader_error::<D, FF, _>(&file_path, e))?
+        {
             if let Either::Left(reading) = reading {
                 let unix_timestamp = timestamp_lut.get_unix_timestamp(reading.timestamp);
 
@@ -11,11 +11,12 @@ use super::readers::BufReaderWrapper;
 pub async fn parse_serialized_enums<SR: SerializedEnumReader<BufReaderWrapper<File>>>(
     file_path: PathBuf,
 ) -> Result<impl Stream<Item = SR::Output>> {
+    let reader = BufReader::new(File::open(file_path).await?);
+    let reader = BufReaderWrapper(reader);
+    let mut reader = SR::new(reader);
     let stream = stream! {
-        let reader = BufReader::new(File::open(file_path).await.unwrap());
-        let reader = BufReaderWrapper(reader);
-        let mut reader = SR::new(reader);
-        while let Some(log) = reader.read_next().await.unwrap() {
+        // reading BufReaderWrapper never fails
+        while let Some(log) = reader.read_next().await.unwrap_or_else(|e| match e {}) {
             yield log;
         }
     };
@@ -4,6 +4,8 @@ use crate::create_serial;
 use anyhow::anyhow;
 use anyhow::Result;
 use embedded_hal_async::delay::DelayNs;
+use firmware_common::common::console::create_rpc::{RpcClientError, RpcHandshake};
+use firmware_common::driver::serial::SplitableSerial;
 use firmware_common::sg_rpc;
 use firmware_common::{common::console::DeviceType, vl_rpc};
 use tokio::time::sleep;
@@ -21,8 +23,12 @@ pub async fn probe_device_type(serial_port_name: String) -> Result<String> {
     let fut = async {
         let mut serial = create_serial(serial_port_name)?;
         let mut client = vl_rpc::RpcClient::new(&mut serial, Delay);
-        client.reset().await.map_err(|_| anyhow!("reset error"))?;
+        client
+            .reset_unchecked()
+            .await
+            .map_err(|_| anyhow!("reset error"))?;
 
+        // rpc 0 is the same for every device type
         let device_type = client
             .get_device_type()
             .await
@@ -31,33 +37,72 @@ pub async fn probe_device_type(serial_port_name: String) -> Result<String> {
 
         Result::<String, anyhow::Error>::Ok(match device_type {
             DeviceType::VoidLake => {
-                let who_am_i = client
-                    .who_am_i()
-                    .await
-                    .map_err(|_| anyhow!("who_am_i error"))?;
-                format!(
-                    "{:?}, {}, SN: {:02X?}",
-                    device_type,
-                    who_am_i
-                        .name
-                        .map_or("".into(), |s| String::from(s.as_str())),
-                    who_am_i.serial_number
-                )
+                let reset_result = client.reset().await;
+                let firmware = describe_firmware(client.remote_handshake(), &reset_result);
+                if reset_result.is_err() {
+                    format!("{:?}, {}", device_type, firmware)
+                } else {
+                    let who_am_i = client
+                        .who_am_i()
+                        .await
+                        .map_err(|_| anyhow!("who_am_i error"))?;
+                    format!(
+                        "{:?}, {}, SN: {:02X?}, {}",
+                        device_type,
+                        who_am_i
+                            .name
+                            .map_or("".into(), |s| String::from(s.as_str())),
+                        who_am_i.serial_number,
+                        firmware
+                    )
+                }
             }
             DeviceType::OZYS => {
                 drop(client);
                 let mut client = sg_rpc::RpcClient::new(&mut serial, Delay);
-                let who_am_i = client
-                    .who_am_i()
-                    .await
-                    .map_err(|_| anyhow!("who_am_i error"))?;
-                format!("{:?}, SN: {:02X?}", device_type, who_am_i.serial_number)
+                let reset_result = client.reset().await;
+                let firmware = describe_firmware(client.remote_handshake(), &reset_result);
+                if reset_result.is_err() {
+                    format!("{:?}, {}", device_type, firmware)
+                } else {
+                    let who_am_i = client
+                        .who_am_i()
+                        .await
+                        .map_err(|_| anyhow!("who_am_i error"))?;
+                    format!(
+                        "{:?}, SN: {:02X?}, {}",
+                        device_type, who_am_i.serial_number, firmware
+                    )
+                }
             }
         })
     };
 
-    match timeout(Duration::from_millis(500), fut).await {
+    match timeout(Duration::from_millis(2000), fut).await {
         Ok(r) => r,
         Err(_) => Err(anyhow!("timeout")),
     }
 }
+
+fn describe_firmware<S: SplitableSerial>(
+    handshake: Option<&RpcHandshake>,
+    reset_result: &Result<bool, RpcClientError<S>>,
+) -> String {
+    let version = handshake.map_or("fw unknown".into(), |handshake| {
+        let [major, minor, patch] = handshake.firmware_version;
+        format!(
+            "fw {}.{}.{} ({})",
+            major,
+            minor,
+            patch,
+            handshake.git_hash.as_str()
+        )
+    });
+    match reset_result {
+        Err(RpcClientError::IncompatibleFirmware(reason)) => {
+            format!("{}, incompatible firmware: {:?}", version, reason)
+        }
+        Err(e) => format!("{}, handshake error: {:?}", version, e),
+        Ok(_) => version,
+    }
+}
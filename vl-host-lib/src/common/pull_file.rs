@@ -1,14 +1,20 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
 use std::path::PathBuf;
 use std::time::Instant;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use firmware_common::{
-    common::console::OpenFileStatus, driver::serial::SplitableSerial, CommonRPCTrait,
+    common::{
+        console::{create_rpc::RpcClientError, OpenFileStatus},
+        file_types::file_type_description,
+    },
+    driver::serial::SplitableSerial,
+    CommonRPCTrait,
 };
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
-use tokio::io::BufWriter;
 use vlfs::FileID;
 use vlfs::FileType;
 
@@ -19,53 +25,117 @@ pub async fn pull_file<S: SplitableSerial>(
     file_id: FileID,
     host_path: PathBuf,
 ) -> Result<()> {
-    println!("Pulling file {}", file_id.0);
+    log::info!("Pulling file {}", file_id.0);
     let open_status = rpc.open_file(file_id).await.unwrap();
     if open_status != OpenFileStatus::Sucess {
         return Err(anyhow!("Failed to open file"));
     }
 
-    let file = fs::File::create(&host_path).await?;
-    let mut writer = BufWriter::new(file);
-    let mut length = 0;
+    // the file stays open on the device until closed, close it on errors too
+    let result = save_opened_file(rpc, file_id, &host_path).await;
+    let close_result = rpc.close_file().await;
+    result?;
+    close_result.map_err(|e| anyhow!("Failed to close file {}: {:?}", file_id.0, e))?;
+
+    Ok(())
+}
+
+/// Saves the opened file to `host_path`, removes the partial file on errors
+async fn save_opened_file<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_id: FileID,
+    host_path: &PathBuf,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(host_path)?);
+    let result = stream_file_to(rpc, file_id, &mut writer).await;
+    let result = result.and_then(|length| {
+        writer.flush()?;
+        Ok(length)
+    });
+    drop(writer);
+    if let Err(e) = result {
+        std::fs::remove_file(host_path).ok();
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Writes the opened file to `writer` as it arrives, returns the file length
+async fn stream_file_to<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    file_id: FileID,
+    writer: &mut impl Write,
+) -> Result<usize> {
     let start_time = Instant::now();
-    // TODO ignore corrupted chunk if its the last chunk
-    // let mut i = 0;
-    loop {
-        // println!("Reading chunk {}", i);
-        let read_result = rpc.read_file().await.unwrap();
-        if read_result.length == 0 {
-            break;
+    let mut length = 0;
+    // the sink can't return an error, keep the first one
+    let mut write_error: Option<io::Error> = None;
+    let result = rpc
+        .read_file_stream(&mut |chunk| {
+            if write_error.is_none() {
+                if let Err(e) = writer.write_all(chunk) {
+                    write_error = Some(e);
+                }
+                length += chunk.len();
+            }
+        })
+        .await;
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+    match result {
+        Ok(summary) => {
+            if summary.corrupted {
+                log::warn!("File {} is corrupted", file_id.0);
+            }
         }
-        if read_result.corrupted {
-            // println!("Warning: file is corrupted {}", i);
+        Err(RpcClientError::Unsupported) => {
+            // firmware without streaming support, the file is still open
+            length = read_file_chunks(rpc, writer).await?;
+        }
+        Err(e) => {
+            return Err(anyhow!("Failed to stream file {}: {:?}", file_id.0, e));
         }
-        let data = &read_result.data[..read_result.length as usize];
-        writer.write_all(data).await?;
-        length += data.len();
-        // i += 1;
     }
-    let end_time = Instant::now();
-    println!(
+    let elapsed = Instant::now() - start_time;
+    log::info!(
         "Pulled {} bytes in {:?}, {}KiB/s",
         length,
-        end_time - start_time,
-        length as f64 / 1024.0 / (end_time - start_time).as_secs_f64()
+        elapsed,
+        length as f64 / 1024.0 / elapsed.as_secs_f64()
     );
+    Ok(length)
+}
 
-    writer.flush().await?;
-    rpc.close_file().await.unwrap();
-
-    Ok(())
+/// Reads the opened file 128 bytes at a time with `ReadFile`
+async fn read_file_chunks<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    writer: &mut impl Write,
+) -> Result<usize> {
+    let mut length = 0;
+    // TODO ignore corrupted chunk if its the last chunk
+    loop {
+        let read_result = rpc
+            .read_file()
+            .await
+            .map_err(|e| anyhow!("Failed to read file: {:?}", e))?;
+        if read_result.length == 0 {
+            break;
+        }
+        writer.write_all(&read_result.data[..read_result.length as usize])?;
+        length += read_result.length as usize;
+    }
+    Ok(length)
 }
 
+/// Pulls all the files of `file_type`, the file names are taken from the file type registry
 pub async fn pull_files<S: SplitableSerial>(
     rpc: &mut impl CommonRPCTrait<S>,
     file_type: FileType,
-    file_type_name: &str,
-    file_type_extension: &str,
     save_folder: &PathBuf,
 ) -> Result<Vec<PathBuf>> {
+    let description = file_type_description(file_type)
+        .ok_or(anyhow!("File type {} is not registered", file_type.0))?;
     let file_ids = list_files(rpc, Some(file_type)).await?;
     let mut pulled_file_paths = vec![];
 
@@ -73,7 +143,7 @@ pub async fn pull_files<S: SplitableSerial>(
         let mut file_path = save_folder.clone();
         file_path.push(format!(
             "{}.{}.{}",
-            file_id.0, file_type_name, file_type_extension
+            file_id.0, description.file_name, description.extension
         ));
         pulled_file_paths.push(file_path.clone());
         pull_file(rpc, file_id, file_path).await?;
@@ -0,0 +1,227 @@
+use std::{convert::Infallible, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use either::Either;
+use firmware_common::{
+    common::{
+        delta_logger::{
+            delta_factory::Deltable,
+            delta_logger::{DeltaLogger, DeltaLoggerReader},
+            header::{BARO_DATA_SCHEMA_ID, IMU_DATA_SCHEMA_ID},
+            prelude::DeltaLoggerTrait,
+            quantized::{QuantizableSensorData, Quantized, SensorQuantizer},
+            timestamp_factories::{SensorsFF1, SensorsFF2, VacuumTestFF},
+        },
+        fixed_point::F64FixedPointFactory,
+        sensor_reading::{SensorData, SensorReading},
+    },
+    driver::{
+        barometer::{BaroData, BaroQuantizerTier1, BaroQuantizerTier2},
+        imu::{IMUData, IMUQuantizerTier1, IMUQuantizerTier2},
+        timestamp::BootTimestamp,
+    },
+};
+use futures_util::{pin_mut, StreamExt};
+
+use super::{
+    delta_log_decoder::{
+        describe_delta_log_header, read_delta_log_header_or_legacy, try_delta_log_decoders,
+    },
+    parse_delta_readings::parse_delta_readings,
+};
+
+#[derive(Debug, Clone)]
+pub struct FieldError {
+    pub name: &'static str,
+    pub max_error_bound: f32,
+    /// Largest difference between a recorded value and its reconstruction
+    pub max_error: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuantizationReport {
+    pub quantizer: &'static str,
+    pub readings: usize,
+    /// Readings logged as full values, the first one and every reading whose
+    /// delta doesn't fit
+    pub full_readings: usize,
+    pub original_bytes: u64,
+    pub quantized_bytes: usize,
+    pub fields: Vec<FieldError>,
+}
+
+impl QuantizationReport {
+    pub fn compression_ratio(&self) -> f64 {
+        self.original_bytes as f64 / self.quantized_bytes as f64
+    }
+}
+
+struct VecWriter(Vec<u8>);
+
+impl embedded_io_async::ErrorType for VecWriter {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Write for VecWriter {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Logs the readings with `Q` in memory, then reads them back and compares
+/// them with the originals
+async fn measure<Q, FF>(
+    readings: &[SensorReading<BootTimestamp, Q::Data>],
+    original_bytes: u64,
+) -> Result<QuantizationReport>
+where
+    Q: SensorQuantizer,
+    FF: F64FixedPointFactory,
+    [(); size_of::<Quantized<Q>>() + 10]:,
+{
+    let mut logger = DeltaLogger::<Quantized<Q>, _, FF>::new(VecWriter(vec![]), [0; 12]);
+    // the logger drops readings too close to the previous one
+    let mut logged = vec![];
+    let mut last_quantized: Option<Quantized<Q>> = None;
+    let mut full_readings = 0usize;
+    for reading in readings {
+        let quantized = Quantized::<Q>::new(&reading.data);
+        if logger
+            .log(SensorReading::new(reading.timestamp, quantized.clone()))
+            .await
+            .unwrap()
+        {
+            if last_quantized
+                .as_ref()
+                .map_or(true, |last| quantized.subtract(last).is_none())
+            {
+                full_readings += 1;
+            }
+            last_quantized = Some(quantized);
+            logged.push(reading);
+        }
+    }
+    logger.flush().await.unwrap();
+    let bytes = logger.into_inner().await.unwrap().0;
+
+    let field_names = <Q::Data as QuantizableSensorData>::FIELD_NAMES;
+    let mut fields: Vec<FieldError> = field_names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| FieldError {
+            name: *name,
+            max_error_bound: Q::max_error(i),
+            max_error: 0.0,
+        })
+        .collect();
+
+    let mut reader = DeltaLoggerReader::<Quantized<Q>, _, FF>::new(bytes.as_slice());
+    let mut logged = logged.into_iter();
+    while let Some(entry) = reader
+        .read()
+        .await
+        .map_err(|e| anyhow!("Failed to read back {}: {:?}", Q::NAME, e))?
+    {
+        if let Either::Left(reading) = entry {
+            let original = logged
+                .next()
+                .ok_or(anyhow!("{} read back more readings than logged", Q::NAME))?
+                .data
+                .to_fields();
+            let reconstructed = reading.data.data().to_fields();
+            for (i, field) in fields.iter_mut().enumerate() {
+                let error = (original[i] - reconstructed[i]).abs();
+                if error > field.max_error || error.is_nan() {
+                    field.max_error = error;
+                }
+            }
+        }
+    }
+
+    Ok(QuantizationReport {
+        quantizer: Q::NAME,
+        readings: readings.len(),
+        full_readings,
+        original_bytes,
+        quantized_bytes: bytes.len(),
+        fields,
+    })
+}
+
+/// Sensor data with quantizers to compare
+trait QuantizerCandidates: QuantizableSensorData {
+    async fn measure_all<FF: F64FixedPointFactory>(
+        readings: &[SensorReading<BootTimestamp, Self>],
+        original_bytes: u64,
+    ) -> Result<Vec<QuantizationReport>>;
+}
+
+impl QuantizerCandidates for IMUData {
+    async fn measure_all<FF: F64FixedPointFactory>(
+        readings: &[SensorReading<BootTimestamp, Self>],
+        original_bytes: u64,
+    ) -> Result<Vec<QuantizationReport>> {
+        Ok(vec![
+            measure::<IMUQuantizerTier1, FF>(readings, original_bytes).await?,
+            measure::<IMUQuantizerTier2, FF>(readings, original_bytes).await?,
+        ])
+    }
+}
+
+impl QuantizerCandidates for BaroData {
+    async fn measure_all<FF: F64FixedPointFactory>(
+        readings: &[SensorReading<BootTimestamp, Self>],
+        original_bytes: u64,
+    ) -> Result<Vec<QuantizationReport>> {
+        Ok(vec![
+            measure::<BaroQuantizerTier1, FF>(readings, original_bytes).await?,
+            measure::<BaroQuantizerTier2, FF>(readings, original_bytes).await?,
+        ])
+    }
+}
+
+async fn measure_candidates<D, FF>(
+    file_path: &PathBuf,
+    original_bytes: u64,
+) -> Result<Vec<QuantizationReport>>
+where
+    D: QuantizerCandidates + SensorData,
+    FF: F64FixedPointFactory,
+    [(); size_of::<D>() + 10]:,
+{
+    let stream = parse_delta_readings::<D, FF>(file_path.clone()).await?;
+    pin_mut!(stream);
+    let mut readings = vec![];
+    while let Some((reading, _)) = stream.next().await.transpose()? {
+        readings.push(reading);
+    }
+    D::measure_all::<FF>(&readings, original_bytes).await
+}
+
+/// Re-encodes a recorded delta log with every quantizer of its sensor data,
+/// the timestamps are encoded the same way as in the recording
+pub async fn quantization_report(file_path: &PathBuf) -> Result<Vec<QuantizationReport>> {
+    let header = read_delta_log_header_or_legacy(file_path, None).await?;
+    let original_bytes = tokio::fs::metadata(file_path).await?.len();
+    let reports = match header.schema_id {
+        IMU_DATA_SCHEMA_ID => try_delta_log_decoders!(
+            header,
+            measure_candidates(file_path, original_bytes),
+            IMUData,
+            [SensorsFF1, SensorsFF2]
+        ),
+        BARO_DATA_SCHEMA_ID => try_delta_log_decoders!(
+            header,
+            measure_candidates(file_path, original_bytes),
+            BaroData,
+            [SensorsFF1, SensorsFF2, VacuumTestFF]
+        ),
+        _ => None,
+    };
+    reports.ok_or(anyhow!(
+        "No quantizers for {:?}: {}",
+        file_path,
+        describe_delta_log_header(&header)
+    ))?
+}
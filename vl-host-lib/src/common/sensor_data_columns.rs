@@ -0,0 +1,173 @@
+use std::fmt::Display;
+
+use firmware_common::{
+    common::{
+        delta_logger::quantized::{Quantized, SensorQuantizer},
+        sensor_reading::SensorData,
+    },
+    driver::{
+        adc::{ADCData, Ampere, Volt},
+        barometer::BaroData,
+        gps::GPSData,
+        imu::IMUData,
+        mag::MagData,
+    },
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    F32,
+    F64,
+    I64,
+    U8,
+}
+
+/// All the columns are nullable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Column {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub typ: ColumnType,
+}
+
+impl Column {
+    pub const fn new(name: &'static str, typ: ColumnType) -> Self {
+        Self { name, typ }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnValue {
+    F32(Option<f32>),
+    F64(Option<f64>),
+    I64(Option<i64>),
+    U8(Option<u8>),
+}
+
+/// Empty string for null values
+impl Display for ColumnValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnValue::F32(Some(v)) => write!(f, "{}", v),
+            ColumnValue::F64(Some(v)) => write!(f, "{}", v),
+            ColumnValue::I64(Some(v)) => write!(f, "{}", v),
+            ColumnValue::U8(Some(v)) => write!(f, "{}", v),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Typed columns of a `SensorData` in exported files, the timestamp columns
+/// are added by the exporters
+pub trait SensorDataColumns: SensorData {
+    const COLUMNS: &'static [Column];
+
+    /// Same order and types as `COLUMNS`
+    fn column_values(&self) -> Vec<ColumnValue>;
+}
+
+impl SensorDataColumns for BaroData {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("pressure", ColumnType::F32),
+        Column::new("altitude", ColumnType::F32),
+        Column::new("temperature", ColumnType::F32),
+    ];
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::F32(Some(self.pressure)),
+            ColumnValue::F32(Some(self.altitude())),
+            ColumnValue::F32(Some(self.temperature)),
+        ]
+    }
+}
+
+impl SensorDataColumns for IMUData {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("acc_x", ColumnType::F32),
+        Column::new("acc_y", ColumnType::F32),
+        Column::new("acc_z", ColumnType::F32),
+        Column::new("gyro_x", ColumnType::F32),
+        Column::new("gyro_y", ColumnType::F32),
+        Column::new("gyro_z", ColumnType::F32),
+    ];
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        self.acc
+            .iter()
+            .chain(self.gyro.iter())
+            .map(|v| ColumnValue::F32(Some(*v)))
+            .collect()
+    }
+}
+
+impl SensorDataColumns for GPSData {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("timestamp", ColumnType::I64),
+        Column::new("lat", ColumnType::F64),
+        Column::new("lon", ColumnType::F64),
+        Column::new("altitude", ColumnType::F32),
+        Column::new("satellites", ColumnType::U8),
+        Column::new("hdop", ColumnType::F32),
+        Column::new("vdop", ColumnType::F32),
+        Column::new("pdop", ColumnType::F32),
+    ];
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        vec![
+            ColumnValue::I64(self.timestamp),
+            ColumnValue::F64(self.lat_lon.map(|(lat, _)| lat)),
+            ColumnValue::F64(self.lat_lon.map(|(_, lon)| lon)),
+            ColumnValue::F32(self.altitude),
+            ColumnValue::U8(Some(self.num_of_fix_satellites)),
+            ColumnValue::F32(self.hdop),
+            ColumnValue::F32(self.vdop),
+            ColumnValue::F32(self.pdop),
+        ]
+    }
+}
+
+impl SensorDataColumns for MagData {
+    const COLUMNS: &'static [Column] = &[
+        Column::new("mag_x", ColumnType::F32),
+        Column::new("mag_y", ColumnType::F32),
+        Column::new("mag_z", ColumnType::F32),
+    ];
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        self.mag
+            .iter()
+            .map(|v| ColumnValue::F32(Some(*v)))
+            .collect()
+    }
+}
+
+impl SensorDataColumns for ADCData<Volt> {
+    const COLUMNS: &'static [Column] = &[Column::new("voltage", ColumnType::F32)];
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        vec![ColumnValue::F32(Some(self.value))]
+    }
+}
+
+impl SensorDataColumns for ADCData<Ampere> {
+    const COLUMNS: &'static [Column] = &[Column::new("current", ColumnType::F32)];
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        vec![ColumnValue::F32(Some(self.value))]
+    }
+}
+
+/// Same columns as the sensor data, with the quantized values
+impl<Q: SensorQuantizer> SensorDataColumns for Quantized<Q>
+where
+    Q::Data: SensorDataColumns,
+{
+    const COLUMNS: &'static [Column] = <Q::Data as SensorDataColumns>::COLUMNS;
+
+    fn column_values(&self) -> Vec<ColumnValue> {
+        self.data().column_values()
+    }
+}
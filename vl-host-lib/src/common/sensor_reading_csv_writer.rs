@@ -7,12 +7,26 @@ use futures_core::Stream;
 use futures_util::{pin_mut, StreamExt};
 use std::{fs::File, marker::PhantomData, path::PathBuf};
 
+use super::sensor_data_columns::SensorDataColumns;
+
 pub struct SensorReadingCSVWriter<D: SensorData, G: Fn(D) -> Vec<String>> {
     writer: csv::Writer<File>,
     row_data_getter: G,
     phantom: PhantomData<D>,
 }
 
+impl<D: SensorDataColumns> SensorReadingCSVWriter<D, fn(D) -> Vec<String>> {
+    pub fn from_sensor_data(file_path: &PathBuf) -> Result<Self> {
+        let row_titles: Vec<&str> = D::COLUMNS.iter().map(|column| column.name).collect();
+        Self::new(file_path, &row_titles, |data| {
+            data.column_values()
+                .iter()
+                .map(|value| value.to_string())
+                .collect()
+        })
+    }
+}
+
 impl<D: SensorData, G: Fn(D) -> Vec<String>> SensorReadingCSVWriter<D, G> {
     pub fn new(file_path: &PathBuf, row_titles: &[&str], row_data_getter: G) -> Result<Self> {
         let mut writer = csv::Writer::from_path(file_path)?;
@@ -45,10 +59,10 @@ impl<D: SensorData, G: Fn(D) -> Vec<String>> SensorReadingCSVWriter<D, G> {
 
     pub async fn write_all(
         &mut self,
-        stream: impl Stream<Item = (SensorReading<BootTimestamp, D>, Option<f64>)>,
+        stream: impl Stream<Item = Result<(SensorReading<BootTimestamp, D>, Option<f64>)>>,
     ) -> Result<()> {
         pin_mut!(stream);
-        while let Some((reading, unix_timestamp)) = stream.next().await {
+        while let Some((reading, unix_timestamp)) = stream.next().await.transpose()? {
             self.write(reading, unix_timestamp)?;
         }
         Ok(())
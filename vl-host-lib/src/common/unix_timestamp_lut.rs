@@ -1,6 +1,7 @@
 use map_range::MapRange;
 
-// FIXME shoule be per-file
+/// Maps boot timestamps to unix timestamps using the `UnixTimestampLog` entries
+/// of one boot session, boot timestamps of different sessions are unrelated.
 pub struct UnixTimestampLUT {
     // (boot_timestamp, unix_timestamp)
     points: Vec<(f64, f64)>,
@@ -16,16 +17,29 @@ impl UnixTimestampLUT {
     }
 
     pub fn sort_timestamps(&mut self) {
-        self.points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        self.points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        // two points with the same boot timestamp can't be interpolated between
+        self.points.dedup_by(|a, b| a.0 == b.0);
     }
 
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// With a single point the clocks are assumed to run at the same rate
     pub fn get_unix_timestamp(&self, boot_timestamp: f64) -> Option<f64> {
-        if self.points.len() < 2 {
-            return None;
+        match self.points.len() {
+            0 => return None,
+            1 => return Some(boot_timestamp - self.points[0].0 + self.points[0].1),
+            _ => {}
         }
 
         let binary_search_result = self.points.binary_search_by(|(point_boot_timestamp, _)| {
-            point_boot_timestamp.partial_cmp(&boot_timestamp).unwrap()
+            point_boot_timestamp.total_cmp(&boot_timestamp)
         });
 
         match binary_search_result {
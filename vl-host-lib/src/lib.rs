@@ -1,8 +1,10 @@
 #![feature(generic_const_exprs)]
 
+pub mod can_bus;
 pub mod common;
 mod create_serial;
 pub mod ozys;
+pub mod timeline;
 pub mod vl;
 
 pub use create_serial::create_serial;
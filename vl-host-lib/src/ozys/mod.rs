@@ -1,7 +1,9 @@
 mod pull_ozys_data;
 mod parse_sg_data;
 mod sg_data_csv_writer;
+mod sg_data_exporter;
 
 pub use pull_ozys_data::pull_ozys_data;
 pub use parse_sg_data::parse_sg_data;
-pub use sg_data_csv_writer::SGCSVWriter;
\ No newline at end of file
+pub use sg_data_csv_writer::SGCSVWriter;
+pub use sg_data_exporter::SGDataExporter;
\ No newline at end of file
@@ -1,4 +1,4 @@
-use async_stream::stream;
+use async_stream::try_stream;
 use firmware_common::strain_gauges::{
     mid_prio::{SGReadingLog, SGReadingLoggerReader},
     ProcessedSGReading,
@@ -17,7 +17,7 @@ use crate::common::{
 
 pub async fn parse_sg_data(
     file_path: PathBuf,
-) -> Result<impl Stream<Item = (ProcessedSGReading, Option<f64>)>> {
+) -> Result<impl Stream<Item = Result<(ProcessedSGReading, Option<f64>)>>> {
     // Pass 1: get all the unix timestamps
     let mut timestamp_lut = UnixTimestampLUT::new();
     let stream =
@@ -33,11 +33,11 @@ pub async fn parse_sg_data(
     timestamp_lut.sort_timestamps();
 
     // Pass 2
-    let stream = stream! {
+    let stream = try_stream! {
         let enum_stream = parse_serialized_enums::<SGReadingLoggerReader<BufReaderWrapper<File>>>(
             file_path.clone(),
         )
-        .await.unwrap();
+        .await?;
         pin_mut!(enum_stream);
         while let Some(reading) = enum_stream.next().await {
             if let SGReadingLog::ProcessedSGReading(reading) = reading {
@@ -1,59 +1,56 @@
 use anyhow::Result;
 use firmware_common::{
-    common::file_types::{SG_BATTERY_LOGGER, SG_READINGS},
-    driver::{
-        adc::{ADCData, Volt},
-        serial::SplitableSerial,
-    },
-    strain_gauges::mid_prio::BatteryFF,
+    common::file_types::SG_READINGS, driver::serial::SplitableSerial, sg_delta_loggers,
     CommonRPCTrait,
 };
 use futures_util::{pin_mut, StreamExt};
-use std::{array, vec};
 use std::{fs, path::PathBuf};
 
 use crate::common::{
-    extend_path, pull_file::pull_files, parse_delta_readings::parse_delta_readings,
-    SensorReadingCSVWriter,
+    delta_log_pipeline::delta_log_pipeline,
+    export::{DataExport, ExportFormat, ExportManifest},
+    pull_file::pull_files,
 };
 
-use super::{parse_sg_data::parse_sg_data, sg_data_csv_writer::SGCSVWriter};
+use super::{parse_sg_data::parse_sg_data, sg_data_exporter::SGDataExporter};
 
+const SG_COUNT: u8 = 4;
+
+/// The strain gauge fft is only exported as csv
 pub async fn pull_ozys_data<S: SplitableSerial>(
     rpc: &mut impl CommonRPCTrait<S>,
     save_folder: &PathBuf,
-) -> Result<()> {
+    format: ExportFormat,
+) -> Result<ExportManifest> {
     fs::create_dir_all(&save_folder)?;
+    let mut export = DataExport::new(save_folder, format);
 
-    // battery readings
-    let battery_files = pull_files(rpc, SG_BATTERY_LOGGER, "battery", "vldr", &save_folder).await?;
-    let mut csv_writer = SensorReadingCSVWriter::new(
-        &extend_path(&save_folder, "battery.csv"),
-        &["voltage"],
-        |data: ADCData<Volt>| vec![format!("{}", data.value)],
-    )
-    .unwrap();
-    for file_path in battery_files {
-        let stream = parse_delta_readings::<ADCData<Volt>, BatteryFF>(file_path).await?;
-        csv_writer.write_all(stream).await?;
-    }
-    csv_writer.flush()?;
+    sg_delta_loggers!(delta_log_pipeline!(rpc, &mut export));
 
     // sg readings
-    let mut csv_writers: [SGCSVWriter; 4] =
-        array::from_fn(|i| SGCSVWriter::new(save_folder, i as u8).unwrap());
-    let sg_reading_files = pull_files(rpc, SG_READINGS, "sg", "vle", &save_folder).await?;
-    for file_path in sg_reading_files {
-        let stream = parse_sg_data(file_path).await?;
+    let mut exporters = (0..SG_COUNT)
+        .map(|i| SGDataExporter::new(save_folder, i, format))
+        .collect::<Result<Vec<_>>>()?;
+    let sg_reading_files = pull_files(rpc, SG_READINGS, &save_folder).await?;
+    for file_path in &sg_reading_files {
+        let stream = parse_sg_data(file_path.clone()).await?;
         pin_mut!(stream);
-        while let Some((reading, unix_timestamp)) = stream.next().await {
-            let writer = &mut csv_writers[reading.sg_i as usize];
-            writer.write(reading, unix_timestamp)?;
+        while let Some((reading, unix_timestamp)) = stream.next().await.transpose()? {
+            let exporter = &mut exporters[reading.sg_i as usize];
+            exporter.write(reading, unix_timestamp)?;
         }
     }
-    for writer in &mut csv_writers {
-        writer.flush()?;
+    for exporter in exporters {
+        exporter.finish()?;
+    }
+    for i in 0..SG_COUNT {
+        for mut file in SGDataExporter::exported_files(i, format) {
+            for file_path in &sg_reading_files {
+                file.add_source_file(file_path);
+            }
+            export.add_file(file);
+        }
     }
 
-    Ok(())
+    export.finish()
 }
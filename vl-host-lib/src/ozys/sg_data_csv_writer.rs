@@ -39,16 +39,10 @@ impl SGCSVWriter {
         reading: ProcessedSGReading,
         unix_timestamp: Option<f64>,
     ) -> Result<()> {
-        assert_eq!(reading.samples.len(), 80);
         // assert_eq!(reading.amplitudes.len(), 400);
 
         // samples
-        for i in 0..(reading.samples.len() / 2) {
-            let timestamp = reading.start_time + 5f64 * i as f64;
-            let unix_timestamp = unix_timestamp.map(|t| t + 5f64 * i as f64);
-            let sample =
-                half::f16::from_le_bytes([reading.samples[i * 2], reading.samples[i * 2 + 1]])
-                    .to_f32();
+        for (timestamp, unix_timestamp, sample) in sg_samples(&reading, unix_timestamp) {
             self.mag_writer.write_record([
                 format!("{}", timestamp),
                 unix_timestamp.map_or("".into(), |t| format!("{}", t)),
@@ -79,3 +73,19 @@ impl SGCSVWriter {
         Ok(())
     }
 }
+
+/// (boot timestamp, unix timestamp, sample) of every sample in the reading,
+/// the samples are 5ms apart
+pub fn sg_samples(
+    reading: &ProcessedSGReading,
+    unix_timestamp: Option<f64>,
+) -> impl Iterator<Item = (f64, Option<f64>, f32)> + '_ {
+    assert_eq!(reading.samples.len(), 80);
+    (0..(reading.samples.len() / 2)).map(move |i| {
+        let timestamp = reading.start_time + 5f64 * i as f64;
+        let unix_timestamp = unix_timestamp.map(|t| t + 5f64 * i as f64);
+        let sample =
+            half::f16::from_le_bytes([reading.samples[i * 2], reading.samples[i * 2 + 1]]).to_f32();
+        (timestamp, unix_timestamp, sample)
+    })
+}
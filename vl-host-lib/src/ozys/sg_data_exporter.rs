@@ -0,0 +1,83 @@
+use anyhow::Result;
+use firmware_common::strain_gauges::ProcessedSGReading;
+use std::path::PathBuf;
+
+use crate::common::{
+    export::{
+        ArrowTableWriter, ExportFormat, ExportedFile, BOOT_TIMESTAMP_COLUMN, UNIX_TIMESTAMP_COLUMN,
+    },
+    extend_path,
+    sensor_data_columns::{Column, ColumnType, ColumnValue},
+};
+
+use super::sg_data_csv_writer::{sg_samples, SGCSVWriter};
+
+const SG_READING_COLUMN: Column = Column::new("reading", ColumnType::F32);
+
+/// Exports the readings of one strain gauge, the fft is only exported as csv
+pub enum SGDataExporter {
+    CSV(SGCSVWriter),
+    Arrow(ArrowTableWriter),
+}
+
+impl SGDataExporter {
+    pub fn new(save_folder: &PathBuf, sg_i: u8, format: ExportFormat) -> Result<Self> {
+        let mag_file_path = extend_path(save_folder, &mag_file_name(sg_i, format));
+        Ok(match format {
+            ExportFormat::CSV => Self::CSV(SGCSVWriter::new(save_folder, sg_i)?),
+            ExportFormat::Parquet => Self::Arrow(ArrowTableWriter::new_parquet(
+                &mag_file_path,
+                &[SG_READING_COLUMN],
+            )?),
+            ExportFormat::Arrow => Self::Arrow(ArrowTableWriter::new_ipc(
+                &mag_file_path,
+                &[SG_READING_COLUMN],
+            )?),
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        reading: ProcessedSGReading,
+        unix_timestamp: Option<f64>,
+    ) -> Result<()> {
+        match self {
+            Self::CSV(writer) => writer.write(reading, unix_timestamp),
+            Self::Arrow(table) => {
+                for (timestamp, unix_timestamp, sample) in sg_samples(&reading, unix_timestamp) {
+                    table.write(timestamp, unix_timestamp, [ColumnValue::F32(Some(sample))])?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::CSV(mut writer) => writer.flush(),
+            Self::Arrow(table) => table.finish(),
+        }
+    }
+
+    /// Manifest entries of the files written by `new(_, sg_i, format)`
+    pub fn exported_files(sg_i: u8, format: ExportFormat) -> Vec<ExportedFile> {
+        let mut mag_file =
+            ExportedFile::other(mag_file_name(sg_i, format), format.extension(), "sg");
+        mag_file.columns = vec![
+            BOOT_TIMESTAMP_COLUMN,
+            UNIX_TIMESTAMP_COLUMN,
+            SG_READING_COLUMN,
+        ];
+        match format {
+            ExportFormat::CSV => vec![
+                mag_file,
+                ExportedFile::other(format!("sg-{}.fft.csv", sg_i), "csv", "sg"),
+            ],
+            _ => vec![mag_file],
+        }
+    }
+}
+
+fn mag_file_name(sg_i: u8, format: ExportFormat) -> String {
+    format!("sg-{}.mag.{}", sg_i, format.extension())
+}
@@ -0,0 +1,299 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use sessions::{assign_boot_sessions, FileSpan, SessionMappings};
+use source::{load_source_file, parse_pulled_file_name, SourceFile};
+
+use crate::common::{
+    export::UNIX_TIMESTAMP_COLUMN,
+    sensor_data_columns::{Column, ColumnValue},
+    unix_timestamp_lut::UnixTimestampLUT,
+};
+
+pub mod resample;
+pub mod sessions;
+pub mod source;
+
+pub use resample::{ResampleMethod, ResampleOptions};
+
+#[derive(Debug, Clone)]
+pub struct TimelineChannel {
+    /// File type name, prefixed by the device when merging multiple devices
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelineEntry {
+    pub unix_timestamp: f64,
+    /// Index into `Timeline::channels`
+    pub channel: usize,
+    pub values: Vec<ColumnValue>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelineEvent {
+    pub unix_timestamp: f64,
+    pub device: String,
+    pub description: String,
+}
+
+/// Samples and events of every pulled file, sorted by unix time
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    pub channels: Vec<TimelineChannel>,
+    pub entries: Vec<TimelineEntry>,
+    pub events: Vec<TimelineEvent>,
+    pub files: usize,
+    /// Boot sessions of all devices
+    pub sessions: usize,
+    /// Samples and events from boot sessions without any unix time
+    pub unmapped: usize,
+}
+
+impl Timeline {
+    fn channel_index(&mut self, name: &str, columns: &[Column]) -> usize {
+        match self.channels.iter().position(|c| c.name == name) {
+            Some(i) => i,
+            None => {
+                self.channels.push(TimelineChannel {
+                    name: name.into(),
+                    columns: columns.to_vec(),
+                });
+                self.channels.len() - 1
+            }
+        }
+    }
+
+    pub fn time_span(&self) -> Option<(f64, f64)> {
+        let first = self.entries.first().map(|e| e.unix_timestamp);
+        let last = self.entries.last().map(|e| e.unix_timestamp);
+        first.zip(last)
+    }
+}
+
+async fn load_folder(folder: &PathBuf, device: &str) -> Result<Vec<SourceFile>> {
+    let mut sources = vec![];
+    let mut dir = tokio::fs::read_dir(folder).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        if let Some((file_id, file_type)) = parse_pulled_file_name(&path) {
+            match load_source_file(&path, device, file_id, file_type).await {
+                Ok(Some(source)) => sources.push(source),
+                Ok(None) => {}
+                Err(e) => log::warn!("Skipping {:?}: {:?}", path, e),
+            }
+        }
+    }
+    Ok(sources)
+}
+
+/// Merges the files pulled by `pull_files` from one or more devices (one
+/// folder per device) into one timeline.
+///
+/// Boot timestamps are converted with the file's own unix time points when
+/// it has at least two of them, otherwise with the points of every file in
+/// the same boot session of the device.
+pub async fn build_timeline(folders: &[PathBuf]) -> Result<Timeline> {
+    let mut sources = vec![];
+    for folder in folders {
+        let device = if folders.len() > 1 {
+            folder
+                .file_name()
+                .map_or(String::new(), |name| name.to_string_lossy().to_string())
+        } else {
+            String::new()
+        };
+        sources.extend(load_folder(folder, &device).await?);
+    }
+
+    let mut sources_with_span = vec![];
+    let mut spans = vec![];
+    for source in sources {
+        if let Some((first, last)) = source.boot_time_span() {
+            spans.push(FileSpan {
+                device: source.device.clone(),
+                stream: source.file_type.name.into(),
+                file_id: source.file_id,
+                first_boot_timestamp: first,
+                last_boot_timestamp: last,
+            });
+            sources_with_span.push(source);
+        }
+    }
+    let sessions = assign_boot_sessions(&spans);
+
+    let mut mappings = SessionMappings::new();
+    for (source, session) in sources_with_span.iter().zip(sessions.iter()) {
+        mappings.add_points(&source.device, *session, &source.unix_time_points);
+    }
+    mappings.sort();
+
+    let mut timeline = Timeline {
+        files: sources_with_span.len(),
+        sessions: spans
+            .iter()
+            .zip(sessions.iter())
+            .map(|(span, session)| (span.device.as_str(), *session))
+            .collect::<std::collections::HashSet<_>>()
+            .len(),
+        ..Default::default()
+    };
+    for (source, session) in sources_with_span.into_iter().zip(sessions.into_iter()) {
+        let own_lut;
+        let lut = if source.unix_time_points.len() >= 2 {
+            let mut lut = UnixTimestampLUT::new();
+            for (boot_timestamp, unix_timestamp) in &source.unix_time_points {
+                lut.add_timestamp(*boot_timestamp, *unix_timestamp);
+            }
+            lut.sort_timestamps();
+            own_lut = lut;
+            Some(&own_lut)
+        } else {
+            mappings.get(&source.device, session)
+        };
+        let Some(lut) = lut else {
+            timeline.unmapped += source
+                .channels
+                .iter()
+                .map(|c| c.samples.len())
+                .sum::<usize>()
+                + source.events.len();
+            continue;
+        };
+
+        for channel in source.channels {
+            let channel_i = timeline.channel_index(&channel.name, &channel.columns);
+            for (boot_timestamp, values) in channel.samples {
+                timeline.entries.push(TimelineEntry {
+                    unix_timestamp: lut.get_unix_timestamp(boot_timestamp).unwrap(),
+                    channel: channel_i,
+                    values,
+                });
+            }
+        }
+        for (boot_timestamp, description) in source.events {
+            timeline.events.push(TimelineEvent {
+                unix_timestamp: lut.get_unix_timestamp(boot_timestamp).unwrap(),
+                device: source.device.clone(),
+                description,
+            });
+        }
+    }
+
+    timeline
+        .entries
+        .sort_by(|a, b| a.unix_timestamp.total_cmp(&b.unix_timestamp));
+    timeline
+        .events
+        .sort_by(|a, b| a.unix_timestamp.total_cmp(&b.unix_timestamp));
+    Ok(timeline)
+}
+
+fn event_text(event: &TimelineEvent) -> String {
+    if event.device.is_empty() {
+        event.description.clone()
+    } else {
+        format!("{}/{}", event.device, event.description)
+    }
+}
+
+/// Writes one column per channel column, raw samples fill only the columns
+/// of their channel, resampled rows fill every channel with data nearby.
+/// Events go to the row with the closest timestamp when resampling.
+///
+/// Returns the number of rows written.
+pub fn write_timeline_csv(
+    timeline: &Timeline,
+    path: &PathBuf,
+    resample: Option<&ResampleOptions>,
+) -> Result<usize> {
+    let mut writer = csv::Writer::from_path(path)?;
+
+    let mut header = vec![UNIX_TIMESTAMP_COLUMN.name.to_string()];
+    // offset of each channel's first column in a row
+    let mut offsets = vec![];
+    for channel in &timeline.channels {
+        offsets.push(header.len());
+        for column in &channel.columns {
+            header.push(format!("{}.{}", channel.name, column.name));
+        }
+    }
+    header.push("events".into());
+    let row_len = header.len();
+    writer.write_record(&header)?;
+
+    let mut rows = 0usize;
+    let mut write_row = |writer: &mut csv::Writer<std::fs::File>,
+                         unix_timestamp: f64,
+                         cells: Vec<String>|
+     -> Result<()> {
+        let mut record = cells;
+        record[0] = unix_timestamp.to_string();
+        writer.write_record(&record)?;
+        rows += 1;
+        Ok(())
+    };
+
+    match resample {
+        None => {
+            let mut events = timeline.events.iter().peekable();
+            for entry in &timeline.entries {
+                while let Some(event) = events.next_if(|e| e.unix_timestamp <= entry.unix_timestamp)
+                {
+                    let mut cells = vec![String::new(); row_len];
+                    cells[row_len - 1] = event_text(event);
+                    write_row(&mut writer, event.unix_timestamp, cells)?;
+                }
+                let mut cells = vec![String::new(); row_len];
+                for (i, value) in entry.values.iter().enumerate() {
+                    cells[offsets[entry.channel] + i] = value.to_string();
+                }
+                write_row(&mut writer, entry.unix_timestamp, cells)?;
+            }
+            for event in events {
+                let mut cells = vec![String::new(); row_len];
+                cells[row_len - 1] = event_text(event);
+                write_row(&mut writer, event.unix_timestamp, cells)?;
+            }
+        }
+        Some(options) => {
+            let mut channel_samples: Vec<Vec<(f64, &[ColumnValue])>> =
+                vec![vec![]; timeline.channels.len()];
+            for entry in &timeline.entries {
+                channel_samples[entry.channel]
+                    .push((entry.unix_timestamp, entry.values.as_slice()));
+            }
+
+            let mut grid_events: HashMap<i64, Vec<String>> = HashMap::new();
+            for event in &timeline.events {
+                let grid_i = (event.unix_timestamp / options.interval_ms).round() as i64;
+                grid_events
+                    .entry(grid_i)
+                    .or_default()
+                    .push(event_text(event));
+            }
+
+            if let Some((start, end)) = timeline.time_span() {
+                for timestamp in resample::resample_grid(start, end, options.interval_ms)? {
+                    let mut cells = vec![String::new(); row_len];
+                    for (channel_i, samples) in channel_samples.iter().enumerate() {
+                        if let Some(values) = resample::sample_at(samples, timestamp, options) {
+                            for (i, value) in values.iter().enumerate() {
+                                cells[offsets[channel_i] + i] = value.to_string();
+                            }
+                        }
+                    }
+                    let grid_i = (timestamp / options.interval_ms).round() as i64;
+                    if let Some(events) = grid_events.get(&grid_i) {
+                        cells[row_len - 1] = events.join("; ");
+                    }
+                    write_row(&mut writer, timestamp, cells)?;
+                }
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(rows)
+}
@@ -0,0 +1,169 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+
+use crate::common::sensor_data_columns::ColumnValue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResampleMethod {
+    /// Last sample at or before the grid time
+    Hold,
+    /// Closest sample
+    Nearest,
+    /// Linear interpolation of float columns, integer columns use the
+    /// closest sample
+    #[default]
+    Linear,
+}
+
+impl FromStr for ResampleMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "hold" => Ok(ResampleMethod::Hold),
+            "nearest" => Ok(ResampleMethod::Nearest),
+            "linear" => Ok(ResampleMethod::Linear),
+            _ => bail!(
+                "Unknown resample method {}, expected hold, nearest or linear",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ResampleOptions {
+    pub interval_ms: f64,
+    pub method: ResampleMethod,
+    /// Samples further than this from the grid time are not used, so the
+    /// channel is left empty during gaps in the data
+    pub max_gap_ms: f64,
+}
+
+fn interpolate(a: &ColumnValue, b: &ColumnValue, ratio: f64) -> ColumnValue {
+    match (a, b) {
+        (ColumnValue::F32(Some(a)), ColumnValue::F32(Some(b))) => {
+            ColumnValue::F32(Some(a + (b - a) * ratio as f32))
+        }
+        (ColumnValue::F64(Some(a)), ColumnValue::F64(Some(b))) => {
+            ColumnValue::F64(Some(a + (b - a) * ratio))
+        }
+        _ if ratio < 0.5 => *a,
+        _ => *b,
+    }
+}
+
+/// Values of a channel at `timestamp`, `samples` must be sorted by time
+pub fn sample_at<'a>(
+    samples: &'a [(f64, &'a [ColumnValue])],
+    timestamp: f64,
+    options: &ResampleOptions,
+) -> Option<Vec<ColumnValue>> {
+    let i = samples.partition_point(|(t, _)| *t <= timestamp);
+    let before = if i > 0 { Some(samples[i - 1]) } else { None };
+    let after = samples.get(i).copied();
+    let within_gap = |(t, _): &(f64, &[ColumnValue])| (t - timestamp).abs() <= options.max_gap_ms;
+    let before = before.filter(within_gap);
+    let after = after.filter(within_gap);
+
+    match (options.method, before, after) {
+        (ResampleMethod::Hold, Some((_, values)), _) => Some(values.to_vec()),
+        (ResampleMethod::Hold, None, _) => None,
+        (_, Some((_, values)), None) | (_, None, Some((_, values))) => Some(values.to_vec()),
+        (_, None, None) => None,
+        (ResampleMethod::Nearest, Some((t0, v0)), Some((t1, v1))) => {
+            if timestamp - t0 <= t1 - timestamp {
+                Some(v0.to_vec())
+            } else {
+                Some(v1.to_vec())
+            }
+        }
+        (ResampleMethod::Linear, Some((t0, v0)), Some((t1, v1))) => {
+            if t1 - t0 > options.max_gap_ms {
+                // don't interpolate across a gap
+                return Some(if timestamp - t0 <= t1 - timestamp {
+                    v0.to_vec()
+                } else {
+                    v1.to_vec()
+                });
+            }
+            let ratio = (timestamp - t0) / (t1 - t0);
+            Some(
+                v0.iter()
+                    .zip(v1.iter())
+                    .map(|(a, b)| interpolate(a, b, ratio))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Resampling a long timeline at a short interval would write an unusably
+/// large csv, e.g. a day at 1ms is 86.4M rows
+pub const MAX_RESAMPLE_ROWS: u64 = 10_000_000;
+
+/// Grid times from `start` to `end` (inclusive), aligned to multiples of the interval
+pub fn resample_grid(start: f64, end: f64, interval_ms: f64) -> Result<impl Iterator<Item = f64>> {
+    if !(interval_ms > 0.0 && interval_ms.is_finite()) {
+        bail!("Resample interval must be positive, got {}", interval_ms);
+    }
+    let first = (start / interval_ms).ceil();
+    let last = (end / interval_ms).floor();
+    let rows = last - first + 1.0;
+    if rows > MAX_RESAMPLE_ROWS as f64 {
+        bail!(
+            "Resampling {}ms to {}ms at {}ms would write {} rows, more than {}",
+            start,
+            end,
+            interval_ms,
+            rows,
+            MAX_RESAMPLE_ROWS
+        );
+    }
+    let (first, last) = (first as i64, last as i64);
+    Ok((first..=last).map(move |i| i as f64 * interval_ms))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_at() {
+        let v0 = [ColumnValue::F32(Some(0.0)), ColumnValue::U8(Some(1))];
+        let v1 = [ColumnValue::F32(Some(10.0)), ColumnValue::U8(Some(2))];
+        let v2 = [ColumnValue::F32(Some(20.0)), ColumnValue::U8(Some(3))];
+        let samples: Vec<(f64, &[ColumnValue])> = vec![(0.0, &v0), (10.0, &v1), (100.0, &v2)];
+        let mut options = ResampleOptions {
+            interval_ms: 1.0,
+            method: ResampleMethod::Linear,
+            max_gap_ms: 20.0,
+        };
+
+        assert_eq!(
+            sample_at(&samples, 4.0, &options),
+            Some(vec![ColumnValue::F32(Some(4.0)), ColumnValue::U8(Some(1))])
+        );
+        // gap between 10 and 100
+        assert_eq!(sample_at(&samples, 50.0, &options), None);
+        assert_eq!(sample_at(&samples, 15.0, &options), Some(v1.to_vec()));
+
+        options.method = ResampleMethod::Hold;
+        assert_eq!(sample_at(&samples, 9.0, &options), Some(v0.to_vec()));
+        assert_eq!(sample_at(&samples, -1.0, &options), None);
+
+        options.method = ResampleMethod::Nearest;
+        assert_eq!(sample_at(&samples, 9.0, &options), Some(v1.to_vec()));
+    }
+
+    #[test]
+    fn test_resample_grid() {
+        let grid: Vec<f64> = resample_grid(1.5, 4.0, 1.0).unwrap().collect();
+        assert_eq!(grid, vec![2.0, 3.0, 4.0]);
+
+        assert!(resample_grid(0.0, 1.0, 0.0).is_err());
+        assert!(resample_grid(0.0, 1.0, -1.0).is_err());
+        assert!(resample_grid(0.0, 24.0 * 3600.0 * 1000.0, 1.0).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use crate::common::unix_timestamp_lut::UnixTimestampLUT;
+
+/// Boot time span of a pulled file
+#[derive(Debug, Clone)]
+pub struct FileSpan {
+    pub device: String,
+    /// Files of the same stream are written one after another, e.g. the
+    /// segments of a ring logger
+    pub stream: String,
+    pub file_id: u64,
+    pub first_boot_timestamp: f64,
+    pub last_boot_timestamp: f64,
+}
+
+/// Returns the boot session index of every file, starting from 0 for each device.
+///
+/// Boot time restarts from 0 on reboot, this shows up as a file starting
+/// before the previous file of the same stream ended. Files created after a
+/// reboot always have larger file ids than the ones created before it, so a
+/// reboot seen by a stream between file ids `a` and `b` happened somewhere in
+/// `(a, b]`. Reboots seen by different streams are the same reboot when these
+/// ranges overlap.
+pub fn assign_boot_sessions(files: &[FileSpan]) -> Vec<usize> {
+    let mut streams: HashMap<(&str, &str), Vec<&FileSpan>> = HashMap::new();
+    for file in files {
+        streams
+            .entry((file.device.as_str(), file.stream.as_str()))
+            .or_default()
+            .push(file);
+    }
+
+    // (after file id, up to file id) of every reboot, per device
+    let mut reboot_ranges: HashMap<&str, Vec<(u64, u64)>> = HashMap::new();
+    for ((device, _), mut stream_files) in streams {
+        stream_files.sort_by_key(|f| f.file_id);
+        for pair in stream_files.windows(2) {
+            if pair[1].first_boot_timestamp < pair[0].last_boot_timestamp {
+                reboot_ranges
+                    .entry(device)
+                    .or_default()
+                    .push((pair[0].file_id, pair[1].file_id));
+            }
+        }
+    }
+
+    // first file id after each reboot, per device
+    let mut boundaries: HashMap<&str, Vec<u64>> = HashMap::new();
+    for (device, mut ranges) in reboot_ranges {
+        ranges.sort();
+        let mut merged: Vec<(u64, u64)> = vec![];
+        for (after, up_to) in ranges {
+            match merged.last_mut() {
+                Some(last) if after < last.1 => {
+                    last.0 = last.0.max(after);
+                    last.1 = last.1.min(up_to);
+                }
+                _ => merged.push((after, up_to)),
+            }
+        }
+        boundaries.insert(device, merged.into_iter().map(|(_, up_to)| up_to).collect());
+    }
+
+    files
+        .iter()
+        .map(|file| {
+            boundaries
+                .get(file.device.as_str())
+                .map_or(0, |boundaries| {
+                    boundaries.iter().filter(|b| **b <= file.file_id).count()
+                })
+        })
+        .collect()
+}
+
+/// Unix time mappings of every (device, boot session), pooled from the
+/// `UnixTimestampLog` entries of all the files in the session
+pub struct SessionMappings {
+    mappings: HashMap<(String, usize), UnixTimestampLUT>,
+}
+
+impl SessionMappings {
+    pub fn new() -> Self {
+        Self {
+            mappings: HashMap::new(),
+        }
+    }
+
+    pub fn add_points(&mut self, device: &str, session: usize, points: &[(f64, f64)]) {
+        let lut = self
+            .mappings
+            .entry((device.to_string(), session))
+            .or_insert_with(UnixTimestampLUT::new);
+        for (boot_timestamp, unix_timestamp) in points {
+            lut.add_timestamp(*boot_timestamp, *unix_timestamp);
+        }
+    }
+
+    pub fn sort(&mut self) {
+        for lut in self.mappings.values_mut() {
+            lut.sort_timestamps();
+        }
+    }
+
+    pub fn get(&self, device: &str, session: usize) -> Option<&UnixTimestampLUT> {
+        self.mappings
+            .get(&(device.to_string(), session))
+            .filter(|lut| lut.len() > 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn span(stream: &str, file_id: u64, first: f64, last: f64) -> FileSpan {
+        FileSpan {
+            device: "vl".into(),
+            stream: stream.into(),
+            file_id,
+            first_boot_timestamp: first,
+            last_boot_timestamp: last,
+        }
+    }
+
+    #[test]
+    fn test_assign_boot_sessions() {
+        let files = vec![
+            span("baro", 1, 0.0, 1000.0),
+            span("imu", 2, 0.0, 1000.0),
+            span("baro", 3, 1000.0, 2000.0),
+            // reboot
+            span("imu", 4, 10.0, 500.0),
+            span("baro", 5, 10.0, 500.0),
+            span("baro", 6, 500.0, 900.0),
+            // reboot, only seen by baro
+            span("baro", 7, 10.0, 500.0),
+        ];
+        assert_eq!(assign_boot_sessions(&files), vec![0, 0, 0, 1, 1, 1, 2]);
+    }
+
+    #[test]
+    fn test_session_mappings() {
+        let mut mappings = SessionMappings::new();
+        mappings.add_points("vl", 0, &[(1000.0, 1_700_000_001_000.0)]);
+        mappings.add_points("vl", 0, &[(0.0, 1_700_000_000_000.0)]);
+        mappings.sort();
+
+        let lut = mappings.get("vl", 0).unwrap();
+        assert_eq!(lut.get_unix_timestamp(500.0), Some(1_700_000_000_500.0));
+        assert!(mappings.get("vl", 1).is_none());
+    }
+}
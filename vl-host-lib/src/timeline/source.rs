@@ -0,0 +1,210 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use either::Either;
+use firmware_common::{
+    common::{
+        delta_logger::delta_logger::DeltaLoggerReader,
+        file_types::{
+            file_type_by_file_name, FileDecoderKind, FileTypeDescription,
+            GROUND_TEST_LOG_FILE_TYPE, SG_READINGS, VACUUM_TEST_LOG_FILE_TYPE,
+        },
+        fixed_point::F64FixedPointFactory,
+    },
+    strain_gauges::mid_prio::{SGReadingLog, SGReadingLoggerReader},
+    vacuum_test::{VacuumTestLog, VacuumTestLoggerReader},
+    GroundTestLog, GroundTestLoggerReader,
+};
+use futures_util::{pin_mut, StreamExt};
+use tokio::{fs::File, io::BufReader};
+
+use crate::common::{
+    delta_log_decoder::{read_delta_log_header_or_legacy, with_delta_log_decoder},
+    parse_serialized_enums::parse_serialized_enums,
+    readers::BufReaderWrapper,
+    sensor_data_columns::{Column, ColumnType, ColumnValue, SensorDataColumns},
+};
+
+/// Samples of one sensor in a pulled file, in boot time
+#[derive(Debug, Clone)]
+pub struct SourceChannel {
+    pub name: String,
+    pub columns: Vec<Column>,
+    /// (boot timestamp, values in the same order as `columns`)
+    pub samples: Vec<(f64, Vec<ColumnValue>)>,
+}
+
+/// Content of a file pulled from a device, see `pull_files` for the file names
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub device: String,
+    pub file_id: u64,
+    pub file_type: &'static FileTypeDescription,
+    pub channels: Vec<SourceChannel>,
+    /// (boot timestamp, description)
+    pub events: Vec<(f64, String)>,
+    /// (boot timestamp, unix timestamp) from the `UnixTimestampLog` entries
+    pub unix_time_points: Vec<(f64, f64)>,
+}
+
+impl SourceFile {
+    /// (first, last) boot timestamp of the samples and events
+    pub fn boot_time_span(&self) -> Option<(f64, f64)> {
+        self.channels
+            .iter()
+            .flat_map(|c| c.samples.iter().map(|(t, _)| *t))
+            .chain(self.events.iter().map(|(t, _)| *t))
+            .chain(self.unix_time_points.iter().map(|(t, _)| *t))
+            .fold(None, |span, t| match span {
+                None => Some((t, t)),
+                Some((first, last)) => Some((f64::min(first, t), f64::max(last, t))),
+            })
+    }
+}
+
+/// Parses `<file id>.<pulled file name>.<extension>`
+pub fn parse_pulled_file_name(path: &PathBuf) -> Option<(u64, &'static FileTypeDescription)> {
+    let file_name = path.file_name()?.to_str()?;
+    let (file_id, rest) = file_name.split_once('.')?;
+    let (file_type_name, extension) = rest.rsplit_once('.')?;
+    let file_type = file_type_by_file_name(file_type_name, extension)?;
+    Some((file_id.parse().ok()?, file_type))
+}
+
+async fn load_delta_log<D, FF>(
+    file_path: &PathBuf,
+    channel_name: &str,
+) -> Result<(SourceChannel, Vec<(f64, f64)>)>
+where
+    D: SensorDataColumns,
+    FF: F64FixedPointFactory,
+    [(); size_of::<D>() + 10]:,
+{
+    let reader = BufReaderWrapper(BufReader::new(File::open(file_path).await?));
+    let mut reader = DeltaLoggerReader::<D, _, FF>::new(reader);
+    let mut channel = SourceChannel {
+        name: channel_name.into(),
+        columns: D::COLUMNS.to_vec(),
+        samples: vec![],
+    };
+    let mut unix_time_points = vec![];
+    while let Some(entry) = reader
+        .read()
+        .await
+        .map_err(|e| anyhow!("Failed to read {:?}: {:?}", file_path, e))?
+    {
+        match entry {
+            Either::Left(reading) => channel
+                .samples
+                .push((reading.timestamp, reading.data.column_values())),
+            Either::Right(log) => unix_time_points.push((log.boot_timestamp, log.unix_timestamp)),
+        }
+    }
+    Ok((channel, unix_time_points))
+}
+
+fn sg_channel_name(channel_prefix: &str, sg_i: u8) -> String {
+    format!("{}_{}", channel_prefix, sg_i)
+}
+
+/// Each strain gauge becomes a channel, every reading holds 40 samples 5ms apart
+async fn load_sg_readings(source: &mut SourceFile, channel_prefix: &str) -> Result<()> {
+    let stream = parse_serialized_enums::<SGReadingLoggerReader<BufReaderWrapper<File>>>(
+        source.path.clone(),
+    )
+    .await?;
+    pin_mut!(stream);
+    while let Some(log) = stream.next().await {
+        match log {
+            SGReadingLog::ProcessedSGReading(reading) => {
+                let name = sg_channel_name(channel_prefix, reading.sg_i);
+                let channel = match source.channels.iter_mut().position(|c| c.name == name) {
+                    Some(i) => &mut source.channels[i],
+                    None => {
+                        source.channels.push(SourceChannel {
+                            name,
+                            columns: vec![Column::new("reading", ColumnType::F32)],
+                            samples: vec![],
+                        });
+                        source.channels.last_mut().unwrap()
+                    }
+                };
+                for (i, sample) in reading.samples.chunks_exact(2).enumerate() {
+                    let sample = half::f16::from_le_bytes([sample[0], sample[1]]).to_f32();
+                    channel.samples.push((
+                        reading.start_time + 5.0 * i as f64,
+                        vec![ColumnValue::F32(Some(sample))],
+                    ));
+                }
+            }
+            SGReadingLog::UnixTimestampLog(log) => source
+                .unix_time_points
+                .push((log.boot_timestamp, log.unix_timestamp)),
+        }
+    }
+    Ok(())
+}
+
+/// Loads a pulled file, returns None for file types that don't hold timed
+/// data (e.g. configs)
+pub async fn load_source_file(
+    path: &PathBuf,
+    device: &str,
+    file_id: u64,
+    file_type: &'static FileTypeDescription,
+) -> Result<Option<SourceFile>> {
+    let channel_name = if device.is_empty() {
+        file_type.name.to_string()
+    } else {
+        format!("{}/{}", device, file_type.name)
+    };
+    let mut source = SourceFile {
+        path: path.clone(),
+        device: device.into(),
+        file_id,
+        file_type,
+        channels: vec![],
+        events: vec![],
+        unix_time_points: vec![],
+    };
+
+    match file_type.decoder {
+        FileDecoderKind::DeltaLog => {
+            let header = read_delta_log_header_or_legacy(path, Some(file_type.file_type)).await?;
+            let (channel, unix_time_points) =
+                with_delta_log_decoder!(header, load_delta_log(path, &channel_name))
+                    .ok_or(anyhow!("No decoder for {:?}", path))??;
+            source.channels.push(channel);
+            source.unix_time_points = unix_time_points;
+        }
+        FileDecoderKind::SerializedEnum if file_type.file_type == SG_READINGS => {
+            load_sg_readings(&mut source, &channel_name).await?;
+        }
+        FileDecoderKind::SerializedEnum if file_type.file_type == VACUUM_TEST_LOG_FILE_TYPE => {
+            let stream = parse_serialized_enums::<VacuumTestLoggerReader<BufReaderWrapper<File>>>(
+                path.clone(),
+            )
+            .await?;
+            pin_mut!(stream);
+            while let Some(VacuumTestLog::FlightCoreEventLog(log)) = stream.next().await {
+                source
+                    .events
+                    .push((log.timestamp, format!("{:?}", log.event)));
+            }
+        }
+        FileDecoderKind::SerializedEnum if file_type.file_type == GROUND_TEST_LOG_FILE_TYPE => {
+            let stream = parse_serialized_enums::<GroundTestLoggerReader<BufReaderWrapper<File>>>(
+                path.clone(),
+            )
+            .await?;
+            pin_mut!(stream);
+            while let Some(GroundTestLog::FireEvent(event)) = stream.next().await {
+                source.events.push((event.timestamp, "Fire".into()));
+            }
+        }
+        _ => return Ok(None),
+    }
+
+    Ok(Some(source))
+}
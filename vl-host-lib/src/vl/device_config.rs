@@ -13,19 +13,35 @@ pub struct DeviceConfigSerde {
     pub mode: DeviceModeConfigSerde,
     pub lora: LoraConfigSerde,
     pub lora_key: [u8; 32],
+    #[serde(default)]
+    pub vlp_device_id: Option<u8>,
 }
 
 impl Into<DeviceConfig> for DeviceConfigSerde {
     fn into(self) -> DeviceConfig {
-        let mut name = self.name.as_bytes();
-        if name.len() > 64 {
-            name = &name[..64];
+        // truncate on a char boundary so the stored name is still valid utf-8
+        let mut name_len = self.name.len().min(64);
+        while !self.name.is_char_boundary(name_len) {
+            name_len -= 1;
         }
         DeviceConfig {
-            name: RkyvString::from_slice(name),
+            name: RkyvString::from_str(&self.name[..name_len]),
             mode: self.mode.into(),
             lora: self.lora.into(),
             lora_key: self.lora_key,
+            vlp_device_id: self.vlp_device_id,
+        }
+    }
+}
+
+impl From<DeviceConfig> for DeviceConfigSerde {
+    fn from(config: DeviceConfig) -> Self {
+        DeviceConfigSerde {
+            name: String::from(config.name.as_str()),
+            mode: config.mode.into(),
+            lora: config.lora.into(),
+            lora_key: config.lora_key,
+            vlp_device_id: config.vlp_device_id,
         }
     }
 }
@@ -51,6 +67,18 @@ impl Into<LoraConfig> for LoraConfigSerde {
     }
 }
 
+impl From<LoraConfig> for LoraConfigSerde {
+    fn from(lora: LoraConfig) -> Self {
+        LoraConfigSerde {
+            frequency: lora.frequency,
+            sf: lora.sf,
+            bw: lora.bw,
+            cr: lora.cr,
+            power: lora.power,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "mode")]
 pub enum DeviceModeConfigSerde {
@@ -80,7 +108,53 @@ impl Into<DeviceModeConfig> for DeviceModeConfigSerde {
     }
 }
 
+impl From<DeviceModeConfig> for DeviceModeConfigSerde {
+    fn from(mode: DeviceModeConfig) -> Self {
+        match mode {
+            DeviceModeConfig::Avionics => DeviceModeConfigSerde::Avionics,
+            DeviceModeConfig::GCM => DeviceModeConfigSerde::GCM,
+            DeviceModeConfig::GroundTestAvionics {
+                drogue_pyro,
+                main_pyro,
+            } => DeviceModeConfigSerde::GroundTestAvionics {
+                drogue_pyro: drogue_pyro.into(),
+                main_pyro: main_pyro.into(),
+            },
+            DeviceModeConfig::VacuumTest => DeviceModeConfigSerde::VacuumTest,
+        }
+    }
+}
+
 pub fn json_to_device_config(json: String) -> Result<DeviceConfig> {
     let config: DeviceConfigSerde = serde_json::from_str(&json)?;
     Ok(config.into())
-}
\ No newline at end of file
+}
+/// Same format as `json_to_device_config` accepts
+pub fn device_config_to_json(config: DeviceConfig) -> Result<String> {
+    let config: DeviceConfigSerde = config.into();
+    Ok(serde_json::to_string_pretty(&config)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn long_name_truncated_on_char_boundary() {
+        let json = format!(
+            r#"{{
+                "name": "{}",
+                "mode": {{"mode": "GCM"}},
+                "lora": {{"frequency": 915000000, "sf": 12, "bw": 250000, "cr": 8, "power": 22}},
+                "lora_key": [{}]
+            }}"#,
+            "a".repeat(63) + "é",
+            ["0"; 32].join(", ")
+        );
+        let config = json_to_device_config(json).unwrap();
+        assert_eq!(config.name.as_str(), "a".repeat(63));
+
+        let config: DeviceConfigSerde = config.into();
+        assert_eq!(config.name, "a".repeat(63));
+    }
+}
@@ -31,6 +31,22 @@ impl Into<FlightProfile> for FlightProfileSerde {
     }
 }
 
+impl From<FlightProfile> for FlightProfileSerde {
+    fn from(profile: FlightProfile) -> Self {
+        FlightProfileSerde {
+            drogue_pyro: profile.drogue_pyro.into(),
+            drogue_chute_minimum_time_ms: profile.drogue_chute_minimum_time_ms,
+            drogue_chute_minimum_altitude_agl: profile.drogue_chute_minimum_altitude_agl,
+            drogue_chute_delay_ms: profile.drogue_chute_delay_ms,
+            main_pyro: profile.main_pyro.into(),
+            main_chute_altitude_agl: profile.main_chute_altitude_agl,
+            main_chute_delay_ms: profile.main_chute_delay_ms,
+            drouge_to_main_ms: profile.drouge_to_main_ms,
+            main_to_landed_ms: profile.main_to_landed_ms,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum PyroSelectionSerde {
     Pyro1 = 1,
@@ -48,7 +64,23 @@ impl Into<PyroSelection> for PyroSelectionSerde {
     }
 }
 
+impl From<PyroSelection> for PyroSelectionSerde {
+    fn from(pyro: PyroSelection) -> Self {
+        match pyro {
+            PyroSelection::Pyro1 => PyroSelectionSerde::Pyro1,
+            PyroSelection::Pyro2 => PyroSelectionSerde::Pyro2,
+            PyroSelection::Pyro3 => PyroSelectionSerde::Pyro3,
+        }
+    }
+}
+
 pub fn json_to_flight_profile(json: String) -> Result<FlightProfile> {
     let profile: FlightProfileSerde = serde_json::from_str(&json)?;
     Ok(profile.into())
 }
+
+/// Same format as `json_to_flight_profile` accepts
+pub fn flight_profile_to_json(profile: FlightProfile) -> Result<String> {
+    let profile: FlightProfileSerde = profile.into();
+    Ok(serde_json::to_string_pretty(&profile)?)
+}
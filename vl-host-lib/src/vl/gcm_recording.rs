@@ -0,0 +1,74 @@
+use anyhow::Result;
+use async_stream::try_stream;
+use embedded_hal_async::delay::DelayNs;
+use firmware_common::{
+    common::file_types::GCM_TELEMETRY_RECORDING_FILE_TYPE,
+    driver::serial::SplitableSerial,
+    gcm::{GCMRecordingLog, GCMRecordingLoggerReader, ReceivedVLPPacket},
+    vl_rpc,
+};
+use futures_core::Stream;
+use futures_util::{pin_mut, StreamExt};
+use std::{fs, path::PathBuf, time::Duration};
+use tokio::{fs::File, time::sleep};
+
+use crate::common::{
+    parse_serialized_enums::parse_serialized_enums, pull_file::pull_files,
+    readers::BufReaderWrapper,
+};
+
+/// Pull all the telemetry recordings from the GCM, returns the paths of the pulled files
+pub async fn pull_gcm_recording<S: SplitableSerial, D: DelayNs>(
+    client: &mut vl_rpc::RpcClient<'_, S, D>,
+    save_folder: &PathBuf,
+) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(&save_folder)?;
+
+    match client.g_c_m_get_recording_status().await {
+        Ok(status) => match status.file_id {
+            Some(file_id) => log::info!(
+                "GCM is recording to file {}, {} packets recorded",
+                file_id,
+                status.recorded_packets
+            ),
+            None => log::warn!("GCM is not recording, only older recordings will be pulled"),
+        },
+        Err(e) => log::warn!("Failed to get the recording status: {:?}", e),
+    }
+
+    pull_files(client, GCM_TELEMETRY_RECORDING_FILE_TYPE, &save_folder).await
+}
+
+/// Re-emit the packets in the recording files with their original timing,
+/// as if they were received live from the GCM.
+///
+/// `speed` scales the playback rate, e.g. 2.0 replays twice as fast, must be
+/// positive.
+pub fn replay_gcm_recording(
+    file_paths: Vec<PathBuf>,
+    speed: f64,
+) -> impl Stream<Item = Result<ReceivedVLPPacket>> {
+    try_stream! {
+        let mut last_timestamp: Option<f64> = None;
+        for file_path in file_paths {
+            let logs = parse_serialized_enums::<GCMRecordingLoggerReader<BufReaderWrapper<File>>>(
+                file_path,
+            )
+            .await?;
+            pin_mut!(logs);
+
+            while let Some(log) = logs.next().await {
+                let GCMRecordingLog::ReceivedVLPPacket(received) = log;
+                if let Some(last_timestamp) = last_timestamp {
+                    // gaps between recordings (e.g. GCM reboots) are not replayed
+                    let gap_ms = received.unix_timestamp - last_timestamp;
+                    if gap_ms > 0.0 && gap_ms < 60_000.0 {
+                        sleep(Duration::from_secs_f64(gap_ms / 1000.0 / speed)).await;
+                    }
+                }
+                last_timestamp = Some(received.unix_timestamp);
+                yield received;
+            }
+        }
+    }
+}
@@ -3,9 +3,19 @@ mod device_config;
 mod flight_profile;
 mod pull_vacuum_test;
 mod pull_flight_data;
+mod pull_ground_test;
+mod gcm_recording;
+mod vlp_codec;
+mod push_config;
+mod verify_config;
 
 pub use lora_key::*;
-pub use device_config::json_to_device_config;
-pub use flight_profile::json_to_flight_profile;
+pub use device_config::{device_config_to_json, json_to_device_config};
+pub use flight_profile::{flight_profile_to_json, json_to_flight_profile};
 pub use pull_vacuum_test::pull_vacuum_test;
-pub use pull_flight_data::pull_flight_data;
\ No newline at end of file
+pub use pull_flight_data::pull_flight_data;
+pub use pull_ground_test::pull_ground_test;
+pub use gcm_recording::{pull_gcm_recording, replay_gcm_recording};
+pub use vlp_codec::{DecodedVLPPacket, VLPCodec};
+pub use push_config::gcm_push_config;
+pub use verify_config::{diff_device_config, diff_flight_profile};
\ No newline at end of file
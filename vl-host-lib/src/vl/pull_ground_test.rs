@@ -0,0 +1,33 @@
+use anyhow::Result;
+use firmware_common::{
+    common::file_types::GROUND_TEST_LOG_FILE_TYPE, driver::serial::SplitableSerial,
+    ground_test_delta_loggers, CommonRPCTrait, GroundTestLoggerReader,
+};
+use std::{fs, path::PathBuf};
+use tokio::fs::File;
+
+use crate::common::{
+    delta_log_pipeline::{delta_log_pipeline, pull_serialized_enum_logs},
+    export::{DataExport, ExportFormat, ExportManifest},
+    readers::BufReaderWrapper,
+};
+
+pub async fn pull_ground_test<S: SplitableSerial>(
+    rpc: &mut impl CommonRPCTrait<S>,
+    save_folder: &PathBuf,
+    format: ExportFormat,
+) -> Result<ExportManifest> {
+    fs::create_dir_all(&save_folder)?;
+    let mut export = DataExport::new(save_folder, format);
+
+    pull_serialized_enum_logs::<_, GroundTestLoggerReader<BufReaderWrapper<File>>>(
+        rpc,
+        GROUND_TEST_LOG_FILE_TYPE,
+        &mut export,
+    )
+    .await?;
+
+    ground_test_delta_loggers!(delta_log_pipeline!(rpc, &mut export));
+
+    export.finish()
+}
@@ -1,74 +1,33 @@
 use anyhow::Result;
 use firmware_common::{
-    common::file_types::{VACUUM_TEST_BARO_LOGGER, VACUUM_TEST_LOG_FILE_TYPE},
-    driver::{barometer::BaroData, serial::SplitableSerial},
-    vacuum_test::{SensorsFF1, VacuumTestLoggerReader},
-    CommonRPCTrait,
+    common::file_types::VACUUM_TEST_LOG_FILE_TYPE, driver::serial::SplitableSerial,
+    vacuum_test::VacuumTestLoggerReader, vacuum_test_delta_loggers, CommonRPCTrait,
 };
-use futures_util::{pin_mut, StreamExt};
-use std::vec;
 use std::{fs, path::PathBuf};
-use tokio::{fs::File, io::AsyncWriteExt};
+use tokio::fs::File;
 
 use crate::common::{
-    extend_path, parse_delta_readings::parse_delta_readings,
-    parse_serialized_enums::parse_serialized_enums, pull_file::pull_files,
-    readers::BufReaderWrapper, SensorReadingCSVWriter,
+    delta_log_pipeline::{delta_log_pipeline, pull_serialized_enum_logs},
+    export::{DataExport, ExportFormat, ExportManifest},
+    readers::BufReaderWrapper,
 };
 
 pub async fn pull_vacuum_test<S: SplitableSerial>(
     rpc: &mut impl CommonRPCTrait<S>,
     save_folder: &PathBuf,
-) -> Result<()> {
+    format: ExportFormat,
+) -> Result<ExportManifest> {
     fs::create_dir_all(&save_folder)?;
+    let mut export = DataExport::new(save_folder, format);
 
-    // logs
-    let log_files = pull_files(
+    pull_serialized_enum_logs::<_, VacuumTestLoggerReader<BufReaderWrapper<File>>>(
         rpc,
         VACUUM_TEST_LOG_FILE_TYPE,
-        "vacuum_test_log",
-        "vle",
-        &save_folder,
+        &mut export,
     )
     .await?;
 
-    let mut logs_writer = tokio::io::BufWriter::new(
-        File::create(extend_path(&save_folder, "vacuum_test_log.log")).await?,
-    );
-    for file_path in log_files {
-        let stream =
-            parse_serialized_enums::<VacuumTestLoggerReader<BufReaderWrapper<File>>>(file_path)
-                .await?;
-        pin_mut!(stream);
+    vacuum_test_delta_loggers!(delta_log_pipeline!(rpc, &mut export));
 
-        while let Some(log) = stream.next().await {
-            logs_writer
-                .write_all(format!("{:?}\n", log).as_bytes())
-                .await?;
-        }
-    }
-    logs_writer.flush().await?;
-
-    // baro readings
-    let baro_files = pull_files(rpc, VACUUM_TEST_BARO_LOGGER, "baro", "vldr", &save_folder).await?;
-
-    let mut csv_writer = SensorReadingCSVWriter::new(
-        &extend_path(&save_folder, "baro.csv"),
-        &["pressure", "altitude", "temperature"],
-        |data: BaroData| {
-            vec![
-                format!("{}", data.pressure),
-                format!("{}", data.altitude()),
-                format!("{}", data.temperature),
-            ]
-        },
-    )
-    .unwrap();
-    for file_path in baro_files {
-        let stream = parse_delta_readings::<BaroData, SensorsFF1>(file_path).await?;
-        csv_writer.write_all(stream).await?;
-    }
-    csv_writer.flush()?;
-
-    Ok(())
+    export.finish()
 }
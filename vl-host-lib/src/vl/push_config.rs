@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use embedded_hal_async::delay::DelayNs;
+use firmware_common::{
+    common::{
+        console::vl_rpc::{GCMPollDownlinkPacketResponse, GCMSendUplinkPacketResponse},
+        vlp::{
+            config_update::{config_hash, config_update_mac, create_config_chunks},
+            packet::{
+                ConfigCommitPacket, ConfigUpdateResultPacket, ConfigUpdateStatus,
+                ConfigUpdateType, VLPDownlinkPacket, VLPUplinkPacket,
+            },
+        },
+    },
+    driver::serial::SplitableSerial,
+    vl_rpc,
+};
+use rand::random;
+use tokio::time::sleep;
+
+const MAX_RETRIES: usize = 5;
+const RESULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Push a serialized config to a rocket through the GCM.
+///
+/// Every chunk is retried until the rocket acks it, then the commit packet is sent
+/// and the result reported by the rocket is returned. The hash of an applied config
+/// is checked against the pushed config.
+pub async fn gcm_push_config<S: SplitableSerial, D: DelayNs>(
+    client: &mut vl_rpc::RpcClient<'_, S, D>,
+    device_id: u8,
+    config_type: ConfigUpdateType,
+    data: &[u8],
+    key: &[u8; 32],
+) -> Result<ConfigUpdateResultPacket> {
+    let transfer_id: u8 = random();
+
+    let chunks = create_config_chunks(config_type, transfer_id, data).collect::<Vec<_>>();
+    for chunk in chunks {
+        let chunk_index = chunk.chunk_index;
+        send_with_retries(client, device_id, chunk.into())
+            .await
+            .map_err(|e| anyhow!("Failed to send chunk {}: {}", chunk_index, e))?;
+    }
+
+    // the same commit is resent, so the rocket answers a retry of an applied
+    // commit with the same result instead of rejecting it as a replay
+    let timestamp = unix_now_ms();
+    let commit = ConfigCommitPacket {
+        timestamp,
+        config_type,
+        transfer_id,
+        length: data.len() as u32,
+        mac: config_update_mac(config_type, transfer_id, timestamp, data, key),
+    };
+    for _ in 0..MAX_RETRIES {
+        send_with_retries(client, device_id, commit.clone().into()).await?;
+
+        if let Some(result) = wait_result(client, device_id, transfer_id).await? {
+            if result.status == ConfigUpdateStatus::Applied && result.hash != config_hash(data) {
+                return Err(anyhow!(
+                    "Config hash mismatch, expected {:08X}, got {:08X}",
+                    config_hash(data),
+                    result.hash
+                ));
+            }
+            return Ok(result);
+        }
+    }
+
+    Err(anyhow!("No config update result received from device {}", device_id))
+}
+
+async fn send_with_retries<S: SplitableSerial, D: DelayNs>(
+    client: &mut vl_rpc::RpcClient<'_, S, D>,
+    device_id: u8,
+    packet: VLPUplinkPacket,
+) -> Result<()> {
+    for _ in 0..MAX_RETRIES {
+        match client.g_c_m_send_uplink_packet(device_id, packet.clone()).await {
+            Ok(GCMSendUplinkPacketResponse { status: Some(_) }) => return Ok(()),
+            Ok(GCMSendUplinkPacketResponse { status: None }) => {}
+            Err(_) => return Err(anyhow!("RPC error")),
+        }
+    }
+    Err(anyhow!("Not acked after {} tries", MAX_RETRIES))
+}
+
+async fn wait_result<S: SplitableSerial, D: DelayNs>(
+    client: &mut vl_rpc::RpcClient<'_, S, D>,
+    device_id: u8,
+    transfer_id: u8,
+) -> Result<Option<ConfigUpdateResultPacket>> {
+    let start = Instant::now();
+    while start.elapsed() < RESULT_TIMEOUT {
+        match client.g_c_m_poll_downlink_packet().await {
+            Ok(GCMPollDownlinkPacketResponse {
+                packet: Some((source, VLPDownlinkPacket::ConfigUpdateResultPacket(result), _)),
+            }) if source == device_id && result.transfer_id == transfer_id => {
+                return Ok(Some(result));
+            }
+            Ok(_) => sleep(Duration::from_millis(50)).await,
+            Err(_) => return Err(anyhow!("RPC error")),
+        }
+    }
+    Ok(None)
+}
+
+fn unix_now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+        * 1000.0
+}
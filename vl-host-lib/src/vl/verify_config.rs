@@ -0,0 +1,106 @@
+use firmware_common::{avionics::flight_profile::FlightProfile, common::device_config::DeviceConfig};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{device_config::DeviceConfigSerde, flight_profile::FlightProfileSerde};
+
+/// Differences between a local flight profile and the one read back from a
+/// device, one line per field
+pub fn diff_flight_profile(local: FlightProfile, device: FlightProfile) -> Vec<String> {
+    diff_serde(
+        &FlightProfileSerde::from(local),
+        &FlightProfileSerde::from(device),
+    )
+}
+
+/// Differences between a local device config and the one read back from a
+/// device, one line per field
+pub fn diff_device_config(local: DeviceConfig, device: DeviceConfig) -> Vec<String> {
+    diff_serde(
+        &DeviceConfigSerde::from(local),
+        &DeviceConfigSerde::from(device),
+    )
+}
+
+// both sides go through the firmware types first, so the local json is
+// rounded / truncated the same way the device stores it
+fn diff_serde<T: Serialize>(local: &T, device: &T) -> Vec<String> {
+    let local = serde_json::to_value(local).unwrap();
+    let device = serde_json::to_value(device).unwrap();
+    let mut diffs = vec![];
+    diff_json("", &local, &device, &mut diffs);
+    diffs
+}
+
+/// Fields whose values are never printed, only whether they match
+const REDACTED_FIELDS: &[&str] = &["lora_key"];
+
+fn diff_json(path: &str, local: &Value, device: &Value, diffs: &mut Vec<String>) {
+    match (local, device) {
+        (Value::Object(local), Value::Object(device)) => {
+            let mut keys: Vec<&String> = local.keys().chain(device.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let field_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                diff_json(
+                    &field_path,
+                    local.get(key).unwrap_or(&Value::Null),
+                    device.get(key).unwrap_or(&Value::Null),
+                    diffs,
+                );
+            }
+        }
+        (local, device) if local != device => {
+            let field = path.rsplit('.').next().unwrap_or(path);
+            if REDACTED_FIELDS.contains(&field) {
+                diffs.push(format!("{}: local and device differ", path));
+            } else {
+                diffs.push(format!("{}: local {}, device {}", path, local, device));
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_diff_json() {
+        let local = json!({
+            "main_chute_altitude_agl": 300.0,
+            "main_pyro": "Pyro1",
+            "mode": { "mode": "Avionics" },
+            "lora_key": [1, 2, 3],
+        });
+        let device = json!({
+            "main_chute_altitude_agl": 350.0,
+            "main_pyro": "Pyro1",
+            "mode": { "mode": "GCM" },
+            "lora_key": [1, 2, 4],
+        });
+
+        let mut diffs = vec![];
+        diff_json("", &local, &device, &mut diffs);
+        assert_eq!(
+            diffs,
+            vec![
+                "lora_key: local and device differ",
+                "main_chute_altitude_agl: local 300.0, device 350.0",
+                "mode.mode: local \"Avionics\", device \"GCM\"",
+            ]
+        );
+
+        diffs.clear();
+        diff_json("", &local, &local, &mut diffs);
+        assert!(diffs.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use firmware_common::{
+    common::{
+        device_config::LoraConfig,
+        unix_clock::UnixClockTask,
+        vlp::{
+            packet::{VLPDownlinkPacket, VLPUplinkPacket},
+            packet_builder::{
+                calculate_ecc_length_from_total_length, VLPPacketBuilder, MAX_VLP_PACKET_SIZE,
+            },
+        },
+    },
+    driver::clock::Clock,
+};
+use heapless::Vec;
+
+#[derive(Debug, Clone)]
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as f64
+            / 1000.0
+    }
+}
+
+/// A VLP packet decoded from raw LoRa payload bytes
+#[derive(Debug, Clone)]
+pub struct DecodedVLPPacket<P> {
+    /// Destination device id for uplink packets, source device id for downlink packets
+    pub device_id: u8,
+    pub packet: P,
+    pub total_length: usize,
+    pub ecc_length: usize,
+}
+
+/// Encodes and decodes raw LoRa payloads the same way the firmware does,
+/// without needing a GCM in the loop.
+pub struct VLPCodec {
+    lora_config: LoraConfig,
+    key: [u8; 32],
+    unix_clock_task: UnixClockTask<SystemClock>,
+}
+
+impl VLPCodec {
+    pub fn new(lora_config: LoraConfig, key: [u8; 32]) -> Self {
+        Self {
+            lora_config,
+            key,
+            unix_clock_task: UnixClockTask::new(SystemClock),
+        }
+    }
+
+    fn packet_builder(&self) -> VLPPacketBuilder<'_, '_, SystemClock> {
+        VLPPacketBuilder::new(
+            self.unix_clock_task.get_clock(),
+            (&self.lora_config).into(),
+            &self.key,
+        )
+    }
+
+    pub fn encode_uplink(&self, destination: u8, packet: &VLPUplinkPacket) -> Result<std::vec::Vec<u8>> {
+        let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
+        self.packet_builder()
+            .serialize_uplink(&mut buffer, destination, packet)
+            .map_err(|_| anyhow!("Packet too large"))?;
+        Ok(buffer.to_vec())
+    }
+
+    pub fn decode_uplink(&self, data: &[u8]) -> Result<DecodedVLPPacket<VLPUplinkPacket>> {
+        let buffer = Self::to_buffer(data)?;
+        let (device_id, packet) = self
+            .packet_builder()
+            .deserialize_uplink(&buffer)
+            .map_err(|_| anyhow!("Not a valid uplink packet for this key"))?;
+        Ok(DecodedVLPPacket {
+            device_id,
+            packet,
+            total_length: data.len(),
+            ecc_length: calculate_ecc_length_from_total_length(data.len()),
+        })
+    }
+
+    pub fn encode_downlink(&self, source: u8, packet: &VLPDownlinkPacket) -> Result<std::vec::Vec<u8>> {
+        let mut buffer = Vec::<u8, MAX_VLP_PACKET_SIZE>::new();
+        self.packet_builder()
+            .serialize_downlink(&mut buffer, source, packet)
+            .map_err(|_| anyhow!("Packet too large"))?;
+        Ok(buffer.to_vec())
+    }
+
+    pub fn decode_downlink(&self, data: &[u8]) -> Result<DecodedVLPPacket<VLPDownlinkPacket>> {
+        let buffer = Self::to_buffer(data)?;
+        let (device_id, packet) = self
+            .packet_builder()
+            .deserialize_downlink(&buffer)
+            .map_err(|_| anyhow!("Not a valid downlink packet for this key"))?;
+        Ok(DecodedVLPPacket {
+            device_id,
+            packet,
+            total_length: data.len(),
+            ecc_length: calculate_ecc_length_from_total_length(data.len()),
+        })
+    }
+
+    fn to_buffer(data: &[u8]) -> Result<Vec<u8, MAX_VLP_PACKET_SIZE>> {
+        Vec::from_slice(data).map_err(|_| {
+            anyhow!(
+                "Payload is {} bytes, VLP packets are at most {} bytes",
+                data.len(),
+                MAX_VLP_PACKET_SIZE
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use firmware_common::common::vlp::packet::{AckPacket, SoftArmPacket};
+
+    use super::*;
+
+    fn create_codec(key: [u8; 32]) -> VLPCodec {
+        VLPCodec::new(
+            LoraConfig {
+                frequency: 915_000_000,
+                sf: 12,
+                bw: 250000,
+                cr: 8,
+                power: 22,
+            },
+            key,
+        )
+    }
+
+    #[test]
+    fn uplink_round_trip() {
+        let codec = create_codec([0x69; 32]);
+        let packet: VLPUplinkPacket = SoftArmPacket {
+            timestamp: 1000.0,
+            armed: true,
+        }
+        .into();
+        let encoded = codec.encode_uplink(42, &packet).unwrap();
+        let decoded = codec.decode_uplink(&encoded).unwrap();
+        assert_eq!(decoded.device_id, 42);
+        assert_eq!(decoded.packet, packet);
+        assert_eq!(decoded.total_length, encoded.len());
+
+        assert!(create_codec([0x42; 32]).decode_uplink(&encoded).is_err());
+    }
+
+    #[test]
+    fn downlink_round_trip() {
+        let codec = create_codec([0x69; 32]);
+        let packet: VLPDownlinkPacket = AckPacket {
+            timestamp: 1000.0,
+        }
+        .into();
+        let encoded = codec.encode_downlink(42, &packet).unwrap();
+        let decoded = codec.decode_downlink(&encoded).unwrap();
+        assert_eq!(decoded.device_id, 42);
+        assert_eq!(decoded.packet, packet);
+
+        assert!(create_codec([0x42; 32]).decode_downlink(&encoded).is_err());
+    }
+
+    // golden vectors, a change here means deployed rockets and GCMs can no longer
+    // talk to the ground software
+    const UPLINK_GOLDEN_VECTOR: [u8; 13] = [
+        0x22, 0x69, 0x93, 0x66, 0x1D, 0xB8, 0x67, 0xB3, 0x1D, 0xFD, 0xE7, 0x57, 0x39,
+    ];
+
+    const DOWNLINK_GOLDEN_VECTOR: [u8; 13] = [
+        0xA8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3D, 0x02, 0x01, 0xC0, 0x3A, 0x6C,
+    ];
+
+    #[test]
+    fn uplink_golden_vector() {
+        let codec = create_codec([0x69; 32]);
+        let packet: VLPUplinkPacket = SoftArmPacket {
+            timestamp: 1000.0,
+            armed: true,
+        }
+        .into();
+        assert_eq!(codec.encode_uplink(42, &packet).unwrap(), UPLINK_GOLDEN_VECTOR);
+
+        let decoded = codec.decode_uplink(&UPLINK_GOLDEN_VECTOR).unwrap();
+        assert_eq!(decoded.device_id, 42);
+        assert_eq!(decoded.packet, packet);
+        assert_eq!(decoded.ecc_length, 2);
+    }
+
+    #[test]
+    fn downlink_golden_vector() {
+        let codec = create_codec([0x69; 32]);
+        let packet: VLPDownlinkPacket = AckPacket {
+            timestamp: 1000.0,
+        }
+        .into();
+        assert_eq!(codec.encode_downlink(42, &packet).unwrap(), DOWNLINK_GOLDEN_VECTOR);
+
+        let decoded = codec.decode_downlink(&DOWNLINK_GOLDEN_VECTOR).unwrap();
+        assert_eq!(decoded.device_id, 42);
+        assert_eq!(decoded.packet, packet);
+        assert_eq!(decoded.ecc_length, 2);
+    }
+}